@@ -0,0 +1,39 @@
+/* C API of trayson, built with
+ *
+ *     cargo rustc --release --lib --features ffi --crate-type cdylib
+ *
+ * Changes are passed to the callback as JSON objects with an "event" of
+ * "added" or "changed" (and the "item"), "removed" (and its "id") or "error"
+ * (and the "item" and a "reason"), on a thread of the tray.
+ */
+#ifndef TRAYSON_H
+#define TRAYSON_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct trayson trayson;
+
+typedef void (*trayson_callback)(const char *event, void *data);
+
+/* Starts following the items, NULL if the host could not start. */
+trayson *trayson_start(trayson_callback callback, void *data);
+
+/* Stops the tray and frees it, no callback runs anymore once it returns. */
+void trayson_stop(trayson *tray);
+
+/* The actions below return 0 on success and -1 otherwise. */
+int trayson_activate(const trayson *tray, const char *id, int x, int y);
+int trayson_secondary_activate(const trayson *tray, const char *id, int x, int y);
+int trayson_context_menu(const trayson *tray, const char *id, int x, int y);
+/* orientation is "vertical" or "horizontal" */
+int trayson_scroll(const trayson *tray, const char *id, int delta, const char *orientation);
+/* event is usually "clicked" */
+int trayson_menu_event(const trayson *tray, const char *id, int menu_id, const char *event);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
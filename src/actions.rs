@@ -0,0 +1,113 @@
+use serde_json::Value;
+use std::path::Path;
+
+/// Builds the `trayson ...` command lines added to items for `--click-commands`.
+pub struct ClickCommands {
+    exe: String,
+    /// `--control-socket` after the subcommand, which takes it as a client option
+    socket: String,
+}
+
+impl ClickCommands {
+    pub fn new(control_socket: Option<&Path>) -> Self {
+        let exe = std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.to_str().map(shell_quote))
+            .unwrap_or_else(|| "trayson".to_string());
+        let socket = match control_socket.and_then(Path::to_str) {
+            Some(socket) => format!(" --control-socket {}", shell_quote(socket)),
+            None => String::new(),
+        };
+        ClickCommands { exe, socket }
+    }
+
+    /// Adds `onclick`, `onmiddleclick`, `onrightclick`, `onscrollup` and
+    /// `onscrolldown` to a serialized item.
+    pub fn annotate(&self, item: &mut Value) {
+        let id = match item["id"].as_str() {
+            Some(id) => shell_quote(id),
+            None => return,
+        };
+        let (exe, socket) = (&self.exe, &self.socket);
+        let commands = [
+            ("onclick", format!("{} activate {}{}", exe, id, socket)),
+            (
+                "onmiddleclick",
+                format!("{} secondary {}{}", exe, id, socket),
+            ),
+            (
+                "onrightclick",
+                format!("{} context-menu {}{}", exe, id, socket),
+            ),
+            ("onscrollup", format!("{} scroll {} 1{}", exe, id, socket)),
+            (
+                "onscrolldown",
+                format!("{} scroll {} -1{}", exe, id, socket),
+            ),
+        ];
+        if let Some(item) = item.as_object_mut() {
+            for (field, command) in commands {
+                item.insert(field.to_string(), Value::String(command));
+            }
+        }
+    }
+}
+
+fn shell_quote(s: &str) -> String {
+    if !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/-_.:=".contains(c))
+    {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{Cli, Cmd};
+    use clap::Parser;
+    use serde_json::json;
+
+    #[test]
+    fn commands_parse_with_and_without_a_socket() {
+        for socket in [None, Some(Path::new("/run/user/1000/trayson.sock"))] {
+            let mut item = json!({"id": "nm-applet"});
+            ClickCommands::new(socket).annotate(&mut item);
+            for field in [
+                "onclick",
+                "onmiddleclick",
+                "onrightclick",
+                "onscrollup",
+                "onscrolldown",
+            ] {
+                let line = item[field].as_str().unwrap();
+                let cli = Cli::try_parse_from(line.split_whitespace())
+                    .unwrap_or_else(|e| panic!("`{}`: {}", line, e));
+                let client = match cli.command {
+                    Some(Cmd::Activate(args) | Cmd::Secondary(args) | Cmd::ContextMenu(args)) => {
+                        assert_eq!(args.item, "nm-applet");
+                        args.client
+                    }
+                    Some(Cmd::Scroll(args)) => {
+                        assert_eq!(args.item, "nm-applet");
+                        assert_eq!(args.delta.abs(), 1);
+                        args.client
+                    }
+                    other => panic!("`{}` parsed as {:?}", line, other),
+                };
+                assert_eq!(client.control_socket.as_deref(), socket);
+            }
+        }
+    }
+
+    #[test]
+    fn quotes_for_the_shell() {
+        assert_eq!(shell_quote("/usr/bin/trayson"), "/usr/bin/trayson");
+        assert_eq!(shell_quote("my tray"), "'my tray'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote(""), "''");
+    }
+}
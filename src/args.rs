@@ -0,0 +1,552 @@
+use crate::filter::{Filter, HideRule, Pattern};
+use crate::logging::LogTarget;
+use crate::mock::Script;
+use crate::naming::FieldCase;
+use crate::output::Destination;
+use crate::overrides::Override;
+use crate::sink::{Format, SinkConfig};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::Value;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Debug, Parser)]
+#[command(name = "trayson", about = "Prints StatusNotifierItems as JSON")]
+#[command(args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Cmd>,
+
+    /// Options for `run`, which is the default when no subcommand is given
+    #[command(flatten)]
+    pub run: RunArgs,
+
+    /// Where the records enabled by `$TRAYSON_LOG` go [default: journald if
+    /// stderr is connected to it, else stderr]
+    #[arg(long, global = true, value_enum)]
+    pub log: Option<LogTarget>,
+}
+
+#[derive(Debug, Subcommand)]
+#[allow(clippy::large_enum_variant)]
+pub enum Cmd {
+    /// Run the tray daemon
+    Run(RunArgs),
+    /// Print the currently registered items once and exit, without a running instance
+    Snapshot(SnapshotArgs),
+    /// Emit the items of a `--record` file again, through the filters and sinks
+    /// given as for `run`
+    Replay(ReplayArgs),
+    /// Register fake items with the running watcher, for testing
+    MockItem(MockArgs),
+    /// Print the items of the running instance
+    List(ClientArgs),
+    /// Print a single item of the running instance
+    #[command(alias = "query")]
+    Get {
+        /// `id` of the item as printed in the output
+        item: String,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Activate an item, usually a left click
+    Activate(ClickArgs),
+    /// Secondary activation of an item, usually a middle click
+    Secondary(ClickArgs),
+    /// Ask an item to open its context menu, usually a right click
+    ContextMenu(ClickArgs),
+    /// Scroll on an item, e.g. to change the volume
+    Scroll(ScrollArgs),
+    /// Raise the window of an item with a `window_id`, needs the `x11` feature
+    Raise {
+        /// `id` of the item as printed in the output
+        item: String,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Trigger an entry of an item's menu
+    MenuEvent(MenuEventArgs),
+    /// Print the menu of an item
+    Menu(MenuArgs),
+    /// Print the JSON Schema of the output
+    Schema {
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+    },
+    /// Print the `id` of the item at offset `x` of the strip emitted last by a
+    /// `format=strip` sink
+    HitTest {
+        #[arg(allow_negative_numbers = true)]
+        x: i32,
+        /// Destination of the `format=strip` sink, needed if there are several
+        #[arg(long, value_name = "DEST")]
+        sink: Option<String>,
+        #[command(flatten)]
+        client: ClientArgs,
+    },
+    /// Print the memory used by the running instance, its icon files and per item
+    Stats(ClientArgs),
+    /// Show the menu of an item as a popup at `--x`, `--y` and click the chosen entry
+    #[cfg(feature = "gui")]
+    Popup(ClickArgs),
+    /// Open a window showing all items of the running instance live
+    #[cfg(feature = "gui")]
+    DebugView(ClientArgs),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct MenuArgs {
+    /// `id` of the item as printed in the output
+    pub item: String,
+    /// Print one line per entry, read the chosen line from stdin and click it,
+    /// as with `rofi -dmenu` or `dmenu`
+    #[arg(long)]
+    pub dmenu: bool,
+    #[command(flatten)]
+    pub client: ClientArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct MenuEventArgs {
+    /// `id` of the item as printed in the output
+    pub item: String,
+    /// `id` of the menu entry
+    pub menu_id: i32,
+    #[arg(default_value = "clicked")]
+    pub event: String,
+    #[command(flatten)]
+    pub client: ClientArgs,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Orientation {
+    Vertical,
+    Horizontal,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ScrollArgs {
+    /// `id` of the item as printed in the output
+    pub item: String,
+    #[arg(allow_negative_numbers = true)]
+    pub delta: i32,
+    #[arg(value_enum, default_value_t = Orientation::Vertical)]
+    pub orientation: Orientation,
+    #[command(flatten)]
+    pub client: ClientArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ReplayArgs {
+    /// File written with `--record`
+    pub file: PathBuf,
+    /// Emit everything right away instead of with the recorded timing
+    #[arg(long)]
+    pub fast: bool,
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct MockArgs {
+    /// SNI `Id`, numbered from `-1` on with `--count`
+    #[arg(long, default_value = "trayson-mock")]
+    pub id: String,
+    #[arg(long, default_value = "Mock item")]
+    pub title: String,
+    #[arg(long, default_value = "ApplicationStatus")]
+    pub category: String,
+    #[arg(long, default_value = "Active")]
+    pub status: String,
+    /// Themed icon announced along with the pixmap
+    #[arg(long)]
+    pub icon_name: Option<String>,
+    #[arg(long)]
+    pub attention_icon_name: Option<String>,
+    #[arg(long)]
+    pub overlay_icon_name: Option<String>,
+    /// Edge of the square pixmap in pixels, 0 for none
+    #[arg(long, default_value_t = 22)]
+    pub pixmap_size: i32,
+    /// `WindowId`, 0 for none
+    #[arg(long, default_value_t = 0)]
+    pub window_id: i32,
+    /// Entry of the menu, `-` for a separator. May be given multiple times
+    #[arg(long = "menu", value_name = "LABEL")]
+    pub menu: Vec<String>,
+    /// Register this many items
+    #[arg(long, default_value_t = 1)]
+    pub count: u32,
+    /// Changes to make to all items, e.g. `status NeedsAttention; sleep 500; icon`
+    #[arg(long)]
+    pub script: Option<Script>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SnapshotArgs {
+    /// Only print items matching this expression
+    #[arg(long)]
+    pub filter: Option<Filter>,
+
+    #[arg(long, value_enum, default_value_t = Format::Json)]
+    pub format: Format,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ClientArgs {
+    /// Control socket of the running instance [default: $XDG_RUNTIME_DIR/trayson.sock]
+    #[arg(long)]
+    pub control_socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ClickArgs {
+    /// `id` of the item as printed in the output
+    pub item: String,
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    pub x: i32,
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true)]
+    pub y: i32,
+    /// XDG activation token for the window the application raises, passed on
+    /// by `activate` and `secondary` [default: $XDG_ACTIVATION_TOKEN]
+    #[arg(long, value_name = "TOKEN")]
+    pub activation_token: Option<String>,
+    #[command(flatten)]
+    pub client: ClientArgs,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    /// Serve the StatusNotifierWatcher unless another process already does,
+    /// become a host of the new one if it is taken over later
+    Auto,
+    /// Serve the StatusNotifierWatcher and act as its host
+    Full,
+    /// Only act as a host of an already running watcher, e.g. the one of KDE
+    Host,
+    /// Only serve the StatusNotifierWatcher, for other hosts like the tray of waybar
+    Watcher,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RunArgs {
+    /// Which StatusNotifier roles to take on the bus
+    #[arg(long, value_enum, default_value_t = Mode::Auto)]
+    pub mode: Mode,
+
+    /// Configuration file [default: $XDG_CONFIG_HOME/trayson/config.toml]
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Only emit items matching this expression,
+    /// e.g. `status != "Passive" && category == "Communications"`
+    #[arg(long)]
+    pub filter: Option<Filter>,
+
+    /// Hide items with this status, category or both, e.g. `Passive SystemServices`
+    /// or `Hardware`. May be given multiple times
+    #[arg(long, value_name = "RULE")]
+    pub hide: Vec<HideRule>,
+
+    /// Hide items whose SNI `Id` matches this glob, or regex when wrapped in
+    /// slashes, e.g. `steam` or `/^chrome_status_icon_\d+$/`. May be given
+    /// multiple times
+    #[arg(long, value_name = "PATTERN")]
+    pub ignore: Vec<Pattern>,
+
+    /// Only show items whose SNI `Id` matches one of these patterns
+    #[arg(long, value_name = "PATTERN")]
+    pub only: Vec<Pattern>,
+
+    /// Put items whose SNI `Id` matches this pattern first, in the order given.
+    /// The others follow sorted by `id`
+    #[arg(long = "pin", value_name = "PATTERN")]
+    pub pinned: Vec<Pattern>,
+
+    /// Write the latest state to this file (replaced atomically) or named pipe
+    /// instead of stdout
+    #[arg(long)]
+    pub output_path: Option<PathBuf>,
+
+    /// Output format of the default sink [default: json]
+    #[arg(long, value_enum)]
+    pub format: Option<Format>,
+
+    /// Give up on calls to an application after this many milliseconds and mark
+    /// its item `unresponsive` [default: 5000]
+    #[arg(long, value_name = "MS")]
+    pub call_timeout: Option<u64>,
+
+    /// Ask every item for its status this often in seconds, 0 for never, and
+    /// mark those not answering in time `stale` [default: 30]
+    #[arg(long, value_name = "SECS")]
+    pub ping_interval: Option<u64>,
+
+    /// Drop items that stay `stale` for this many seconds, as apps that froze
+    /// but still own their bus name [default: 120]
+    #[arg(long, value_name = "SECS")]
+    pub stale_grace: Option<u64>,
+
+    /// Read at most this many new items at once, the others wait for their
+    /// turn; 0 for no limit [default: 4]
+    #[arg(long, value_name = "ITEMS")]
+    pub init_concurrency: Option<usize>,
+
+    /// Read the icon of an item at most this many times a second, dropping
+    /// the `NewIcon` signals in between; 0 for no limit [default: 4]
+    #[arg(long, value_name = "ICONS")]
+    pub icon_rate: Option<u32>,
+
+    /// Wait this many milliseconds for further changes before emitting, so a
+    /// burst of signals results in one update [default: 50]
+    #[arg(long, value_name = "MS")]
+    pub coalesce: Option<u64>,
+
+    /// Output as `DEST [format=FORMAT] [filter=EXPR]`, where DEST is `stdout`,
+    /// `socket:PATH` or a file path. Replaces the default sink, may be given
+    /// multiple times. Error events go to stderr and the control socket only
+    #[arg(long = "sink", value_name = "SPEC")]
+    pub sinks: Vec<SinkConfig>,
+
+    /// Path of the JSON-RPC control socket [default: $XDG_RUNTIME_DIR/trayson.sock]
+    #[arg(long)]
+    pub control_socket: Option<PathBuf>,
+
+    /// Don't serve the control socket
+    #[arg(long)]
+    pub no_control: bool,
+
+    /// Add `onclick`, `onmiddleclick`, `onrightclick`, `onscrollup` and
+    /// `onscrolldown` command lines to every item
+    #[arg(long)]
+    pub click_commands: bool,
+
+    /// Emit icons as `file://` URIs instead of paths, as `uri` in the `[icon]`
+    /// table of the config file
+    #[arg(long)]
+    pub icon_uris: bool,
+
+    /// Keep `icons/<id>.png` in the icon directory linked to the current icon
+    /// of each item, as `links` in the `[icon]` table of the config file
+    #[arg(long)]
+    pub icon_links: bool,
+
+    /// How the sinks name the fields, see `[rename]` in the config file for
+    /// single ones [default: snake]
+    #[arg(long, value_enum)]
+    pub field_case: Option<FieldCase>,
+
+    /// Add the tooltip as Pango markup in `tooltip_pango`, next to the plain
+    /// text in `tooltip_plain`
+    #[arg(long)]
+    pub tooltip_pango: bool,
+
+    /// Send a desktop notification when an item changes to `NeedsAttention`
+    #[arg(long)]
+    pub notify_attention: bool,
+
+    /// Run this command line with `sh -c` when an item appears, with the item
+    /// as JSON on stdin and in `$TRAYSON_ITEM` and its id in `$TRAYSON_ID`
+    #[arg(long, value_name = "COMMAND")]
+    pub on_item_added: Option<String>,
+
+    /// Run this command line when an item goes away, like `--on-item-added`
+    #[arg(long, value_name = "COMMAND")]
+    pub on_item_removed: Option<String>,
+
+    /// Run this command line when an item changes to `NeedsAttention`, like
+    /// `--on-item-added`
+    #[arg(long, value_name = "COMMAND")]
+    pub on_attention: Option<String>,
+
+    /// Pass the items through this Lua script before emitting them. Its
+    /// function `transform(items)` changes the array of items or returns the
+    /// one to emit
+    #[arg(long, value_name = "PATH")]
+    pub script: Option<PathBuf>,
+
+    /// Run this command line for an XDG activation token when an item is
+    /// activated without one, for a compositor integration that hands them out
+    #[arg(long, value_name = "COMMAND")]
+    pub activation_token_command: Option<String>,
+
+    /// Serve every item again on the bus at this D-Bus address and register
+    /// it with the watcher there, e.g. `unix:path=/run/user/1000/nested-bus`
+    #[arg(long, value_name = "ADDRESS")]
+    pub forward_to: Option<String>,
+
+    /// Refuse items of processes running as another user, which are emitted
+    /// with `"foreign": true` otherwise
+    #[arg(long)]
+    pub reject_foreign: bool,
+
+    /// Serve the watcher exactly as the specification has it: no argument to
+    /// the host signals and no `UnregisterStatusNotifierItem`
+    #[arg(long)]
+    pub conformant_watcher: bool,
+
+    /// Serve items, icons and a WebSocket update stream on this address,
+    /// e.g. `127.0.0.1:8765`
+    #[cfg(feature = "http")]
+    #[arg(long, value_name = "ADDR")]
+    pub http: Option<String>,
+
+    /// Origin of a web page allowed to read `--http`, like
+    /// `http://localhost:3000`. Pages of other sites get neither CORS headers
+    /// nor WebSocket upgrades. May be given multiple times
+    #[cfg(feature = "http")]
+    #[arg(long, value_name = "ORIGIN")]
+    pub http_allow_origin: Vec<String>,
+
+    /// Serve the items with their icons on this TCP address or `unix:PATH`,
+    /// for `--remote` on another machine
+    #[cfg(feature = "bridge")]
+    #[arg(long, value_name = "ADDR")]
+    pub bridge: Option<String>,
+
+    /// Emit the items of the `--bridge` at this address instead of the local
+    /// ones, forwarding clicks to it
+    #[cfg(feature = "bridge")]
+    #[arg(long, value_name = "ADDR", conflicts_with = "bridge")]
+    pub remote: Option<String>,
+
+    /// File with the secret that `--bridge` and `--remote` authenticate with
+    #[cfg(feature = "bridge")]
+    #[arg(long, value_name = "PATH")]
+    pub bridge_token_file: Option<PathBuf>,
+
+    /// Publish the items to the MQTT broker at this address, e.g.
+    /// `localhost:1883`
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "ADDR")]
+    pub mqtt: Option<String>,
+
+    /// Prefix of the `--mqtt` topics
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "TOPIC", default_value = "trayson")]
+    pub mqtt_topic: String,
+
+    /// User name to log in to the `--mqtt` broker with
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "NAME")]
+    pub mqtt_user: Option<String>,
+
+    /// File with the password of `--mqtt-user`
+    #[cfg(feature = "mqtt")]
+    #[arg(long, value_name = "PATH", requires = "mqtt_user")]
+    pub mqtt_password_file: Option<PathBuf>,
+
+    /// Keep the latest state in this file, in the `stamped` format, replaced
+    /// atomically on every update and removed on exit
+    /// [default: $XDG_RUNTIME_DIR/trayson/state.json]
+    #[arg(long, value_name = "PATH")]
+    pub state_file: Option<PathBuf>,
+
+    /// Don't keep the state file
+    #[arg(long)]
+    pub no_state_file: bool,
+
+    /// Write Prometheus metrics to this file on every update, for the
+    /// node_exporter textfile collector
+    #[arg(long, value_name = "PATH")]
+    pub metrics_file: Option<PathBuf>,
+
+    /// Append every item that is added, changed or removed to this file as
+    /// JSON lines, see `--history-max-size`
+    #[arg(long, value_name = "PATH")]
+    pub history: Option<PathBuf>,
+
+    /// Rotate the `--history` file once it has this many MiB [default: 10]
+    #[arg(long, value_name = "MIB")]
+    pub history_max_size: Option<u64>,
+
+    /// Write every signal and item value to this file as JSON lines, for
+    /// `trayson replay`
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+
+    /// Quit after this many minutes without items and without other hosts,
+    /// e.g. when started by D-Bus activation
+    #[arg(long, value_name = "MINUTES")]
+    pub exit_when_idle: Option<u64>,
+
+    /// `[[override]]` tables of the config file
+    #[arg(skip)]
+    pub overrides: Vec<Override>,
+
+    /// `[sink.NAME]` tables of the config file, emitted to next to `--sink`
+    /// or the default sink
+    #[arg(skip)]
+    pub sink_profiles: Vec<SinkConfig>,
+
+    /// Whether the `[sink.NAME]` tables go without the default sink, by
+    /// `default_sink = false` in the config file
+    #[arg(skip)]
+    pub no_default_sink: bool,
+}
+
+impl RunArgs {
+    /// The settings of `run` without any flags given.
+    pub fn defaults() -> RunArgs {
+        #[derive(Parser)]
+        struct Defaults {
+            #[command(flatten)]
+            run: RunArgs,
+        }
+        Defaults::parse_from(["trayson"]).run
+    }
+
+    /// Whether a serialized item passes `--filter`, `--hide`, `--ignore`, `--only`
+    /// and `--reject-foreign`.
+    pub fn shows(&self, item: &Value) -> bool {
+        let id = item["sni_id"].as_str().unwrap_or_default();
+        (!self.reject_foreign || item["foreign"] != true)
+            && self.filter.as_ref().is_none_or(|f| f.matches(item))
+            && !self.hide.iter().any(|rule| rule.matches(item))
+            && !self.ignore.iter().any(|pattern| pattern.matches(id))
+            && (self.only.is_empty() || self.only.iter().any(|pattern| pattern.matches(id)))
+    }
+
+    /// Moves the `--pin` items to the front, followed by those with the lowest
+    /// `ordering_index`, keeping the order otherwise.
+    pub fn sort(&self, items: &mut [Value]) {
+        items.sort_by_cached_key(|item| {
+            let id = item["sni_id"].as_str().unwrap_or_default();
+            let pinned = self
+                .pinned
+                .iter()
+                .position(|pattern| pattern.matches(id))
+                .unwrap_or(self.pinned.len());
+            (pinned, item["ordering_index"].as_u64().unwrap_or(u64::MAX))
+        });
+    }
+
+    pub fn coalesce(&self) -> Duration {
+        Duration::from_millis(self.coalesce.unwrap_or(50))
+    }
+
+    /// Where the state file is kept, if anywhere.
+    pub fn state_path(&self) -> Option<PathBuf> {
+        match self.no_state_file {
+            true => None,
+            false => self
+                .state_file
+                .clone()
+                .or_else(crate::output::default_state_path),
+        }
+    }
+
+    /// `--sink`, or else the default sink, followed by the `[sink.NAME]` tables.
+    pub fn sink_configs(&self) -> Vec<SinkConfig> {
+        let mut configs = self.sinks.clone();
+        if configs.is_empty() && (!self.no_default_sink || self.sink_profiles.is_empty()) {
+            let dest = match &self.output_path {
+                Some(path) => Destination::Path(path.clone()),
+                None => Destination::Stdout,
+            };
+            configs.push(SinkConfig::new(dest, self.format.unwrap_or(Format::Json)));
+        }
+        configs.extend(self.sink_profiles.iter().cloned());
+        configs
+    }
+}
@@ -0,0 +1,513 @@
+//! `--bridge ADDR` serves the protocol of [`crate::control`] on a TCP address
+//! or `unix:PATH`, with the icons embedded in the items as base64 `data`, so
+//! that `trayson run --remote ADDR` on another machine, a VM or the other end
+//! of an SSH tunnel can emit them as its own and forward clicks back.
+//!
+//! Both sides read the secret from `--bridge-token-file`. Clients send it as
+//! their first request and are disconnected otherwise:
+//!
+//! ```text
+//! {"jsonrpc":"2.0","id":0,"method":"auth","params":{"token":"..."}}
+//! ```
+//!
+//! There is no encryption, so on untrusted networks the bridge should listen on
+//! a unix socket or localhost and be reached through `ssh -L`.
+
+use crate::actions::ClickCommands;
+use crate::args::RunArgs;
+use crate::config;
+use crate::control::{self, ControlServer};
+use crate::host::RECONNECT_DELAY;
+use crate::icon;
+use crate::registry::{Registry, SharedRegistry};
+use crate::rt;
+use crate::script::Script;
+use crate::sink::Sink;
+use crate::stamp::Stamp;
+use async_std::channel::{self, Sender};
+use async_std::future;
+use async_std::io::{self, prelude::BufReadExt, BufReader, ReadExt, WriteExt};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::os::unix::net::{UnixListener, UnixStream};
+use async_std::sync::Mutex;
+use async_std::task;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures_util::future::Either;
+use futures_util::{try_join, StreamExt};
+use serde_json::{json, Value};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook_async_std::Signals;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long a new connection has to authenticate.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The longest first request read before authentication, an `auth` with a
+/// token fits many times over.
+const MAX_AUTH_LINE: u64 = 4096;
+
+/// Encoded icons kept for the next update, they rarely change.
+const CACHED_ICONS: usize = 256;
+
+type Reader = Box<dyn io::Read + Send + Unpin>;
+type Writer = Box<dyn io::Write + Send + Unpin>;
+
+/// The secret of `--bridge-token-file`, which `--bridge` and `--remote` need.
+pub fn token(args: &RunArgs) -> Result<String, Box<dyn Error>> {
+    let path = args
+        .bridge_token_file
+        .as_ref()
+        .ok_or("--bridge and --remote need --bridge-token-file")?;
+    let token = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    match token.trim() {
+        "" => Err(format!("{}: empty token", path.display()).into()),
+        token => Ok(token.to_string()),
+    }
+}
+
+async fn connect(addr: &str) -> io::Result<(Reader, Writer)> {
+    Ok(match addr.strip_prefix("unix:") {
+        Some(path) => {
+            let stream = UnixStream::connect(path).await?;
+            (Box::new(stream.clone()), Box::new(stream))
+        }
+        None => {
+            let stream = TcpStream::connect(addr).await?;
+            stream.set_nodelay(true)?;
+            (Box::new(stream.clone()), Box::new(stream))
+        }
+    })
+}
+
+/// Compares in constant time, so the token cannot be guessed byte by byte.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+/// Base64 of the icon files by path, which are named after their content
+type Icons = std::sync::Mutex<HashMap<String, String>>;
+
+#[derive(Clone)]
+pub struct BridgeServer {
+    control: ControlServer,
+    icons: Arc<Icons>,
+}
+
+impl BridgeServer {
+    pub async fn bind(addr: &str, token: String, registry: SharedRegistry) -> io::Result<Self> {
+        let server = BridgeServer {
+            control: ControlServer::new(registry),
+            icons: Default::default(),
+        };
+        let control = server.control.clone();
+        match addr.strip_prefix("unix:") {
+            Some(path) => {
+                if Path::new(path).exists() && UnixStream::connect(path).await.is_err() {
+                    std::fs::remove_file(path)?;
+                }
+                let listener = UnixListener::bind(path).await?;
+                rt::spawn(async move {
+                    let mut incoming = listener.incoming();
+                    while let Some(Ok(stream)) = incoming.next().await {
+                        let (reader, writer): (Reader, Writer) =
+                            (Box::new(stream.clone()), Box::new(stream));
+                        rt::spawn(authenticate(control.clone(), token.clone(), reader, writer));
+                    }
+                });
+            }
+            None => {
+                let listener = TcpListener::bind(addr).await?;
+                rt::spawn(async move {
+                    let mut incoming = listener.incoming();
+                    while let Some(Ok(stream)) = incoming.next().await {
+                        let _ = stream.set_nodelay(true);
+                        let (reader, writer): (Reader, Writer) =
+                            (Box::new(stream.clone()), Box::new(stream));
+                        rt::spawn(authenticate(control.clone(), token.clone(), reader, writer));
+                    }
+                });
+            }
+        }
+        Ok(server)
+    }
+
+    /// Pushes the new item list with the icons embedded to all clients.
+    pub async fn publish(&self, items: &[Value], stamp: &Stamp) {
+        let (icons, items) = (self.icons.clone(), items.to_vec());
+        // reading the icon files blocks
+        let items = rt::spawn_blocking(move || {
+            items
+                .into_iter()
+                .map(|item| embed(&icons, item))
+                .collect::<Vec<_>>()
+        })
+        .await;
+        self.control.publish(&items, stamp).await;
+    }
+
+    pub async fn publish_event(&self, event: &Value) {
+        self.control.publish_event(event).await;
+    }
+}
+
+/// `item` with the files of its icons embedded as base64, cached in `icons`.
+fn embed(icons: &Icons, mut item: Value) -> Value {
+    // mostly the same as the icon, sent once then
+    let keys = match item["effective_icon"]["path"] == item["icon"]["path"] {
+        true => &["icon"][..],
+        false => &["icon", "effective_icon"],
+    };
+    for key in keys {
+        if let Some(data) = item[key]["path"]
+            .as_str()
+            .and_then(|path| encode(icons, path))
+        {
+            item[*key]["data"] = json!(data);
+        }
+    }
+    if let Some(Value::Array(entries)) = item.get_mut("menu") {
+        embed_menu(icons, entries);
+    }
+    item
+}
+
+fn embed_menu(icons: &Icons, entries: &mut [Value]) {
+    for entry in entries {
+        if let Some(data) = entry["icon"].as_str().and_then(|path| encode(icons, path)) {
+            entry["icon_data"] = json!(data);
+        }
+        if let Some(Value::Array(children)) = entry.get_mut("children") {
+            embed_menu(icons, children);
+        }
+    }
+}
+
+fn encode(icons: &Icons, path: &str) -> Option<String> {
+    let mut icons = icons.lock().unwrap();
+    if let Some(data) = icons.get(path) {
+        return Some(data.clone());
+    }
+    let data = BASE64.encode(std::fs::read(path).ok()?);
+    if icons.len() >= CACHED_ICONS {
+        icons.clear();
+    }
+    icons.insert(path.to_string(), data.clone());
+    Some(data)
+}
+
+/// Serves the connection once its first request is an `auth` with `token`.
+async fn authenticate(control: ControlServer, token: String, reader: Reader, mut writer: Writer) {
+    let mut reader = BufReader::new(reader);
+    let mut line = String::new();
+    let mut first = (&mut reader).take(MAX_AUTH_LINE);
+    let request = match future::timeout(AUTH_TIMEOUT, first.read_line(&mut line)).await {
+        // longer than allowed when cut off before the newline
+        Ok(Ok(_)) if line.ends_with('\n') => {
+            serde_json::from_str::<Value>(&line).unwrap_or_default()
+        }
+        _ => return,
+    };
+    let authorized = request["method"] == "auth"
+        && request["params"]["token"]
+            .as_str()
+            .is_some_and(|given| same(given, &token));
+    let id = request["id"].clone();
+    let response = match authorized {
+        true => json!({"jsonrpc": "2.0", "id": id, "result": null}),
+        false => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": -32003, "message": "unauthorized"},
+        }),
+    };
+    if writer
+        .write_all(format!("{}\n", response).as_bytes())
+        .await
+        .is_err()
+        || !authorized
+    {
+        return;
+    }
+    control.serve(reader, writer).await;
+}
+
+/// The other end of a `--bridge`.
+#[derive(Clone)]
+pub struct Remote {
+    addr: String,
+    token: String,
+}
+
+impl Remote {
+    pub fn new(addr: &str, token: String) -> Remote {
+        Remote {
+            addr: addr.to_string(),
+            token,
+        }
+    }
+
+    /// An authenticated connection, with `method` requested on it.
+    async fn request(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<(BufReader<Reader>, Writer), Box<dyn Error>> {
+        let (reader, mut writer) = connect(&self.addr)
+            .await
+            .map_err(|e| format!("{}: {}", self.addr, e))?;
+        let mut reader = BufReader::new(reader);
+        let auth =
+            json!({"jsonrpc": "2.0", "id": 0, "method": "auth", "params": {"token": self.token}});
+        writer.write_all(format!("{}\n", auth).as_bytes()).await?;
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let response: Value = serde_json::from_str(&line)?;
+        if let Some(message) = response["error"]["message"].as_str() {
+            return Err(format!("{}: {}", self.addr, message).into());
+        }
+        let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+        writer
+            .write_all(format!("{}\n", request).as_bytes())
+            .await?;
+        Ok((reader, writer))
+    }
+
+    /// Calls `method` on the other end and returns its result.
+    pub async fn call(&self, method: &str, params: Value) -> Result<Value, Box<dyn Error>> {
+        let (mut reader, _writer) = self.request(method, params).await?;
+        let mut line = String::new();
+        reader.read_line(&mut line).await?;
+        let mut response: Value = serde_json::from_str(&line)?;
+        if let Some(error) = response.get("error") {
+            return Err(error["message"].as_str().unwrap_or("request failed").into());
+        }
+        Ok(response["result"].take())
+    }
+}
+
+/// Writes the embedded icons of a remote item to files and points to them.
+fn save_icons(item: &mut Value) {
+    let same = item["effective_icon"]["path"] == item["icon"]["path"];
+    for key in ["icon", "effective_icon"] {
+        if let Some(path) = saved(&item[key]["data"], &item[key]["path"]) {
+            item[key]["path"] = json!(path);
+        }
+        if let Some(icon) = item.get_mut(key).and_then(Value::as_object_mut) {
+            icon.remove("data");
+        }
+    }
+    if same {
+        item["effective_icon"]["path"] = item["icon"]["path"].clone();
+    }
+    if let Some(Value::Array(entries)) = item.get_mut("menu") {
+        save_menu_icons(entries);
+    }
+}
+
+fn save_menu_icons(entries: &mut [Value]) {
+    for entry in entries {
+        if let Some(path) = saved(&entry["icon_data"], &entry["icon"]) {
+            entry["icon"] = json!(path);
+        }
+        if let Some(entry) = entry.as_object_mut() {
+            entry.remove("icon_data");
+        }
+        if let Some(Value::Array(children)) = entry.get_mut("children") {
+            save_menu_icons(children);
+        }
+    }
+}
+
+/// Saves base64 `data` with the extension of the remote `path`.
+fn saved(data: &Value, path: &Value) -> Option<String> {
+    let data = BASE64.decode(data.as_str()?).ok()?;
+    let extension = Path::new(path.as_str()?).extension()?.to_str()?;
+    icon::save_encoded(&data, extension)
+}
+
+/// What [`follow`] acts on, in order.
+enum Input {
+    /// From the bridge
+    Line(String),
+    /// The connection to the bridge, which is being connected to again
+    Lost,
+    Signal(i32),
+}
+
+/// Sends the lines of `connection`, and once it is lost those of new ones.
+async fn receive(
+    remote: &Remote,
+    mut connection: (BufReader<Reader>, Writer),
+    inputs: &Sender<Input>,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        let (reader, _writer) = connection;
+        let mut lines = reader.lines();
+        while let Some(Ok(line)) = lines.next().await {
+            inputs.send(Input::Line(line)).await?;
+        }
+        inputs.send(Input::Lost).await?;
+        connection = loop {
+            task::sleep(RECONNECT_DELAY).await;
+            if let Ok(connection) = remote.request("subscribe", json!({})).await {
+                break connection;
+            }
+        };
+    }
+}
+
+/// `--remote`: emits the items of the bridge at `addr` instead of following
+/// the local bus, reconnecting whenever the connection is lost. Only failing
+/// to connect the first time is fatal. Like without it, SIGHUP reloads the
+/// config and SIGINT or SIGTERM stop after removing the icons saved.
+pub async fn follow(addr: &str, cli: RunArgs) -> Result<(), Box<dyn Error>> {
+    let mut settings = config::load(cli.clone())?;
+    let remote = Remote::new(addr, token(&settings)?);
+    let mut click_commands = settings
+        .click_commands
+        .then(|| ClickCommands::new(settings.control_socket.as_deref()));
+    let mut sinks = Vec::new();
+    for config in settings.sink_configs() {
+        sinks.push(Sink::open(config).await?);
+    }
+    let mut state_file = Sink::state_file(settings.state_path()).await;
+    let mut script = settings.script.as_deref().map(Script::new);
+    let registry = Arc::new(Mutex::new(Registry::default()));
+    let local = ControlServer::new(registry).forward_to(remote.clone());
+    let control = match settings
+        .control_socket
+        .clone()
+        .or_else(control::default_socket_path)
+    {
+        Some(path) if !settings.no_control => match local.listen(path).await {
+            Ok(server) => Some(server),
+            Err(e) => {
+                tracing::warn!(error = %e, "control socket disabled");
+                None
+            }
+        },
+        _ => None,
+    };
+
+    let connection = remote.request("subscribe", json!({})).await?;
+    let (inputs, received) = channel::unbounded();
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGTERM])?;
+    let producers = async {
+        let signals = async {
+            while let Some(signal) = signals.next().await {
+                inputs.send(Input::Signal(signal)).await?;
+            }
+            Ok(())
+        };
+        try_join!(receive(&remote, connection, &inputs), signals)
+    };
+    let output = async {
+        while let Ok(input) = received.recv().await {
+            let line = match input {
+                Input::Line(line) => line,
+                Input::Lost => {
+                    tracing::warn!(bridge = addr, "lost the bridge, reconnecting");
+                    emit(&[], &mut sinks, &mut state_file, control.as_ref()).await?;
+                    continue;
+                }
+                Input::Signal(SIGHUP) => {
+                    match config::load(cli.clone()) {
+                        Ok(reloaded) => {
+                            if let Err(e) = Sink::reload(&mut sinks, reloaded.sink_configs()).await
+                            {
+                                tracing::error!(error = %e, "failed to reload the sinks");
+                            }
+                            script = reloaded.script.as_deref().map(Script::new);
+                            click_commands = reloaded
+                                .click_commands
+                                .then(|| ClickCommands::new(reloaded.control_socket.as_deref()));
+                            settings = reloaded;
+                        }
+                        Err(e) => tracing::error!(error = %e, "failed to reload the config"),
+                    }
+                    continue;
+                }
+                Input::Signal(_) => break,
+            };
+            let mut message: Value = match serde_json::from_str(&line) {
+                Ok(message) => message,
+                Err(e) => {
+                    tracing::warn!(bridge = addr, error = %e, "skipping a malformed message");
+                    continue;
+                }
+            };
+            if message["method"] == "event" {
+                // numbered along with the events emitted here
+                let mut event = message["params"].take();
+                Stamp::event().add_to(&mut event);
+                eprintln!("{}", event);
+                if let Some(control) = &control {
+                    control.publish_event(&event).await;
+                }
+                continue;
+            }
+            let items = match message.get_mut("result") {
+                Some(result) => result.take(),
+                None => message["params"]["items"].take(),
+            };
+            let Value::Array(mut items) = items else {
+                continue;
+            };
+            items.iter_mut().for_each(save_icons);
+            let mut values = crate::cli::render(items, &settings, click_commands.as_ref());
+            if let Some(script) = &script {
+                values = script.transform(values).await;
+            }
+            emit(&values, &mut sinks, &mut state_file, control.as_ref()).await?;
+            // saved again with every update, also those of hidden items
+            icon::release_unused(&values);
+        }
+        // an empty state one last time
+        emit(&[], &mut sinks, &mut state_file, control.as_ref()).await?;
+        icon::remove_saved();
+        if let Some(path) = settings.state_path() {
+            let _ = async_std::fs::remove_file(path).await;
+        }
+        Ok::<(), Box<dyn Error>>(())
+    };
+    futures_util::pin_mut!(output, producers);
+    // the output only stops on signals, the producers on errors
+    match futures_util::future::select(output, producers).await {
+        Either::Left((result, _)) => result?,
+        Either::Right((result, _)) => result.map(|_| ())?,
+    }
+    Ok(())
+}
+
+/// Writes `values` to the sinks, the state file and the control socket.
+async fn emit(
+    values: &[Value],
+    sinks: &mut [Sink],
+    state_file: &mut Option<Sink>,
+    control: Option<&ControlServer>,
+) -> io::Result<()> {
+    icon::link(values);
+    let stamp = Stamp::next();
+    for sink in sinks.iter_mut() {
+        sink.emit(values, &stamp).await?;
+    }
+    if let Some(state) = state_file {
+        if let Err(e) = state.emit(values, &stamp).await {
+            tracing::warn!(error = %e, "state file disabled");
+            *state_file = None;
+        }
+    }
+    if let Some(control) = control {
+        control.publish(values, &stamp).await;
+    }
+    Ok(())
+}
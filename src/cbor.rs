@@ -0,0 +1,162 @@
+//! `format=cbor`: the items as CBOR (RFC 8949) instead of JSON text, for
+//! small dashboards reading the stream where parsing JSON costs too much.
+//! Each frame is one data item with the self-described CBOR tag 55799 in
+//! front, so the frames follow each other without a delimiter as a CBOR
+//! sequence (RFC 8742) and a reader can tell them from JSON lines.
+//!
+//! Integers are encoded as such, other numbers as doubles and objects as maps
+//! with text keys, sorted as the JSON sinks have them.
+
+use serde_json::Value;
+
+const UNSIGNED: u8 = 0;
+const NEGATIVE: u8 = 1;
+const TEXT: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+const FALSE: u8 = 0xf4;
+const TRUE: u8 = 0xf5;
+const NULL: u8 = 0xf6;
+const DOUBLE: u8 = 0xfb;
+/// Marks the data item after it as CBOR, which starts with `d9 d9 f7`
+const SELF_DESCRIBED: u64 = 55799;
+
+/// `value` as a frame of the stream.
+pub fn frame(value: &Value) -> Vec<u8> {
+    let mut data = Vec::new();
+    head(&mut data, TAG, SELF_DESCRIBED);
+    encode(&mut data, value);
+    data
+}
+
+/// The initial byte of major type `major` with `argument`, in as few bytes
+/// as it fits.
+fn head(data: &mut Vec<u8>, major: u8, argument: u64) {
+    let major = major << 5;
+    match argument {
+        0..=23 => data.push(major | argument as u8),
+        24..=0xff => data.extend_from_slice(&[major | 24, argument as u8]),
+        0x100..=0xffff => {
+            data.push(major | 25);
+            data.extend_from_slice(&(argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            data.push(major | 26);
+            data.extend_from_slice(&(argument as u32).to_be_bytes());
+        }
+        _ => {
+            data.push(major | 27);
+            data.extend_from_slice(&argument.to_be_bytes());
+        }
+    }
+}
+
+fn encode(data: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Null => data.push(NULL),
+        Value::Bool(false) => data.push(FALSE),
+        Value::Bool(true) => data.push(TRUE),
+        Value::Number(number) => {
+            if let Some(n) = number.as_u64() {
+                head(data, UNSIGNED, n);
+            } else if let Some(n) = number.as_i64() {
+                // -1 - n, which is what the negative ones carry
+                head(data, NEGATIVE, !n as u64);
+            } else {
+                data.push(DOUBLE);
+                data.extend_from_slice(&number.as_f64().unwrap_or(f64::NAN).to_be_bytes());
+            }
+        }
+        Value::String(s) => text(data, s),
+        Value::Array(values) => {
+            head(data, ARRAY, values.len() as u64);
+            values.iter().for_each(|value| encode(data, value));
+        }
+        Value::Object(map) => {
+            head(data, MAP, map.len() as u64);
+            for (key, value) in map {
+                text(data, key);
+                encode(data, value);
+            }
+        }
+    }
+}
+
+fn text(data: &mut Vec<u8>, s: &str) {
+    head(data, TEXT, s.len() as u64);
+    data.extend_from_slice(s.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// The frame of `value` without the self-described tag in front.
+    fn item(value: Value) -> Vec<u8> {
+        let frame = frame(&value);
+        assert_eq!(frame[..3], [0xd9, 0xd9, 0xf7]);
+        frame[3..].to_vec()
+    }
+
+    #[test]
+    fn encodes_simple_values() {
+        assert_eq!(item(json!(null)), [0xf6]);
+        assert_eq!(item(json!(false)), [0xf4]);
+        assert_eq!(item(json!(true)), [0xf5]);
+    }
+
+    #[test]
+    fn encodes_integers_in_as_few_bytes_as_they_fit() {
+        assert_eq!(item(json!(0)), [0x00]);
+        assert_eq!(item(json!(23)), [0x17]);
+        assert_eq!(item(json!(24)), [0x18, 24]);
+        assert_eq!(item(json!(255)), [0x18, 0xff]);
+        assert_eq!(item(json!(256)), [0x19, 0x01, 0x00]);
+        assert_eq!(item(json!(65536)), [0x1a, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(
+            item(json!(u64::MAX)),
+            [0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn encodes_negative_integers() {
+        assert_eq!(item(json!(-1)), [0x20]);
+        assert_eq!(item(json!(-24)), [0x37]);
+        assert_eq!(item(json!(-25)), [0x38, 24]);
+        assert_eq!(item(json!(-1000)), [0x39, 0x03, 0xe7]);
+        assert_eq!(
+            item(json!(i64::MIN)),
+            [0x3b, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn encodes_other_numbers_as_doubles() {
+        assert_eq!(
+            item(json!(1.5)),
+            [0xfb, 0x3f, 0xf8, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn encodes_text_with_its_length_in_bytes() {
+        assert_eq!(item(json!("")), [0x60]);
+        assert_eq!(item(json!("ü")), [0x62, 0xc3, 0xbc]);
+        let long = "x".repeat(300);
+        let data = item(json!(long));
+        assert_eq!(data[..3], [0x79, 0x01, 0x2c]);
+        assert_eq!(data.len(), 3 + 300);
+    }
+
+    #[test]
+    fn encodes_arrays_and_maps_with_sorted_keys() {
+        assert_eq!(item(json!([1, [2, 3]])), [0x82, 0x01, 0x82, 0x02, 0x03]);
+        assert_eq!(
+            item(json!({"b": 1, "a": [true]})),
+            [0xa2, 0x61, b'a', 0x81, 0xf5, 0x61, b'b', 0x01]
+        );
+    }
+}
@@ -0,0 +1,581 @@
+//! `trayson` itself: the subcommands, and for `run` the output loop turning
+//! the [`Update`]s of the host into emitted JSON.
+
+use crate::actions::ClickCommands;
+use crate::args::{Cli, Cmd, Mode, RunArgs, SnapshotArgs};
+use crate::client;
+use crate::command::Command;
+use crate::config;
+use crate::control::{self, ControlServer};
+use crate::forward::Forwarder;
+use crate::history::History;
+use crate::hooks::Hooks;
+use crate::host::{self, Bus, Roles, Update};
+use crate::i3blocks;
+use crate::icon;
+use crate::interface::TrayInterface;
+use crate::item::{self, Item};
+use crate::logging;
+use crate::markup;
+use crate::metrics::METRICS;
+use crate::mock;
+use crate::notify::Notifier;
+use crate::output::{Destination, Output};
+use crate::record;
+use crate::registry::Registry;
+use crate::rt;
+use crate::schema::{self, SCHEMA_VERSION};
+use crate::script::ScriptRunner;
+use crate::sink::Sink;
+use crate::stamp::Stamp;
+use crate::systemd;
+use crate::tray::TrayEvent;
+use crate::watcher::{self, StatusNotifierWatcherProxy, FREEDESKTOP_WATCHER, WATCHER};
+use async_std::channel;
+use async_std::io::{prelude::BufReadExt, stdin, BufReader};
+use async_std::sync::Mutex;
+use async_std::task;
+use clap::Parser;
+use futures_util::future::{self, Either};
+use futures_util::try_join;
+use serde_json::{json, Value};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM, SIGUSR1};
+use signal_hook_async_std::Signals;
+use std::error::Error;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use zbus::{export::futures_util::StreamExt, Connection, ConnectionBuilder};
+
+/// How often `--exit-when-idle` looks at the items and hosts.
+const IDLE_CHECK: Duration = Duration::from_secs(5);
+
+/// Runs the subcommand given on the command line, exiting with 1 on errors.
+pub fn main() {
+    rt::block_on(dispatch())
+}
+
+async fn dispatch() {
+    let cli = Cli::parse();
+    if let Err(e) = logging::init(cli.log) {
+        eprintln!("trayson: {}", e);
+        std::process::exit(1);
+    }
+    let result = match cli.command.unwrap_or(Cmd::Run(cli.run)) {
+        Cmd::Run(args) => run(args).await.map(|()| Value::Null),
+        Cmd::Snapshot(args) => snapshot(args).await.map(|()| Value::Null),
+        Cmd::Replay(args) => record::replay(args).await.map(|()| Value::Null),
+        Cmd::MockItem(args) => mock::run(args).await.map(|()| Value::Null),
+        Cmd::List(client) => client::list(&client).await,
+        Cmd::Get { item, client } => client::get(&client, &item).await,
+        Cmd::Activate(click) => click.dispatch("activate").await,
+        Cmd::Secondary(click) => click.dispatch("secondary_activate").await,
+        Cmd::ContextMenu(click) => click.dispatch("context_menu").await,
+        Cmd::Scroll(scroll) => scroll.dispatch().await,
+        Cmd::Raise { item, client } => client::raise(&client, &item).await,
+        Cmd::MenuEvent(event) => event.dispatch().await,
+        Cmd::Menu(menu) => menu.run().await,
+        Cmd::HitTest { x, sink, client } => client::hit_test(&client, x, sink).await,
+        Cmd::Stats(client) => client::stats(&client).await,
+        Cmd::Schema { format } => Ok(schema::schema(format)),
+        #[cfg(feature = "gui")]
+        Cmd::Popup(click) => click.popup().await,
+        #[cfg(feature = "gui")]
+        Cmd::DebugView(client) => client::debug_view(&client).await,
+    };
+    match result {
+        Ok(result) if result.is_null() => {}
+        Ok(result) => println!("{}", result),
+        Err(e) => {
+            eprintln!("trayson: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Loads all items known to the running watcher and prints them once, as
+/// `run` would emit them with the config file.
+async fn snapshot(args: SnapshotArgs) -> Result<(), Box<dyn Error>> {
+    let mut settings = RunArgs::defaults();
+    settings.filter = args.filter;
+    let settings = config::load(settings)?;
+    let conn = Connection::session().await?;
+    let watcher = StatusNotifierWatcherProxy::builder(&conn)
+        .cache_properties(zbus::CacheProperties::No)
+        .build()
+        .await?;
+    let mut registry = Registry::default();
+    for service in watcher.registered_status_notifier_items().await? {
+        let proxy = item::proxy(&conn, &service).await?;
+        match Item::fetch(&proxy).await {
+            Ok(item) => registry.insert(service, item, proxy),
+            Err(e) => tracing::warn!(service = %service, error = %e, "failed to read the item"),
+        }
+    }
+    let items = registry
+        .items()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    let click_commands = settings
+        .click_commands
+        .then(|| ClickCommands::new(settings.control_socket.as_deref()));
+    let values = render(items, &settings, click_commands.as_ref());
+    let frame = args
+        .format
+        .render(&values.iter().collect::<Vec<_>>(), &Stamp::next());
+    std::io::Write::write_all(&mut std::io::stdout(), &frame)?;
+    Ok(())
+}
+
+/// Applies the overrides, click commands, filters and order of `settings` to
+/// the serialized items, and adds the tooltip as text.
+pub fn render(
+    items: Vec<Value>,
+    settings: &RunArgs,
+    click_commands: Option<&ClickCommands>,
+) -> Vec<Value> {
+    let mut values = items
+        .into_iter()
+        .map(|mut item| {
+            for item_override in &settings.overrides {
+                item_override.apply(&mut item);
+            }
+            markup::annotate(&mut item, settings.tooltip_pango);
+            schema::mark(&mut item);
+            if let Some(click) = click_commands {
+                click.annotate(&mut item);
+            }
+            item
+        })
+        .filter(|item| settings.shows(item))
+        .collect::<Vec<_>>();
+    settings.sort(&mut values);
+    values
+}
+
+/// The state of every followed item for SIGUSR1, a JSON line each: the item
+/// as serialized, or none while it is read, retried after errors, and the
+/// last error.
+fn dump(registry: &Registry) -> String {
+    let mut lines = registry
+        .entries()
+        .map(|(service, entry)| {
+            let state = match (entry.item.unresponsive, entry.item.stale) {
+                (true, _) => "unresponsive",
+                (_, true) => "stale",
+                _ => "read",
+            };
+            (service, state, serde_json::to_value(&entry.item).ok())
+        })
+        .chain(registry.pending().map(|service| (service, "reading", None)))
+        .map(|(service, state, item)| {
+            let line = serde_json::json!({
+                "service": service,
+                "state": state,
+                "error": registry.error(service),
+                "item": item,
+            });
+            format!("{}\n", line)
+        })
+        .collect::<Vec<_>>();
+    lines.sort();
+    lines.concat()
+}
+
+/// Returns once `idle` kept returning true for `minutes`.
+async fn idle_for<F: Future<Output = bool>>(minutes: u64, mut idle: impl FnMut() -> F) {
+    let timeout = Duration::from_secs(minutes * 60);
+    let mut since = Instant::now();
+    loop {
+        task::sleep(IDLE_CHECK).await;
+        if !idle().await {
+            since = Instant::now();
+        } else if since.elapsed() >= timeout {
+            return;
+        }
+    }
+}
+
+async fn run(cli: RunArgs) -> Result<(), Box<dyn Error>> {
+    let args = config::load(cli.clone())?;
+    if args.mode == Mode::Watcher {
+        rt::spawn(systemd::watchdog());
+        let mut reconnecting = false;
+        loop {
+            let builder = ConnectionBuilder::session()?
+                .name(WATCHER)?
+                .name(FREEDESKTOP_WATCHER)?;
+            match watcher::serve(builder, args.reject_foreign, args.conformant_watcher, None)?
+                .build()
+                .await
+            {
+                Ok(conn) => {
+                    systemd::notify("READY=1");
+                    let idle = async {
+                        match args.exit_when_idle {
+                            Some(minutes) => idle_for(minutes, || watcher::idle(&conn, "")).await,
+                            None => future::pending().await,
+                        }
+                    };
+                    let lost = host::disconnected(&conn);
+                    futures_util::pin_mut!(lost, idle);
+                    if let Either::Right(_) = future::select(lost, idle).await {
+                        return Ok(());
+                    }
+                    tracing::warn!("lost the session bus, reconnecting");
+                }
+                Err(e) if !reconnecting => return Err(e.into()),
+                Err(_) => {}
+            }
+            reconnecting = true;
+            task::sleep(host::RECONNECT_DELAY).await;
+        }
+    }
+    #[cfg(feature = "bridge")]
+    if let Some(addr) = &args.remote {
+        return crate::bridge::follow(addr, cli.clone()).await;
+    }
+    let mut history = match &args.history {
+        Some(path) => {
+            let max_size = args.history_max_size.unwrap_or(10).max(1) << 20;
+            let history = History::open(path, max_size)
+                .map_err(|e| format!("--history {}: {}", path.display(), e))?;
+            Some(history)
+        }
+        None => None,
+    };
+    if let Some(path) = &args.record {
+        record::start(path).map_err(|e| format!("--record {}: {}", path.display(), e))?;
+    }
+    let (updates, r2) = channel::unbounded();
+    let bus = std::sync::Mutex::new(None::<Bus>);
+    let registry = Arc::new(Mutex::new(Registry::default()));
+    let mut sinks = Vec::new();
+    for config in args.sink_configs() {
+        sinks.push(Sink::open(config).await?);
+    }
+    let click_commands = args
+        .click_commands
+        .then(|| ClickCommands::new(args.control_socket.as_deref()));
+    let control = match args
+        .control_socket
+        .clone()
+        .or_else(control::default_socket_path)
+    {
+        Some(path) if !args.no_control => match ControlServer::bind(path, registry.clone()).await {
+            Ok(server) => Some(server),
+            Err(e) => {
+                tracing::warn!(error = %e, "control socket disabled");
+                None
+            }
+        },
+        _ => None,
+    };
+    let mut metrics_file = match &args.metrics_file {
+        Some(path) => Some(Output::new(Destination::Path(path.clone())).await?),
+        None => None,
+    };
+    let mut state_file = Sink::state_file(args.state_path()).await;
+    #[cfg(feature = "http")]
+    let http = match &args.http {
+        Some(addr) => {
+            let origins = args.http_allow_origin.clone();
+            Some(crate::http::HttpServer::bind(addr, origins, registry.clone()).await?)
+        }
+        None => None,
+    };
+    #[cfg(feature = "mqtt")]
+    let mqtt = match &args.mqtt {
+        Some(_) => Some(crate::mqtt::MqttPublisher::start(&args)?),
+        None => None,
+    };
+    #[cfg(feature = "bridge")]
+    let bridge = match &args.bridge {
+        Some(addr) => {
+            let token = crate::bridge::token(&args)?;
+            Some(crate::bridge::BridgeServer::bind(addr, token, registry.clone()).await?)
+        }
+        None => None,
+    };
+
+    let mut notifier = Notifier::default();
+    let mut script = args
+        .script
+        .as_deref()
+        .map(|path| ScriptRunner::start(path, updates.clone()));
+    let mut hooks = Hooks::default();
+    let forwarder = match &args.forward_to {
+        Some(address) => Some(Forwarder::start(address).await?),
+        None => None,
+    };
+
+    let mut settings = args.clone();
+    let mut collected = false;
+    let output = async {
+        while let Ok(first) = r2.recv().await {
+            // apps tend to change several properties at once, emit them together
+            let deadline = Instant::now() + settings.coalesce();
+            let mut shutdown = false;
+            // only task handles, errors and the host being ready, which leave the state as it is
+            let mut unchanged = true;
+            let mut events = Vec::new();
+            let mut changes = Vec::new();
+            let mut scripted = None;
+            let mut next = Some(first);
+            while let Some(update) = next.take() {
+                shutdown |= matches!(update, Update::Shutdown);
+                unchanged &= matches!(
+                    update,
+                    Update::Ready | Update::Tasks(..) | Update::Error(..) | Update::Scripted(..)
+                );
+                if matches!(update, Update::Reload) {
+                    match config::load(cli.clone()) {
+                        Ok(reloaded) => {
+                            if let Err(e) = Sink::reload(&mut sinks, reloaded.sink_configs()).await
+                            {
+                                tracing::error!(error = %e, "failed to reload the sinks");
+                            }
+                            // loaded again, the file may have changed
+                            script = reloaded
+                                .script
+                                .as_deref()
+                                .map(|path| ScriptRunner::start(path, updates.clone()));
+                            settings = reloaded;
+                        }
+                        Err(e) => tracing::error!(error = %e, "failed to reload the config"),
+                    }
+                }
+                let mut registry = registry.lock().await;
+                if matches!(update, Update::Dump) {
+                    eprint!("{}", dump(&registry));
+                    eprint!("{}", METRICS.render());
+                }
+                if let Update::Scripted(values) = update {
+                    scripted = Some(values);
+                } else {
+                    for event in host::apply_update(&mut registry, update, &updates) {
+                        match event {
+                            TrayEvent::Error { item, reason } => events.push(serde_json::json!({
+                                "event": "error",
+                                "item": item,
+                                "reason": reason,
+                                "schema_version": SCHEMA_VERSION,
+                            })),
+                            change => changes.push(change),
+                        }
+                    }
+                }
+                drop(registry);
+                if !shutdown {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    next = async_std::future::timeout(left, r2.recv())
+                        .await
+                        .ok()
+                        .and_then(Result::ok);
+                }
+            }
+            for event in &mut events {
+                Stamp::event().add_to(event);
+                eprintln!("{}", event);
+                if let Some(control) = &control {
+                    control.publish_event(event).await;
+                }
+                #[cfg(feature = "bridge")]
+                if let Some(bridge) = &bridge {
+                    bridge.publish_event(event).await;
+                }
+                #[cfg(feature = "mqtt")]
+                if let Some(mqtt) = &mqtt {
+                    mqtt.publish_event(event);
+                }
+            }
+            if !changes.is_empty() {
+                let registry = registry.lock().await;
+                for change in &changes {
+                    if settings.notify_attention {
+                        notifier.observe(change, &registry, |item| {
+                            serde_json::to_value(item).is_ok_and(|item| settings.shows(&item))
+                        });
+                    }
+                    hooks.observe(change, &registry, &settings);
+                    if let Some(history) = &mut history {
+                        history.observe(change);
+                    }
+                    if let Some(forwarder) = &forwarder {
+                        forwarder.observe(change, &registry);
+                    }
+                }
+            }
+            if unchanged && scripted.is_none() {
+                continue;
+            }
+            let (items, values, unserialized) = {
+                let registry = registry.lock().await;
+                METRICS.set_items(registry.items().count());
+                systemd::notify(&format!("STATUS={} items", registry.items().count()));
+                let items = registry
+                    .items()
+                    .filter_map(|item| serde_json::to_value(item).ok())
+                    .collect::<Vec<_>>();
+                let values = render(items.clone(), &settings, click_commands.as_ref());
+                let unserialized = registry
+                    .items()
+                    .flat_map(|item| [&item.attention_icon, &item.overlay_icon])
+                    .flatten()
+                    .map(|icon| &icon.path)
+                    .collect::<Vec<_>>();
+                (items, values, json!(unserialized))
+            };
+            let values = match &script {
+                // the answer to an earlier state, this one's comes later
+                Some(script) if !shutdown => {
+                    if !unchanged {
+                        script.submit(values);
+                    }
+                    match scripted {
+                        Some(scripted) => scripted,
+                        None => continue,
+                    }
+                }
+                _ => values,
+            };
+            icon::link(&values);
+            if !collected {
+                // the icons of the items found at startup are saved by now
+                icon::collect_garbage();
+                collected = true;
+            }
+            let stamp = Stamp::next();
+            for sink in sinks.iter_mut() {
+                sink.emit(&values, &stamp).await?;
+            }
+            // hidden items keep theirs for when they are shown again
+            icon::release_unused(items.iter().chain(&values).chain([&unserialized]));
+            if let Some(state) = &mut state_file {
+                if let Err(e) = state.emit(&values, &stamp).await {
+                    tracing::warn!(error = %e, "state file disabled");
+                    state_file = None;
+                }
+            }
+            if let Some(control) = &control {
+                control.publish(&values, &stamp).await;
+            }
+            #[cfg(feature = "http")]
+            if let Some(http) = &http {
+                http.publish(&values, &stamp).await;
+            }
+            #[cfg(feature = "bridge")]
+            if let Some(bridge) = &bridge {
+                bridge.publish(&values, &stamp).await;
+            }
+            #[cfg(feature = "mqtt")]
+            if let Some(mqtt) = &mqtt {
+                mqtt.publish(&values, &stamp);
+            }
+            let current = bus.lock().unwrap().clone();
+            if let Some(current) = current {
+                // fails while the bus is gone, the next session publishes again
+                let _ = TrayInterface::publish(&current.conn, &values).await;
+            }
+            METRICS.update();
+            if let Some(metrics_file) = &mut metrics_file {
+                metrics_file.write(METRICS.render().trim_end()).await?;
+            }
+            if shutdown {
+                break;
+            }
+        }
+        Ok::<(), Box<dyn Error>>(())
+    };
+    let signals = async {
+        let mut signals = Signals::new([SIGHUP, SIGUSR1, SIGINT, SIGTERM])?;
+        while let Some(signal) = signals.next().await {
+            match signal {
+                SIGHUP => updates.send(Update::Reload).await?,
+                SIGUSR1 => updates.send(Update::Dump).await?,
+                _ => {
+                    updates.send(Update::Shutdown).await?;
+                    break;
+                }
+            }
+        }
+        Ok::<(), Box<dyn Error>>(())
+    };
+    let others = async {
+        try_join!(
+            async {
+                let mut lines = BufReader::new(stdin()).lines();
+                while let Some(Ok(line)) = lines.next().await {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    // click events of an i3blocks persistent block
+                    let command = match line.trim_start().starts_with('{') {
+                        true => i3blocks::click(&line),
+                        false => line.parse::<Command>(),
+                    };
+                    // not awaited, so that scroll events queued behind
+                    // each other are summed up
+                    match command {
+                        Ok(command) => {
+                            let registry = registry.clone();
+                            rt::spawn(async move {
+                                if let Err(e) = command.dispatch(&registry).await {
+                                    tracing::warn!(error = %e, "command failed");
+                                }
+                            });
+                        }
+                        Err(e) => tracing::warn!(error = %e, "invalid command"),
+                    }
+                }
+                Ok::<(), Box<dyn Error>>(())
+            },
+            host::follow_bus(
+                Roles {
+                    mode: args.mode,
+                    reject_foreign: args.reject_foreign,
+                    conformant_watcher: args.conformant_watcher,
+                    tray_interface: true,
+                },
+                &updates,
+                &bus,
+            ),
+            signals,
+            async {
+                systemd::watchdog().await;
+                Ok(())
+            },
+            async {
+                if let Some(minutes) = args.exit_when_idle {
+                    idle_for(minutes, || async {
+                        let current = bus.lock().unwrap().clone();
+                        let watcher_idle = match current {
+                            Some(current) => watcher::idle(&current.conn, &host::host_name()).await,
+                            None => true,
+                        };
+                        watcher_idle && registry.lock().await.items().next().is_none()
+                    })
+                    .await;
+                    updates.send(Update::Shutdown).await?;
+                }
+                Ok(())
+            }
+        )?;
+        Ok::<(), Box<dyn Error>>(())
+    };
+    futures_util::pin_mut!(output, others);
+    // the output loop only stops on shutdown, the others on errors
+    match future::select(output, others).await {
+        Either::Left((result, _)) | Either::Right((result, _)) => result?,
+    }
+    systemd::notify("STOPPING=1");
+    let current = bus.lock().unwrap().take();
+    if let Some(current) = current {
+        current.release().await;
+    }
+    icon::remove_saved();
+    if let Some(path) = args.state_path() {
+        let _ = async_std::fs::remove_file(path).await;
+    }
+    Ok(())
+}
@@ -0,0 +1,199 @@
+//! Queries against a running instance, over the control socket or, if that
+//! is unavailable, the `org.trayson.Tray` D-Bus interface.
+
+use crate::args::{ClickArgs, ClientArgs, MenuArgs, MenuEventArgs, Orientation, ScrollArgs};
+use crate::control;
+use crate::interface;
+use async_std::io::{self, WriteExt};
+use serde_json::{json, Value};
+use std::error::Error;
+use zbus::Connection;
+
+pub async fn list(client: &ClientArgs) -> Result<Value, Box<dyn Error>> {
+    match control::call(client.control_socket.clone(), "snapshot", json!({})).await {
+        Ok(items) => Ok(items),
+        Err(e) if client.control_socket.is_some() => Err(e),
+        Err(_) => list_dbus().await,
+    }
+}
+
+pub async fn get(client: &ClientArgs, item: &str) -> Result<Value, Box<dyn Error>> {
+    let params = json!({ "item": item });
+    match control::call(client.control_socket.clone(), "get", params).await {
+        Ok(item) => Ok(item),
+        Err(e) if client.control_socket.is_some() => Err(e),
+        Err(_) => list_dbus()
+            .await?
+            .as_array()
+            .and_then(|items| items.iter().find(|i| i["id"] == item).cloned())
+            .ok_or_else(|| format!("no item `{}`", item).into()),
+    }
+}
+
+pub async fn raise(client: &ClientArgs, item: &str) -> Result<Value, Box<dyn Error>> {
+    let params = json!({ "item": item });
+    control::call(client.control_socket.clone(), "raise", params).await
+}
+
+pub async fn hit_test(
+    client: &ClientArgs,
+    x: i32,
+    sink: Option<String>,
+) -> Result<Value, Box<dyn Error>> {
+    let params = json!({ "x": x, "sink": sink });
+    match control::call(client.control_socket.clone(), "hit_test", params).await? {
+        Value::String(id) => {
+            // unquoted, for `$(trayson hit-test X)`
+            println!("{}", id);
+            Ok(Value::Null)
+        }
+        _ => Err(format!("no item at {}", x).into()),
+    }
+}
+
+pub async fn stats(client: &ClientArgs) -> Result<Value, Box<dyn Error>> {
+    control::call(client.control_socket.clone(), "stats", json!({})).await
+}
+
+#[cfg(feature = "gui")]
+pub async fn debug_view(client: &ClientArgs) -> Result<Value, Box<dyn Error>> {
+    let socket = client.control_socket.clone();
+    crate::rt::spawn_blocking(move || crate::gui::debug::run(socket).map_err(|e| e.to_string()))
+        .await?;
+    Ok(Value::Null)
+}
+
+async fn list_dbus() -> Result<Value, Box<dyn Error>> {
+    let conn = Connection::session().await?;
+    let reply = conn
+        .call_method(
+            Some("org.trayson.Tray"),
+            interface::PATH,
+            Some("org.trayson.Tray"),
+            "GetItems",
+            &(),
+        )
+        .await?;
+    let items: String = reply.body()?;
+    Ok(serde_json::from_str(&items)?)
+}
+
+impl ClickArgs {
+    /// Forwards the click to the item through the running instance.
+    pub async fn dispatch(&self, method: &str) -> Result<Value, Box<dyn Error>> {
+        let mut params = json!({"item": self.item, "x": self.x, "y": self.y});
+        if method != "context_menu" {
+            let token = self
+                .activation_token
+                .clone()
+                .or_else(|| std::env::var("XDG_ACTIVATION_TOKEN").ok())
+                .filter(|token| !token.is_empty());
+            if let Some(token) = token {
+                params["token"] = token.into();
+            }
+        }
+        control::call(self.client.control_socket.clone(), method, params).await
+    }
+
+    /// Shows the item's menu at the click position and clicks the chosen entry.
+    #[cfg(feature = "gui")]
+    pub async fn popup(&self) -> Result<Value, Box<dyn Error>> {
+        let params = json!({ "item": self.item });
+        let menu = control::call(self.client.control_socket.clone(), "menu", params).await?;
+        let mut entries = Vec::new();
+        flatten(&menu, "", &mut entries);
+        let (x, y) = (self.x, self.y);
+        let chosen = crate::rt::spawn_blocking(move || {
+            crate::gui::popup::show(entries, x, y).map_err(|e| e.to_string())
+        })
+        .await?;
+        match chosen {
+            Some(menu_id) => {
+                let params = json!({"item": self.item, "menu_id": menu_id, "event": "clicked"});
+                control::call(self.client.control_socket.clone(), "menu_event", params).await
+            }
+            None => Ok(Value::Null),
+        }
+    }
+}
+
+impl ScrollArgs {
+    pub async fn dispatch(&self) -> Result<Value, Box<dyn Error>> {
+        let orientation = match self.orientation {
+            Orientation::Vertical => "vertical",
+            Orientation::Horizontal => "horizontal",
+        };
+        let params = json!({"item": self.item, "delta": self.delta, "orientation": orientation});
+        control::call(self.client.control_socket.clone(), "scroll", params).await
+    }
+}
+
+impl MenuEventArgs {
+    pub async fn dispatch(&self) -> Result<Value, Box<dyn Error>> {
+        let params = json!({"item": self.item, "menu_id": self.menu_id, "event": self.event});
+        control::call(self.client.control_socket.clone(), "menu_event", params).await
+    }
+}
+
+impl MenuArgs {
+    /// Prints the menu as JSON or, with `--dmenu`, lets the user pick an entry.
+    pub async fn run(&self) -> Result<Value, Box<dyn Error>> {
+        let params = json!({ "item": self.item });
+        let menu = control::call(self.client.control_socket.clone(), "menu", params).await?;
+        if !self.dmenu {
+            return Ok(menu);
+        }
+
+        let mut entries = Vec::new();
+        flatten(&menu, "", &mut entries);
+        let mut stdout = io::stdout();
+        for (_, line) in &entries {
+            stdout.write_all(format!("{}\n", line).as_bytes()).await?;
+        }
+        stdout.flush().await?;
+
+        let mut choice = String::new();
+        io::stdin().read_line(&mut choice).await?;
+        let choice = choice.trim_end_matches('\n');
+        if choice.is_empty() {
+            return Ok(Value::Null);
+        }
+        let menu_id = entries
+            .iter()
+            .find(|(_, line)| line == choice)
+            .map(|(id, _)| *id)
+            .ok_or_else(|| format!("no menu entry `{}`", choice))?;
+        let params = json!({"item": self.item, "menu_id": menu_id, "event": "clicked"});
+        control::call(self.client.control_socket.clone(), "menu_event", params).await
+    }
+}
+
+/// Collects the clickable entries as `(id, "Parent > Label")`, toggles prefixed
+/// with their state.
+fn flatten(entries: &Value, prefix: &str, out: &mut Vec<(i64, String)>) {
+    for entry in entries.as_array().into_iter().flatten() {
+        if entry["separator"] == true || entry["visible"] == false {
+            continue;
+        }
+        let checked = entry["toggle_state"] == 1;
+        let toggle = match entry["toggle_type"].as_str() {
+            Some("checkmark") if checked => "[x] ",
+            Some("checkmark") => "[ ] ",
+            Some("radio") if checked => "(*) ",
+            Some("radio") => "( ) ",
+            _ => "",
+        };
+        let label = format!(
+            "{}{}{}",
+            prefix,
+            toggle,
+            entry["label"].as_str().unwrap_or("")
+        );
+        let children = &entry["children"];
+        if children.as_array().is_some_and(|c| !c.is_empty()) {
+            flatten(children, &format!("{} > ", label), out);
+        } else if entry["enabled"] != false {
+            out.push((entry["id"].as_i64().unwrap_or(0), label));
+        }
+    }
+}
@@ -0,0 +1,397 @@
+use crate::item::{timed, Item, StatusNotifierItemProxy};
+use crate::registry::SharedRegistry;
+use async_process::{self as process, Stdio};
+use async_std::task;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use zbus::zvariant::Value;
+
+/// Scroll events arriving within this window are summed up into a single call.
+const SCROLL_WINDOW: Duration = Duration::from_millis(40);
+
+/// Deltas not yet sent, per destination and orientation.
+static PENDING_SCROLL: Mutex<BTreeMap<(String, String), i32>> = Mutex::new(BTreeMap::new());
+
+/// An action forwarded to a tracked item.
+#[derive(Debug, Clone)]
+pub enum Command {
+    /// With the XDG activation `token` to pass on first, for the window it
+    /// raises to take focus under Wayland
+    Activate {
+        item: String,
+        x: i32,
+        y: i32,
+        token: Option<String>,
+    },
+    SecondaryActivate {
+        item: String,
+        x: i32,
+        y: i32,
+        token: Option<String>,
+    },
+    ContextMenu {
+        item: String,
+        x: i32,
+        y: i32,
+    },
+    Scroll {
+        item: String,
+        delta: i32,
+        orientation: String,
+    },
+    /// Asks the window manager to activate the window of the item's `WindowId`
+    Raise {
+        item: String,
+    },
+    /// Sends a dbusmenu event (usually `clicked`) for one of the item's menu entries.
+    MenuEvent {
+        item: String,
+        menu_id: i32,
+        event: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+    Parse(String),
+    UnknownItem(String),
+    NoMenu(String),
+    NoWindow(String),
+    Raise(String),
+    DBus(zbus::Error),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Parse(reason) => write!(f, "invalid command: {}", reason),
+            CommandError::UnknownItem(item) => write!(f, "no item `{}`", item),
+            CommandError::NoMenu(item) => write!(f, "item `{}` has no menu", item),
+            CommandError::NoWindow(item) => write!(f, "item `{}` has no window", item),
+            CommandError::Raise(reason) => write!(f, "raise: {}", reason),
+            CommandError::DBus(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+impl Command {
+    pub fn item(&self) -> &str {
+        match self {
+            Command::Activate { item, .. }
+            | Command::SecondaryActivate { item, .. }
+            | Command::ContextMenu { item, .. }
+            | Command::Scroll { item, .. }
+            | Command::Raise { item }
+            | Command::MenuEvent { item, .. } => item,
+        }
+    }
+
+    pub async fn dispatch(&self, registry: &SharedRegistry) -> Result<(), CommandError> {
+        let (item, proxy) = registry
+            .lock()
+            .await
+            .find(self.item())
+            .map(|entry| (entry.item.clone(), entry.proxy.clone()))
+            .ok_or_else(|| CommandError::UnknownItem(self.item().to_string()))?;
+        match self {
+            Command::Activate { x, y, token, .. } => {
+                provide_token(&proxy, token).await;
+                timed("Activate", proxy.activate(*x, *y)).await
+            }
+            Command::SecondaryActivate { x, y, token, .. } => {
+                provide_token(&proxy, token).await;
+                timed("SecondaryActivate", proxy.secondary_activate(*x, *y)).await
+            }
+            Command::ContextMenu { x, y, .. } => {
+                timed("ContextMenu", proxy.context_menu(*x, *y)).await
+            }
+            Command::Scroll {
+                delta, orientation, ..
+            } => {
+                let key = (proxy.destination().to_string(), orientation.clone());
+                {
+                    let mut pending = PENDING_SCROLL.lock().unwrap();
+                    if let Some(sum) = pending.get_mut(&key) {
+                        // the first event of this burst is already waiting to send it
+                        *sum = sum.saturating_add(*delta);
+                        return Ok(());
+                    }
+                    pending.insert(key.clone(), *delta);
+                }
+                task::sleep(SCROLL_WINDOW).await;
+                let delta = PENDING_SCROLL.lock().unwrap().remove(&key).unwrap_or(0);
+                if delta == 0 {
+                    return Ok(());
+                }
+                timed("Scroll", proxy.scroll(&delta, orientation.clone())).await
+            }
+            Command::Raise { .. } => return raise(&item).await,
+            Command::MenuEvent { menu_id, event, .. } => {
+                let menu = item
+                    .menu_proxy(&proxy)
+                    .await
+                    .ok_or_else(|| CommandError::NoMenu(self.item().to_string()))?;
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |t| t.as_secs() as u32);
+                timed(
+                    "Event",
+                    menu.event(*menu_id, event, &Value::I32(0), timestamp),
+                )
+                .await
+            }
+        }
+        .map_err(|e| {
+            crate::metrics::METRICS.dbus_error(proxy.destination());
+            CommandError::DBus(e)
+        })
+    }
+}
+
+#[cfg(feature = "x11")]
+async fn raise(item: &Item) -> Result<(), CommandError> {
+    let window = item
+        .window_id
+        .ok_or_else(|| CommandError::NoWindow(item.id.clone()))?;
+    crate::rt::spawn_blocking(move || crate::x11::activate(window))
+        .await
+        .map_err(|e| CommandError::Raise(e.to_string()))
+}
+
+#[cfg(not(feature = "x11"))]
+async fn raise(_item: &Item) -> Result<(), CommandError> {
+    Err(CommandError::Raise(
+        "built without the `x11` feature".to_string(),
+    ))
+}
+
+/// Passes `token`, or one from `--activation-token-command`, to the item.
+/// Items that don't know `ProvideXdgActivationToken` are activated anyway.
+async fn provide_token(proxy: &StatusNotifierItemProxy<'static>, token: &Option<String>) {
+    let token = match token {
+        Some(token) => token.clone(),
+        None => match activation_token().await {
+            Some(token) => token,
+            None => return,
+        },
+    };
+    let provided = timed(
+        "ProvideXdgActivationToken",
+        proxy.provide_xdg_activation_token(token),
+    )
+    .await;
+    if let Err(e) = provided {
+        tracing::debug!(item = %proxy.destination(), "no activation token: {}", e);
+    }
+}
+
+/// `--activation-token-command`, asked for a token when a click comes without one.
+static TOKEN_COMMAND: RwLock<Option<String>> = RwLock::new(None);
+
+/// How long `--activation-token-command` may take.
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn set_token_command(command: Option<String>) {
+    *TOKEN_COMMAND.write().unwrap() = command;
+}
+
+/// The first line `--activation-token-command` prints, run with `sh -c`.
+async fn activation_token() -> Option<String> {
+    let command = TOKEN_COMMAND.read().unwrap().clone()?;
+    let output = process::Command::new("sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output();
+    let output = match async_std::future::timeout(TOKEN_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => output,
+        Ok(Ok(output)) => {
+            tracing::warn!(command = %command, status = %output.status, "activation token command failed");
+            return None;
+        }
+        Ok(Err(e)) => {
+            tracing::warn!(command = %command, error = %e, "activation token command failed");
+            return None;
+        }
+        Err(_) => {
+            tracing::warn!(command = %command, "activation token command timed out");
+            return None;
+        }
+    };
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some(stdout.lines().next()?.trim().to_string()).filter(|token| !token.is_empty())
+}
+
+/// Parses the line protocol read from stdin:
+///
+/// ```text
+/// activate <item> [x y [token]]
+/// secondary <item> [x y [token]]
+/// menu <item> [x y]
+/// scroll <item> <delta> [vertical|horizontal]
+/// raise <item>
+/// menu-event <item> <menu-id> [clicked|hovered|opened|closed]
+/// ```
+impl FromStr for Command {
+    type Err = CommandError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut words = s.split_whitespace();
+        let verb = words
+            .next()
+            .ok_or_else(|| CommandError::Parse("empty command".to_string()))?;
+        let item = words
+            .next()
+            .ok_or_else(|| CommandError::Parse(format!("`{}` needs an item", verb)))?
+            .to_string();
+        let mut int = |name: &str| -> Result<Option<i32>, CommandError> {
+            words
+                .next()
+                .map(|w| {
+                    w.parse()
+                        .map_err(|_| CommandError::Parse(format!("invalid {} `{}`", name, w)))
+                })
+                .transpose()
+        };
+        let command = match verb {
+            "activate" | "secondary" | "menu" => {
+                let x = int("x")?.unwrap_or(0);
+                let y = int("y")?.unwrap_or(0);
+                let token = words.next().map(str::to_string);
+                match verb {
+                    "activate" => Command::Activate { item, x, y, token },
+                    "secondary" => Command::SecondaryActivate { item, x, y, token },
+                    _ => Command::ContextMenu { item, x, y },
+                }
+            }
+            "scroll" => {
+                let delta = int("delta")?
+                    .ok_or_else(|| CommandError::Parse("missing delta".to_string()))?;
+                let orientation = match words.next() {
+                    None | Some("vertical") => "vertical",
+                    Some("horizontal") => "horizontal",
+                    Some(other) => {
+                        return Err(CommandError::Parse(format!(
+                            "invalid orientation `{}`",
+                            other
+                        )))
+                    }
+                };
+                Command::Scroll {
+                    item,
+                    delta,
+                    orientation: orientation.to_string(),
+                }
+            }
+            "raise" => Command::Raise { item },
+            "menu-event" => {
+                let menu_id = int("menu id")?
+                    .ok_or_else(|| CommandError::Parse("missing menu id".to_string()))?;
+                Command::MenuEvent {
+                    item,
+                    menu_id,
+                    event: words.next().unwrap_or("clicked").to_string(),
+                }
+            }
+            _ => return Err(CommandError::Parse(format!("unknown command `{}`", verb))),
+        };
+        if let Some(extra) = words.next() {
+            return Err(CommandError::Parse(format!(
+                "unexpected `{}` after `{}`",
+                extra, verb
+            )));
+        }
+        Ok(command)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Command {
+        s.parse().unwrap()
+    }
+
+    fn error(s: &str) -> String {
+        match s.parse::<Command>() {
+            Err(CommandError::Parse(reason)) => reason,
+            other => panic!("`{}` gave {:?}", s, other),
+        }
+    }
+
+    #[test]
+    fn parses_clicks() {
+        assert!(matches!(
+            parse("activate nm 10 -20 token"),
+            Command::Activate { item, x: 10, y: -20, token: Some(token) }
+                if item == "nm" && token == "token"
+        ));
+        assert!(matches!(
+            parse("secondary nm"),
+            Command::SecondaryActivate {
+                x: 0,
+                y: 0,
+                token: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            parse("  menu   nm 5 "),
+            Command::ContextMenu { x: 5, y: 0, .. }
+        ));
+        assert!(matches!(parse("raise nm"), Command::Raise { item } if item == "nm"));
+    }
+
+    #[test]
+    fn parses_scrolls_and_menu_events() {
+        assert!(matches!(
+            parse("scroll nm -3"),
+            Command::Scroll { delta: -3, orientation, .. } if orientation == "vertical"
+        ));
+        assert!(matches!(
+            parse("scroll nm 1 horizontal"),
+            Command::Scroll { orientation, .. } if orientation == "horizontal"
+        ));
+        assert!(matches!(
+            parse("menu-event nm 7"),
+            Command::MenuEvent { menu_id: 7, event, .. } if event == "clicked"
+        ));
+        assert!(matches!(
+            parse("menu-event nm 7 hovered"),
+            Command::MenuEvent { event, .. } if event == "hovered"
+        ));
+    }
+
+    #[test]
+    fn reports_invalid_commands() {
+        assert_eq!(error(""), "empty command");
+        assert_eq!(error("activate"), "`activate` needs an item");
+        assert_eq!(error("frobnicate nm"), "unknown command `frobnicate`");
+        assert_eq!(error("activate nm ten"), "invalid x `ten`");
+        assert_eq!(error("scroll nm"), "missing delta");
+        assert_eq!(
+            error("scroll nm 1 diagonal"),
+            "invalid orientation `diagonal`"
+        );
+        assert_eq!(error("menu-event nm"), "missing menu id");
+        assert_eq!(
+            error("raise nm garbage"),
+            "unexpected `garbage` after `raise`"
+        );
+        assert_eq!(
+            error("scroll nm 1 vertical x"),
+            "unexpected `x` after `scroll`"
+        );
+        assert_eq!(
+            error("activate nm 1 2 token x"),
+            "unexpected `x` after `activate`"
+        );
+    }
+}
@@ -0,0 +1,291 @@
+//! Settings from `$XDG_CONFIG_HOME/trayson/config.toml` for what is unwieldy as
+//! flags. Flags given on the command line take precedence.
+//!
+//! ```toml
+//! filter = 'status != "Passive"'
+//! hide = ["Passive SystemServices"]
+//! pinned = ["nm-applet", "pasystray", "cbatticon"]
+//! ignore = ["steam", "/^chrome_status_icon_\\d+$/"]
+//! sinks = ["stdout format=waybar", "socket:/run/user/1000/tray.sock"]
+//! click_commands = true
+//! notify_attention = true
+//! script = "route.lua"
+//! on_attention = "paplay /usr/share/sounds/freedesktop/stereo/message.oga"
+//! reject_foreign = true
+//! exit_when_idle = 10
+//!
+//! [icon]
+//! size = 24
+//! format = "qoi"
+//! dir = "/run/user/1000/trayson"
+//! theme = "Papirus"
+//! uri = true
+//! links = true
+//! max_age = 60
+//!
+//! [[override]]
+//! id = "chrome_status_icon_*"
+//! title = "{tooltip}"
+//!
+//! [sink.bar1]
+//! dest = "socket:/run/user/1000/bar1.sock"
+//! format = "waybar"
+//! only = ["nm-applet"]
+//!
+//! [sink.bar2]
+//! format = "json"
+//! ```
+//!
+//! The `[sink.NAME]` tables are sinks in addition to `--sink` and `sinks`, or
+//! to the default sink without them, each with the items of its `filter`,
+//! `only` and `ignore` in its `format`, written to its `dest` of the sink spec
+//! or stdout. `default_sink = false` leaves out the default sink.
+//!
+//! See [`crate::overrides`] for the per-item tables, [`crate::strip`] for
+//! the `[strip]` table and [`crate::naming`] for `[rename]`.
+//!
+//! It is read again on SIGHUP, the filters, sinks and icon settings then apply
+//! to all following updates.
+
+use crate::args::RunArgs;
+use crate::command;
+use crate::filter::Pattern;
+use crate::host;
+use crate::icon::{self, IconConfig};
+use crate::item;
+use crate::naming::{self, FieldCase};
+use crate::overrides::OverrideConfig;
+use crate::sink::{self, Format, SinkConfig};
+use crate::strip::{self, StripConfig};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Config {
+    filter: Option<String>,
+    hide: Vec<String>,
+    ignore: Vec<String>,
+    only: Vec<String>,
+    pinned: Vec<String>,
+    format: Option<String>,
+    coalesce: Option<u64>,
+    call_timeout: Option<u64>,
+    ping_interval: Option<u64>,
+    stale_grace: Option<u64>,
+    init_concurrency: Option<usize>,
+    icon_rate: Option<u32>,
+    output_path: Option<PathBuf>,
+    sinks: Vec<String>,
+    sink: BTreeMap<String, SinkProfile>,
+    default_sink: Option<bool>,
+    click_commands: bool,
+    tooltip_pango: bool,
+    field_case: Option<FieldCase>,
+    rename: HashMap<String, String>,
+    notify_attention: bool,
+    #[serde(alias = "on-item-added")]
+    on_item_added: Option<String>,
+    #[serde(alias = "on-item-removed")]
+    on_item_removed: Option<String>,
+    #[serde(alias = "on-attention")]
+    on_attention: Option<String>,
+    script: Option<PathBuf>,
+    activation_token_command: Option<String>,
+    forward_to: Option<String>,
+    reject_foreign: bool,
+    conformant_watcher: bool,
+    exit_when_idle: Option<u64>,
+    metrics_file: Option<PathBuf>,
+    state_file: Option<PathBuf>,
+    history: Option<PathBuf>,
+    history_max_size: Option<u64>,
+    icon: IconConfig,
+    strip: StripConfig,
+    #[serde(rename = "override")]
+    overrides: Vec<OverrideConfig>,
+}
+
+/// A `[sink.NAME]` table.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SinkProfile {
+    dest: Option<String>,
+    format: Option<String>,
+    filter: Option<String>,
+    only: Vec<String>,
+    ignore: Vec<String>,
+}
+
+impl SinkProfile {
+    fn resolve(self) -> Result<SinkConfig, ConfigError> {
+        let dest = sink::destination(self.dest.as_deref().unwrap_or("stdout"))
+            .map_err(|e| ConfigError(format!("{}", e)))?;
+        let format = match self.format {
+            Some(format) => Format::from_str(&format, true)
+                .map_err(|e| ConfigError(format!("invalid format: {}", e)))?,
+            None => Format::Json,
+        };
+        let mut config = SinkConfig::new(dest, format);
+        config.filter = self
+            .filter
+            .map(|filter| filter.parse())
+            .transpose()
+            .map_err(|e| ConfigError(format!("{}", e)))?;
+        config.only = patterns(&self.only)?;
+        config.ignore = patterns(&self.ignore)?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "config: {}", self.0)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+pub fn default_path() -> Option<PathBuf> {
+    let dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(dir.join("trayson").join("config.toml"))
+}
+
+fn read(path: &Path) -> Result<Config, ConfigError> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))?;
+    toml::from_str(&text).map_err(|e| ConfigError(format!("{}: {}", path.display(), e)))
+}
+
+/// Fills in what `args` leaves unset from the config file, which only has to
+/// exist if given with `--config`, and applies its icon settings and the call timeout.
+pub fn load(mut args: RunArgs) -> Result<RunArgs, ConfigError> {
+    let path = args
+        .config
+        .clone()
+        .or_else(|| default_path().filter(|path| path.exists()));
+    let mut config = match &path {
+        Some(path) => read(path)?,
+        None => Config::default(),
+    };
+    if args.filter.is_none() {
+        args.filter = config
+            .filter
+            .map(|filter| filter.parse())
+            .transpose()
+            .map_err(|e| ConfigError(format!("{}", e)))?;
+    }
+    if args.hide.is_empty() {
+        args.hide = config
+            .hide
+            .iter()
+            .map(|rule| rule.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|e| ConfigError(format!("{}", e)))?;
+    }
+    if args.ignore.is_empty() {
+        args.ignore = patterns(&config.ignore)?;
+    }
+    if args.only.is_empty() {
+        args.only = patterns(&config.only)?;
+    }
+    if args.pinned.is_empty() {
+        args.pinned = patterns(&config.pinned)?;
+    }
+    if args.format.is_none() {
+        args.format = config
+            .format
+            .map(|format| Format::from_str(&format, true))
+            .transpose()
+            .map_err(|e| ConfigError(format!("invalid format: {}", e)))?;
+    }
+    if args.sinks.is_empty() {
+        args.sinks = config
+            .sinks
+            .iter()
+            .map(|sink| sink.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|e| ConfigError(format!("{}", e)))?;
+    }
+    args.sink_profiles = config
+        .sink
+        .into_iter()
+        .map(|(name, profile)| {
+            profile
+                .resolve()
+                .map_err(|e| ConfigError(format!("sink.{}: {}", name, e.0)))
+        })
+        .collect::<Result<_, _>>()?;
+    args.no_default_sink = config.default_sink == Some(false);
+    args.coalesce = args.coalesce.or(config.coalesce);
+    args.call_timeout = args.call_timeout.or(config.call_timeout);
+    args.ping_interval = args.ping_interval.or(config.ping_interval);
+    args.stale_grace = args.stale_grace.or(config.stale_grace);
+    args.init_concurrency = args.init_concurrency.or(config.init_concurrency);
+    args.icon_rate = args.icon_rate.or(config.icon_rate);
+    args.output_path = args.output_path.or(config.output_path);
+    args.metrics_file = args.metrics_file.or(config.metrics_file);
+    args.state_file = args.state_file.or(config.state_file);
+    args.history = args.history.or(config.history);
+    args.history_max_size = args.history_max_size.or(config.history_max_size);
+    args.click_commands |= config.click_commands;
+    args.tooltip_pango |= config.tooltip_pango;
+    args.field_case = args.field_case.or(config.field_case);
+    args.notify_attention |= config.notify_attention;
+    args.on_item_added = args.on_item_added.or(config.on_item_added);
+    args.on_item_removed = args.on_item_removed.or(config.on_item_removed);
+    args.on_attention = args.on_attention.or(config.on_attention);
+    if args.script.is_none() {
+        let dir = path
+            .as_deref()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""));
+        args.script = config.script.map(|script| dir.join(script));
+    }
+    args.activation_token_command = args
+        .activation_token_command
+        .or(config.activation_token_command);
+    args.forward_to = args.forward_to.or(config.forward_to);
+    args.reject_foreign |= config.reject_foreign;
+    args.conformant_watcher |= config.conformant_watcher;
+    args.exit_when_idle = args.exit_when_idle.or(config.exit_when_idle);
+    let theme = config.icon.theme.as_deref();
+    args.overrides = config
+        .overrides
+        .into_iter()
+        .map(|item_override| item_override.resolve(theme))
+        .collect::<Result<_, _>>()
+        .map_err(|e| ConfigError(format!("override: {}", e)))?;
+    config.icon.uri |= args.icon_uris;
+    config.icon.links |= args.icon_links;
+    icon::configure(config.icon);
+    strip::configure(config.strip);
+    naming::configure(args.field_case.unwrap_or_default(), config.rename);
+    command::set_token_command(args.activation_token_command.clone());
+    host::set_health(
+        Duration::from_secs(args.ping_interval.unwrap_or(30)),
+        Duration::from_secs(args.stale_grace.unwrap_or(120)),
+    );
+    host::set_init_concurrency(args.init_concurrency.unwrap_or(4));
+    host::set_icon_rate(args.icon_rate.unwrap_or(4));
+    item::set_call_timeout(Duration::from_millis(args.call_timeout.unwrap_or(5000)));
+    Ok(args)
+}
+
+fn patterns(patterns: &[String]) -> Result<Vec<Pattern>, ConfigError> {
+    patterns
+        .iter()
+        .map(|pattern| pattern.parse())
+        .collect::<Result<_, _>>()
+        .map_err(|e| ConfigError(format!("{}", e)))
+}
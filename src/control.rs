@@ -0,0 +1,503 @@
+//! Newline-delimited JSON-RPC 2.0 server on a unix socket.
+//!
+//! Requests without an `id` are notifications and get no response.
+//!
+//! Methods:
+//! - `snapshot` returns the current item list
+//! - `get` returns the item given as `{"item"}`, as `snapshot` has it
+//! - `menu` returns the freshly fetched menu of the item given as `{"item"}`,
+//!   announcing it to the application with `AboutToShow` first
+//! - `subscribe` returns the current item list and afterwards sends an
+//!   `update` notification with the full list on every change, and `event`
+//!   notifications like `{"event": "error", "item", "reason"}` for items
+//!   that could not be read or followed, both with the `seq` and `ts` of
+//!   [`crate::stamp`]
+//! - `activate`, `secondary_activate`, `context_menu` take `{"item", "x", "y"}`,
+//!   the first two also an XDG activation `"token"` for the application
+//! - `scroll` takes `{"item", "delta", "orientation"}`
+//! - `menu_event` takes `{"item", "menu_id", "event"}`, `event` defaulting to `clicked`
+//! - `raise` takes `{"item"}` and activates the window of its `window_id`,
+//!   with the `x11` feature
+//! - `hit_test` takes `{"x"}` and returns the `id` of the item at that offset
+//!   of the strip emitted last by a `format=strip` sink, or null; with
+//!   several such sinks `"sink"` names the destination of the one meant
+//! - `stats` returns the memory used by the process, its icon files and
+//!   per item, see [`crate::metrics::stats`]
+//!
+//! Items are addressed by their `id` (or service name).
+//!
+//! The same protocol is served by [`crate::bridge`] to other machines.
+//!
+//! [`call`] is the client side used by the CLI subcommands.
+
+use crate::command::{Command, CommandError};
+use crate::output::Subscriber;
+use crate::registry::SharedRegistry;
+use crate::rt;
+use crate::stamp::Stamp;
+use crate::{item, menu};
+use async_std::io::{self, prelude::BufReadExt, BufReader, WriteExt};
+use async_std::os::unix::net::{UnixListener, UnixStream};
+use async_std::path::PathBuf;
+use async_std::sync::Mutex;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::sync::Arc;
+
+#[derive(Deserialize)]
+struct Request {
+    /// Missing for notifications, which get no response
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Tells `"id": null` apart from no `id` at all.
+fn present<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+#[derive(Deserialize)]
+struct ItemParams {
+    item: String,
+}
+
+#[derive(Deserialize)]
+struct HitTestParams {
+    x: i32,
+    /// Destination of the strip sink, as given in its spec
+    #[serde(default)]
+    sink: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ClickParams {
+    item: String,
+    #[serde(default)]
+    x: i32,
+    #[serde(default)]
+    y: i32,
+    token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ScrollParams {
+    item: String,
+    delta: i32,
+    #[serde(default = "default_orientation")]
+    orientation: String,
+}
+
+#[derive(Deserialize)]
+struct MenuEventParams {
+    item: String,
+    menu_id: i32,
+    #[serde(default = "default_event")]
+    event: String,
+}
+
+fn default_event() -> String {
+    "clicked".to_string()
+}
+
+fn default_orientation() -> String {
+    "vertical".to_string()
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ControlServer {
+    registry: SharedRegistry,
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+    last: Arc<Mutex<Vec<Value>>>,
+    /// Where the items of the published list are, with `--remote`
+    #[cfg(feature = "bridge")]
+    remote: Option<crate::bridge::Remote>,
+}
+
+/// Default socket location, `$XDG_RUNTIME_DIR/trayson.sock`.
+pub fn default_socket_path() -> Option<std::path::PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| std::path::PathBuf::from(dir).join("trayson.sock"))
+}
+
+/// Sends a single request to the instance listening on `path` and returns its result.
+pub async fn call(
+    path: Option<std::path::PathBuf>,
+    method: &str,
+    params: Value,
+) -> Result<Value, Box<dyn Error>> {
+    let mut reader = request(path, method, params).await?;
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut response: Value = serde_json::from_str(&line)?;
+    if let Some(error) = response.get("error") {
+        return Err(error["message"].as_str().unwrap_or("request failed").into());
+    }
+    Ok(response["result"].take())
+}
+
+/// Calls `on_update` with the current item list and again on every change,
+/// until the instance goes away.
+#[cfg_attr(not(feature = "gui"), allow(dead_code))]
+pub async fn subscribe(
+    path: Option<std::path::PathBuf>,
+    mut on_update: impl FnMut(Vec<Value>),
+) -> Result<(), Box<dyn Error>> {
+    let mut lines = request(path, "subscribe", json!({})).await?.lines();
+    while let Some(line) = lines.next().await {
+        let mut message: Value = serde_json::from_str(&line?)?;
+        let items = match message.get_mut("result") {
+            Some(result) => result.take(),
+            None => message["params"]["items"].take(),
+        };
+        if let Value::Array(items) = items {
+            on_update(items);
+        }
+    }
+    Ok(())
+}
+
+async fn request(
+    path: Option<std::path::PathBuf>,
+    method: &str,
+    params: Value,
+) -> Result<BufReader<UnixStream>, Box<dyn Error>> {
+    let path = path
+        .or_else(default_socket_path)
+        .ok_or("no control socket given and XDG_RUNTIME_DIR is not set")?;
+    let mut stream = UnixStream::connect(&path)
+        .await
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let request = json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params});
+    stream
+        .write_all(format!("{}\n", request).as_bytes())
+        .await?;
+    Ok(BufReader::new(stream))
+}
+
+impl ControlServer {
+    /// A server for connections accepted elsewhere, see [`ControlServer::serve`].
+    pub fn new(registry: SharedRegistry) -> Self {
+        ControlServer {
+            registry,
+            subscribers: Default::default(),
+            last: Default::default(),
+            #[cfg(feature = "bridge")]
+            remote: None,
+        }
+    }
+
+    /// Forwards calls about items to `remote` instead of the registry, which
+    /// stays empty.
+    #[cfg(feature = "bridge")]
+    pub fn forward_to(mut self, remote: crate::bridge::Remote) -> Self {
+        self.remote = Some(remote);
+        self
+    }
+
+    pub async fn bind(path: impl Into<PathBuf>, registry: SharedRegistry) -> io::Result<Self> {
+        ControlServer::new(registry).listen(path).await
+    }
+
+    /// Accepts connections on the unix socket at `path`.
+    pub async fn listen(self, path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        if path.exists().await {
+            if UnixStream::connect(&path).await.is_ok() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("{} is used by another instance", path.display()),
+                ));
+            }
+            async_std::fs::remove_file(&path).await?;
+        }
+        let listener = UnixListener::bind(&path).await?;
+
+        let s = self.clone();
+        rt::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                rt::spawn(s.clone().serve(BufReader::new(stream.clone()), stream));
+            }
+        });
+        Ok(self)
+    }
+
+    /// Pushes the new item list to all subscribers.
+    pub async fn publish(&self, items: &[Value], stamp: &Stamp) {
+        *self.last.lock().await = items.to_vec();
+        let params = json!({"items": items, "seq": stamp.seq, "ts": stamp.ts});
+        self.notify("update", params).await;
+    }
+
+    /// Pushes an `event` notification to all subscribers.
+    pub async fn publish_event(&self, event: &Value) {
+        self.notify("event", event.clone()).await;
+    }
+
+    async fn notify(&self, method: &str, params: Value) {
+        let line = format!(
+            "{}\n",
+            json!({"jsonrpc": "2.0", "method": method, "params": params})
+        );
+        let line = Arc::<[u8]>::from(line.into_bytes());
+        self.subscribers
+            .lock()
+            .await
+            .retain(|client| client.send(line.clone()));
+    }
+
+    /// Answers the requests read from `reader` until it is closed.
+    pub async fn serve<R, W>(self, reader: BufReader<R>, writer: W)
+    where
+        R: io::Read + Unpin,
+        W: io::Write + Send + Unpin + 'static,
+    {
+        let client = Subscriber::spawn(writer);
+        let mut lines = reader.lines();
+        while let Some(Ok(line)) = lines.next().await {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut subscribing = None;
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(request) if request.method == "subscribe" => {
+                    // held until the client is added, so that no update is
+                    // published between the list it gets and being added
+                    let subscribers = self.subscribers.lock().await;
+                    let items = json!(*self.last.lock().await);
+                    subscribing = Some(subscribers);
+                    request
+                        .id
+                        .map(|id| json!({"jsonrpc": "2.0", "id": id, "result": items}))
+                }
+                Ok(request) => {
+                    let id = request.id.clone();
+                    let result = self.handle(request).await;
+                    id.map(|id| match result {
+                        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                        Err(e) => json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "error": {"code": e.code, "message": e.message},
+                        }),
+                    })
+                }
+                Err(e) => Some(json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {"code": -32700, "message": e.to_string()},
+                })),
+            };
+            // notifications are not answered
+            if let Some(response) = response {
+                let line = format!("{}\n", response);
+                if !client.send(line.into_bytes().into()) {
+                    break;
+                }
+            }
+            // only after the response, which comes before any notification
+            if let Some(mut subscribers) = subscribing {
+                subscribers.push(client.clone());
+            }
+        }
+    }
+
+    async fn handle(&self, request: Request) -> Result<Value, RpcError> {
+        #[cfg(feature = "bridge")]
+        if let Some(remote) = &self.remote {
+            match request.method.as_str() {
+                "snapshot" | "hit_test" => {}
+                "get" => {
+                    let ItemParams { item } = params(request.params)?;
+                    return self.published(&item).await;
+                }
+                method => {
+                    return remote
+                        .call(method, request.params)
+                        .await
+                        .map_err(|e| RpcError::new(-32000, e));
+                }
+            }
+        }
+        match request.method.as_str() {
+            "snapshot" => Ok(json!(*self.last.lock().await)),
+            "get" => {
+                let ItemParams { item } = params(request.params)?;
+                let id = self
+                    .registry
+                    .lock()
+                    .await
+                    .find(&item)
+                    .map(|entry| entry.item.id.clone());
+                match id {
+                    Some(id) => self.published(&id).await,
+                    None => Err(RpcError::new(-32001, CommandError::UnknownItem(item))),
+                }
+            }
+            "menu" => {
+                let ItemParams { item } = params(request.params)?;
+                let entry = self
+                    .registry
+                    .lock()
+                    .await
+                    .find(&item)
+                    .map(|entry| (entry.item.clone(), entry.proxy.clone()));
+                let (found, proxy) = entry.ok_or_else(|| {
+                    RpcError::new(-32001, CommandError::UnknownItem(item.clone()))
+                })?;
+                let menu = found
+                    .menu_proxy(&proxy)
+                    .await
+                    .ok_or_else(|| RpcError::new(-32000, CommandError::NoMenu(item)))?;
+                item::timed("GetLayout", menu::fetch_shown(&menu))
+                    .await
+                    .map(|entries| json!(entries))
+                    .map_err(|e| RpcError::new(-32000, e))
+            }
+            "hit_test" => {
+                let HitTestParams { x, sink } = params(request.params)?;
+                // the same destination may be written differently
+                let sink = match sink {
+                    Some(sink) => Some(
+                        crate::sink::destination(&sink)
+                            .map_err(|e| RpcError::new(-32602, e))?
+                            .to_string(),
+                    ),
+                    None => None,
+                };
+                Ok(json!(crate::strip::hit_test(x, sink.as_deref())))
+            }
+            "stats" => Ok(crate::metrics::stats(self.registry.lock().await.items())),
+            "activate" | "secondary_activate" | "context_menu" => {
+                let ClickParams { item, x, y, token } = params(request.params)?;
+                let command = match request.method.as_str() {
+                    "activate" => Command::Activate { item, x, y, token },
+                    "secondary_activate" => Command::SecondaryActivate { item, x, y, token },
+                    _ => Command::ContextMenu { item, x, y },
+                };
+                self.dispatch(command).await
+            }
+            "scroll" => {
+                let ScrollParams {
+                    item,
+                    delta,
+                    orientation,
+                } = params(request.params)?;
+                self.dispatch(Command::Scroll {
+                    item,
+                    delta,
+                    orientation,
+                })
+                .await
+            }
+            "raise" => {
+                let ItemParams { item } = params(request.params)?;
+                self.dispatch(Command::Raise { item }).await
+            }
+            "menu_event" => {
+                let MenuEventParams {
+                    item,
+                    menu_id,
+                    event,
+                } = params(request.params)?;
+                self.dispatch(Command::MenuEvent {
+                    item,
+                    menu_id,
+                    event,
+                })
+                .await
+            }
+            method => Err(RpcError::new(
+                -32601,
+                format!("unknown method `{}`", method),
+            )),
+        }
+    }
+
+    /// The item of `id` as published last, like `snapshot` has it, so hidden
+    /// items are unknown.
+    async fn published(&self, id: &str) -> Result<Value, RpcError> {
+        self.last
+            .lock()
+            .await
+            .iter()
+            .find(|found| found["id"] == id)
+            .cloned()
+            .ok_or_else(|| RpcError::new(-32001, CommandError::UnknownItem(id.to_string())))
+    }
+
+    async fn dispatch(&self, command: Command) -> Result<Value, RpcError> {
+        match command.dispatch(&self.registry).await {
+            Ok(()) => Ok(Value::Null),
+            Err(e @ CommandError::UnknownItem(_)) => Err(RpcError::new(-32001, e)),
+            Err(e) => Err(RpcError::new(-32000, e)),
+        }
+    }
+}
+
+fn params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(-32602, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::io::ReadExt;
+    use async_std::os::unix::net::UnixStream;
+
+    /// The responses of a server with no items to `requests`.
+    fn responses(requests: &str) -> Vec<Value> {
+        rt::block_on(async {
+            let (server, mut client) = UnixStream::pair().unwrap();
+            let control = ControlServer::new(Default::default());
+            client.write_all(requests.as_bytes()).await.unwrap();
+            client.shutdown(std::net::Shutdown::Write).unwrap();
+            // until all is read, the responses are written by a task of their own
+            control.serve(BufReader::new(server.clone()), server).await;
+            let mut output = String::new();
+            client.read_to_string(&mut output).await.unwrap();
+            output
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn answers_requests_but_not_notifications() {
+        let responses = responses(concat!(
+            "{\"jsonrpc\": \"2.0\", \"method\": \"snapshot\"}\n",
+            "{\"jsonrpc\": \"2.0\", \"method\": \"unknown\"}\n",
+            "{\"jsonrpc\": \"2.0\", \"id\": 1, \"method\": \"snapshot\"}\n",
+            "{\"jsonrpc\": \"2.0\", \"id\": null, \"method\": \"unknown\"}\n",
+            "not json\n",
+        ));
+        assert_eq!(responses.len(), 3, "{:?}", responses);
+        assert_eq!(
+            responses[0],
+            json!({"jsonrpc": "2.0", "id": 1, "result": []})
+        );
+        assert_eq!(responses[1]["id"], Value::Null);
+        assert_eq!(responses[1]["error"]["code"], -32601);
+        assert_eq!(responses[2]["error"]["code"], -32700);
+    }
+}
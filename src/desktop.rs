@@ -0,0 +1,189 @@
+//! Finds the desktop entry of the application behind an item, for a readable
+//! name and a themed icon instead of `Id`s like `chrome_status_icon_1`.
+//!
+//! An entry matches if its file name, the last part of a reverse DNS file name
+//! or `StartupWMClass` equals the `Id`, or else if `Exec` runs the executable
+//! of the process owning the item. The entries are read once on first use.
+
+use crate::icon;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static ENTRIES: OnceLock<Vec<Entry>> = OnceLock::new();
+
+/// What an item is enriched with.
+#[derive(Debug, Clone, Serialize)]
+pub struct App {
+    /// `Name`, localized for `$LC_ALL`, `$LC_MESSAGES` or `$LANG`
+    pub name: String,
+    /// The `Icon` as a file, if found
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    pub desktop_file: String,
+}
+
+struct Entry {
+    path: PathBuf,
+    /// Lowercase file name without `.desktop`
+    stem: String,
+    name: String,
+    icon: String,
+    /// Lowercase file name of the program run by `Exec`
+    exec: String,
+    wm_class: String,
+}
+
+impl Entry {
+    fn matches_id(&self, id: &str) -> bool {
+        self.stem == id || self.stem.rsplit('.').next() == Some(id) || self.wm_class == id
+    }
+
+    fn matches_exe(&self, exe: &str) -> bool {
+        self.exec == exe || self.stem == exe
+    }
+
+    fn app(&self) -> App {
+        let icon = match self.icon.as_str() {
+            "" => None,
+            path if path.starts_with('/') => Some(PathBuf::from(path)).filter(|p| p.is_file()),
+            name => icon::find_themed(name, icon::theme().as_deref()),
+        };
+        App {
+            name: self.name.clone(),
+            icon: icon.map(|icon| icon.to_string_lossy().into_owned()),
+            desktop_file: self.path.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+/// Looks up the application of the item with `sni_id`, owned by a process
+/// running `exe`.
+pub fn find(sni_id: &str, exe: Option<&Path>) -> Option<App> {
+    let entries = ENTRIES.get_or_init(load);
+    let id = sni_id.to_lowercase();
+    let exe = exe
+        .and_then(|exe| exe.file_name()?.to_str())
+        .map(str::to_lowercase);
+    let entry = entries
+        .iter()
+        .find(|entry| !id.is_empty() && entry.matches_id(&id))
+        .or_else(|| {
+            let exe = exe.as_deref()?;
+            entries.iter().find(|entry| entry.matches_exe(exe))
+        })?;
+    Some(entry.app())
+}
+
+/// All application entries, the first of each desktop file ID winning.
+fn load() -> Vec<Entry> {
+    let locales = locales();
+    let mut seen = std::collections::HashSet::new();
+    let mut entries = Vec::new();
+    for base in icon::data_dirs() {
+        let base = base.join("applications");
+        for path in desktop_files(&base) {
+            let Ok(relative) = path.strip_prefix(&base) else {
+                continue;
+            };
+            let file_id = relative.to_string_lossy().replace('/', "-");
+            if !seen.insert(file_id) {
+                continue;
+            }
+            let Ok(text) = std::fs::read_to_string(&path) else {
+                continue;
+            };
+            if let Some(entry) = parse(&path, &text, &locales) {
+                entries.push(entry);
+            }
+        }
+    }
+    entries
+}
+
+fn desktop_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(read) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files = Vec::new();
+    for path in read.flatten().map(|entry| entry.path()) {
+        if path.is_dir() {
+            files.extend(desktop_files(&path));
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "desktop")
+        {
+            files.push(path);
+        }
+    }
+    files
+}
+
+/// Reads the `[Desktop Entry]` group, skipping anything but visible applications.
+fn parse(path: &Path, text: &str, locales: &[String]) -> Option<Entry> {
+    let mut in_group = false;
+    let mut fields = std::collections::HashMap::new();
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_group = line == "[Desktop Entry]";
+        } else if let Some((key, value)) = line.split_once('=').filter(|_| in_group) {
+            fields.insert(key.trim(), value.trim());
+        }
+    }
+    let field = |key: &str| fields.get(key).copied().unwrap_or_default();
+    if field("Type") != "Application" || field("Hidden") == "true" {
+        return None;
+    }
+    let name = locales
+        .iter()
+        .find_map(|locale| fields.get(format!("Name[{}]", locale).as_str()))
+        .copied()
+        .unwrap_or(field("Name"));
+    let exec = field("Exec")
+        .split_whitespace()
+        .find(|arg| *arg != "env" && !arg.contains('='))
+        .unwrap_or_default()
+        .rsplit('/')
+        .next()
+        .unwrap_or_default();
+    Some(Entry {
+        path: path.to_path_buf(),
+        stem: path.file_stem()?.to_string_lossy().to_lowercase(),
+        name: name.to_string(),
+        icon: field("Icon").to_string(),
+        exec: exec.to_lowercase(),
+        wm_class: field("StartupWMClass").to_lowercase(),
+    })
+}
+
+/// The `Name[...]` suffixes to try, most specific first, for a locale like
+/// `de_DE.UTF-8@euro`.
+fn locales() -> Vec<String> {
+    let locale = ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .unwrap_or_default();
+    let (locale, modifier) = match locale.split_once('@') {
+        Some((locale, modifier)) => (locale, Some(modifier)),
+        None => (locale.as_str(), None),
+    };
+    let locale = locale.split('.').next().unwrap_or_default();
+    if matches!(locale, "" | "C" | "POSIX") {
+        return Vec::new();
+    }
+    let lang = locale.split('_').next().unwrap_or_default();
+    let bases = if lang == locale {
+        vec![locale]
+    } else {
+        vec![locale, lang]
+    };
+    let mut locales = Vec::new();
+    for base in bases {
+        if let Some(modifier) = modifier {
+            locales.push(format!("{}@{}", base, modifier));
+        }
+        locales.push(base.to_string());
+    }
+    locales
+}
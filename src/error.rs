@@ -0,0 +1,79 @@
+//! What can go wrong while following an item. None of it stops the tray, it is
+//! reported as an `{"event": "error", "item", "reason", "schema_version"}`
+//! record instead: on stderr, as `event` notifications of the control socket
+//! and the bridge, on the `events` topic of `--mqtt` and as
+//! [`TrayEvent::Error`](crate::tray::TrayEvent::Error).
+//!
+//! Sinks and the state file don't get them. They carry the item list alone,
+//! as a file sink holds only the last frame and socket clients get it when
+//! they connect.
+
+use crate::item;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Error {
+    DBus(zbus::Error),
+    Io(io::Error),
+    Image(image::ImageError),
+    /// A pixmap with a negative size or data not matching it
+    Pixmap {
+        width: i32,
+        height: i32,
+        len: usize,
+    },
+    /// The application did not answer pings for this long
+    Stale(Duration),
+    /// The output loop stopped, so there is nobody left to tell
+    Closed,
+}
+
+impl Error {
+    /// Whether the application did not answer within `--call-timeout`.
+    pub fn timed_out(&self) -> bool {
+        matches!(self, Error::DBus(e) if item::timed_out(e))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DBus(e) => write!(f, "{}", e),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Image(e) => write!(f, "icon: {}", e),
+            Error::Pixmap { width, height, len } => {
+                write!(f, "icon: {} bytes for a {}x{} pixmap", len, width, height)
+            }
+            Error::Stale(since) => write!(f, "no answer for {}s, dropped", since.as_secs()),
+            Error::Closed => write!(f, "shutting down"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<zbus::Error> for Error {
+    fn from(e: zbus::Error) -> Self {
+        Error::DBus(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Image(e)
+    }
+}
+
+impl<T> From<async_std::channel::SendError<T>> for Error {
+    fn from(_: async_std::channel::SendError<T>) -> Self {
+        Error::Closed
+    }
+}
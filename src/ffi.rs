@@ -0,0 +1,255 @@
+//! A C API over [`Tray`], declared in `include/trayson.h`, so bars in other
+//! languages can embed the host instead of running `trayson run`. Build the
+//! shared library with
+//!
+//! ```text
+//! cargo rustc --release --lib --features ffi --crate-type cdylib
+//! ```
+//!
+//! Every change is passed to the callback as one JSON object on the thread
+//! of the tray:
+//!
+//! ```text
+//! {"event":"added","item":{"id":"nm-applet",...}}
+//! {"event":"changed","item":{"id":"nm-applet",...}}
+//! {"event":"removed","id":"nm-applet"}
+//! {"event":"error","item":"nm-applet","reason":"..."}
+//! ```
+
+use crate::command::Command;
+use crate::registry::SharedRegistry;
+use crate::rt;
+use crate::tray::{Tray, TrayEvent};
+use async_std::channel::{self, Sender};
+use futures_util::future::{self, Either};
+use futures_util::StreamExt;
+use serde_json::json;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+
+pub type Callback = extern "C" fn(event: *const c_char, data: *mut c_void);
+
+/// The callback with the pointer it is given back, which the caller of
+/// `trayson_start` promises may be used from the thread of the tray.
+struct Listener {
+    callback: Callback,
+    data: *mut c_void,
+}
+
+unsafe impl Send for Listener {}
+
+impl Listener {
+    fn call(&self, event: TrayEvent) {
+        let event = match event {
+            TrayEvent::Added(item) => json!({"event": "added", "item": item}),
+            TrayEvent::Changed(item) => json!({"event": "changed", "item": item}),
+            TrayEvent::Removed(id) => json!({"event": "removed", "id": id}),
+            TrayEvent::Error { item, reason } => {
+                json!({"event": "error", "item": item, "reason": reason})
+            }
+        };
+        // JSON escapes control characters, so there is no NUL in it
+        if let Ok(event) = CString::new(event.to_string()) {
+            (self.callback)(event.as_ptr(), self.data);
+        }
+    }
+}
+
+/// A running tray, opaque to C.
+pub struct Handle {
+    registry: SharedRegistry,
+    stop: Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Starts following the items on a thread of its own, calling `callback`
+/// with `data` for every change. Returns NULL if the host could not start.
+///
+/// # Safety
+///
+/// `callback` is called from another thread with `data` until
+/// [`trayson_stop`] returns.
+#[no_mangle]
+pub unsafe extern "C" fn trayson_start(callback: Callback, data: *mut c_void) -> *mut Handle {
+    let listener = Listener { callback, data };
+    let (started, start) = mpsc::channel();
+    let (stop, stopped) = channel::bounded(1);
+    let thread = thread::spawn(move || {
+        rt::block_on(async move {
+            let mut tray = match Tray::new().await {
+                Ok(tray) => tray,
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to start the tray");
+                    let _ = started.send(None);
+                    return;
+                }
+            };
+            let _ = started.send(Some(tray.registry().clone()));
+            while let Either::Left((Some(event), _)) =
+                future::select(tray.next(), stopped.recv()).await
+            {
+                listener.call(event);
+            }
+        })
+    });
+    match start.recv() {
+        Ok(Some(registry)) => Box::into_raw(Box::new(Handle {
+            registry,
+            stop,
+            thread: Some(thread),
+        })),
+        _ => {
+            let _ = thread.join();
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Stops the tray and frees it. No callback runs anymore once this returns.
+///
+/// # Safety
+///
+/// `tray` is NULL or was returned by [`trayson_start`] and not stopped yet.
+#[no_mangle]
+pub unsafe extern "C" fn trayson_stop(tray: *mut Handle) {
+    if tray.is_null() {
+        return;
+    }
+    let mut tray = Box::from_raw(tray);
+    let _ = tray.stop.try_send(());
+    if let Some(thread) = tray.thread.take() {
+        let _ = thread.join();
+    }
+}
+
+/// Runs `command` on the tray, waiting for it. 0 on success, -1 otherwise.
+unsafe fn dispatch(tray: *const Handle, command: Command) -> c_int {
+    let Some(tray) = tray.as_ref() else {
+        return -1;
+    };
+    let registry = tray.registry.clone();
+    let (done, result) = mpsc::channel();
+    let spawned = rt::try_spawn(async move {
+        let _ = done.send(command.dispatch(&registry).await);
+    });
+    if let Err(e) = spawned {
+        tracing::warn!(error = %e, "command not run");
+        return -1;
+    }
+    match result.recv() {
+        Ok(Ok(())) => 0,
+        Ok(Err(e)) => {
+            tracing::warn!(error = %e, "command failed");
+            -1
+        }
+        Err(_) => -1,
+    }
+}
+
+/// The string at `s`, empty if it is NULL or not UTF-8.
+unsafe fn string(s: *const c_char) -> String {
+    if s.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(s).to_str().unwrap_or_default().to_string()
+}
+
+/// Activates the item of `id`, usually on a left click at `x`, `y`.
+///
+/// # Safety
+///
+/// `tray` is NULL or a running tray, `id` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn trayson_activate(
+    tray: *const Handle,
+    id: *const c_char,
+    x: c_int,
+    y: c_int,
+) -> c_int {
+    let item = string(id);
+    let token = None;
+    dispatch(tray, Command::Activate { item, x, y, token })
+}
+
+/// Usually on a middle click.
+///
+/// # Safety
+///
+/// As for [`trayson_activate`].
+#[no_mangle]
+pub unsafe extern "C" fn trayson_secondary_activate(
+    tray: *const Handle,
+    id: *const c_char,
+    x: c_int,
+    y: c_int,
+) -> c_int {
+    let item = string(id);
+    let token = None;
+    dispatch(tray, Command::SecondaryActivate { item, x, y, token })
+}
+
+/// Asks the item to open its own context menu, usually on a right click.
+///
+/// # Safety
+///
+/// As for [`trayson_activate`].
+#[no_mangle]
+pub unsafe extern "C" fn trayson_context_menu(
+    tray: *const Handle,
+    id: *const c_char,
+    x: c_int,
+    y: c_int,
+) -> c_int {
+    let item = string(id);
+    dispatch(tray, Command::ContextMenu { item, x, y })
+}
+
+/// Scrolls by `delta` in `orientation`, `"vertical"` or `"horizontal"`.
+///
+/// # Safety
+///
+/// As for [`trayson_activate`], and `orientation` NULL or a NUL-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn trayson_scroll(
+    tray: *const Handle,
+    id: *const c_char,
+    delta: c_int,
+    orientation: *const c_char,
+) -> c_int {
+    let item = string(id);
+    let orientation = string(orientation);
+    dispatch(
+        tray,
+        Command::Scroll {
+            item,
+            delta,
+            orientation,
+        },
+    )
+}
+
+/// Sends `event`, usually `"clicked"`, for the entry `menu_id` of the menu.
+///
+/// # Safety
+///
+/// As for [`trayson_activate`], and `event` NULL or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn trayson_menu_event(
+    tray: *const Handle,
+    id: *const c_char,
+    menu_id: c_int,
+    event: *const c_char,
+) -> c_int {
+    let item = string(id);
+    let event = string(event);
+    dispatch(
+        tray,
+        Command::MenuEvent {
+            item,
+            menu_id,
+            event,
+        },
+    )
+}
@@ -0,0 +1,551 @@
+//! Tiny expression language used by `--filter`.
+//!
+//! Expressions are evaluated against the JSON representation of an item, so
+//! every serialized field can be referenced by name (`icon.width` for nested
+//! ones). Supported syntax:
+//!
+//! ```text
+//! status != "Passive" && (category == "Communications" || title == "Steam")
+//! !title || icon.width >= 22
+//! ```
+//!
+//! [`HideRule`]s for `--hide` name a status, a category or both, like
+//! `Passive SystemServices`.
+//!
+//! [`Pattern`]s for `--ignore` and `--only` are globs like `chrome_status_icon_*`,
+//! or regular expressions when wrapped in slashes like `/^steam$/`, which match
+//! part of the id unless anchored.
+
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Literal(Value),
+    Op(CmpOp),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Field(Vec<String>),
+    Literal(Value),
+}
+
+/// How deep `!` and parentheses may nest, well within the stack of a thread.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Vec<Expr>),
+    Or(Vec<Expr>),
+    Not(Box<Expr>),
+    Cmp(Operand, CmpOp, Operand),
+    Truthy(Operand),
+}
+
+/// A parsed `--filter` expression.
+#[derive(Debug, Clone)]
+pub struct Filter {
+    expr: Expr,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParseError(String);
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid filter: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Filter {
+    /// Evaluates the filter against a serialized item.
+    pub fn matches(&self, item: &Value) -> bool {
+        self.expr.eval(item)
+    }
+}
+
+const STATUSES: [&str; 3] = ["Active", "Passive", "NeedsAttention"];
+const CATEGORIES: [&str; 4] = [
+    "ApplicationStatus",
+    "Communications",
+    "SystemServices",
+    "Hardware",
+];
+
+/// Hides items that have the given status and category, each if given.
+#[derive(Debug, Clone)]
+pub struct HideRule {
+    status: Option<String>,
+    category: Option<String>,
+}
+
+impl HideRule {
+    pub fn matches(&self, item: &Value) -> bool {
+        let is = |field: &str, expected: &Option<String>| {
+            expected
+                .as_ref()
+                .is_none_or(|expected| item[field].as_str() == Some(expected))
+        };
+        is("status", &self.status) && is("category", &self.category)
+    }
+}
+
+impl FromStr for HideRule {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rule = HideRule {
+            status: None,
+            category: None,
+        };
+        for word in s.split_whitespace() {
+            let slot = if STATUSES.contains(&word) {
+                &mut rule.status
+            } else if CATEGORIES.contains(&word) {
+                &mut rule.category
+            } else {
+                return Err(ParseError(format!("unknown status or category `{}`", word)));
+            };
+            if slot.replace(word.to_string()).is_some() {
+                return Err(ParseError(format!(
+                    "`{}` conflicts with the rest of the rule",
+                    word
+                )));
+            }
+        }
+        if rule.status.is_none() && rule.category.is_none() {
+            return Err(ParseError("empty rule".to_string()));
+        }
+        Ok(rule)
+    }
+}
+
+/// Case insensitive glob with `*` and `?` matching a whole string, or a
+/// regular expression matching anywhere in it unless anchored with `^` and `$`.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Glob(Vec<char>),
+    Regex(regex::Regex),
+}
+
+impl Pattern {
+    pub fn matches(&self, s: &str) -> bool {
+        match self {
+            Pattern::Glob(glob) => {
+                glob_matches(glob, &s.to_lowercase().chars().collect::<Vec<_>>())
+            }
+            Pattern::Regex(regex) => regex.is_match(s),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PatternError(String);
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid pattern: {}", self.0)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+impl FromStr for Pattern {
+    type Err = PatternError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('/').and_then(|s| s.strip_suffix('/')) {
+            Some(regex) => regex::Regex::new(regex)
+                .map(Pattern::Regex)
+                .map_err(|e| PatternError(e.to_string())),
+            None => Ok(Pattern::Glob(s.to_lowercase().chars().collect())),
+        }
+    }
+}
+
+fn glob_matches(glob: &[char], s: &[char]) -> bool {
+    let (mut g, mut i) = (0, 0);
+    // position after the last `*` and where its match currently ends
+    let mut star = None;
+    while i < s.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g + 1, i));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == s[i] => {
+                g += 1;
+                i += 1;
+            }
+            _ => match star {
+                Some((after, end)) => {
+                    star = Some((after, end + 1));
+                    g = after;
+                    i = end + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+impl FromStr for Filter {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(ParseError(format!("unexpected {:?}", token)));
+        }
+        Ok(Filter { expr })
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.next() != Some(c) {
+                    return Err(ParseError(format!("expected `{0}{0}`", c)));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let eq = chars.next_if_eq(&'=').is_some();
+                tokens.push(match (c, eq) {
+                    ('=', true) => Token::Op(CmpOp::Eq),
+                    ('!', true) => Token::Op(CmpOp::Ne),
+                    ('!', false) => Token::Not,
+                    ('<', false) => Token::Op(CmpOp::Lt),
+                    ('<', true) => Token::Op(CmpOp::Le),
+                    ('>', false) => Token::Op(CmpOp::Gt),
+                    ('>', true) => Token::Op(CmpOp::Ge),
+                    _ => return Err(ParseError("expected `==`".to_string())),
+                });
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut lit = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => lit.push(escaped),
+                            None => return Err(ParseError("unterminated string".to_string())),
+                        },
+                        Some(q) if q == c => break,
+                        Some(other) => lit.push(other),
+                        None => return Err(ParseError("unterminated string".to_string())),
+                    }
+                }
+                tokens.push(Token::Literal(Value::String(lit)));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut num = String::new();
+                while let Some(d) = chars.next_if(|d| d.is_ascii_digit() || *d == '.' || *d == '-')
+                {
+                    num.push(d);
+                }
+                let value = num
+                    .parse::<serde_json::Number>()
+                    .map_err(|_| ParseError(format!("invalid number `{}`", num)))?;
+                tokens.push(Token::Literal(Value::Number(value)));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(d) =
+                    chars.next_if(|d| d.is_alphanumeric() || *d == '_' || *d == '.' || *d == '-')
+                {
+                    ident.push(d);
+                }
+                tokens.push(match ident.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    _ => Token::Ident(ident),
+                });
+            }
+            other => return Err(ParseError(format!("unexpected character `{}`", other))),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// `!` and parentheses around the current token
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, ParseError> {
+        let mut terms = vec![self.and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            terms.push(self.and()?);
+        }
+        Ok(match terms.len() {
+            1 => terms.remove(0),
+            _ => Expr::Or(terms),
+        })
+    }
+
+    fn and(&mut self) -> Result<Expr, ParseError> {
+        let mut terms = vec![self.unary()?];
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            terms.push(self.unary()?);
+        }
+        Ok(match terms.len() {
+            1 => terms.remove(0),
+            _ => Expr::And(terms),
+        })
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        match self.peek() {
+            Some(Token::Not | Token::LParen) if self.depth == MAX_DEPTH => Err(ParseError(
+                format!("nested deeper than {} levels", MAX_DEPTH),
+            )),
+            Some(Token::Not) => {
+                self.next();
+                self.depth += 1;
+                let expr = self.unary();
+                self.depth -= 1;
+                Ok(Expr::Not(Box::new(expr?)))
+            }
+            Some(Token::LParen) => {
+                self.next();
+                self.depth += 1;
+                let expr = self.or();
+                self.depth -= 1;
+                let expr = expr?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(ParseError("expected `)`".to_string())),
+                }
+            }
+            _ => {
+                let lhs = self.operand()?;
+                if let Some(Token::Op(op)) = self.peek().cloned() {
+                    self.next();
+                    Ok(Expr::Cmp(lhs, op, self.operand()?))
+                } else {
+                    Ok(Expr::Truthy(lhs))
+                }
+            }
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, ParseError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(Operand::Field(
+                name.split('.').map(str::to_string).collect(),
+            )),
+            Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+            Some(token) => Err(ParseError(format!("unexpected {:?}", token))),
+            None => Err(ParseError("unexpected end of expression".to_string())),
+        }
+    }
+}
+
+impl Operand {
+    fn resolve<'a>(&'a self, item: &'a Value) -> &'a Value {
+        match self {
+            Operand::Literal(value) => value,
+            Operand::Field(path) => path
+                .iter()
+                .try_fold(item, |value, key| value.get(key))
+                .unwrap_or(&Value::Null),
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, item: &Value) -> bool {
+        match self {
+            Expr::And(terms) => terms.iter().all(|term| term.eval(item)),
+            Expr::Or(terms) => terms.iter().any(|term| term.eval(item)),
+            Expr::Not(expr) => !expr.eval(item),
+            Expr::Truthy(operand) => truthy(operand.resolve(item)),
+            Expr::Cmp(lhs, op, rhs) => {
+                let (lhs, rhs) = (lhs.resolve(item), rhs.resolve(item));
+                match op {
+                    CmpOp::Eq => lhs == rhs,
+                    CmpOp::Ne => lhs != rhs,
+                    _ => matches!(
+                        (compare(lhs, rhs), op),
+                        (Some(Ordering::Less), CmpOp::Lt | CmpOp::Le)
+                            | (Some(Ordering::Greater), CmpOp::Gt | CmpOp::Ge)
+                            | (Some(Ordering::Equal), CmpOp::Le | CmpOp::Ge)
+                    ),
+                }
+            }
+        }
+    }
+}
+
+fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn matches(filter: &str, item: &Value) -> bool {
+        filter.parse::<Filter>().unwrap().matches(item)
+    }
+
+    fn error(filter: &str) -> String {
+        filter.parse::<Filter>().unwrap_err().0
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let item = json!({"a": true, "b": false, "c": false});
+        assert!(matches("a || b && c", &item));
+        assert!(!matches("(a || b) && c", &item));
+        assert!(matches("c && b || a", &item));
+    }
+
+    #[test]
+    fn not_applies_to_the_next_term() {
+        let item = json!({"a": true, "b": false});
+        assert!(!matches("!a || b", &item));
+        assert!(matches("!(a && b)", &item));
+        assert!(matches("!!a", &item));
+    }
+
+    #[test]
+    fn compares_fields() {
+        let item = json!({"status": "Active", "icon": {"width": 22}, "title": ""});
+        assert!(matches(r#"status == "Active""#, &item));
+        assert!(matches("status != 'Passive'", &item));
+        assert!(matches("icon.width >= 22 && icon.width < 23", &item));
+        assert!(!matches("title", &item));
+        assert!(!matches("missing > 0", &item));
+        assert!(matches("missing == null", &item));
+    }
+
+    #[test]
+    fn rejects_invalid_filters() {
+        assert_eq!(error("a &"), "expected `&&`");
+        assert_eq!(error("a = 1"), "expected `==`");
+        assert_eq!(error("(a"), "expected `)`");
+        assert_eq!(error("a ==").as_str(), "unexpected end of expression");
+        assert_eq!(error("'a"), "unterminated string");
+        assert_eq!(error("a b"), r#"unexpected Ident("b")"#);
+        assert_eq!(error("a # b"), "unexpected character `#`");
+    }
+
+    #[test]
+    fn limits_the_nesting() {
+        let nested = format!("{}a", "!".repeat(MAX_DEPTH));
+        assert!(nested.parse::<Filter>().is_ok());
+        let deep = format!("{}a", "!".repeat(300_000));
+        assert!(error(&deep).starts_with("nested deeper"));
+        let parens = format!("{}a{}", "(".repeat(100), ")".repeat(100));
+        assert!(error(&parens).starts_with("nested deeper"));
+        // long chains are flat and need no nesting
+        let chain = vec!["a"; 100_000].join(" || ");
+        assert!(matches(&chain, &json!({"a": true})));
+    }
+
+    #[test]
+    fn globs_match_whole_ids() {
+        let pattern = "chrome_status_icon_*".parse::<Pattern>().unwrap();
+        assert!(pattern.matches("chrome_status_icon_1"));
+        assert!(pattern.matches("Chrome_Status_Icon_"));
+        assert!(!pattern.matches("x_chrome_status_icon_1"));
+        let pattern = "a?c*d".parse::<Pattern>().unwrap();
+        assert!(pattern.matches("abcd") && pattern.matches("abcxxd"));
+        assert!(!pattern.matches("acd") && !pattern.matches("abcde"));
+        let regex = "/^steam$/".parse::<Pattern>().unwrap();
+        assert!(regex.matches("steam") && !regex.matches("steam2"));
+        let regex = "/steam/".parse::<Pattern>().unwrap();
+        assert!(regex.matches("steam2") && regex.matches("x_steam"));
+        assert!("/(/".parse::<Pattern>().is_err());
+    }
+
+    #[test]
+    fn hide_rules() {
+        let rule = "Passive SystemServices".parse::<HideRule>().unwrap();
+        assert!(rule.matches(&json!({"status": "Passive", "category": "SystemServices"})));
+        assert!(!rule.matches(&json!({"status": "Passive", "category": "Hardware"})));
+        let rule = "Passive".parse::<HideRule>().unwrap();
+        assert!(rule.matches(&json!({"status": "Passive", "category": "Hardware"})));
+        assert!("Passive Active".parse::<HideRule>().is_err());
+        assert!("Sleepy".parse::<HideRule>().is_err());
+        assert!("".parse::<HideRule>().is_err());
+    }
+}
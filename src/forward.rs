@@ -0,0 +1,459 @@
+//! `--forward-to ADDRESS`: serves every tracked item again on another bus and
+//! registers it with the watcher there, for hosts that cannot reach the bus
+//! the items are on, like a Flatpak'd bar or one in a nested session. Calls to
+//! the copies and their menus go to the original items.
+//!
+//! Each copy has a connection and bus name of its own. It has the icon as a
+//! pixmap, or the path as `IconName` for what cannot be read as one, and
+//! registers again whenever a watcher appears on the other bus.
+
+use crate::icon;
+use crate::item::{timed, Item, Pixmap, StatusNotifierItemProxy};
+use crate::menu::{DBusMenuProxy, RawLayout};
+use crate::registry::Registry;
+use crate::rt;
+use crate::tray::TrayEvent;
+use crate::watcher::{self, WATCHER};
+use async_std::channel::{self, Receiver, Sender};
+use futures_util::future::{AbortHandle, Abortable};
+use futures_util::StreamExt;
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicU32, Ordering};
+use zbus::fdo::DBusProxy;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue};
+use zbus::{dbus_interface, Connection, ConnectionBuilder, SignalContext};
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+
+/// Numbers the bus names of the copies.
+static COPIES: AtomicU32 = AtomicU32::new(0);
+
+fn failed(e: zbus::Error) -> zbus::fdo::Error {
+    zbus::fdo::Error::Failed(e.to_string())
+}
+
+/// The icon is read again for each `IconPixmap` instead of keeping the pixels.
+struct ItemCopy {
+    item: Item,
+    proxy: StatusNotifierItemProxy<'static>,
+}
+
+/// The icon file as an ARGB32 pixmap, empty if it is no bitmap.
+fn pixmap(path: &str) -> Pixmap {
+    let Some(image) = icon::open(path) else {
+        return Vec::new();
+    };
+    let data = image
+        .pixels()
+        .flat_map(|pixel| [pixel[3], pixel[0], pixel[1], pixel[2]])
+        .collect();
+    vec![(image.width() as i32, image.height() as i32, data)]
+}
+
+#[dbus_interface(name = "org.kde.StatusNotifierItem")]
+impl ItemCopy {
+    #[dbus_interface(property)]
+    fn category(&self) -> String {
+        self.item.category.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn id(&self) -> String {
+        self.item.sni_id.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn title(&self) -> String {
+        self.item.title.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> String {
+        self.item.status.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn window_id(&self) -> i32 {
+        self.item.window_id.map_or(0, |id| id as i32)
+    }
+
+    #[dbus_interface(property)]
+    fn icon_name(&self) -> String {
+        // the size of bitmaps only is known
+        match self.item.icon.width == 0 {
+            true => self.item.icon.path.clone(),
+            false => String::new(),
+        }
+    }
+
+    #[dbus_interface(property)]
+    async fn icon_pixmap(&self) -> Pixmap {
+        let path = self.item.icon.path.clone();
+        rt::spawn_blocking(move || pixmap(&path)).await
+    }
+
+    #[dbus_interface(property)]
+    fn tool_tip(&self) -> (String, Pixmap, String, String) {
+        let tooltip = &self.item.tooltip;
+        let (title, description) = (tooltip.title.clone(), tooltip.description.clone());
+        (String::new(), Vec::new(), title, description)
+    }
+
+    #[dbus_interface(property)]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[dbus_interface(property)]
+    fn menu(&self) -> OwnedObjectPath {
+        let path = match self.item.menu_path {
+            Some(_) => MENU_PATH,
+            None => "/NO_DBUSMENU",
+        };
+        OwnedObjectPath::try_from(path).unwrap_or_default()
+    }
+
+    #[dbus_interface(property, name = "XAyatanaLabel")]
+    fn x_ayatana_label(&self) -> String {
+        self.item.label.clone()
+    }
+
+    #[dbus_interface(property, name = "XAyatanaLabelGuide")]
+    fn x_ayatana_label_guide(&self) -> String {
+        self.item.label_guide.clone()
+    }
+
+    async fn activate(&self, x: i32, y: i32) -> zbus::fdo::Result<()> {
+        timed("Activate", self.proxy.activate(x, y))
+            .await
+            .map_err(failed)
+    }
+
+    async fn secondary_activate(&self, x: i32, y: i32) -> zbus::fdo::Result<()> {
+        timed("SecondaryActivate", self.proxy.secondary_activate(x, y))
+            .await
+            .map_err(failed)
+    }
+
+    async fn provide_xdg_activation_token(&self, token: String) -> zbus::fdo::Result<()> {
+        let provided = self.proxy.provide_xdg_activation_token(token);
+        timed("ProvideXdgActivationToken", provided)
+            .await
+            .map_err(failed)
+    }
+
+    async fn context_menu(&self, x: i32, y: i32) -> zbus::fdo::Result<()> {
+        timed("ContextMenu", self.proxy.context_menu(x, y))
+            .await
+            .map_err(failed)
+    }
+
+    async fn scroll(&self, delta: i32, orientation: String) -> zbus::fdo::Result<()> {
+        timed("Scroll", self.proxy.scroll(&delta, orientation))
+            .await
+            .map_err(failed)
+    }
+
+    #[dbus_interface(signal)]
+    async fn new_title(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_icon(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_tool_tip(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_status(ctxt: &SignalContext<'_>, status: &str) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_menu(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal, name = "XAyatanaNewLabel")]
+    async fn x_ayatana_new_label(
+        ctxt: &SignalContext<'_>,
+        label: &str,
+        guide: &str,
+    ) -> zbus::Result<()>;
+}
+
+struct MenuCopy {
+    proxy: DBusMenuProxy<'static>,
+    /// Counts the layouts announced, the original ones are not followed
+    revision: u32,
+}
+
+#[dbus_interface(name = "com.canonical.dbusmenu")]
+impl MenuCopy {
+    async fn get_layout(
+        &self,
+        parent_id: i32,
+        recursion_depth: i32,
+        property_names: Vec<String>,
+    ) -> zbus::fdo::Result<(u32, RawLayout)> {
+        let names = property_names
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let layout = self.proxy.get_layout(parent_id, recursion_depth, &names);
+        let (_, layout) = timed("GetLayout", layout).await.map_err(failed)?;
+        Ok((self.revision, layout))
+    }
+
+    async fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        property_names: Vec<String>,
+    ) -> zbus::fdo::Result<Vec<(i32, HashMap<String, OwnedValue>)>> {
+        let names = property_names
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let properties = self.proxy.get_group_properties(&ids, &names);
+        timed("GetGroupProperties", properties)
+            .await
+            .map_err(failed)
+    }
+
+    async fn event(
+        &self,
+        id: i32,
+        event_id: String,
+        data: OwnedValue,
+        timestamp: u32,
+    ) -> zbus::fdo::Result<()> {
+        let event = self.proxy.event(id, &event_id, &data, timestamp);
+        timed("Event", event).await.map_err(failed)
+    }
+
+    async fn about_to_show(&self, id: i32) -> zbus::fdo::Result<bool> {
+        timed("AboutToShow", self.proxy.about_to_show(id))
+            .await
+            .map_err(failed)
+    }
+
+    async fn about_to_show_group(&self, ids: Vec<i32>) -> zbus::fdo::Result<(Vec<i32>, Vec<i32>)> {
+        timed("AboutToShowGroup", self.proxy.about_to_show_group(&ids))
+            .await
+            .map_err(failed)
+    }
+
+    #[dbus_interface(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[dbus_interface(property)]
+    fn text_direction(&self) -> String {
+        "ltr".to_string()
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> String {
+        "normal".to_string()
+    }
+
+    #[dbus_interface(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    #[dbus_interface(signal)]
+    async fn layout_updated(
+        ctxt: &SignalContext<'_>,
+        revision: u32,
+        parent: i32,
+    ) -> zbus::Result<()>;
+}
+
+enum Change {
+    Added(Item, StatusNotifierItemProxy<'static>),
+    Changed(Item),
+    Removed(String),
+}
+
+/// A copy being served, by `id` of the item.
+struct Copy {
+    conn: Connection,
+    /// Stops registering it again with new watchers
+    registering: AbortHandle,
+}
+
+impl Drop for Copy {
+    fn drop(&mut self) {
+        self.registering.abort();
+    }
+}
+
+pub struct Forwarder {
+    changes: Sender<Change>,
+}
+
+impl Forwarder {
+    /// Connects to the bus at `address`, which must not be the session bus
+    /// the items are on.
+    pub async fn start(address: &str) -> Result<Forwarder, Box<dyn Error>> {
+        let target = ConnectionBuilder::address(address)?.build().await?;
+        let session = Connection::session().await?;
+        let id = |conn: Connection| async move { DBusProxy::new(&conn).await?.get_id().await };
+        if id(target).await? == id(session).await? {
+            return Err(
+                format!("--forward-to {}: that is the bus the items are on", address).into(),
+            );
+        }
+        let (changes, received) = channel::unbounded();
+        rt::spawn(forward(address.to_string(), received));
+        Ok(Forwarder { changes })
+    }
+
+    /// Passes `event` on, with the items of `registry` after all changes
+    /// emitted together.
+    pub fn observe(&self, event: &TrayEvent, registry: &Registry) {
+        let change = match event {
+            TrayEvent::Added(item) | TrayEvent::Changed(item) => match registry.find(&item.id) {
+                Some(entry) if matches!(event, TrayEvent::Added(_)) => {
+                    Change::Added(entry.item.clone(), entry.proxy.clone())
+                }
+                Some(entry) => Change::Changed(entry.item.clone()),
+                None => return,
+            },
+            TrayEvent::Removed(id) => Change::Removed(id.clone()),
+            TrayEvent::Error { .. } => return,
+        };
+        let _ = self.changes.try_send(change);
+    }
+}
+
+async fn forward(address: String, changes: Receiver<Change>) {
+    let mut copies = HashMap::new();
+    while let Ok(change) = changes.recv().await {
+        let result = match change {
+            Change::Added(item, proxy) => {
+                let id = item.id.clone();
+                copies.remove(&id);
+                match serve(&address, item, proxy).await {
+                    Ok(copy) => {
+                        copies.insert(id, copy);
+                        Ok(())
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            Change::Changed(item) => match copies.get(&item.id) {
+                Some(copy) => update(&copy.conn, item).await,
+                None => Ok(()),
+            },
+            Change::Removed(id) => {
+                copies.remove(&id);
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            tracing::warn!(error = %e, "failed to forward an item");
+        }
+    }
+}
+
+/// Serves a copy of `item` on a new connection to `address`.
+async fn serve(
+    address: &str,
+    item: Item,
+    proxy: StatusNotifierItemProxy<'static>,
+) -> zbus::Result<Copy> {
+    let name = format!(
+        "org.kde.StatusNotifierItem-{}-{}",
+        std::process::id(),
+        COPIES.fetch_add(1, Ordering::Relaxed) + 1
+    );
+    let menu = item.menu_proxy(&proxy).await;
+    let mut builder = ConnectionBuilder::address(address)?
+        .name(name.as_str())?
+        .serve_at(ITEM_PATH, ItemCopy { item, proxy })?;
+    if let Some(proxy) = menu {
+        builder = builder.serve_at(MENU_PATH, MenuCopy { proxy, revision: 1 })?;
+    }
+    let conn = builder.build().await?;
+    let (registering, registration) = AbortHandle::new_pair();
+    rt::spawn(Abortable::new(register(conn.clone(), name), registration));
+    Ok(Copy { conn, registering })
+}
+
+/// Registers with the watcher, and again with every one that replaces it.
+async fn register(conn: Connection, name: String) {
+    let register = || async {
+        conn.call_method(
+            Some(WATCHER),
+            watcher::PATH,
+            Some(WATCHER),
+            "RegisterStatusNotifierItem",
+            &(name.as_str()),
+        )
+        .await
+    };
+    let Ok(dbus) = DBusProxy::new(&conn).await else {
+        return;
+    };
+    let Ok(mut owners) = dbus
+        .receive_name_owner_changed_with_args(&[(0, WATCHER)])
+        .await
+    else {
+        return;
+    };
+    if let Err(e) = register().await {
+        tracing::debug!(error = %e, "no watcher to register with yet");
+    }
+    while let Some(changed) = owners.next().await {
+        match changed.args() {
+            Ok(args) if args.new_owner().is_some() => {
+                if let Err(e) = register().await {
+                    tracing::warn!(error = %e, "failed to register with the new watcher");
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Updates the copy served on `conn` to `item`, emitting the signals for what
+/// changed.
+async fn update(conn: &Connection, item: Item) -> zbus::Result<()> {
+    let server = conn.object_server();
+    let iface = server.interface::<_, ItemCopy>(ITEM_PATH).await?;
+    let ctxt = iface.signal_context();
+    let mut copy = iface.get_mut().await;
+    let old = std::mem::replace(&mut copy.item, item);
+    let new = &copy.item;
+    if new.icon.path != old.icon.path {
+        ItemCopy::new_icon(ctxt).await?;
+    }
+    let new = &copy.item;
+    if new.title != old.title {
+        ItemCopy::new_title(ctxt).await?;
+    }
+    if new.status != old.status {
+        ItemCopy::new_status(ctxt, &new.status).await?;
+    }
+    if (&new.tooltip.title, &new.tooltip.description)
+        != (&old.tooltip.title, &old.tooltip.description)
+    {
+        ItemCopy::new_tool_tip(ctxt).await?;
+    }
+    if (&new.label, &new.label_guide) != (&old.label, &old.label_guide) {
+        ItemCopy::x_ayatana_new_label(ctxt, &new.label, &new.label_guide).await?;
+    }
+    let menu_changed = serde_json::to_value(&new.menu).ok() != serde_json::to_value(&old.menu).ok();
+    drop(copy);
+    if menu_changed {
+        if let Ok(menu) = server.interface::<_, MenuCopy>(MENU_PATH).await {
+            let revision = {
+                let mut menu = menu.get_mut().await;
+                menu.revision += 1;
+                menu.revision
+            };
+            MenuCopy::layout_updated(menu.signal_context(), revision, 0).await?;
+        }
+    }
+    Ok(())
+}
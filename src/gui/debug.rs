@@ -0,0 +1,269 @@
+//! Window showing the icon, title and status of every item of a running
+//! instance, redrawn on each update.
+
+use super::{Canvas, BACKGROUND, GLYPH_WIDTH, LINE_HEIGHT, PADDING, TEXT};
+use crate::control;
+use serde_json::Value;
+use smithay_client_toolkit::reexports::client::{
+    globals::registry_queue_init,
+    protocol::{wl_callback, wl_output, wl_shm, wl_surface},
+    Connection, Dispatch, QueueHandle,
+};
+use smithay_client_toolkit::{
+    compositor::{CompositorHandler, CompositorState},
+    delegate_compositor, delegate_output, delegate_registry, delegate_shm, delegate_xdg_shell,
+    delegate_xdg_window,
+    output::{OutputHandler, OutputState},
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+    shell::{
+        xdg::{
+            window::{Window, WindowConfigure, WindowDecorations, WindowHandler},
+            XdgShell,
+        },
+        WaylandSurface,
+    },
+    shm::{slot::SlotPool, Shm, ShmHandler},
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+
+const WIDTH: u32 = 480;
+const ICON_SIZE: u32 = LINE_HEIGHT - 8;
+const PASSIVE: u32 = 0xff80_8080;
+const ATTENTION: u32 = 0xffff_6060;
+
+/// Opens the window and keeps it updated until it is closed or the instance
+/// behind `control_socket` goes away.
+pub fn run(control_socket: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let conn = Connection::connect_to_env()?;
+    let (globals, mut queue) = registry_queue_init(&conn)?;
+    let qh = queue.handle();
+
+    let compositor = CompositorState::bind(&globals, &qh)?;
+    let xdg_shell = XdgShell::bind(&globals, &qh)?;
+    let shm = Shm::bind(&globals, &qh)?;
+
+    let surface = compositor.create_surface(&qh);
+    let window = xdg_shell.create_window(surface, WindowDecorations::RequestServer, &qh);
+    window.set_title("trayson");
+    window.set_app_id("trayson");
+    window.commit();
+
+    // `None` once the subscription ended
+    let (updates, receiver) = mpsc::channel();
+    {
+        let (conn, qh) = (conn.clone(), qh.clone());
+        std::thread::spawn(move || {
+            // a display roundtrip wakes up the event queue for each update
+            let wake = |update| {
+                let _ = updates.send(update);
+                conn.display().sync(&qh, ());
+                let _ = conn.flush();
+            };
+            let _ = async_std::task::block_on(control::subscribe(control_socket, |items| {
+                wake(Some(items))
+            }));
+            wake(None);
+        });
+    }
+
+    let mut view = DebugView {
+        registry: RegistryState::new(&globals),
+        outputs: OutputState::new(&globals, &qh),
+        pool: SlotPool::new((WIDTH * LINE_HEIGHT * 4) as usize, &shm)?,
+        shm,
+        window,
+        updates: receiver,
+        items: Vec::new(),
+        icons: HashMap::new(),
+        size: None,
+        configured: false,
+        done: false,
+    };
+    while !view.done {
+        queue.blocking_dispatch(&mut view)?;
+    }
+    Ok(())
+}
+
+struct DebugView {
+    registry: RegistryState,
+    outputs: OutputState,
+    shm: Shm,
+    pool: SlotPool,
+    window: Window,
+    updates: Receiver<Option<Vec<Value>>>,
+    items: Vec<Value>,
+    /// Decoded and scaled icons by path
+    icons: HashMap<String, Option<image::RgbaImage>>,
+    /// Size chosen by the compositor, if any
+    size: Option<(u32, u32)>,
+    configured: bool,
+    done: bool,
+}
+
+impl DebugView {
+    fn draw(&mut self) {
+        let (width, height) = self
+            .size
+            .unwrap_or((WIDTH, LINE_HEIGHT * (self.items.len() as u32).max(1)));
+        let Ok((buffer, data)) = self.pool.create_buffer(
+            width as i32,
+            height as i32,
+            width as i32 * 4,
+            wl_shm::Format::Argb8888,
+        ) else {
+            self.done = true;
+            return;
+        };
+        let mut canvas = Canvas {
+            data,
+            width,
+            height,
+        };
+        canvas.fill(0, 0, width, height, BACKGROUND);
+        if self.items.is_empty() {
+            canvas.text(PADDING, PADDING, "no items", PASSIVE);
+        }
+        for (row, item) in self.items.iter().enumerate() {
+            let top = row as u32 * LINE_HEIGHT;
+            let path = item["icon"]["path"].as_str().unwrap_or_default();
+            let icon = self.icons.entry(path.to_string()).or_insert_with(|| {
+                let icon = crate::icon::open(path)?;
+                Some(image::imageops::resize(
+                    &icon,
+                    ICON_SIZE,
+                    ICON_SIZE,
+                    image::imageops::FilterType::Triangle,
+                ))
+            });
+            if let Some(icon) = icon {
+                canvas.image(PADDING, top + 4, icon);
+            }
+            let status = item["status"].as_str().unwrap_or_default();
+            let color = match status {
+                "Passive" => PASSIVE,
+                "NeedsAttention" => ATTENTION,
+                _ => TEXT,
+            };
+            let left = 2 * PADDING + ICON_SIZE;
+            let status = format!("{:<15}", status);
+            canvas.text(left, top + PADDING, &status, color);
+            let title = format!(
+                "{} ({})",
+                item["title"].as_str().unwrap_or_default(),
+                item["id"].as_str().unwrap_or_default()
+            );
+            let left = left + (status.len() as u32 + 1) * GLYPH_WIDTH;
+            canvas.text(left, top + PADDING, &title, TEXT);
+        }
+
+        let surface = self.window.wl_surface();
+        surface.damage_buffer(0, 0, width as i32, height as i32);
+        if buffer.attach_to(surface).is_err() {
+            self.done = true;
+            return;
+        }
+        self.window.commit();
+    }
+}
+
+impl Dispatch<wl_callback::WlCallback, ()> for DebugView {
+    fn event(
+        view: &mut Self,
+        _: &wl_callback::WlCallback,
+        _: wl_callback::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        while let Ok(update) = view.updates.try_recv() {
+            match update {
+                Some(items) => view.items = items,
+                None => view.done = true,
+            }
+        }
+        if view.configured {
+            view.draw();
+        }
+    }
+}
+
+impl CompositorHandler for DebugView {
+    fn scale_factor_changed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: i32,
+    ) {
+    }
+
+    fn transform_changed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: wl_output::Transform,
+    ) {
+    }
+
+    fn frame(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &wl_surface::WlSurface, _: u32) {}
+}
+
+impl OutputHandler for DebugView {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.outputs
+    }
+
+    fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn update_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn output_destroyed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+}
+
+impl WindowHandler for DebugView {
+    fn request_close(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &Window) {
+        self.done = true;
+    }
+
+    fn configure(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &Window,
+        configure: WindowConfigure,
+        _: u32,
+    ) {
+        self.size = match configure.new_size {
+            (Some(width), Some(height)) => Some((width.get(), height.get())),
+            _ => None,
+        };
+        self.configured = true;
+        self.draw();
+    }
+}
+
+impl ShmHandler for DebugView {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+impl ProvidesRegistryState for DebugView {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry
+    }
+    registry_handlers![OutputState];
+}
+
+delegate_compositor!(DebugView);
+delegate_output!(DebugView);
+delegate_shm!(DebugView);
+delegate_xdg_shell!(DebugView);
+delegate_xdg_window!(DebugView);
+delegate_registry!(DebugView);
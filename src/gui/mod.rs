@@ -0,0 +1,86 @@
+//! Small Wayland windows drawn in software (feature `gui`).
+//!
+//! - [`popup::show`] the menu of an item on a wlr-layer-shell surface
+//! - [`debug::run`] a window listing all items live, for `debug-view`
+//!
+//! Text uses a built-in 8x8 bitmap font, so no fonts or system libraries are needed.
+
+pub mod debug;
+pub mod popup;
+
+use font8x8::UnicodeFonts;
+
+/// Glyphs are 8x8, drawn at this scale.
+const SCALE: u32 = 2;
+const PADDING: u32 = 6;
+const LINE_HEIGHT: u32 = 8 * SCALE + 2 * PADDING;
+const GLYPH_WIDTH: u32 = 8 * SCALE;
+
+const BACKGROUND: u32 = 0xff20_2020;
+const HOVER: u32 = 0xff3d_4a5c;
+const TEXT: u32 = 0xffe0_e0e0;
+
+/// An ARGB8888 shm buffer being drawn into.
+struct Canvas<'a> {
+    data: &'a mut [u8],
+    width: u32,
+    height: u32,
+}
+
+impl Canvas<'_> {
+    fn put(&mut self, x: u32, y: u32, color: u32) {
+        if x < self.width && y < self.height {
+            let i = ((y * self.width + x) * 4) as usize;
+            self.data[i..i + 4].copy_from_slice(&color.to_le_bytes());
+        }
+    }
+
+    fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, color: u32) {
+        for y in y..y + height {
+            for x in x..x + width {
+                self.put(x, y, color);
+            }
+        }
+    }
+
+    /// Draws `text` with its top left corner at `(x, y)`.
+    fn text(&mut self, x: u32, y: u32, text: &str, color: u32) {
+        for (column, c) in text.chars().enumerate() {
+            let glyph = font8x8::BASIC_FONTS.get(c).unwrap_or([0; 8]);
+            let left = x + column as u32 * GLYPH_WIDTH;
+            for (row, bits) in glyph.iter().enumerate() {
+                for bit in 0..8 {
+                    if bits & (1 << bit) != 0 {
+                        self.fill(
+                            left + bit * SCALE,
+                            y + row as u32 * SCALE,
+                            SCALE,
+                            SCALE,
+                            color,
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Blends an RGBA image onto the canvas.
+    fn image(&mut self, x: u32, y: u32, image: &image::RgbaImage) {
+        for (dx, dy, pixel) in image.enumerate_pixels() {
+            let [r, g, b, a] = pixel.0;
+            if a == 0 {
+                continue;
+            }
+            let (a, i) = (a as u32, (((y + dy) * self.width + x + dx) * 4) as usize);
+            if x + dx >= self.width || y + dy >= self.height {
+                continue;
+            }
+            let under = u32::from_le_bytes(self.data[i..i + 4].try_into().unwrap());
+            let mix = |over: u8, shift: u32| {
+                (over as u32 * a + ((under >> shift) & 0xff) * (255 - a)) / 255
+            };
+            let color = 0xff00_0000 | mix(r, 16) << 16 | mix(g, 8) << 8 | mix(b, 0);
+            self.put(x + dx, y + dy, color);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+//! Menu popup on a wlr-layer-shell surface.
+//!
+//! Clicking an entry selects it, moving the pointer out of the popup
+//! dismisses it.
+
+use super::{Canvas, BACKGROUND, GLYPH_WIDTH, HOVER, LINE_HEIGHT, PADDING, TEXT};
+use smithay_client_toolkit::reexports::client::{
+    globals::registry_queue_init,
+    protocol::{wl_output, wl_pointer, wl_seat, wl_shm, wl_surface},
+    Connection, QueueHandle,
+};
+use smithay_client_toolkit::{
+    compositor::{CompositorHandler, CompositorState},
+    delegate_compositor, delegate_layer, delegate_output, delegate_pointer, delegate_registry,
+    delegate_seat, delegate_shm,
+    output::{OutputHandler, OutputState},
+    registry::{ProvidesRegistryState, RegistryState},
+    registry_handlers,
+    seat::{
+        pointer::{PointerEvent, PointerEventKind, PointerHandler, BTN_LEFT},
+        Capability, SeatHandler, SeatState,
+    },
+    shell::{
+        wlr_layer::{
+            Anchor, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
+            LayerSurfaceConfigure,
+        },
+        WaylandSurface,
+    },
+    shm::{slot::SlotPool, Shm, ShmHandler},
+};
+use std::error::Error;
+
+/// Shows `entries` with the top left corner at `(x, y)` and returns the id of
+/// the clicked entry, `None` if the popup was dismissed.
+pub fn show(entries: Vec<(i64, String)>, x: i32, y: i32) -> Result<Option<i64>, Box<dyn Error>> {
+    if entries.is_empty() {
+        return Ok(None);
+    }
+    let conn = Connection::connect_to_env()?;
+    let (globals, mut queue) = registry_queue_init(&conn)?;
+    let qh = queue.handle();
+
+    let compositor = CompositorState::bind(&globals, &qh)?;
+    let layer_shell = LayerShell::bind(&globals, &qh)?;
+    let shm = Shm::bind(&globals, &qh)?;
+
+    let columns = entries
+        .iter()
+        .map(|(_, label)| label.chars().count())
+        .max()
+        .unwrap_or(0) as u32;
+    let width = columns * GLYPH_WIDTH + 2 * PADDING;
+    let height = entries.len() as u32 * LINE_HEIGHT;
+
+    let surface = compositor.create_surface(&qh);
+    let layer =
+        layer_shell.create_layer_surface(&qh, surface, Layer::Overlay, Some("trayson"), None);
+    layer.set_anchor(Anchor::TOP | Anchor::LEFT);
+    layer.set_margin(y.max(0), 0, 0, x.max(0));
+    layer.set_keyboard_interactivity(KeyboardInteractivity::None);
+    layer.set_size(width, height);
+    layer.commit();
+
+    let mut popup = Popup {
+        registry: RegistryState::new(&globals),
+        seats: SeatState::new(&globals, &qh),
+        outputs: OutputState::new(&globals, &qh),
+        pool: SlotPool::new((width * height * 4) as usize, &shm)?,
+        shm,
+        layer,
+        pointer: None,
+        entries,
+        width,
+        height,
+        configured: false,
+        hover: None,
+        entered: false,
+        done: false,
+        selected: None,
+    };
+    while !popup.done {
+        queue.blocking_dispatch(&mut popup)?;
+    }
+    Ok(popup.selected)
+}
+
+struct Popup {
+    registry: RegistryState,
+    seats: SeatState,
+    outputs: OutputState,
+    shm: Shm,
+    pool: SlotPool,
+    layer: LayerSurface,
+    pointer: Option<wl_pointer::WlPointer>,
+    entries: Vec<(i64, String)>,
+    width: u32,
+    height: u32,
+    configured: bool,
+    hover: Option<usize>,
+    /// The pointer was over the popup once, so leaving it dismisses the popup
+    entered: bool,
+    done: bool,
+    selected: Option<i64>,
+}
+
+impl Popup {
+    fn draw(&mut self) {
+        let (width, height) = (self.width, self.height);
+        let Ok((buffer, canvas)) = self.pool.create_buffer(
+            width as i32,
+            height as i32,
+            width as i32 * 4,
+            wl_shm::Format::Argb8888,
+        ) else {
+            self.done = true;
+            return;
+        };
+
+        let mut canvas = Canvas {
+            data: canvas,
+            width,
+            height,
+        };
+        canvas.fill(0, 0, width, height, BACKGROUND);
+        for (line, (_, label)) in self.entries.iter().enumerate() {
+            let top = line as u32 * LINE_HEIGHT;
+            if self.hover == Some(line) {
+                canvas.fill(0, top, width, LINE_HEIGHT, HOVER);
+            }
+            canvas.text(PADDING, top + PADDING, label, TEXT);
+        }
+
+        let surface = self.layer.wl_surface();
+        surface.damage_buffer(0, 0, width as i32, height as i32);
+        if buffer.attach_to(surface).is_err() {
+            self.done = true;
+            return;
+        }
+        self.layer.commit();
+    }
+}
+
+impl CompositorHandler for Popup {
+    fn scale_factor_changed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: i32,
+    ) {
+    }
+
+    fn transform_changed(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_surface::WlSurface,
+        _: wl_output::Transform,
+    ) {
+    }
+
+    fn frame(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &wl_surface::WlSurface, _: u32) {}
+}
+
+impl OutputHandler for Popup {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.outputs
+    }
+
+    fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn update_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn output_destroyed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+}
+
+impl LayerShellHandler for Popup {
+    fn closed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &LayerSurface) {
+        self.done = true;
+    }
+
+    fn configure(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _: u32,
+    ) {
+        if configure.new_size.0 != 0 && configure.new_size.1 != 0 {
+            (self.width, self.height) = configure.new_size;
+        }
+        self.configured = true;
+        self.draw();
+    }
+}
+
+impl SeatHandler for Popup {
+    fn seat_state(&mut self) -> &mut SeatState {
+        &mut self.seats
+    }
+
+    fn new_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+
+    fn new_capability(
+        &mut self,
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+        seat: wl_seat::WlSeat,
+        capability: Capability,
+    ) {
+        if capability == Capability::Pointer && self.pointer.is_none() {
+            self.pointer = self.seats.get_pointer(qh, &seat).ok();
+        }
+    }
+
+    fn remove_capability(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: wl_seat::WlSeat,
+        capability: Capability,
+    ) {
+        if capability == Capability::Pointer {
+            if let Some(pointer) = self.pointer.take() {
+                pointer.release();
+            }
+        }
+    }
+
+    fn remove_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+}
+
+impl PointerHandler for Popup {
+    fn pointer_frame(
+        &mut self,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+        _: &wl_pointer::WlPointer,
+        events: &[PointerEvent],
+    ) {
+        for event in events {
+            if &event.surface != self.layer.wl_surface() {
+                continue;
+            }
+            let line = (event.position.1.max(0.0) as u32 / LINE_HEIGHT) as usize;
+            let line = (line < self.entries.len()).then_some(line);
+            match event.kind {
+                PointerEventKind::Enter { .. } | PointerEventKind::Motion { .. } => {
+                    self.entered = true;
+                    if self.hover != line {
+                        self.hover = line;
+                        if self.configured {
+                            self.draw();
+                        }
+                    }
+                }
+                PointerEventKind::Leave { .. } if self.entered => self.done = true,
+                PointerEventKind::Release { button, .. } => {
+                    if button == BTN_LEFT {
+                        self.selected = line.map(|line| self.entries[line].0);
+                    }
+                    self.done = true;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl ShmHandler for Popup {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+impl ProvidesRegistryState for Popup {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry
+    }
+    registry_handlers![OutputState, SeatState];
+}
+
+delegate_compositor!(Popup);
+delegate_output!(Popup);
+delegate_shm!(Popup);
+delegate_seat!(Popup);
+delegate_pointer!(Popup);
+delegate_layer!(Popup);
+delegate_registry!(Popup);
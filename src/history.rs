@@ -0,0 +1,95 @@
+//! `--history` appends every item that comes, changes or goes away to a JSON
+//! lines file, with the time in RFC 3339, to find out afterwards which
+//! application kept the tray busy or what was in it before a bar crashed:
+//!
+//! ```text
+//! {"event":"added","id":"nm-applet","item":{...},"ts":"2024-05-01T02:14:00.120Z"}
+//! {"event":"changed","id":"nm-applet","item":{...},"ts":"2024-05-01T02:14:03.541Z"}
+//! {"event":"removed","id":"nm-applet","ts":"2024-05-01T02:20:09.002Z"}
+//! ```
+//!
+//! Items are logged as they are, before filters and overrides. Once the file
+//! has grown past `--history-max-size` it is renamed to `FILE.1`, the old
+//! `FILE.1` to `FILE.2` and so on, keeping the last [`ROTATED`] of them.
+
+use crate::stamp;
+use crate::tray::TrayEvent;
+use serde_json::json;
+use std::fs::{File, OpenOptions};
+use std::io::{self, LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// How many rotated files are kept next to the current one.
+pub const ROTATED: u32 = 3;
+
+pub struct History {
+    path: PathBuf,
+    file: LineWriter<File>,
+    /// Bytes in the current file
+    size: u64,
+    max_size: u64,
+}
+
+fn append(path: &Path) -> io::Result<LineWriter<File>> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(LineWriter::new(file))
+}
+
+impl History {
+    /// Appends to `path`, rotating it once it has `max_size` bytes.
+    pub fn open(path: &Path, max_size: u64) -> io::Result<History> {
+        let file = append(path)?;
+        Ok(History {
+            size: file.get_ref().metadata()?.len(),
+            path: path.to_path_buf(),
+            file,
+            max_size,
+        })
+    }
+
+    pub fn observe(&mut self, event: &TrayEvent) {
+        let ts = stamp::rfc3339(SystemTime::now());
+        let line = match event {
+            TrayEvent::Added(item) => {
+                json!({"event": "added", "id": item.id, "item": item, "ts": ts})
+            }
+            TrayEvent::Changed(item) => {
+                json!({"event": "changed", "id": item.id, "item": item, "ts": ts})
+            }
+            TrayEvent::Removed(id) => json!({"event": "removed", "id": id, "ts": ts}),
+            TrayEvent::Error { .. } => return,
+        };
+        if let Err(e) = self.write(&line.to_string()) {
+            tracing::error!(error = %e, "failed to write the history");
+        }
+    }
+
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 >= self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let rotated = |n: u32| {
+            let mut path = self.path.clone().into_os_string();
+            path.push(format!(".{}", n));
+            PathBuf::from(path)
+        };
+        for n in (1..ROTATED).rev() {
+            match std::fs::rename(rotated(n), rotated(n + 1)) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+        std::fs::rename(&self.path, rotated(1))?;
+        self.file = append(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,91 @@
+//! `--on-item-added`, `--on-item-removed` and `--on-attention`: command lines
+//! run with `sh -c` when an item appears, goes away or changes to
+//! `NeedsAttention`. They get the item as in the output on stdin and in
+//! `$TRAYSON_ITEM`, and its `id` in `$TRAYSON_ID`:
+//!
+//! ```text
+//! trayson run --on-attention 'paplay /usr/share/sounds/freedesktop/stereo/message.oga'
+//! ```
+//!
+//! Items hidden by the filters run none of them.
+
+use crate::args::RunArgs;
+use crate::notify::Attention;
+use crate::registry::Registry;
+use crate::rt;
+use crate::tray::TrayEvent;
+use async_process::{Command, Stdio};
+use async_std::io::WriteExt;
+use serde_json::Value;
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Hooks {
+    attention: Attention,
+    /// The items as last emitted, for `--on-item-removed`
+    items: HashMap<String, Value>,
+}
+
+impl Hooks {
+    /// Runs the hooks `event` calls for, with the items of `registry` after
+    /// all changes emitted together.
+    pub fn observe(&mut self, event: &TrayEvent, registry: &Registry, settings: &RunArgs) {
+        let began = self.attention.began(event, registry).is_some();
+        let id = match event {
+            TrayEvent::Added(item) | TrayEvent::Changed(item) => &item.id,
+            TrayEvent::Removed(id) => {
+                if let (Some(item), Some(hook)) = (self.items.remove(id), &settings.on_item_removed)
+                {
+                    run(hook, id, item);
+                }
+                return;
+            }
+            TrayEvent::Error { .. } => return,
+        };
+        let shown = registry
+            .find(id)
+            .and_then(|entry| serde_json::to_value(&entry.item).ok())
+            .and_then(|item| crate::cli::render(vec![item], settings, None).pop());
+        let Some(item) = shown else {
+            self.items.remove(id);
+            return;
+        };
+        if let (TrayEvent::Added(_), Some(hook)) = (event, &settings.on_item_added) {
+            run(hook, id, item.clone());
+        }
+        if let (true, Some(hook)) = (began, &settings.on_attention) {
+            run(hook, id, item.clone());
+        }
+        self.items.insert(id.clone(), item);
+    }
+}
+
+fn run(hook: &str, id: &str, item: Value) {
+    let item = item.to_string();
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(hook)
+        .env("TRAYSON_ID", id)
+        .env("TRAYSON_ITEM", &item)
+        .stdin(Stdio::piped());
+    let (hook, id) = (hook.to_string(), id.to_string());
+    rt::spawn(async move {
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                tracing::warn!(hook, error = %e, "failed to run");
+                return;
+            }
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            // the hook does not have to read it
+            let _ = stdin.write_all(item.as_bytes()).await;
+        }
+        match child.status().await {
+            Ok(status) if !status.success() => tracing::warn!(hook, item = id, %status, "failed"),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(hook, error = %e, "failed to run"),
+        }
+    });
+}
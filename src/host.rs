@@ -0,0 +1,824 @@
+//! The StatusNotifierHost side shared by `trayson run` and [`crate::Tray`]:
+//! owning the names on the session bus, following every item in a task of its
+//! own and sending what changed as [`Update`]s.
+
+use crate::args::Mode;
+use crate::error;
+use crate::icon;
+use crate::interface::{self, TrayInterface};
+use crate::item::{self, Item, StatusNotifierItemProxy};
+use crate::menu::{self, DBusMenuProxy};
+use crate::metrics::METRICS;
+use crate::record;
+use crate::registry::Registry;
+use crate::rt;
+use crate::systemd;
+use crate::tray::TrayEvent;
+use crate::watcher::{self, StatusNotifierWatcherProxy, FREEDESKTOP_WATCHER, WATCHER};
+use async_std::channel::{self, Receiver, Sender};
+use async_std::task;
+use futures_util::future::{self, AbortHandle, Abortable, Either};
+use futures_util::{stream, try_join, FutureExt, Stream};
+use serde_json::Value;
+use std::collections::hash_map::{Entry, HashMap};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tracing::Instrument;
+use zbus::fdo::{DBusProxy, RequestNameFlags, RequestNameReply};
+use zbus::{
+    dbus_interface, export::futures_util::StreamExt, Connection, ConnectionBuilder, MessageStream,
+};
+
+struct StatusNotifierHost {}
+#[dbus_interface(name = "org.kde.StatusNotifierHost")]
+impl StatusNotifierHost {}
+
+/// Pause before connecting again after the session bus went away.
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Attempts to read a new item, which may register before exporting its object,
+/// waiting twice as long as before after each failed one.
+const FETCH_RETRIES: u32 = 5;
+const FETCH_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Seconds between two pings of an item, 0 for none.
+static PING_INTERVAL: AtomicU64 = AtomicU64::new(30);
+/// Seconds an item may stay `stale` before it is dropped.
+static STALE_GRACE: AtomicU64 = AtomicU64::new(120);
+
+/// `--ping-interval` and `--stale-grace`, for the items followed from now on.
+pub fn set_health(interval: Duration, grace: Duration) {
+    PING_INTERVAL.store(interval.as_secs(), Ordering::Relaxed);
+    STALE_GRACE.store(grace.as_secs(), Ordering::Relaxed);
+}
+
+/// Icons read of an item a second, for apps emitting `NewIcon` many times a
+/// second; 0 for any number.
+static ICON_RATE: AtomicU32 = AtomicU32::new(4);
+
+/// `--icon-rate`, for the items followed from now on.
+pub fn set_icon_rate(icons: u32) {
+    ICON_RATE.store(icons, Ordering::Relaxed);
+}
+
+/// Least time between two icons of an item, see [`ICON_RATE`].
+fn icon_interval() -> Duration {
+    match ICON_RATE.load(Ordering::Relaxed) {
+        0 => Duration::ZERO,
+        icons => Duration::from_secs(1) / icons,
+    }
+}
+
+/// Items read at once, 0 for any number.
+static INIT_CONCURRENCY: AtomicUsize = AtomicUsize::new(4);
+
+/// `--init-concurrency`, for the sessions started from now on.
+pub fn set_init_concurrency(items: usize) {
+    INIT_CONCURRENCY.store(items, Ordering::Relaxed);
+}
+
+/// Lets a number of tasks at a time in, the others wait for their turn.
+struct Limit(Option<(Sender<()>, Receiver<()>)>);
+
+/// A turn in a [`Limit`], over once dropped.
+struct Turn<'a>(Option<&'a Receiver<()>>);
+
+impl Limit {
+    /// Up to `tasks` at a time, any number for 0.
+    fn new(tasks: usize) -> Limit {
+        Limit((tasks > 0).then(|| channel::bounded(tasks)))
+    }
+
+    async fn turn(&self) -> Turn<'_> {
+        match &self.0 {
+            Some((taken, returned)) => {
+                // the channel holds one message per turn taken
+                let _ = taken.send(()).await;
+                Turn(Some(returned))
+            }
+            None => Turn(None),
+        }
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        if let Some(returned) = self.0 {
+            let _ = returned.try_recv();
+        }
+    }
+}
+
+/// A [`Refresh::Ping`] every `interval`.
+fn pings(interval: Duration) -> impl Stream<Item = Refresh> {
+    stream::unfold((), move |()| async move {
+        task::sleep(interval).await;
+        Some((Refresh::Ping, ()))
+    })
+}
+
+/// Bus name of our host, unique so several instances can run side by side.
+pub fn host_name() -> String {
+    format!("org.kde.StatusNotifierHost-trayson-{}", std::process::id())
+}
+
+/// Registers the host with whoever currently owns [`WATCHER`].
+async fn register_host(conn: &Connection) -> zbus::Result<()> {
+    conn.call_method(
+        Some(WATCHER),
+        watcher::PATH,
+        Some(WATCHER),
+        "RegisterStatusNotifierHost",
+        &(host_name()),
+    )
+    .await?;
+    Ok(())
+}
+
+/// Changes to the tracked items, applied in order by the output loop.
+#[allow(clippy::large_enum_variant)]
+pub enum Update {
+    /// `None` once the item went away, else with the icons saved for it held
+    /// until it is in the registry
+    Item(
+        String,
+        Option<(Item, StatusNotifierItemProxy<'static>, icon::Hold)>,
+    ),
+    /// Handle to cancel the tasks following the item of the service
+    Tasks(String, AbortHandle),
+    /// Reports what went wrong with the item of the service
+    Error(String, error::Error),
+    /// Drops the first item, which stopped answering, for the second with the same Id
+    Collapse(String, String),
+    /// The host is registered, sent once per session before its items
+    Ready,
+    /// Drops all items once the bus is gone, they are enumerated again on reconnecting
+    Reset,
+    /// Applies the settings again and emits the current state
+    Reload,
+    /// Emits the current state and prints the internal one to stderr
+    Dump,
+    /// Emits an empty state one last time and stops the output loop
+    Shutdown,
+    /// What the `--script` made of the items sent to it last, to emit
+    Scripted(Vec<Value>),
+}
+
+/// Applies `update` to `registry`, returning what changed for users of
+/// [`crate::Tray`]. Settings and output are up to the caller.
+pub fn apply_update(
+    registry: &mut Registry,
+    update: Update,
+    updates: &Sender<Update>,
+) -> Vec<TrayEvent> {
+    let id = |registry: &Registry, service: &str| {
+        registry.find(service).map(|entry| entry.item.id.clone())
+    };
+    let mut events = Vec::new();
+    match update {
+        Update::Item(service, Some((item, proxy, _icons))) => {
+            let previous = id(registry, &service);
+            let new = previous.is_none();
+            registry.insert(service.clone(), item, proxy);
+            if let Some(entry) = registry.find(&service) {
+                record::item(&service, &entry.item);
+                let item = entry.item.clone();
+                match previous {
+                    Some(previous) if previous == item.id => events.push(TrayEvent::Changed(item)),
+                    // its id changed, e.g. once it answered with its SNI `Id`
+                    Some(previous) => {
+                        events.extend([TrayEvent::Removed(previous), TrayEvent::Added(item)])
+                    }
+                    None => events.push(TrayEvent::Added(item)),
+                }
+            }
+            // apps re-registering without unregistering leave stale duplicates
+            let duplicates = match new {
+                true => registry.duplicates(&service),
+                false => Vec::new(),
+            };
+            for (stale, proxy) in duplicates {
+                let (updates, live) = (updates.clone(), service.clone());
+                rt::spawn(async move {
+                    if !item::responds(&proxy).await {
+                        let _ = updates.send(Update::Collapse(stale, live)).await;
+                    }
+                });
+            }
+        }
+        Update::Item(service, None) => {
+            events.extend(id(registry, &service).map(TrayEvent::Removed));
+            registry.remove(&service);
+            record::removed(&service);
+        }
+        Update::Tasks(service, abort) => registry.track(service, abort),
+        Update::Error(service, e) => {
+            registry.failed(&service, e.to_string());
+            events.push(TrayEvent::Error {
+                item: id(registry, &service).unwrap_or(service),
+                reason: e.to_string(),
+            })
+        }
+        Update::Collapse(stale, live) => {
+            // the live item takes over the id of the stale one
+            events.extend(id(registry, &live).map(TrayEvent::Removed));
+            registry.collapse(&stale, &live);
+            record::removed(&stale);
+            if let Some(entry) = registry.find(&live) {
+                record::item(&live, &entry.item);
+                events.push(TrayEvent::Changed(entry.item.clone()));
+            }
+        }
+        Update::Reset | Update::Shutdown => {
+            events.extend(
+                registry
+                    .items()
+                    .map(|item| TrayEvent::Removed(item.id.clone())),
+            );
+            registry.clear();
+            record::reset();
+        }
+        Update::Ready | Update::Reload | Update::Dump | Update::Scripted(_) => {}
+    }
+    events
+}
+
+/// Signals after which an item is sent again.
+enum Refresh {
+    Status(String),
+    Label(String, String),
+    Title,
+    ToolTip,
+    Icon,
+    AttentionIcon,
+    OverlayIcon,
+    Menu,
+    /// Time for the next ping of `--ping-interval`
+    Ping,
+    /// Everything, as for an item that registered again
+    All,
+}
+
+impl Refresh {
+    /// The arguments of the signal, for [`record::signal`].
+    fn args(&self) -> Value {
+        match self {
+            Refresh::Status(status) => serde_json::json!([status]),
+            Refresh::Label(label, guide) => serde_json::json!([label, guide]),
+            _ => serde_json::json!([]),
+        }
+    }
+}
+
+/// Passes on the first of `signals` right away and then at most one per
+/// `interval`, dropping those arriving in between but the latest.
+fn throttle<T>(
+    signals: impl Stream<Item = T> + Unpin,
+    interval: Duration,
+) -> impl Stream<Item = T> {
+    stream::unfold(
+        (signals, None::<Instant>),
+        move |(mut signals, last)| async move {
+            let mut signal = signals.next().await?;
+            if let Some(last) = last {
+                task::sleep(interval.saturating_sub(last.elapsed())).await;
+            }
+            while let Some(Some(newer)) = signals.next().now_or_never() {
+                signal = newer;
+            }
+            Some((signal, (signals, Some(Instant::now()))))
+        },
+    )
+}
+
+/// Applies `refresh` to `item`, false if that changed nothing.
+async fn apply(
+    item: &mut Item,
+    proxy: &StatusNotifierItemProxy<'_>,
+    menu: Option<&DBusMenuProxy<'_>>,
+    refresh: Refresh,
+) -> Result<bool, error::Error> {
+    match refresh {
+        Refresh::Status(status) => item.set_status(status),
+        Refresh::Label(label, guide) => {
+            item.label = label;
+            item.label_guide = guide;
+        }
+        Refresh::Title => {
+            let title = item::optional(item::timed("Title", proxy.title()).await)?;
+            item.title = title.unwrap_or_default();
+        }
+        Refresh::ToolTip => item.tooltip = item::timed("ToolTip", proxy.tool_tip()).await?.into(),
+        Refresh::Icon => {
+            let pixmaps = item::optional(item::timed("IconPixmap", proxy.icon_pixmap()).await)?;
+            let pixmaps = pixmaps.unwrap_or_default();
+            let name = item::optional(item::timed("IconName", proxy.icon_name()).await)?;
+            let name = name.unwrap_or_default();
+            let unchanged = match icon::usable_pixmap(&pixmaps) {
+                Some(pixmap) => icon::pixmap_hash(pixmap) == item.pixmap_hash,
+                None => item.pixmap_hash == 0 && name == item.icon_name,
+            };
+            if unchanged {
+                return Ok(false);
+            }
+            let theme_path = item::timed("IconThemePath", proxy.icon_theme_path()).await;
+            let app_icon = item.app.as_ref().and_then(|app| app.icon.as_deref());
+            (item.icon, item.pixmap_hash) = icon::resolve(
+                &pixmaps,
+                &name,
+                &theme_path.unwrap_or_default(),
+                app_icon,
+                &item.sni_id,
+                &item.title,
+            )
+            .await?;
+            item.icon_name = name;
+        }
+        Refresh::AttentionIcon => item.attention_icon = item::attention_icon(proxy).await?,
+        Refresh::OverlayIcon => item.overlay_icon = item::overlay_icon(proxy).await?,
+        Refresh::Ping => return Ok(false),
+        Refresh::All => {
+            let fresh = Item::fetch(proxy).await?;
+            let attention_since = item.attention_since.filter(|_| fresh.urgent);
+            *item = Item {
+                stale: item.stale,
+                attention_since: attention_since.or(fresh.attention_since),
+                ..fresh
+            };
+        }
+        Refresh::Menu => {
+            if let Some(menu) = menu {
+                item.menu = match item::timed("GetLayout", menu::fetch(menu)).await {
+                    Err(e) if item::timed_out(&e) => return Err(e.into()),
+                    menu => menu.ok(),
+                };
+            }
+        }
+    }
+    item.update_effective_icon().await;
+    Ok(true)
+}
+
+/// What a [`session`] takes on besides being a host.
+#[derive(Debug, Clone, Copy)]
+pub struct Roles {
+    pub mode: Mode,
+    /// Refuse items of other users while serving the watcher
+    pub reject_foreign: bool,
+    /// Serve the watcher interfaces of the specification only
+    pub conformant_watcher: bool,
+    /// Serve `org.trayson.Tray`, which only `trayson run` publishes to
+    pub tray_interface: bool,
+}
+
+/// Connections of the current [`session`], kept to publish and to release the
+/// names on shutdown.
+#[derive(Clone)]
+pub struct Bus {
+    pub conn: Connection,
+    pub host: Connection,
+}
+
+impl Bus {
+    /// Gives up all names. There is no call to unregister a host, watchers
+    /// drop it once its name is gone.
+    pub async fn release(&self) {
+        for name in ["org.trayson.Tray", WATCHER, FREEDESKTOP_WATCHER] {
+            let _ = self.conn.release_name(name).await;
+        }
+        let _ = self.host.release_name(host_name()).await;
+    }
+}
+
+/// Returns once `conn` lost its connection to the bus.
+pub async fn disconnected(conn: &Connection) {
+    let mut messages = MessageStream::from(conn);
+    while let Some(Ok(_)) = messages.next().await {}
+}
+
+/// Runs [`session`] again whenever the connection to the session bus is lost
+/// or it fails after having started. Only failing to start the first one is fatal.
+pub async fn follow_bus(
+    roles: Roles,
+    updates: &Sender<Update>,
+    bus: &std::sync::Mutex<Option<Bus>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut reconnecting = false;
+    loop {
+        // set once the names are acquired
+        let started = match session(roles, updates, bus).await {
+            Ok(()) => {
+                tracing::warn!("lost the session bus, reconnecting");
+                true
+            }
+            Err(e) if bus.lock().unwrap().is_some() => {
+                tracing::warn!(error = %e, "session failed, restarting");
+                true
+            }
+            Err(e) if !reconnecting => return Err(e),
+            Err(_) => false,
+        };
+        if started {
+            updates.send(Update::Reset).await?;
+        }
+        bus.lock().unwrap().take();
+        reconnecting = true;
+        task::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+/// Owns the names, registers the host and follows all items on one connection
+/// to the session bus. Returns once that connection is lost.
+async fn session(
+    roles: Roles,
+    updates: &Sender<Update>,
+    bus: &std::sync::Mutex<Option<Bus>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut builder = ConnectionBuilder::session()?;
+    if roles.tray_interface {
+        builder = builder.serve_at(interface::PATH, TrayInterface::default())?;
+    }
+    let (again, mut registered_again) = channel::unbounded();
+    let c1 = watcher::serve(
+        builder,
+        roles.reject_foreign,
+        roles.conformant_watcher,
+        Some(again),
+    )?
+    .build()
+    .await?;
+    // another instance may already serve it
+    if roles.tray_interface {
+        if let Err(e) = c1.request_name("org.trayson.Tray").await {
+            tracing::warn!(error = %e, "org.trayson.Tray disabled");
+        }
+    }
+    let dbus = DBusProxy::new(&c1).await?;
+    let serve_watcher = match roles.mode {
+        Mode::Full => {
+            c1.request_name(WATCHER).await?;
+            c1.request_name(FREEDESKTOP_WATCHER).await?;
+            true
+        }
+        Mode::Auto if !dbus.name_has_owner(WATCHER.try_into()?).await? => {
+            // let a desktop watcher started later take over
+            let flags = RequestNameFlags::AllowReplacement | RequestNameFlags::DoNotQueue;
+            let _ = c1.request_name_with_flags(FREEDESKTOP_WATCHER, flags).await;
+            c1.request_name_with_flags(WATCHER, flags).await? == RequestNameReply::PrimaryOwner
+        }
+        _ => false,
+    };
+    // whether we are the watcher, which can change when another one replaces us
+    let serving = AtomicBool::new(serve_watcher);
+    let mut name_lost = dbus.receive_name_lost().await?;
+
+    let c2 = ConnectionBuilder::session()?
+        .name(host_name())?
+        .serve_at("/StatusNotifierHost", StatusNotifierHost {})?
+        .build()
+        .await?;
+    bus.lock().unwrap().replace(Bus {
+        conn: c1.clone(),
+        host: c2,
+    });
+
+    register_host(&c1).await?;
+    systemd::notify("READY=1");
+    updates.send(Update::Ready).await?;
+
+    let proxy = StatusNotifierWatcherProxy::builder(&c1)
+        .cache_properties(zbus::CacheProperties::No)
+        .build()
+        .await?;
+
+    let mut registered = proxy.receive_status_notifier_item_registered().await?;
+    let mut unregistered = proxy.receive_status_notifier_item_unregistered().await?;
+    let (services, stream) = channel::unbounded();
+    // only an already running watcher knows items at this point
+    for service in proxy.registered_status_notifier_items().await? {
+        services.send(service).await?;
+    }
+
+    let (s, r) = channel::unbounded();
+
+    // shared by the proxies of all items
+    let c3 = ConnectionBuilder::session()?.build().await?;
+    // services followed, to read an item registered twice again instead of
+    // following it twice
+    let followed = std::sync::Mutex::new(HashMap::<String, Sender<()>>::new());
+    let followed = &followed;
+    let limit = Limit::new(INIT_CONCURRENCY.load(Ordering::Relaxed));
+    let limit = &limit;
+    let task1 = stream
+        .map(|service| (s.clone(), updates.clone(), c3.clone(), service))
+        .for_each_concurrent(None, |(s, s2, c3, service)| async move {
+            let (refetch, refetches) = channel::unbounded();
+            match followed.lock().unwrap().entry(service.clone()) {
+                Entry::Occupied(followed) => {
+                    tracing::debug!(service, "registered again, reading it again");
+                    let _ = followed.get().try_send(());
+                    return;
+                }
+                Entry::Vacant(followed) => {
+                    followed.insert(refetch);
+                }
+            }
+            let span = tracing::info_span!("item", service);
+            let followed_item =
+                follow_item(&c3, &service, &s, &s2, refetches, limit).instrument(span);
+            let result = followed_item.await;
+            followed.lock().unwrap().remove(&service);
+            if let Err(e) = result {
+                // fails only once the output loop is gone
+                let _ = s2.send(Update::Error(service.clone(), e)).await;
+                let _ = s2.send(Update::Item(service, None)).await;
+            }
+        });
+    let session = async {
+        try_join!(
+            async {
+                task1.await;
+                Ok::<(), error::Error>(())
+            },
+            async {
+                if serve_watcher {
+                    let known = proxy.registered_status_notifier_items().await?;
+                    for service in watcher::scan_items(&c1).await? {
+                        if known.contains(&service) {
+                            continue;
+                        }
+                        c1.call_method(
+                            Some(WATCHER),
+                            watcher::PATH,
+                            Some(WATCHER),
+                            "RegisterStatusNotifierItem",
+                            &(service),
+                        )
+                        .await?;
+                    }
+                }
+                Ok::<(), error::Error>(())
+            },
+            async {
+                while let Some(signal) = registered.next().await {
+                    services.send(signal.args()?.service.to_string()).await?;
+                }
+                Ok::<(), error::Error>(())
+            },
+            async {
+                while let Some(service) = registered_again.next().await {
+                    services.send(service).await?;
+                }
+                Ok::<(), error::Error>(())
+            },
+            async {
+                while let Some(signal) = name_lost.next().await {
+                    if signal.args()?.name() != WATCHER || !serving.swap(false, Ordering::Relaxed) {
+                        continue;
+                    }
+                    tracing::warn!(
+                        name = WATCHER,
+                        "lost the watcher name, continuing as its host"
+                    );
+                    register_host(&c1).await?;
+                    for service in proxy.registered_status_notifier_items().await? {
+                        services.send(service).await?;
+                    }
+                }
+                Ok::<(), error::Error>(())
+            },
+            async {
+                while let Ok(service) = r.recv().await {
+                    updates.send(Update::Item(service, None)).await?;
+                }
+                Ok::<(), error::Error>(())
+            },
+            async {
+                // a foreign watcher drops items on its own
+                while let Some(signal) = unregistered.next().await {
+                    if !serving.load(Ordering::Relaxed) {
+                        updates
+                            .send(Update::Item(signal.args()?.service.to_string(), None))
+                            .await?;
+                    }
+                }
+                Ok::<(), error::Error>(())
+            }
+        )?;
+        Ok::<(), Box<dyn Error>>(())
+    };
+    let lost = disconnected(&c1);
+    futures_util::pin_mut!(session, lost);
+    match future::select(session, lost).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Ok(()),
+    }
+}
+
+/// Reads the item of `service` and keeps sending it on `updates` until it
+/// goes away, then sends its service on `vanished`. Errors end following it.
+/// Each of `refetches` reads all of it again. Reading it waits for a turn
+/// in `limit`, so that a login starting many applications at once does not have
+/// all of their icons decoded at the same time.
+async fn follow_item(
+    conn: &Connection,
+    service: &str,
+    vanished: &Sender<String>,
+    updates: &Sender<Update>,
+    refetches: Receiver<()>,
+    limit: &Limit,
+) -> Result<(), error::Error> {
+    let started = Instant::now();
+    let proxy = item::proxy(conn, service).await?;
+    let mut owner_change = proxy.receive_owner_changed().await?;
+    let (abort, registration) = AbortHandle::new_pair();
+    updates
+        .send(Update::Tasks(service.to_string(), abort))
+        .await?;
+    let send = |item: &Item, icons: icon::Hold| {
+        updates.send(Update::Item(
+            service.to_string(),
+            Some((item.clone(), proxy.clone(), icons)),
+        ))
+    };
+    // aborted once the item is removed from the registry
+    let tasks = Abortable::new(
+        async {
+            try_join!(
+                async {
+                    let mut unresponsive = false;
+                    let mut attempt = 0;
+                    let icons = icon::hold();
+                    let mut item = loop {
+                        let turn = limit.turn().await;
+                        let fetched = Item::fetch(&proxy).await;
+                        drop(turn);
+                        match fetched {
+                            Ok(item) => break item,
+                            // shown as unresponsive meanwhile, frozen apps may recover
+                            Err(e) if e.timed_out() => {
+                                if !std::mem::replace(&mut unresponsive, true) {
+                                    let placeholder = Item {
+                                        unresponsive: true,
+                                        ..Default::default()
+                                    };
+                                    send(&placeholder, icon::hold()).await?;
+                                }
+                            }
+                            Err(_) if attempt < FETCH_RETRIES => {
+                                task::sleep(FETCH_BACKOFF * 2u32.pow(attempt)).await;
+                                attempt += 1;
+                            }
+                            Err(e) => {
+                                METRICS.dbus_error(service);
+                                return Err(e);
+                            }
+                        }
+                    };
+
+                    send(&item, icons).await?;
+                    METRICS.item_initialized(started.elapsed());
+
+                    let menu = item.menu_proxy(&proxy).await;
+                    let recorded = |signal: &'static str| {
+                        move |refresh: &Refresh| record::signal(service, signal, refresh.args())
+                    };
+                    let mut sources = vec![
+                        proxy
+                            .receive_new_status()
+                            .await?
+                            .filter_map(|signal| async move {
+                                Some(Refresh::Status(signal.args().ok()?.status))
+                            })
+                            .inspect(recorded("NewStatus"))
+                            .boxed(),
+                        proxy
+                            .receive_x_ayatana_new_label()
+                            .await?
+                            .filter_map(|signal| async move {
+                                let args = signal.args().ok()?;
+                                Some(Refresh::Label(args.label, args.guide))
+                            })
+                            .inspect(recorded("XAyatanaNewLabel"))
+                            .boxed(),
+                        proxy
+                            .receive_new_title()
+                            .await?
+                            .map(|_| Refresh::Title)
+                            .inspect(recorded("NewTitle"))
+                            .boxed(),
+                        proxy
+                            .receive_new_tool_tip()
+                            .await?
+                            .map(|_| Refresh::ToolTip)
+                            .inspect(recorded("NewToolTip"))
+                            .boxed(),
+                        // recorded before dropping those in between
+                        throttle(
+                            proxy
+                                .receive_new_icon()
+                                .await?
+                                .map(|_| Refresh::Icon)
+                                .inspect(recorded("NewIcon")),
+                            icon_interval(),
+                        )
+                        .boxed(),
+                        proxy
+                            .receive_new_attention_icon()
+                            .await?
+                            .map(|_| Refresh::AttentionIcon)
+                            .inspect(recorded("NewAttentionIcon"))
+                            .boxed(),
+                        proxy
+                            .receive_new_overlay_icon()
+                            .await?
+                            .map(|_| Refresh::OverlayIcon)
+                            .inspect(recorded("NewOverlayIcon"))
+                            .boxed(),
+                    ];
+                    if let Some(menu) = &menu {
+                        sources.push(
+                            menu.receive_layout_updated()
+                                .await?
+                                .map(|_| Refresh::Menu)
+                                .inspect(recorded("LayoutUpdated"))
+                                .boxed(),
+                        );
+                        sources.push(
+                            menu.receive_items_properties_updated()
+                                .await?
+                                .map(|_| Refresh::Menu)
+                                .inspect(recorded("ItemsPropertiesUpdated"))
+                                .boxed(),
+                        );
+                    }
+                    sources.push(refetches.map(|()| Refresh::All).boxed());
+                    let interval = Duration::from_secs(PING_INTERVAL.load(Ordering::Relaxed));
+                    if !interval.is_zero() {
+                        sources.push(pings(interval).boxed());
+                    }
+                    let grace = Duration::from_secs(STALE_GRACE.load(Ordering::Relaxed));
+                    let mut stale_since = None;
+                    let mut refreshes = stream::select_all(sources);
+                    while let Some(refresh) = refreshes.next().await {
+                        if let Refresh::Ping = refresh {
+                            // `Status` as the cheapest property, a frozen main
+                            // loop answers no Get while bus libraries may still
+                            // answer `Peer.Ping`
+                            let stale = match item::timed("Status", proxy.status()).await {
+                                Err(e) if item::timed_out(&e) => {
+                                    let since = *stale_since.get_or_insert_with(Instant::now);
+                                    if since.elapsed() >= grace {
+                                        return Err(error::Error::Stale(since.elapsed()));
+                                    }
+                                    true
+                                }
+                                _ => {
+                                    stale_since = None;
+                                    false
+                                }
+                            };
+                            if std::mem::replace(&mut item.stale, stale) != stale {
+                                send(&item, icon::hold()).await?;
+                            }
+                            continue;
+                        }
+                        let icons = icon::hold();
+                        let changed = match apply(&mut item, &proxy, menu.as_ref(), refresh).await {
+                            Ok(changed) => {
+                                std::mem::replace(&mut item.unresponsive, false) || changed
+                            }
+                            Err(e) if e.timed_out() => {
+                                !std::mem::replace(&mut item.unresponsive, true)
+                            }
+                            // the previous value stays
+                            Err(e) => {
+                                updates.send(Update::Error(service.to_string(), e)).await?;
+                                false
+                            }
+                        };
+                        if changed {
+                            send(&item, icons).await?;
+                        }
+                    }
+                    Ok::<(), error::Error>(())
+                },
+                async {
+                    while let Some(name) = owner_change.next().await {
+                        if name.is_none() {
+                            break;
+                        }
+                    }
+                    vanished.send(service.to_string()).await?;
+                    Ok::<(), error::Error>(())
+                }
+            )
+        },
+        registration,
+    );
+    match tasks.await {
+        Ok(result) => result.map(|_| ()),
+        Err(_) => Ok(()),
+    }
+}
@@ -0,0 +1,420 @@
+//! Minimal HTTP server (feature `http`).
+//!
+//! - `GET /items` returns the current item list
+//! - `GET /metrics` returns Prometheus metrics
+//! - `GET /icons/<id>.png` returns the icon of the item with that `id`, in
+//!   whatever format it has, see `Content-Type`
+//! - a WebSocket upgrade on any path streams
+//!   `{"event": "update", "items": [...], "seq", "ts"}`
+//!
+//! Web pages may only read any of it if their origin is given with
+//! `--http-allow-origin`: others get no CORS headers, and their WebSocket
+//! upgrades, which browsers don't check, are refused.
+
+use crate::output::Subscriber;
+use crate::registry::SharedRegistry;
+use crate::rt;
+use crate::stamp::Stamp;
+use async_std::io::{self, prelude::BufReadExt, BufReader, ReadExt, WriteExt};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::sync::Mutex;
+use base64::Engine;
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The longest request or header line read, requests here have no body.
+const MAX_LINE: u64 = 8192;
+
+/// Headers read before the request is refused.
+const MAX_HEADERS: usize = 64;
+
+/// How long a client may take to send its request and read the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The parts of a request looked at.
+#[derive(Debug, Default, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    /// By lowercase name
+    headers: HashMap<String, String>,
+}
+
+#[derive(Clone)]
+pub struct HttpServer {
+    registry: SharedRegistry,
+    /// `--http-allow-origin`
+    origins: Arc<Vec<String>>,
+    last: Arc<Mutex<Vec<Value>>>,
+    sockets: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl HttpServer {
+    pub async fn bind(
+        addr: &str,
+        origins: Vec<String>,
+        registry: SharedRegistry,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let server = HttpServer {
+            registry,
+            origins: Arc::new(origins),
+            last: Default::default(),
+            sockets: Default::default(),
+        };
+
+        let s = server.clone();
+        rt::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                let s = s.clone();
+                rt::spawn(async move {
+                    let _ = s.serve(stream).await;
+                });
+            }
+        });
+        Ok(server)
+    }
+
+    /// Queues the new item list for all WebSocket clients, dropping those
+    /// too far behind.
+    pub async fn publish(&self, items: &[Value], stamp: &Stamp) {
+        *self.last.lock().await = items.to_vec();
+        let update = json!({"event": "update", "items": items, "seq": stamp.seq, "ts": stamp.ts});
+        let frame = Arc::<[u8]>::from(ws_frame(&update.to_string()));
+        self.sockets
+            .lock()
+            .await
+            .retain(|socket| socket.send(frame.clone()));
+    }
+
+    async fn serve(self, mut stream: TcpStream) -> io::Result<()> {
+        let mut reader = BufReader::new(stream.clone());
+        let request = match io::timeout(REQUEST_TIMEOUT, read_request(&mut reader)).await {
+            Ok(request) => request,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                return respond(&mut stream, "400 Bad Request", "text/plain", b"", None).await;
+            }
+            Err(e) => return Err(e),
+        };
+        let (method, path) = (request.method.as_str(), request.path.as_str());
+        // only the origins allowed get a CORS header for them
+        let cors = request
+            .headers
+            .get("origin")
+            .map(String::as_str)
+            .filter(|origin| self.origins.iter().any(|allowed| allowed == origin));
+
+        if let Some(key) = request.headers.get("sec-websocket-key") {
+            if !same_origin(&request) && cors.is_none() {
+                return respond(&mut stream, "403 Forbidden", "text/plain", b"", None).await;
+            }
+            let handshake = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            );
+            io::timeout(REQUEST_TIMEOUT, stream.write_all(handshake.as_bytes())).await?;
+            let mut sockets = self.sockets.lock().await;
+            let items = self.last.lock().await.clone();
+            let socket = Subscriber::spawn(stream.clone());
+            let frame = ws_frame(&json!({"event": "update", "items": items}).to_string());
+            socket.send(frame.into());
+            sockets.push(socket);
+            drop(sockets);
+            // drain client frames until the connection goes away
+            let mut buf = [0; 512];
+            while reader.read(&mut buf).await? > 0 {}
+            return Ok(());
+        }
+
+        if method != "GET" {
+            return respond(
+                &mut stream,
+                "405 Method Not Allowed",
+                "text/plain",
+                b"",
+                cors,
+            )
+            .await;
+        }
+        if path == "/metrics" {
+            let body = crate::metrics::METRICS.render();
+            return respond(
+                &mut stream,
+                "200 OK",
+                "text/plain; version=0.0.4",
+                body.as_bytes(),
+                cors,
+            )
+            .await;
+        }
+        if path == "/items" {
+            let body = json!(*self.last.lock().await).to_string();
+            return respond(
+                &mut stream,
+                "200 OK",
+                "application/json",
+                body.as_bytes(),
+                cors,
+            )
+            .await;
+        }
+        if let Some(name) = path.strip_prefix("/icons/") {
+            // ids have no dots, any extension is taken
+            let id = name.split_once('.').map_or(name, |(id, _)| id);
+            let icon = self
+                .registry
+                .lock()
+                .await
+                .find(id)
+                .map(|entry| entry.item.icon.path.clone());
+            if let Some(path) = icon {
+                if let Ok(data) = async_std::fs::read(&path).await {
+                    let content_type = content_type(&path);
+                    return respond(&mut stream, "200 OK", content_type, &data, cors).await;
+                }
+            }
+        }
+        respond(
+            &mut stream,
+            "404 Not Found",
+            "text/plain",
+            b"not found",
+            cors,
+        )
+        .await
+    }
+}
+
+/// Reads the request line and headers, failing with
+/// [`io::ErrorKind::InvalidData`] for lines or headers beyond the limits.
+async fn read_request<R: io::BufRead + Unpin>(reader: &mut R) -> io::Result<Request> {
+    let request_line = read_line(reader).await?;
+    let mut parts = request_line.split_whitespace();
+    let mut request = Request {
+        method: parts.next().unwrap_or_default().to_string(),
+        path: parts.next().unwrap_or_default().to_string(),
+        headers: HashMap::new(),
+    };
+    loop {
+        let line = read_line(reader).await?;
+        if line.trim().is_empty() {
+            return Ok(request);
+        }
+        if request.headers.len() == MAX_HEADERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many headers",
+            ));
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            request
+                .headers
+                .insert(name.trim().to_lowercase(), value.trim().to_string());
+        }
+    }
+}
+
+/// Whether the request has no `Origin`, as from programs other than browsers,
+/// or one with the host it was sent to.
+fn same_origin(request: &Request) -> bool {
+    let Some(origin) = request.headers.get("origin") else {
+        return true;
+    };
+    let host = origin
+        .split_once("://")
+        .map_or(origin.as_str(), |(_, host)| host);
+    request
+        .headers
+        .get("host")
+        .is_some_and(|to| to.eq_ignore_ascii_case(host))
+}
+
+/// A line of at most [`MAX_LINE`] bytes.
+async fn read_line<R: io::BufRead + Unpin>(reader: &mut R) -> io::Result<String> {
+    let mut line = String::new();
+    reader.take(MAX_LINE).read_line(&mut line).await?;
+    // cut off before the newline
+    if line.len() as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "line too long"));
+    }
+    Ok(line)
+}
+
+/// The media type of an icon file, by its extension.
+fn content_type(path: &str) -> &'static str {
+    let extension = std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default();
+    match extension {
+        "png" => "image/png",
+        "svg" => "image/svg+xml",
+        "xpm" => "image/x-xpixmap",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "bmp" => "image/bmp",
+        "ico" => "image/vnd.microsoft.icon",
+        "ppm" => "image/x-portable-pixmap",
+        "ff" => "image/x-farbfeld",
+        "qoi" => "image/qoi",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+    cors: Option<&str>,
+) -> io::Result<()> {
+    let cors = cors
+        .map(|origin| {
+            format!(
+                "Access-Control-Allow-Origin: {}\r\nVary: Origin\r\n",
+                origin
+            )
+        })
+        .unwrap_or_default();
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n{}\
+         Connection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len(),
+        cors
+    );
+    io::timeout(REQUEST_TIMEOUT, async {
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body).await?;
+        stream.flush().await
+    })
+    .await
+}
+
+/// The `Sec-WebSocket-Accept` answering `Sec-WebSocket-Key`.
+fn accept_key(key: &str) -> String {
+    let mut sha = sha1_smol::Sha1::new();
+    sha.update(key.as_bytes());
+    sha.update(WS_GUID.as_bytes());
+    base64::engine::general_purpose::STANDARD.encode(sha.digest().bytes())
+}
+
+/// Encodes an unmasked, unfragmented text frame.
+fn ws_frame(text: &str) -> Vec<u8> {
+    let payload = text.as_bytes();
+    let mut frame = vec![0x81];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend((len as u64).to_be_bytes());
+        }
+    }
+    frame.extend(payload);
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(request: &str) -> io::Result<Request> {
+        async_std::task::block_on(read_request(&mut request.as_bytes()))
+    }
+
+    fn headers(headers: &[(&str, &str)]) -> Request {
+        Request {
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn parses_requests() {
+        let request =
+            parse("GET /items HTTP/1.1\r\nHost: localhost\r\nX-Seen:  a:b \r\n\r\nbody").unwrap();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/items");
+        assert_eq!(request.headers.len(), 2);
+        assert_eq!(request.headers["host"], "localhost");
+        assert_eq!(request.headers["x-seen"], "a:b");
+        // cut off by the client
+        assert_eq!(parse("GET /metrics").unwrap().path, "/metrics");
+        assert_eq!(parse("").unwrap(), Request::default());
+    }
+
+    #[test]
+    fn refuses_requests_beyond_the_limits() {
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE as usize));
+        let error = parse(&long).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "line too long");
+
+        let fits = format!(
+            "GET /{} HTTP/1.1\r\n\r\n",
+            "a".repeat(MAX_LINE as usize - 17)
+        );
+        assert!(parse(&fits).is_ok());
+
+        let many = (0..=MAX_HEADERS).map(|i| format!("X-{}: {}\r\n", i, i));
+        let error = parse(&format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            many.collect::<String>()
+        ));
+        assert_eq!(error.unwrap_err().to_string(), "too many headers");
+    }
+
+    #[test]
+    fn answers_the_key_of_the_handshake() {
+        // the example of RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn encodes_the_length_of_frames_in_as_few_bytes_as_it_fits() {
+        assert_eq!(ws_frame("hi"), [0x81, 2, b'h', b'i']);
+        let medium = ws_frame(&"a".repeat(126));
+        assert_eq!(medium[..4], [0x81, 126, 0, 126]);
+        assert_eq!(medium.len(), 4 + 126);
+        let long = ws_frame(&"a".repeat(65536));
+        assert_eq!(long[..10], [0x81, 127, 0, 0, 0, 0, 0, 1, 0, 0]);
+        assert_eq!(long.len(), 10 + 65536);
+    }
+
+    #[test]
+    fn tells_same_origin_requests() {
+        assert!(same_origin(&headers(&[("host", "127.0.0.1:8765")])));
+        assert!(same_origin(&headers(&[
+            ("host", "localhost:8765"),
+            ("origin", "http://LOCALHOST:8765"),
+        ])));
+        assert!(!same_origin(&headers(&[
+            ("host", "localhost:8765"),
+            ("origin", "https://example.com"),
+        ])));
+        assert!(!same_origin(&headers(&[(
+            "origin",
+            "http://localhost:8765"
+        )])));
+    }
+}
@@ -0,0 +1,212 @@
+//! `format=i3blocks`: one JSON line per change for a persistent block of
+//! i3blocks, with the titles of the items in `full_text`, the urgent ones in
+//! bold:
+//!
+//! ```ini
+//! [tray]
+//! command=trayson run --format i3blocks
+//! interval=persist
+//! format=json
+//! ```
+//!
+//! i3blocks writes the clicks on the block to stdin as JSON lines, which
+//! [`click`] turns into commands for the item clicked on: the `instance` of
+//! the block if set, else the title under `relative_x`, assuming all
+//! characters are about equally wide. Button 1 activates, 2 is the secondary
+//! activation, 3 opens the context menu and 4 and 5 scroll.
+
+use crate::command::{Command, CommandError};
+use crate::markup;
+use crate::schema::SCHEMA_VERSION;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Mutex;
+
+/// Between the titles of two items in `full_text`.
+const SEPARATOR: &str = "  ";
+
+/// `id` and length in characters of each title of the block emitted last,
+/// for [`click`].
+static LAYOUT: Mutex<Vec<(String, usize)>> = Mutex::new(Vec::new());
+
+/// The block for `items`.
+pub fn render(items: &[&Value]) -> Value {
+    let mut layout = Vec::with_capacity(items.len());
+    let mut full_text = Vec::with_capacity(items.len());
+    for item in items {
+        let (Some(id), Some(title)) = (item["id"].as_str(), item["title"].as_str()) else {
+            continue;
+        };
+        layout.push((id.to_string(), title.chars().count()));
+        full_text.push(match item["urgent"] == true {
+            true => format!("<b>{}</b>", markup::escape(title)),
+            false => markup::escape(title),
+        });
+    }
+    *LAYOUT.lock().unwrap() = layout;
+    json!({
+        "full_text": full_text.join(SEPARATOR),
+        "short_text": items.len().to_string(),
+        "markup": "pango",
+        "urgent": items.iter().any(|item| item["urgent"] == true),
+        "schema_version": SCHEMA_VERSION,
+    })
+}
+
+/// A click event of i3blocks, with the fields used here.
+#[derive(Deserialize)]
+struct Click {
+    #[serde(default)]
+    instance: Option<String>,
+    button: u32,
+    #[serde(default)]
+    x: i32,
+    #[serde(default)]
+    y: i32,
+    #[serde(default)]
+    relative_x: i32,
+    #[serde(default)]
+    width: i32,
+}
+
+/// The command for a click event line of i3blocks.
+pub fn click(line: &str) -> Result<Command, CommandError> {
+    let click: Click = serde_json::from_str(line)
+        .map_err(|e| CommandError::Parse(format!("invalid click event: {}", e)))?;
+    let item = match click.instance.filter(|instance| !instance.is_empty()) {
+        Some(instance) => instance,
+        None => hit(click.relative_x, click.width)
+            .ok_or_else(|| CommandError::Parse("no item under the click".to_string()))?,
+    };
+    let (x, y) = (click.x, click.y);
+    Ok(match click.button {
+        1 => Command::Activate {
+            item,
+            x,
+            y,
+            token: None,
+        },
+        2 => Command::SecondaryActivate {
+            item,
+            x,
+            y,
+            token: None,
+        },
+        3 => Command::ContextMenu { item, x, y },
+        4 | 5 => Command::Scroll {
+            item,
+            delta: if click.button == 4 { -1 } else { 1 },
+            orientation: "vertical".to_string(),
+        },
+        button => {
+            return Err(CommandError::Parse(format!(
+                "button {} does nothing",
+                button
+            )))
+        }
+    })
+}
+
+/// `id` of the item whose title is at `x` of a block `width` pixels wide.
+/// The separator after a title belongs to it.
+fn hit(x: i32, width: i32) -> Option<String> {
+    let layout = LAYOUT.lock().unwrap();
+    let separator = SEPARATOR.chars().count();
+    let total = layout.iter().map(|(_, len)| len + separator).sum::<usize>();
+    let total = total.saturating_sub(separator);
+    if width <= 0 || total == 0 {
+        return layout.first().map(|(id, _)| id.clone());
+    }
+    let at = x.clamp(0, width - 1) as usize * total / width as usize;
+    let mut end = 0;
+    layout
+        .iter()
+        .find(|(_, len)| {
+            end += len + separator;
+            at < end
+        })
+        .map(|(id, _)| id.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(line: &str) -> String {
+        match click(line).unwrap() {
+            Command::Activate { item, .. }
+            | Command::SecondaryActivate { item, .. }
+            | Command::ContextMenu { item, .. }
+            | Command::Scroll { item, .. } => item,
+            other => panic!("`{}` gave {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn maps_buttons_to_commands() {
+        let click = |button| {
+            click(&format!(
+                r#"{{"instance": "nm", "button": {}, "x": 3, "y": 4}}"#,
+                button
+            ))
+        };
+        assert!(matches!(
+            click(1).unwrap(),
+            Command::Activate {
+                x: 3,
+                y: 4,
+                token: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            click(2).unwrap(),
+            Command::SecondaryActivate { .. }
+        ));
+        assert!(matches!(click(3).unwrap(), Command::ContextMenu { .. }));
+        assert!(matches!(
+            click(4).unwrap(),
+            Command::Scroll { delta: -1, .. }
+        ));
+        assert!(matches!(
+            click(5).unwrap(),
+            Command::Scroll { delta: 1, .. }
+        ));
+        assert!(matches!(
+            click(8),
+            Err(CommandError::Parse(reason)) if reason == "button 8 does nothing"
+        ));
+        assert!(matches!(
+            super::click("{}"),
+            Err(CommandError::Parse(reason)) if reason.starts_with("invalid click event")
+        ));
+    }
+
+    #[test]
+    fn finds_the_title_under_the_click() {
+        let a = json!({"id": "a", "title": "abcd"});
+        let b = json!({"id": "b", "title": "ef"});
+        // `abcd  ef`, 8 characters over 80 pixels
+        render(&[&a, &b]);
+        let at = |x| {
+            item(&format!(
+                r#"{{"button": 1, "relative_x": {}, "width": 80}}"#,
+                x
+            ))
+        };
+        assert_eq!(at(0), "a");
+        assert_eq!(at(39), "a");
+        assert_eq!(at(59), "a");
+        assert_eq!(at(60), "b");
+        assert_eq!(at(500), "b");
+        assert_eq!(at(-5), "a");
+        assert_eq!(
+            item(r#"{"button": 1, "relative_x": 70, "instance": ""}"#),
+            "a"
+        );
+        assert_eq!(
+            item(r#"{"button": 1, "relative_x": 70, "instance": "x"}"#),
+            "x"
+        );
+    }
+}
@@ -0,0 +1,866 @@
+use crate::error::Error;
+use crate::metrics::METRICS;
+use crate::raw;
+use crate::rt;
+use font8x8::UnicodeFonts;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashSet};
+use std::env::temp_dir;
+use std::hash::{Hash, Hasher};
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+
+/// How icons are written, the `[icon]` table of the config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IconConfig {
+    /// Scale pixmaps to this many pixels square
+    pub size: Option<u32>,
+    pub format: IconFormat,
+    /// Directory for the icon files, written to a subdirectory named after
+    /// the process id [default: $XDG_RUNTIME_DIR/trayson, or trayson-<uid> in
+    /// the temp dir]
+    pub dir: Option<PathBuf>,
+    /// Icon theme searched before `hicolor` for icons given by name
+    pub theme: Option<String>,
+    /// Emit icons as `file://` URIs instead of paths
+    pub uri: bool,
+    /// Keep `icons/<id>.<extension>` in the directory linked to the current
+    /// icon of every item
+    pub links: bool,
+    /// Minutes after which icons left outside the directories of instances
+    /// are deleted, 0 to keep them; those of instances no longer running are
+    /// deleted at startup either way [default: 1440]
+    pub max_age: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IconFormat {
+    #[default]
+    Png,
+    /// Uncompressed, see [`crate::raw`]
+    Ppm,
+    Farbfeld,
+    Qoi,
+}
+
+impl IconFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            IconFormat::Png => "png",
+            IconFormat::Ppm => "ppm",
+            IconFormat::Farbfeld => "ff",
+            IconFormat::Qoi => "qoi",
+        }
+    }
+
+    fn write(&self, image: &image::RgbaImage, path: &Path) -> Result<(), Error> {
+        let data = match self {
+            IconFormat::Png => return Ok(image.save(path)?),
+            IconFormat::Ppm => raw::encode_ppm(image),
+            IconFormat::Farbfeld => raw::encode_farbfeld(image),
+            IconFormat::Qoi => raw::encode_qoi(image),
+        };
+        Ok(std::fs::write(path, data)?)
+    }
+}
+
+static CONFIG: RwLock<IconConfig> = RwLock::new(IconConfig {
+    size: None,
+    format: IconFormat::Png,
+    dir: None,
+    theme: None,
+    uri: false,
+    links: false,
+    max_age: None,
+});
+
+/// Files written by this process, with the number of the save that wrote
+/// them last and whether that was since the last [`release_unused`], removed
+/// again on shutdown.
+static SAVED: Mutex<BTreeMap<PathBuf, (u64, bool)>> = Mutex::new(BTreeMap::new());
+
+/// The number of the next save.
+static SAVES: AtomicU64 = AtomicU64::new(0);
+
+/// The first save each live [`Hold`] covers, with how many start there.
+static HOLDS: Mutex<BTreeMap<u64, usize>> = Mutex::new(BTreeMap::new());
+
+/// Keeps the files saved from now on from [`release_unused`] until dropped,
+/// for the icons of an item read but not in the registry yet.
+#[derive(Debug)]
+pub struct Hold(u64);
+
+pub fn hold() -> Hold {
+    let mut holds = HOLDS.lock().unwrap();
+    let first = SAVES.load(Ordering::Relaxed);
+    *holds.entry(first).or_default() += 1;
+    Hold(first)
+}
+
+impl Drop for Hold {
+    fn drop(&mut self) {
+        let mut holds = HOLDS.lock().unwrap();
+        if let Some(count) = holds.get_mut(&self.0) {
+            *count -= 1;
+            if *count == 0 {
+                holds.remove(&self.0);
+            }
+        }
+    }
+}
+
+/// The `links` made so far, by `id` of the item.
+static LINKS: Mutex<BTreeMap<String, PathBuf>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Icon {
+    pub width: usize,
+    pub height: usize,
+    pub path: String,
+}
+
+impl Icon {
+    /// An existing file, with its size if it is a bitmap.
+    pub fn from_file(path: &Path) -> Icon {
+        let (width, height) = match path.extension().is_some_and(|e| e == "qoi") {
+            true => open(path).map(|image| image.dimensions()),
+            false => image::image_dimensions(path).ok(),
+        }
+        .unwrap_or_default();
+        Icon {
+            width: width as usize,
+            height: height as usize,
+            path: path.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+/// Applies to all icons saved from now on.
+pub fn configure(config: IconConfig) {
+    if !matches!(config.format, IconFormat::Png) {
+        let dir = config.dir.clone().unwrap_or_else(default_dir);
+        if !on_tmpfs(&dir) {
+            tracing::warn!(dir = %dir.display(), "uncompressed icons are meant for a tmpfs dir");
+        }
+    }
+    *CONFIG.write().unwrap() = config;
+}
+
+/// Whether the mount holding `dir` is a tmpfs, by `/proc/self/mounts`.
+fn on_tmpfs(dir: &Path) -> bool {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let Ok(mounts) = std::fs::read_to_string("/proc/self/mounts") else {
+        return false;
+    };
+    mounts
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let (_, point, kind) = (fields.next()?, fields.next()?, fields.next()?);
+            // spaces in mount points are escaped as \040
+            let point = PathBuf::from(point.replace("\\040", " "));
+            dir.starts_with(&point).then_some((point, kind))
+        })
+        .max_by_key(|(point, _)| point.as_os_str().len())
+        .is_some_and(|(_, kind)| kind == "tmpfs")
+}
+
+/// Reads an icon file of any of the formats saved here.
+pub fn open(path: impl AsRef<Path>) -> Option<image::RgbaImage> {
+    let path = path.as_ref();
+    if path.extension().is_some_and(|e| e == "qoi") {
+        return raw::decode_qoi(&std::fs::read(path).ok()?);
+    }
+    Some(image::open(path).ok()?.to_rgba8())
+}
+
+/// The configured icon theme.
+pub fn theme() -> Option<String> {
+    CONFIG.read().unwrap().theme.clone()
+}
+
+/// Where icons go without a configured `dir`, one only trayson writes to.
+fn default_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("trayson"),
+        // the temp dir is shared with other users
+        None => temp_dir().join(format!("trayson-{}", unsafe { libc::getuid() })),
+    }
+}
+
+/// The configured directory.
+fn base_dir() -> PathBuf {
+    CONFIG
+        .read()
+        .unwrap()
+        .dir
+        .clone()
+        .unwrap_or_else(default_dir)
+}
+
+/// The directory of the icons of this process in the configured one, created
+/// if needed. Other instances have their own, as they may save the same files.
+fn dir() -> PathBuf {
+    let dir = base_dir().join(std::process::id().to_string());
+    let _ = std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir);
+    dir
+}
+
+/// `path` as a `file://` URI, percent-encoded.
+pub fn uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            byte => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Whether icons are emitted as URIs.
+pub fn emits_uris() -> bool {
+    CONFIG.read().unwrap().uri
+}
+
+/// Turns the path at `key` of `object` into a URI, if it has one.
+pub fn to_uri(object: &mut Value, key: &str) {
+    if let Some(path) = object.get_mut(key) {
+        if let Some(converted) = path.as_str().filter(|p| p.starts_with('/')).map(uri) {
+            *path = Value::String(converted);
+        }
+    }
+}
+
+/// Turns the icon paths of a serialized item, its application and its menu
+/// entries into URIs.
+pub fn to_uris(item: &mut Value) {
+    fn menu(entries: Option<&mut Value>) {
+        for entry in entries.and_then(Value::as_array_mut).into_iter().flatten() {
+            to_uri(entry, "icon");
+            menu(entry.get_mut("children"));
+        }
+    }
+    for key in ["icon", "effective_icon"] {
+        if let Some(icon) = item.get_mut(key) {
+            to_uri(icon, "path");
+        }
+    }
+    if let Some(app) = item.get_mut("app") {
+        to_uri(app, "icon");
+    }
+    menu(item.get_mut("menu"));
+}
+
+/// Identifies the content of a pixmap, to skip encoding it again if unchanged.
+pub fn pixmap_hash(icon: &(i32, i32, Vec<u8>)) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    icon.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Converts an ARGB32 pixmap to RGBA, scales it to the configured size and
+/// saves it named after its content, on a thread of its own so that large
+/// icons don't hold up the bus.
+pub async fn save_pixmap(icon: &(i32, i32, Vec<u8>)) -> Result<Icon, Error> {
+    let icon = icon.clone();
+    rt::spawn_blocking(move || encode_pixmap(&icon)).await
+}
+
+fn encode_pixmap(icon: &(i32, i32, Vec<u8>)) -> Result<Icon, Error> {
+    let invalid = || Error::Pixmap {
+        width: icon.0,
+        height: icon.1,
+        len: icon.2.len(),
+    };
+    let (width, height) = (
+        u32::try_from(icon.0).map_err(|_| invalid())?,
+        u32::try_from(icon.1).map_err(|_| invalid())?,
+    );
+    let img = icon
+        .2
+        .chunks_exact(4)
+        .flat_map(|pixel| [pixel[1], pixel[2], pixel[3], pixel[0]])
+        .collect::<Vec<_>>();
+
+    let (size, format) = {
+        let config = CONFIG.read().unwrap();
+        (config.size, config.format)
+    };
+    let mut path = dir();
+    let mut hasher = DefaultHasher::new();
+    Hash::hash_slice(&img, &mut hasher);
+    size.hash(&mut hasher);
+    path.push(format!("{:x}.{}", hasher.finish(), format.extension()));
+
+    let mut a = image::RgbaImage::from_vec(width, height, img).ok_or_else(invalid)?;
+    if let Some(size) = size.filter(|&size| (size, size) != a.dimensions()) {
+        a = image::imageops::resize(&a, size, size, image::imageops::FilterType::Triangle);
+    }
+    format.write(&a, &path)?;
+    remember(&path);
+    if let Ok(meta) = std::fs::metadata(&path) {
+        METRICS.icon_written(meta.len());
+    }
+
+    Ok(Icon {
+        width: a.width() as usize,
+        height: a.height() as usize,
+        path: path.to_string_lossy().into_owned(),
+    })
+}
+
+/// Backgrounds of [`avatar`]s, white letters are readable on all of them.
+const AVATAR_COLORS: [[u8; 3]; 8] = [
+    [0xc6, 0x28, 0x28],
+    [0xad, 0x14, 0x57],
+    [0x6a, 0x1b, 0x9a],
+    [0x28, 0x35, 0x93],
+    [0x02, 0x77, 0xbd],
+    [0x00, 0x69, 0x5c],
+    [0x2e, 0x7d, 0x32],
+    [0xd8, 0x43, 0x15],
+];
+
+/// A square with the first letter of `title`, or else of `id`, for items
+/// without any icon. The color only depends on `id`.
+pub fn avatar(id: &str, title: &str) -> Result<Icon, Error> {
+    let letter = title
+        .chars()
+        .chain(id.chars())
+        .find(|c| c.is_alphanumeric())
+        .map_or('?', |c| c.to_ascii_uppercase());
+    // FNV-1a, to stay the same across builds
+    let hash = id.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    let [r, g, b] = AVATAR_COLORS[(hash % AVATAR_COLORS.len() as u64) as usize];
+
+    let size = CONFIG.read().unwrap().size.unwrap_or(22).max(8);
+    let mut a = image::RgbaImage::from_pixel(size, size, image::Rgba([r, g, b, 255]));
+    let glyph = font8x8::BASIC_FONTS.get(letter).unwrap_or([0; 8]);
+    let scale = ((size * 5 / 8 + 4) / 8).max(1);
+    let offset = (size - 8 * scale) / 2;
+    for (row, bits) in glyph.iter().enumerate() {
+        for bit in (0..8).filter(|bit| bits & (1 << bit) != 0) {
+            for (dx, dy) in (0..scale).flat_map(|dx| (0..scale).map(move |dy| (dx, dy))) {
+                let (x, y) = (offset + bit * scale + dx, offset + row as u32 * scale + dy);
+                a.put_pixel(x, y, image::Rgba([255, 255, 255, 255]));
+            }
+        }
+    }
+
+    let mut path = dir();
+    let format = CONFIG.read().unwrap().format;
+    path.push(format!(
+        "avatar-{:x}-{}-{}.{}",
+        hash,
+        letter as u32,
+        size,
+        format.extension()
+    ));
+    format.write(&a, &path)?;
+    remember(&path);
+    Ok(Icon::from_file(&path))
+}
+
+/// Saves already encoded PNG data, like the `icon-data` of menu entries, named after its content.
+pub fn save_png(data: &[u8]) -> Option<String> {
+    save_encoded(data, "png")
+}
+
+/// Saves an already encoded image of the format that `extension` stands for,
+/// named after its content.
+pub fn save_encoded(data: &[u8], extension: &str) -> Option<String> {
+    let mut path = dir();
+    let mut hasher = DefaultHasher::new();
+    Hash::hash_slice(data, &mut hasher);
+    path.push(format!("{:x}.{}", hasher.finish(), extension));
+
+    std::fs::write(&path, data).ok()?;
+    remember(&path);
+    METRICS.icon_written(data.len() as u64);
+    Some(path.to_str()?.to_string())
+}
+
+fn remember(path: &Path) {
+    let save = SAVES.fetch_add(1, Ordering::Relaxed);
+    SAVED
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), (save, true));
+}
+
+/// Removes the files saved here before the previous call that none of the
+/// serialized `items` refers to anymore, like the frames of animated icons
+/// and strips of earlier states, so a long session doesn't pile them up.
+/// Files saved since the oldest [`Hold`] still around stay.
+pub fn release_unused<'a>(items: impl IntoIterator<Item = &'a Value>) {
+    fn strings<'a>(value: &'a Value, found: &mut HashSet<&'a str>) {
+        match value {
+            Value::String(s) => {
+                found.insert(s);
+            }
+            Value::Array(values) => values.iter().for_each(|v| strings(v, found)),
+            Value::Object(map) => map.values().for_each(|v| strings(v, found)),
+            _ => {}
+        }
+    }
+    let mut used = HashSet::new();
+    items.into_iter().for_each(|item| strings(item, &mut used));
+    let held = HOLDS.lock().unwrap().keys().next().copied();
+    SAVED.lock().unwrap().retain(|path, (save, fresh)| {
+        let keep = std::mem::take(fresh)
+            || held.is_some_and(|first| *save >= first)
+            || path.to_str().is_some_and(|p| used.contains(p));
+        if !keep {
+            let _ = std::fs::remove_file(path);
+        }
+        keep
+    });
+}
+
+/// How many files saved here are still around and their size in bytes.
+pub fn saved_usage() -> (usize, u64) {
+    let saved = SAVED.lock().unwrap();
+    let bytes = saved
+        .keys()
+        .filter_map(|path| std::fs::metadata(path).ok())
+        .map(|meta| meta.len())
+        .sum();
+    (saved.len(), bytes)
+}
+
+/// With `links` configured, points `icons/<id>.<extension>` at the icon of
+/// each of the serialized `items` and removes the links of other items.
+pub fn link(items: &[Value]) {
+    if !CONFIG.read().unwrap().links {
+        return;
+    }
+    let dir = base_dir().join("icons");
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        tracing::warn!(dir = %dir.display(), error = %e, "failed to create");
+        return;
+    }
+    let mut links = LINKS.lock().unwrap();
+    let mut current = BTreeMap::new();
+    for item in items {
+        let (Some(id), Some(target)) = (item["id"].as_str(), item["icon"]["path"].as_str()) else {
+            continue;
+        };
+        let target = Path::new(target);
+        if !target.is_file() || id.contains('/') {
+            continue;
+        }
+        let mut name = id.to_string();
+        if let Some(extension) = target.extension().and_then(|e| e.to_str()) {
+            name = format!("{}.{}", name, extension);
+        }
+        let link = dir.join(name);
+        if std::fs::read_link(&link).ok().as_deref() != Some(target) {
+            // replaced at once, a bar reloading it never finds it missing
+            let temporary = dir.join(format!(".{}.tmp", id));
+            let _ = std::fs::remove_file(&temporary);
+            let linked = std::os::unix::fs::symlink(target, &temporary)
+                .and_then(|()| std::fs::rename(&temporary, &link));
+            if let Err(e) = linked {
+                tracing::warn!(link = %link.display(), error = %e, "failed to link");
+                continue;
+            }
+        }
+        current.insert(id.to_string(), link);
+    }
+    for (id, link) in links.iter() {
+        if current.get(id) != Some(link) {
+            let _ = std::fs::remove_file(link);
+        }
+    }
+    *links = current;
+}
+
+/// Deletes the icons left by earlier runs, which crashed or were killed, and
+/// the links no current item uses. Those in the directories of processes no
+/// longer running go at once, those outside of them, as older versions saved
+/// them, once older than `max_age`. Only files named like those written here
+/// are touched, and none directly in the temp dir, which holds files of other
+/// programs named alike.
+pub fn collect_garbage() {
+    let (dir, max_age) = {
+        let config = CONFIG.read().unwrap();
+        let minutes = config.max_age.unwrap_or(24 * 60);
+        (config.dir.clone().unwrap_or_else(default_dir), minutes)
+    };
+    let max_age =
+        (max_age > 0 && dir != temp_dir()).then(|| std::time::Duration::from_secs(max_age * 60));
+    let mut removed = collect_in(&dir, max_age);
+    // links whose icon is gone now
+    let links = LINKS.lock().unwrap();
+    for entry in std::fs::read_dir(dir.join("icons"))
+        .into_iter()
+        .flatten()
+        .flatten()
+    {
+        let path = entry.path();
+        let dangling =
+            std::fs::symlink_metadata(&path).is_ok_and(|m| m.is_symlink()) && !path.exists();
+        if dangling && !links.values().any(|link| *link == path) {
+            removed += usize::from(std::fs::remove_file(&path).is_ok());
+        }
+    }
+    if removed > 0 {
+        tracing::info!(dir = %dir.display(), removed, "removed stale icons");
+    }
+}
+
+/// Does the work of [`collect_garbage`] in `dir`, returning how many files
+/// were removed. Files of this process that no item uses, left by an earlier
+/// one with the same id, go as well.
+fn collect_in(dir: &Path, max_age: Option<std::time::Duration>) -> usize {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    let uid = unsafe { libc::getuid() };
+    let mut removed = 0;
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        if meta.is_dir() {
+            let Ok(pid) = name.parse::<u32>() else {
+                continue;
+            };
+            let own = pid == std::process::id();
+            if !own && Path::new("/proc").join(&*name).exists() {
+                continue;
+            }
+            let saved = SAVED.lock().unwrap();
+            for file in std::fs::read_dir(entry.path())
+                .into_iter()
+                .flatten()
+                .flatten()
+            {
+                let stale = written_here(&file.file_name().to_string_lossy())
+                    && !(own && saved.contains_key(&file.path()));
+                if stale {
+                    removed += usize::from(std::fs::remove_file(file.path()).is_ok());
+                }
+            }
+            if !own {
+                // once all is gone
+                let _ = std::fs::remove_dir(entry.path());
+            }
+            continue;
+        }
+        let old = max_age.is_some_and(|max_age| {
+            meta.modified()
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age >= max_age)
+        });
+        // the temp dir is shared with other users
+        let mine = std::os::unix::fs::MetadataExt::uid(&meta) == uid;
+        if meta.is_file() && old && mine && written_here(&name) {
+            removed += usize::from(std::fs::remove_file(entry.path()).is_ok());
+        }
+    }
+    removed
+}
+
+/// Whether `name` is one of a file saved by this module, `<hash>.<extension>`
+/// or `avatar-<hash>-<letter>-<size>.<extension>`.
+fn written_here(name: &str) -> bool {
+    let hex = |s: &str| !s.is_empty() && s.len() <= 16 && s.bytes().all(|b| b.is_ascii_hexdigit());
+    let Some((stem, extension)) = name.rsplit_once('.') else {
+        return false;
+    };
+    let known = [
+        "png", "svg", "xpm", "jpg", "ico", "bmp", "gif", "ppm", "ff", "qoi",
+    ];
+    match stem.strip_prefix("avatar-") {
+        Some(avatar) => {
+            let mut parts = avatar.split('-');
+            ["png", "ppm", "ff", "qoi"].contains(&extension)
+                && parts.next().is_some_and(hex)
+                && parts.all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit()))
+        }
+        None => hex(stem) && known.contains(&extension),
+    }
+}
+
+/// Deletes all icons saved and links made so far, and the directory of this
+/// process with them, which no other instance uses.
+pub fn remove_saved() {
+    for (path, _) in std::mem::take(&mut *SAVED.lock().unwrap()) {
+        let _ = std::fs::remove_file(path);
+    }
+    for (_, link) in std::mem::take(&mut *LINKS.lock().unwrap()) {
+        let _ = std::fs::remove_file(link);
+    }
+    let _ = std::fs::remove_dir(base_dir().join(std::process::id().to_string()));
+}
+
+/// `$XDG_DATA_HOME` followed by `$XDG_DATA_DIRS`.
+pub fn data_dirs() -> Vec<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| Some(home.as_ref()?.join(".local/share")));
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    data_home
+        .into_iter()
+        .chain(std::env::split_paths(&data_dirs))
+        .collect()
+}
+
+/// Size of an icon theme directory like `48x48`, `48` or `48x48@2`; scalable
+/// icons rank below every bitmap.
+fn theme_dir_size(dir: &Path) -> Option<u32> {
+    let name = dir.file_name()?.to_str()?;
+    if name == "scalable" {
+        return Some(1);
+    }
+    name.split(['x', '@']).next()?.parse().ok()
+}
+
+/// Looks up a themed icon by name as in the icon theme spec, in `theme` before
+/// `hicolor` and then in `pixmaps`, preferring the largest bitmap.
+pub fn find_themed(name: &str, theme: Option<&str>) -> Option<PathBuf> {
+    let bases = data_dirs();
+    let icon_dirs = bases
+        .iter()
+        .map(|base| base.join("icons"))
+        .collect::<Vec<_>>();
+    if let Some(path) = find_in_themes(&icon_dirs, name, theme) {
+        return Some(path);
+    }
+    bases.iter().find_map(|base| {
+        ["png", "svg", "xpm"]
+            .iter()
+            .map(|extension| base.join("pixmaps").join(format!("{}.{}", name, extension)))
+            .find(|path| path.is_file())
+    })
+}
+
+/// The largest icon `name` in `theme` before `hicolor` under `icon_dirs`.
+fn find_in_themes(icon_dirs: &[PathBuf], name: &str, theme: Option<&str>) -> Option<PathBuf> {
+    for theme in theme.into_iter().chain(["hicolor"]) {
+        let mut best: Option<(u32, PathBuf)> = None;
+        for icon_dir in icon_dirs {
+            let Ok(outer) = std::fs::read_dir(icon_dir.join(theme)) else {
+                continue;
+            };
+            // either `48x48/apps` or `apps/48`
+            for outer in outer.flatten().map(|entry| entry.path()) {
+                let Ok(inner) = std::fs::read_dir(&outer) else {
+                    continue;
+                };
+                for inner in inner.flatten().map(|entry| entry.path()) {
+                    let Some(size) = theme_dir_size(&outer).or_else(|| theme_dir_size(&inner))
+                    else {
+                        continue;
+                    };
+                    for extension in ["png", "svg"] {
+                        let path = inner.join(format!("{}.{}", name, extension));
+                        if best.as_ref().is_none_or(|(best, _)| size > *best) && path.is_file() {
+                            best = Some((size, path));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some((_, path)) = best {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Looks up the `IconName` of an item: a path as it is, else in the item's
+/// `IconThemePath`, directly or as a directory of themes, else as
+/// [`find_themed`] does.
+pub fn find_named(name: &str, theme_path: &str) -> Option<PathBuf> {
+    if name.is_empty() {
+        return None;
+    }
+    if name.starts_with('/') {
+        return Some(PathBuf::from(name)).filter(|path| path.is_file());
+    }
+    let theme = theme();
+    if !theme_path.is_empty() {
+        let dir = Path::new(theme_path);
+        let direct = ["png", "svg"]
+            .iter()
+            .map(|extension| dir.join(format!("{}.{}", name, extension)))
+            .find(|path| path.is_file());
+        let themed = || find_in_themes(&[dir.to_path_buf()], name, theme.as_deref());
+        if let Some(path) = direct.or_else(themed) {
+            return Some(path);
+        }
+    }
+    find_themed(name, theme.as_deref())
+}
+
+/// The first pixmap of `pixmaps` that is not empty.
+pub fn usable_pixmap(pixmaps: &[(i32, i32, Vec<u8>)]) -> Option<&(i32, i32, Vec<u8>)> {
+    pixmaps
+        .iter()
+        .find(|(width, height, data)| *width > 0 && *height > 0 && !data.is_empty())
+}
+
+/// The icon of an item and the [`pixmap_hash`] it was saved from, taken from
+/// the first of these there is: a usable pixmap of `pixmaps`, the icon
+/// `name` as [`find_named`] finds it, the icon of the desktop entry and an
+/// [`avatar`] of `id` and `title`.
+pub async fn resolve(
+    pixmaps: &[(i32, i32, Vec<u8>)],
+    name: &str,
+    theme_path: &str,
+    app_icon: Option<&str>,
+    id: &str,
+    title: &str,
+) -> Result<(Icon, u64), Error> {
+    if let Some(found) = pixmap_or_named(pixmaps, name, theme_path).await {
+        return Ok(found);
+    }
+    let (app_icon, id, title) = (
+        app_icon.map(PathBuf::from),
+        id.to_string(),
+        title.to_string(),
+    );
+    rt::spawn_blocking(move || match app_icon.filter(|path| path.is_file()) {
+        Some(path) => Ok((Icon::from_file(&path), 0)),
+        None => Ok((avatar(&id, &title)?, 0)),
+    })
+    .await
+}
+
+/// A usable pixmap of `pixmaps` saved, else the icon `name` as [`find_named`]
+/// finds it, with the [`pixmap_hash`] as for [`resolve`].
+pub async fn pixmap_or_named(
+    pixmaps: &[(i32, i32, Vec<u8>)],
+    name: &str,
+    theme_path: &str,
+) -> Option<(Icon, u64)> {
+    if let Some(pixmap) = usable_pixmap(pixmaps) {
+        match save_pixmap(pixmap).await {
+            Ok(icon) => return Some((icon, pixmap_hash(pixmap))),
+            Err(e) => tracing::debug!(error = %e, "falling back from the pixmap"),
+        }
+    }
+    let (name, theme_path) = (name.to_string(), theme_path.to_string());
+    // searches the icon themes
+    rt::spawn_blocking(move || {
+        let path = find_named(&name, &theme_path)?;
+        Some((Icon::from_file(&path), 0))
+    })
+    .await
+}
+
+/// `overlay` at half the size in the bottom right corner of `base`, as hosts
+/// draw the `OverlayIcon` of items, saved named after both. `None` if one of
+/// them can't be read, like SVGs. Blocks, see [`rt::spawn_blocking`].
+pub fn composite(base: &Icon, overlay: &Icon) -> Option<Icon> {
+    let format = CONFIG.read().unwrap().format;
+    let mut hasher = DefaultHasher::new();
+    (&base.path, &overlay.path).hash(&mut hasher);
+    let path = dir().join(format!("{:x}.{}", hasher.finish(), format.extension()));
+    // both are named after their content, so is this
+    if !path.is_file() {
+        let mut image = open(&base.path)?;
+        let overlay = open(&overlay.path)?;
+        let (width, height) = image.dimensions();
+        let (w, h) = ((width / 2).max(1), (height / 2).max(1));
+        let overlay =
+            image::imageops::resize(&overlay, w, h, image::imageops::FilterType::Triangle);
+        image::imageops::overlay(&mut image, &overlay, width - w, height - h);
+        if let Err(e) = format.write(&image, &path) {
+            tracing::warn!(path = %path.display(), error = %e, "failed to write");
+            return None;
+        }
+    }
+    remember(&path);
+    Some(Icon::from_file(&path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn collects_icons_of_dead_instances_and_old_ones() {
+        let dir = temp_dir().join(format!("trayson-gc-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        // above the largest pid Linux hands out, and pid 1 that always runs
+        let (dead, live) = (dir.join("4294967"), dir.join("1"));
+        for dir in [&dead, &live] {
+            std::fs::create_dir_all(dir).unwrap();
+        }
+        let write = |path: PathBuf, age: u64| {
+            let file = std::fs::File::create(&path).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+            path
+        };
+        let crashed = write(dead.join("1f2e.png"), 0);
+        let unknown = write(dead.join("notes.txt"), 0);
+        let running = write(live.join("3c4d.png"), 0);
+        let old = write(dir.join("5e6f.png"), 7200);
+        let recent = write(dir.join("7a8b.png"), 0);
+        let avatar = write(dir.join("avatar-9c-65-22.qoi"), 7200);
+
+        assert_eq!(collect_in(&dir, Some(Duration::from_secs(3600))), 3);
+        assert!(!crashed.exists() && !old.exists() && !avatar.exists());
+        assert!(unknown.exists() && running.exists() && recent.exists());
+        // kept as long as something else is in it
+        assert!(dead.exists());
+
+        std::fs::remove_file(unknown).unwrap();
+        write(dead.join("aaaa.ff"), 0);
+        assert_eq!(collect_in(&dir, None), 1);
+        assert!(!dead.exists() && recent.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_held_files_until_released() {
+        let path = temp_dir().join(format!("trayson-hold-test-{}.png", std::process::id()));
+        std::fs::write(&path, b"").unwrap();
+        let held = hold();
+        remember(&path);
+        // fresh for one call, held for any number after
+        release_unused([]);
+        release_unused([]);
+        release_unused([]);
+        assert!(path.exists());
+        drop(held);
+        release_unused([]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn recognizes_files_written_here() {
+        assert!(written_here("0123abcd.png"));
+        assert!(written_here("avatar-ff-65-22.qoi"));
+        assert!(!written_here("avatar-ff-x-22.qoi"));
+        assert!(!written_here("0123abcd.txt"));
+        assert!(!written_here("notes.png"));
+        assert!(!written_here("0123456789abcdef0.png"));
+    }
+}
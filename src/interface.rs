@@ -0,0 +1,159 @@
+use serde_json::Value;
+use std::collections::HashMap;
+use zbus::{dbus_interface, Connection, SignalContext};
+
+pub const PATH: &str = "/org/trayson/Tray";
+
+/// `org.trayson.Tray`, the aggregated tray state for other D-Bus clients.
+/// Items are exchanged as the same JSON documents printed on stdout, in full
+/// with `ItemsChanged` or one by one with `ItemAdded`, `ItemChanged` and
+/// `ItemRemoved`.
+pub struct TrayInterface {
+    items: String,
+    /// Each of `items` by its `id`
+    by_id: HashMap<String, String>,
+}
+
+#[dbus_interface(name = "org.trayson.Tray")]
+impl TrayInterface {
+    async fn get_items(&self) -> String {
+        self.items.clone()
+    }
+
+    #[dbus_interface(property)]
+    async fn items(&self) -> String {
+        self.items.clone()
+    }
+
+    #[dbus_interface(signal, name = "ItemsChanged")]
+    async fn items_updated(ctxt: &SignalContext<'_>, items: &str) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn item_added(ctxt: &SignalContext<'_>, id: &str, item: &str) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn item_changed(ctxt: &SignalContext<'_>, id: &str, item: &str) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn item_removed(ctxt: &SignalContext<'_>, id: &str) -> zbus::Result<()>;
+}
+
+impl Default for TrayInterface {
+    fn default() -> Self {
+        TrayInterface {
+            items: "[]".to_string(),
+            by_id: HashMap::new(),
+        }
+    }
+}
+
+impl TrayInterface {
+    /// Stores the new state served at [`PATH`] on `conn` and notifies listeners,
+    /// logging the signals that fail to go out.
+    pub async fn publish(conn: &Connection, items: &[Value]) -> zbus::Result<()> {
+        let iface = conn
+            .object_server()
+            .interface::<_, TrayInterface>(PATH)
+            .await?;
+        let json = Value::from(items).to_string();
+        let mut tray = iface.get_mut().await;
+        if tray.items == json {
+            return Ok(());
+        }
+        let (by_id, changes) = diff(&tray.by_id, items);
+        tray.items = json;
+        tray.by_id = by_id;
+
+        let ctxt = iface.signal_context();
+        let mut sent = vec![
+            tray.items_changed(ctxt).await,
+            TrayInterface::items_updated(ctxt, &tray.items).await,
+        ];
+        for change in &changes {
+            sent.push(match change {
+                Change::Added(id, json) => TrayInterface::item_added(ctxt, id, json).await,
+                Change::Changed(id, json) => TrayInterface::item_changed(ctxt, id, json).await,
+                Change::Removed(id) => TrayInterface::item_removed(ctxt, id).await,
+            });
+        }
+        for e in sent.into_iter().filter_map(Result::err) {
+            tracing::warn!(error = %e, "failed to signal a change of the items");
+        }
+        Ok(())
+    }
+}
+
+/// How an item differs from when it was published before.
+#[derive(Debug, PartialEq)]
+enum Change {
+    Added(String, String),
+    Changed(String, String),
+    Removed(String),
+}
+
+/// Each of `items` by its `id`, with how they differ from `previous`.
+fn diff(
+    previous: &HashMap<String, String>,
+    items: &[Value],
+) -> (HashMap<String, String>, Vec<Change>) {
+    let mut by_id = HashMap::new();
+    let mut changes = Vec::new();
+    for item in items {
+        let Some(id) = item["id"].as_str() else {
+            continue;
+        };
+        let json = item.to_string();
+        match previous.get(id) {
+            None => changes.push(Change::Added(id.to_string(), json.clone())),
+            Some(old) if *old != json => {
+                changes.push(Change::Changed(id.to_string(), json.clone()))
+            }
+            Some(_) => {}
+        }
+        by_id.insert(id.to_string(), json);
+    }
+    let mut removed = previous
+        .keys()
+        .filter(|id| !by_id.contains_key(*id))
+        .cloned()
+        .collect::<Vec<_>>();
+    removed.sort();
+    changes.extend(removed.into_iter().map(Change::Removed));
+    (by_id, changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn diffs_items_by_id() {
+        let items = [json!({"id": "a", "title": "A"}), json!({"id": "b"})];
+        let (by_id, changes) = diff(&HashMap::new(), &items);
+        assert_eq!(
+            changes,
+            vec![
+                Change::Added("a".to_string(), items[0].to_string()),
+                Change::Added("b".to_string(), items[1].to_string()),
+            ]
+        );
+        let items = [
+            json!({"id": "a", "title": "Changed"}),
+            json!({"id": "c"}),
+            json!({"title": "no id"}),
+        ];
+        let (by_id, changes) = diff(&by_id, &items);
+        assert_eq!(
+            changes,
+            vec![
+                Change::Changed("a".to_string(), items[0].to_string()),
+                Change::Added("c".to_string(), items[1].to_string()),
+                Change::Removed("b".to_string()),
+            ]
+        );
+        assert_eq!(by_id.len(), 2);
+        let (_, changes) = diff(&by_id, &items);
+        assert_eq!(changes, Vec::new());
+    }
+}
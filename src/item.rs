@@ -0,0 +1,658 @@
+use crate::desktop::{self, App};
+use crate::error::Error;
+use crate::icon::{self, Icon};
+use crate::menu::{self, DBusMenuProxy, MenuEntry};
+use crate::metrics::METRICS;
+use crate::rt;
+use async_std::future;
+use serde::Serialize;
+use std::future::Future;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::Instrument;
+use zbus::fdo::{self, ConnectionCredentials, DBusProxy};
+use zbus::zvariant::{DeserializeDict, OwnedObjectPath, OwnedValue, Type, Value};
+use zbus::{dbus_proxy, Connection};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Item {
+    /// Stable identifier used to address the item, assigned by the registry
+    pub id: String,
+    /// The `Id` property as published by the application
+    pub sni_id: String,
+    pub title: String,
+    pub category: String,
+    pub status: String,
+    /// Whether `status` is `NeedsAttention`
+    pub urgent: bool,
+    /// When the item changed to `NeedsAttention`, in seconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attention_since: Option<u64>,
+    pub tooltip: ToolTip,
+    pub icon: Icon,
+    /// `IconName`, for bars looking it up in a theme of their own
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub icon_name: String,
+    /// The icon to show for the status: the attention icon while `urgent`,
+    /// else `icon` with the overlay icon drawn over it, see
+    /// [`Item::update_effective_icon`]
+    pub effective_icon: Icon,
+    /// `AttentionIconPixmap`, else `AttentionIconName`
+    #[serde(skip)]
+    pub attention_icon: Option<Icon>,
+    /// `OverlayIconPixmap`, else `OverlayIconName`
+    #[serde(skip)]
+    pub overlay_icon: Option<Icon>,
+    /// Set while the application does not answer within `--call-timeout`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub unresponsive: bool,
+    /// Set while the application does not answer the pings of
+    /// `--ping-interval`, it is dropped after `--stale-grace`
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+    /// `XAyatanaOrderingIndex` of AppIndicators, lower ones come first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ordering_index: Option<u32>,
+    /// `XAyatanaLabel`, text to show next to the icon
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub label: String,
+    /// `XAyatanaLabelGuide`, the longest text the label is expected to take
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub label_guide: String,
+    /// Process owning the item, as told by the bus
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<u32>,
+    /// Whether the process runs as another user than trayson
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub foreign: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exe: Option<String>,
+    /// `WindowId`, the X11 window of the application, for `raise`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub window_id: Option<u32>,
+    /// The desktop entry of the application, if one matches
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app: Option<App>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub menu: Option<Vec<MenuEntry>>,
+    #[serde(skip)]
+    pub menu_path: Option<OwnedObjectPath>,
+    /// [`icon::pixmap_hash`] of the pixmap `icon` was saved from, 0 if it
+    /// comes from elsewhere
+    #[serde(skip)]
+    pub pixmap_hash: u64,
+}
+
+pub type Pixmap = Vec<(i32, i32, Vec<u8>)>;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ToolTip {
+    pub title: String,
+    pub description: String,
+}
+
+impl From<(String, Pixmap, String, String)> for ToolTip {
+    fn from((_, _, title, description): (String, Pixmap, String, String)) -> Self {
+        ToolTip { title, description }
+    }
+}
+
+//https://www.freedesktop.org/wiki/Specifications/StatusNotifierItem/StatusNotifierItem/
+#[dbus_proxy(
+    interface = "org.kde.StatusNotifierItem",
+    default_path = "/StatusNotifierItem",
+    gen_async = true
+)]
+pub trait StatusNotifierItem {
+    #[dbus_proxy(property)]
+    fn category(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn id(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn title(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn status(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn window_id(&self) -> zbus::Result<OwnedValue>;
+
+    #[dbus_proxy(property)]
+    fn icon_name(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn icon_pixmap(&self) -> zbus::Result<Pixmap>;
+
+    #[dbus_proxy(property)]
+    fn overlay_icon_name(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn overlay_icon_pixmap(&self) -> zbus::Result<Pixmap>;
+
+    #[dbus_proxy(property)]
+    fn attention_icon_name(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn attention_icon_pixmap(&self) -> zbus::Result<Pixmap>;
+
+    #[dbus_proxy(property)]
+    fn attention_movie_name(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn icon_theme_path(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property)]
+    fn tool_tip(&self) -> zbus::Result<(String, Pixmap, String, String)>;
+
+    #[dbus_proxy(property)]
+    fn item_is_menu(&self) -> zbus::Result<bool>;
+
+    #[dbus_proxy(property)]
+    fn menu(&self) -> zbus::Result<OwnedObjectPath>;
+
+    #[dbus_proxy(property, name = "XAyatanaOrderingIndex")]
+    fn x_ayatana_ordering_index(&self) -> zbus::Result<u32>;
+
+    #[dbus_proxy(property, name = "XAyatanaLabel")]
+    fn x_ayatana_label(&self) -> zbus::Result<String>;
+
+    #[dbus_proxy(property, name = "XAyatanaLabelGuide")]
+    fn x_ayatana_label_guide(&self) -> zbus::Result<String>;
+
+    fn context_menu(&self, x: i32, y: i32) -> zbus::Result<()>;
+
+    fn activate(&self, x: i32, y: i32) -> zbus::Result<()>;
+
+    fn secondary_activate(&self, x: i32, y: i32) -> zbus::Result<()>;
+
+    fn scroll(&self, delta: &i32, orientation: String) -> zbus::Result<()>;
+
+    fn provide_xdg_activation_token(&self, token: String) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn new_title(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn new_icon(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn new_attention_icon(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn new_overlay_icon(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn new_tool_tip(&self) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn new_status(&self, status: String) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal, name = "XAyatanaNewLabel")]
+    fn x_ayatana_new_label(&self, label: String, guide: String) -> zbus::Result<()>;
+}
+
+/// Builds a proxy for the item owning `service`, on `org.freedesktop.StatusNotifierItem`
+/// for applications that implement that instead of the KDE interface.
+pub async fn proxy(
+    conn: &Connection,
+    service: &str,
+) -> zbus::Result<StatusNotifierItemProxy<'static>> {
+    match probe(conn, service).await? {
+        Some(proxy) => Ok(proxy),
+        None => build_proxy(conn, service, INTERFACES[0]).await,
+    }
+}
+
+/// Like [`proxy`], but `None` if `service` exports no item under either interface.
+pub async fn probe(
+    conn: &Connection,
+    service: &str,
+) -> zbus::Result<Option<StatusNotifierItemProxy<'static>>> {
+    for interface in INTERFACES {
+        let proxy = build_proxy(conn, service, interface).await?;
+        if timed("Id", proxy.id()).await.is_ok() {
+            return Ok(Some(proxy));
+        }
+    }
+    Ok(None)
+}
+
+/// Milliseconds [`timed`] waits for an answer.
+static CALL_TIMEOUT: AtomicU64 = AtomicU64::new(5000);
+
+pub fn set_call_timeout(timeout: Duration) {
+    CALL_TIMEOUT.store(timeout.as_millis() as u64, Ordering::Relaxed);
+}
+
+/// Fails `call` with [`io::ErrorKind::TimedOut`] if the application does not
+/// answer in time, so a frozen one can't block its caller forever. It runs in
+/// a `call` span named after the `method` it makes.
+pub async fn timed<T>(
+    method: &'static str,
+    call: impl Future<Output = zbus::Result<T>>,
+) -> zbus::Result<T> {
+    let timeout = Duration::from_millis(CALL_TIMEOUT.load(Ordering::Relaxed));
+    async {
+        let started = Instant::now();
+        let res = future::timeout(timeout, call).await.unwrap_or_else(|_| {
+            let e = io::Error::new(io::ErrorKind::TimedOut, "no answer in time");
+            Err(zbus::Error::InputOutput(Arc::new(e)))
+        });
+        match &res {
+            Ok(_) => tracing::trace!(elapsed = ?started.elapsed(), "answered"),
+            Err(e) => tracing::debug!(error = %e, "failed"),
+        }
+        res
+    }
+    .instrument(tracing::debug_span!("call", method))
+    .await
+}
+
+/// Whether `e` comes from [`timed`].
+pub fn timed_out(e: &zbus::Error) -> bool {
+    matches!(e, zbus::Error::InputOutput(e) if e.kind() == io::ErrorKind::TimedOut)
+}
+
+/// Whether the application behind `proxy` still answers at all.
+pub async fn responds(proxy: &StatusNotifierItemProxy<'_>) -> bool {
+    timed("Id", proxy.id()).await.is_ok()
+}
+
+/// Splits a service as registered with the watcher, `busname` or
+/// `busname/objectpath`, into destination and path.
+pub fn parse_service(service: &str) -> (&str, &str) {
+    match service.find('/') {
+        Some(i) => service.split_at(i),
+        None => (service, "/StatusNotifierItem"),
+    }
+}
+
+const INTERFACES: [&str; 2] = [
+    "org.kde.StatusNotifierItem",
+    "org.freedesktop.StatusNotifierItem",
+];
+
+async fn build_proxy(
+    conn: &Connection,
+    service: &str,
+    interface: &'static str,
+) -> zbus::Result<StatusNotifierItemProxy<'static>> {
+    let (destination, path) = parse_service(service);
+    StatusNotifierItemProxy::builder(conn)
+        .cache_properties(zbus::CacheProperties::No)
+        .destination(destination.to_string())?
+        .path(path.to_string())?
+        .interface(interface)?
+        .build()
+        .await
+}
+
+impl Item {
+    /// Reads the properties of the item behind `proxy` and saves its icon.
+    pub async fn fetch(proxy: &StatusNotifierItemProxy<'_>) -> Result<Item, Error> {
+        let service = proxy.destination().to_string();
+        let props = match ItemProperties::get_all(proxy).await {
+            Ok(props) => props,
+            Err(e) if timed_out(&e) || unreachable(&e) => return Err(e.into()),
+            Err(e) => {
+                tracing::debug!(error = %e, "reading the properties one by one");
+                ItemProperties::get_each(proxy).await?
+            }
+        };
+        if props == ItemProperties::default() {
+            return Err(zbus::Error::Failure("no property could be read".to_string()).into());
+        }
+        let sni_id = or_record(props.id, "Id", &service);
+        // the `Id` until a `NewTitle` tells it
+        let title = or_record_else(props.title, "Title", &service, || sni_id.clone());
+        let category = or_record(props.category, "Category", &service);
+        let status = or_record_else(props.status, "Status", &service, || "Active".to_string());
+        let tooltip = or_record(props.tool_tip, "ToolTip", &service).into();
+        let menu_path = props
+            .menu
+            .filter(|path| !matches!(path.as_str(), "/" | "/NO_DBUSMENU"));
+        let credentials = owner_credentials(proxy).await;
+        let pid = credentials.as_ref().and_then(|c| c.process_id());
+        let uid = credentials.as_ref().and_then(|c| c.unix_user_id());
+        let (exe, app) = {
+            let sni_id = sni_id.clone();
+            // reads the desktop entries and icon themes the first time
+            rt::spawn_blocking(move || {
+                let exe = pid.and_then(|pid| std::fs::read_link(format!("/proc/{}/exe", pid)).ok());
+                let app = desktop::find(&sni_id, exe.as_deref());
+                (exe, app)
+            })
+            .await
+        };
+        // apps with only an `IconName` send no pixmap at all
+        let icon_name = props.icon_name.unwrap_or_default();
+        let theme_path = props.icon_theme_path.unwrap_or_default();
+        let (icon, pixmap_hash) = icon::resolve(
+            &props.icon_pixmap.unwrap_or_default(),
+            &icon_name,
+            &theme_path,
+            app.as_ref().and_then(|app| app.icon.as_deref()),
+            &sni_id,
+            &title,
+        )
+        .await
+        .unwrap_or_default();
+        let attention_icon = icon::pixmap_or_named(
+            &props.attention_icon_pixmap.unwrap_or_default(),
+            &props.attention_icon_name.unwrap_or_default(),
+            &theme_path,
+        )
+        .await;
+        let overlay_icon = icon::pixmap_or_named(
+            &props.overlay_icon_pixmap.unwrap_or_default(),
+            &props.overlay_icon_name.unwrap_or_default(),
+            &theme_path,
+        )
+        .await;
+        let mut item = Item {
+            id: String::new(),
+            sni_id,
+            title,
+            category,
+            status: String::new(),
+            urgent: false,
+            attention_since: None,
+            tooltip,
+            icon,
+            icon_name,
+            effective_icon: Icon::default(),
+            attention_icon: attention_icon.map(|(icon, _)| icon),
+            overlay_icon: overlay_icon.map(|(icon, _)| icon),
+            unresponsive: false,
+            stale: false,
+            ordering_index: props.ordering_index,
+            label: props.label.unwrap_or_default(),
+            label_guide: props.label_guide.unwrap_or_default(),
+            pid,
+            uid,
+            foreign: uid.is_some_and(is_foreign),
+            exe: exe.map(|exe| exe.to_string_lossy().into_owned()),
+            window_id: props.window_id.as_deref().and_then(window_id),
+            app,
+            menu: None,
+            menu_path,
+            pixmap_hash,
+        };
+        item.set_status(status);
+        item.update_effective_icon().await;
+        if let Some(menu) = item.menu_proxy(proxy).await {
+            item.menu = timed("GetLayout", menu::fetch_shown(&menu)).await.ok();
+        }
+        Ok(item)
+    }
+
+    /// Sets `status` along with `urgent` and `attention_since`.
+    pub fn set_status(&mut self, status: String) {
+        self.urgent = status == "NeedsAttention";
+        self.attention_since = match self.urgent {
+            true => self.attention_since.or_else(|| {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?;
+                Some(now.as_secs())
+            }),
+            false => None,
+        };
+        self.status = status;
+    }
+
+    /// Picks `effective_icon` for the current status and icons.
+    pub async fn update_effective_icon(&mut self) {
+        self.effective_icon = match (&self.attention_icon, &self.overlay_icon) {
+            (Some(attention), _) if self.urgent => attention.clone(),
+            (_, Some(overlay)) => {
+                let (base, overlay) = (self.icon.clone(), overlay.clone());
+                rt::spawn_blocking(move || icon::composite(&base, &overlay))
+                    .await
+                    .unwrap_or_else(|| self.icon.clone())
+            }
+            _ => self.icon.clone(),
+        };
+    }
+
+    /// Proxy for the dbusmenu exported by the item, if it has one.
+    pub async fn menu_proxy(
+        &self,
+        proxy: &StatusNotifierItemProxy<'_>,
+    ) -> Option<DBusMenuProxy<'static>> {
+        DBusMenuProxy::builder(proxy.connection())
+            .cache_properties(zbus::CacheProperties::No)
+            .destination(proxy.destination().to_owned())
+            .ok()?
+            .path(self.menu_path.clone()?)
+            .ok()?
+            .build()
+            .await
+            .ok()
+    }
+}
+
+/// Process and user of the connection owning the item.
+async fn owner_credentials(proxy: &StatusNotifierItemProxy<'_>) -> Option<ConnectionCredentials> {
+    let dbus = DBusProxy::new(proxy.connection()).await.ok()?;
+    dbus.get_connection_credentials(proxy.destination().to_owned())
+        .await
+        .ok()
+}
+
+/// Whether `uid` is another user than the one running trayson.
+pub fn is_foreign(uid: u32) -> bool {
+    uid != unsafe { libc::getuid() }
+}
+
+/// Falls back to the default for missing properties, counting them apart
+/// from failed calls.
+fn or_record<T: Default>(value: Option<T>, property: &str, service: &str) -> T {
+    or_record_else(value, property, service, T::default)
+}
+
+/// Like [`or_record`], with the result of `default` instead.
+fn or_record_else<T>(
+    value: Option<T>,
+    property: &str,
+    service: &str,
+    default: impl FnOnce() -> T,
+) -> T {
+    value.unwrap_or_else(|| {
+        tracing::debug!(property, "missing, using the default");
+        METRICS.missing_property(service);
+        default()
+    })
+}
+
+/// A `WindowId` of either type, `None` for 0.
+fn window_id(value: &Value) -> Option<u32> {
+    match value {
+        Value::U32(id) => Some(*id),
+        Value::I32(id) => u32::try_from(*id).ok(),
+        _ => None,
+    }
+    .filter(|id| *id != 0)
+}
+
+/// `AttentionIconPixmap`, else `AttentionIconName` of the item behind `proxy`.
+pub async fn attention_icon(proxy: &StatusNotifierItemProxy<'_>) -> zbus::Result<Option<Icon>> {
+    let pixmaps = optional(timed("AttentionIconPixmap", proxy.attention_icon_pixmap()).await)?;
+    let name = optional(timed("AttentionIconName", proxy.attention_icon_name()).await)?;
+    extra_icon(proxy, pixmaps, name).await
+}
+
+/// `OverlayIconPixmap`, else `OverlayIconName` of the item behind `proxy`.
+pub async fn overlay_icon(proxy: &StatusNotifierItemProxy<'_>) -> zbus::Result<Option<Icon>> {
+    let pixmaps = optional(timed("OverlayIconPixmap", proxy.overlay_icon_pixmap()).await)?;
+    let name = optional(timed("OverlayIconName", proxy.overlay_icon_name()).await)?;
+    extra_icon(proxy, pixmaps, name).await
+}
+
+async fn extra_icon(
+    proxy: &StatusNotifierItemProxy<'_>,
+    pixmaps: Option<Pixmap>,
+    name: Option<String>,
+) -> zbus::Result<Option<Icon>> {
+    let theme_path = optional(timed("IconThemePath", proxy.icon_theme_path()).await)?;
+    let icon = icon::pixmap_or_named(
+        &pixmaps.unwrap_or_default(),
+        &name.unwrap_or_default(),
+        &theme_path.unwrap_or_default(),
+    )
+    .await;
+    Ok(icon.map(|(icon, _)| icon))
+}
+
+/// `None` for properties the application does not have. Timeouts are passed
+/// on, the following calls would only time out as well, and so is an object
+/// that is not there (yet).
+pub fn optional<T>(res: zbus::Result<T>) -> zbus::Result<Option<T>> {
+    match res {
+        Err(e) if timed_out(&e) || unreachable(&e) => Err(e),
+        res => Ok(res.ok()),
+    }
+}
+
+/// Whether `e` says there is no such service, object or interface at all,
+/// rather than no such property.
+pub fn unreachable(e: &zbus::Error) -> bool {
+    match e {
+        zbus::Error::FDO(e) => matches!(
+            **e,
+            fdo::Error::ServiceUnknown(_)
+                | fdo::Error::NameHasNoOwner(_)
+                | fdo::Error::UnknownObject(_)
+                | fdo::Error::UnknownInterface(_)
+        ),
+        zbus::Error::MethodError(name, _, _) => matches!(
+            name.as_str(),
+            "org.freedesktop.DBus.Error.ServiceUnknown"
+                | "org.freedesktop.DBus.Error.NameHasNoOwner"
+                | "org.freedesktop.DBus.Error.UnknownObject"
+                | "org.freedesktop.DBus.Error.UnknownInterface"
+        ),
+        _ => false,
+    }
+}
+
+/// The properties [`Item::fetch`] reads, all at once with `GetAll`.
+#[derive(Debug, Default, PartialEq, DeserializeDict, Type)]
+#[zvariant(signature = "a{sv}", rename_all = "PascalCase")]
+struct ItemProperties {
+    id: Option<String>,
+    title: Option<String>,
+    category: Option<String>,
+    status: Option<String>,
+    tool_tip: Option<(String, Pixmap, String, String)>,
+    icon_pixmap: Option<Pixmap>,
+    icon_name: Option<String>,
+    icon_theme_path: Option<String>,
+    attention_icon_pixmap: Option<Pixmap>,
+    attention_icon_name: Option<String>,
+    overlay_icon_pixmap: Option<Pixmap>,
+    overlay_icon_name: Option<String>,
+    menu: Option<OwnedObjectPath>,
+    /// `i` in the spec, `u` for some
+    window_id: Option<OwnedValue>,
+    // only set by libayatana-appindicator
+    #[zvariant(rename = "XAyatanaOrderingIndex")]
+    ordering_index: Option<u32>,
+    #[zvariant(rename = "XAyatanaLabel")]
+    label: Option<String>,
+    #[zvariant(rename = "XAyatanaLabelGuide")]
+    label_guide: Option<String>,
+}
+
+impl ItemProperties {
+    async fn get_all(proxy: &StatusNotifierItemProxy<'_>) -> zbus::Result<Self> {
+        let reply = timed(
+            "GetAll",
+            proxy.connection().call_method(
+                Some(proxy.destination().to_owned()),
+                proxy.path().to_owned(),
+                Some("org.freedesktop.DBus.Properties"),
+                "GetAll",
+                &(proxy.interface().as_str()),
+            ),
+        )
+        .await?;
+        reply.body()
+    }
+
+    /// One call per property, for applications whose `GetAll` fails or returns
+    /// values of the wrong type. Each of them may be missing.
+    async fn get_each(proxy: &StatusNotifierItemProxy<'_>) -> zbus::Result<Self> {
+        Ok(ItemProperties {
+            id: optional(timed("Id", proxy.id()).await)?,
+            title: optional(timed("Title", proxy.title()).await)?,
+            category: optional(timed("Category", proxy.category()).await)?,
+            status: optional(timed("Status", proxy.status()).await)?,
+            tool_tip: optional(timed("ToolTip", proxy.tool_tip()).await)?,
+            icon_pixmap: optional(timed("IconPixmap", proxy.icon_pixmap()).await)?,
+            icon_name: optional(timed("IconName", proxy.icon_name()).await)?,
+            icon_theme_path: optional(timed("IconThemePath", proxy.icon_theme_path()).await)?,
+            attention_icon_pixmap: optional(
+                timed("AttentionIconPixmap", proxy.attention_icon_pixmap()).await,
+            )?,
+            attention_icon_name: optional(
+                timed("AttentionIconName", proxy.attention_icon_name()).await,
+            )?,
+            overlay_icon_pixmap: optional(
+                timed("OverlayIconPixmap", proxy.overlay_icon_pixmap()).await,
+            )?,
+            overlay_icon_name: optional(timed("OverlayIconName", proxy.overlay_icon_name()).await)?,
+            menu: optional(timed("Menu", proxy.menu()).await)?,
+            window_id: optional(timed("WindowId", proxy.window_id()).await)?,
+            ordering_index: optional(
+                timed("XAyatanaOrderingIndex", proxy.x_ayatana_ordering_index()).await,
+            )?,
+            label: optional(timed("XAyatanaLabel", proxy.x_ayatana_label()).await)?,
+            label_guide: optional(
+                timed("XAyatanaLabelGuide", proxy.x_ayatana_label_guide()).await,
+            )?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    const SERVICE: &str = "org.kde.StatusNotifierItem-1-1";
+
+    fn fetch(id: Option<&str>, title: &str) -> Result<Item, Error> {
+        crate::rt::block_on(async {
+            let (conn, _server) = mock::peers(id, title).await?;
+            let proxy = build_proxy(&conn, SERVICE, INTERFACES[0]).await?;
+            Item::fetch(&proxy).await
+        })
+    }
+
+    #[test]
+    fn fails_for_an_object_not_exported() {
+        match fetch(None, "") {
+            Err(Error::DBus(e)) => assert!(unreachable(&e), "{}", e),
+            other => panic!("fetched {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_an_exported_item() {
+        let dir = std::env::temp_dir().join(format!("trayson-test-{}", std::process::id()));
+        icon::configure(icon::IconConfig {
+            dir: Some(dir.clone()),
+            ..Default::default()
+        });
+        let item = fetch(Some("mock"), "Mock").unwrap();
+        assert_eq!(item.sni_id, "mock");
+        assert_eq!(item.title, "Mock");
+        assert_eq!(item.status, "Active");
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
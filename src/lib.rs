@@ -0,0 +1,64 @@
+//! Prints StatusNotifierItems as JSON, see `trayson --help`. Rust programs can
+//! embed the host with [`Tray`] instead of reading that output.
+
+mod actions;
+mod args;
+#[cfg(feature = "bridge")]
+mod bridge;
+mod cbor;
+pub mod cli;
+mod client;
+mod command;
+mod config;
+mod control;
+mod desktop;
+mod error;
+#[cfg(feature = "ffi")]
+mod ffi;
+mod filter;
+mod forward;
+#[cfg(feature = "gui")]
+mod gui;
+mod history;
+mod hooks;
+mod host;
+#[cfg(feature = "http")]
+mod http;
+mod i3blocks;
+mod icon;
+mod interface;
+mod item;
+mod logging;
+mod lua;
+mod markup;
+mod menu;
+mod metrics;
+mod mock;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod naming;
+mod notify;
+mod output;
+mod overrides;
+mod raw;
+mod record;
+mod registry;
+mod rt;
+mod schema;
+mod script;
+mod sink;
+mod stamp;
+mod strip;
+mod systemd;
+mod tray;
+mod watcher;
+#[cfg(feature = "x11")]
+mod x11;
+
+pub use args::Mode;
+pub use command::CommandError;
+pub use desktop::App;
+pub use icon::Icon;
+pub use item::{Item, ToolTip};
+pub use menu::MenuEntry;
+pub use tray::{Tray, TrayError, TrayEvent};
@@ -0,0 +1,400 @@
+//! Diagnostics through `tracing`, warnings and errors unless `$TRAYSON_LOG`
+//! asks for others.
+//!
+//! `$TRAYSON_LOG` takes levels like `RUST_LOG`, for everything or by target:
+//! `debug`, `trayson=trace,zbus=warn`, `off`. Items run in an `item{service}` span and
+//! each call to an application in a `call{method}` one inside it, so
+//!
+//! ```text
+//! DEBUG item{service=":1.42"}:call{method="Title"}: trayson::item: failed error=...
+//! ```
+//!
+//! tells which property of which item could not be read. Records go to stderr
+//! or, with `--log journald`, to the journal with their level as `PRIORITY`.
+
+use clap::ValueEnum;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogTarget {
+    Stderr,
+    Journald,
+}
+
+/// Installs the logger with the levels of `$TRAYSON_LOG`, to `target` or else
+/// the journal when stderr already goes there.
+pub fn init(target: Option<LogTarget>) -> Result<(), FilterError> {
+    let directives = std::env::var("TRAYSON_LOG").unwrap_or_else(|_| "warn".to_string());
+    let filter = directives.parse()?;
+    let target = target.unwrap_or(match std::env::var_os("JOURNAL_STREAM") {
+        Some(_) => LogTarget::Journald,
+        None => LogTarget::Stderr,
+    });
+    let journal = match target {
+        LogTarget::Journald => UnixDatagram::unbound().ok(),
+        LogTarget::Stderr => None,
+    };
+    // only fails if one is set already
+    let _ = tracing::subscriber::set_global_default(Logger::new(filter, journal));
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct FilterError(String);
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "TRAYSON_LOG: invalid level `{}`", self.0)
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+/// The parsed `$TRAYSON_LOG`, the longest matching target wins.
+struct Filter {
+    default: LevelFilter,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl std::str::FromStr for Filter {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut filter = Filter {
+            default: LevelFilter::OFF,
+            targets: Vec::new(),
+        };
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            let parse = |level: &str| level.parse().map_err(|_| FilterError(level.to_string()));
+            match directive.split_once('=') {
+                Some((target, level)) => filter.targets.push((target.to_string(), parse(level)?)),
+                None => match parse(directive) {
+                    Ok(level) => filter.default = level,
+                    // a bare target enables everything of it
+                    Err(_) => filter
+                        .targets
+                        .push((directive.to_string(), LevelFilter::TRACE)),
+                },
+            }
+        }
+        Ok(filter)
+    }
+}
+
+impl Filter {
+    fn enabled(&self, target: &str, level: &Level) -> bool {
+        let target = display_target(target);
+        let filter = self
+            .targets
+            .iter()
+            .filter(|(prefix, _)| {
+                target
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.default, |(_, level)| *level);
+        LevelFilter::from_level(*level) <= filter
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, LevelFilter::max)
+    }
+}
+
+/// Our own targets are named after the binary rather than the package.
+fn display_target(target: &str) -> std::borrow::Cow<'_, str> {
+    match target.strip_prefix("dbus_tray") {
+        Some(rest) => format!("trayson{}", rest).into(),
+        None => target.into(),
+    }
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: String,
+    parent: Option<Id>,
+    refs: usize,
+}
+
+struct Logger {
+    filter: Filter,
+    /// Socket to the journal, otherwise records go to stderr
+    journal: Option<UnixDatagram>,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last.
+    static CURRENT: RefCell<Vec<Id>> = const { RefCell::new(Vec::new()) };
+}
+
+fn current() -> Option<Id> {
+    CURRENT.with(|current| current.borrow().last().cloned())
+}
+
+/// Writes fields as ` name=value`, the message separately.
+#[derive(Default)]
+struct Fields {
+    message: String,
+    rest: String,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            let _ = write!(self.rest, " {}={:?}", field.name(), value);
+        }
+    }
+}
+
+impl Logger {
+    fn new(filter: Filter, journal: Option<UnixDatagram>) -> Logger {
+        Logger {
+            filter,
+            journal,
+            spans: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// `outer{fields}:inner{fields}` for `span` and its parents.
+    fn context(&self, span: Option<Id>) -> String {
+        let spans = self.spans.lock().unwrap();
+        let mut chain = Vec::new();
+        let mut next = span;
+        while let Some(data) = next.and_then(|id| spans.get(&id.into_u64())) {
+            chain.push(format!("{}{{{}}}", data.name, data.fields.trim_start()));
+            next = data.parent.clone();
+        }
+        chain.reverse();
+        chain.join(":")
+    }
+
+    fn journal(&self, journal: &UnixDatagram, level: &Level, message: &str) {
+        let priority = match *level {
+            Level::ERROR => 3,
+            Level::WARN => 4,
+            Level::INFO => 6,
+            _ => 7,
+        };
+        let mut datagram =
+            format!("PRIORITY={}\nSYSLOG_IDENTIFIER=trayson\n", priority).into_bytes();
+        // sent with its length, as it may span lines
+        datagram.extend(b"MESSAGE\n");
+        datagram.extend((message.len() as u64).to_le_bytes());
+        datagram.extend(message.as_bytes());
+        datagram.push(b'\n');
+        let _ = journal.send_to(&datagram, JOURNAL_SOCKET);
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.filter.enabled(metadata.target(), metadata.level())
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.filter.max_level())
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let parent = match attrs.parent() {
+            Some(parent) => Some(parent.clone()),
+            None if attrs.is_contextual() => current(),
+            None => None,
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut spans = self.spans.lock().unwrap();
+        // kept for the context of the new one
+        if let Some(parent) = parent.as_ref().and_then(|p| spans.get_mut(&p.into_u64())) {
+            parent.refs += 1;
+        }
+        spans.insert(
+            id,
+            SpanData {
+                name: attrs.metadata().name(),
+                fields: fields.rest,
+                parent,
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.fields.push_str(&fields.rest);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let span = match event.parent() {
+            Some(parent) => Some(parent.clone()),
+            None if event.is_contextual() => current(),
+            None => None,
+        };
+        let metadata = event.metadata();
+        let mut line = self.context(span);
+        if !line.is_empty() {
+            line.push_str(": ");
+        }
+        let _ = write!(
+            line,
+            "{}: {}{}",
+            display_target(metadata.target()),
+            fields.message,
+            fields.rest
+        );
+        match &self.journal {
+            Some(journal) => self.journal(journal, metadata.level(), &line),
+            None => eprintln!("{:>5} {}", metadata.level(), line),
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        CURRENT.with(|current| current.borrow_mut().push(span.clone()));
+    }
+
+    fn exit(&self, span: &Id) {
+        CURRENT.with(|current| {
+            let mut current = current.borrow_mut();
+            if let Some(i) = current.iter().rposition(|id| id == span) {
+                current.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let mut next = Some(span.into_u64());
+        let mut closed = false;
+        while let Some(id) = next.take() {
+            let Some(data) = spans.get_mut(&id) else {
+                break;
+            };
+            data.refs -= 1;
+            if data.refs > 0 {
+                break;
+            }
+            // the parent loses the reference taken in `new_span`
+            next = spans
+                .remove(&id)
+                .and_then(|data| data.parent)
+                .map(|p| p.into_u64());
+            closed |= id == span.into_u64();
+        }
+        closed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn filter(directives: &str) -> Filter {
+        directives.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_levels_by_target() {
+        let f = filter("info, trayson::item=trace,zbus=warn,");
+        assert!(f.enabled("trayson::host", &Level::INFO));
+        assert!(!f.enabled("trayson::host", &Level::DEBUG));
+        assert!(f.enabled("trayson::item", &Level::TRACE));
+        assert!(f.enabled("dbus_tray::item", &Level::TRACE));
+        assert!(!f.enabled("zbus::connection", &Level::INFO));
+        assert!(f.enabled("zbus", &Level::WARN));
+        // only whole path segments match
+        assert!(!f.enabled("zbus_names", &Level::DEBUG));
+        assert!(f.enabled("zbus_names", &Level::INFO));
+        assert_eq!(f.max_level(), LevelFilter::TRACE);
+    }
+
+    #[test]
+    fn prefers_the_longest_target() {
+        let f = filter("trayson::item=error,trayson=debug");
+        assert!(f.enabled("trayson::host", &Level::DEBUG));
+        assert!(!f.enabled("trayson::item", &Level::WARN));
+    }
+
+    #[test]
+    fn enables_everything_of_a_bare_target() {
+        let f = filter("trayson");
+        assert!(f.enabled("trayson::item", &Level::TRACE));
+        assert!(!f.enabled("zbus", &Level::ERROR));
+        assert!(!filter("").enabled("trayson", &Level::ERROR));
+        assert!(!filter("off").enabled("trayson", &Level::ERROR));
+    }
+
+    #[test]
+    fn rejects_invalid_levels() {
+        let e = "zbus=loud".parse::<Filter>().err().unwrap();
+        assert_eq!(e.to_string(), "TRAYSON_LOG: invalid level `loud`");
+    }
+
+    #[test]
+    fn names_the_spans_of_the_context() {
+        let logger = Arc::new(Logger::new(filter("trace"), None));
+        tracing::subscriber::with_default(logger.clone(), || {
+            let item = tracing::info_span!("item", service = ":1.42");
+            let _entered = item.enter();
+            let call = tracing::debug_span!("call", method = "Title");
+            call.record("method", "ToolTip");
+            assert_eq!(
+                logger.context(call.id()),
+                r#"item{service=":1.42"}:call{method="Title" method="ToolTip"}"#
+            );
+            let detached = tracing::debug_span!(parent: None, "probe");
+            assert_eq!(logger.context(detached.id()), "probe{}");
+            assert_eq!(logger.context(None), "");
+        });
+    }
+
+    #[test]
+    fn forgets_closed_spans() {
+        let logger = Arc::new(Logger::new(filter("trace"), None));
+        tracing::subscriber::with_default(logger.clone(), || {
+            let item = tracing::info_span!("item");
+            let call = tracing::info_span!(parent: &item, "call");
+            drop(item);
+            // still the parent of `call`
+            assert_eq!(logger.context(call.id()), "item{}:call{}");
+            drop(call);
+        });
+        assert!(logger.spans.lock().unwrap().is_empty());
+    }
+}
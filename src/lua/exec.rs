@@ -0,0 +1,616 @@
+//! Walks the tree of a chunk.
+
+use super::parse::{BinOp, Block, Expr, Field, Name, Stat, UnOp};
+use super::{
+    number_to_string, Closure, Error, Function, Lua, Scope, Table, Value, MAX_STEPS, MAX_STRING,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// How deep calls of Lua functions may nest.
+const MAX_DEPTH: usize = 200;
+
+enum Flow {
+    Normal,
+    Break,
+    Return(Vec<Value>),
+}
+
+/// The varargs of the function running.
+struct Frame {
+    varargs: Vec<Value>,
+}
+
+/// Where an assignment stores its value.
+enum Place {
+    Local(Rc<RefCell<Value>>),
+    Global(Name),
+    Index(Value, Value),
+}
+
+impl Lua {
+    fn error(&self, message: &str) -> Error {
+        Error::at(&self.chunk, self.line, message)
+    }
+
+    fn fatal(&self, message: &str) -> Error {
+        Error {
+            fatal: true,
+            ..self.error(message)
+        }
+    }
+
+    /// Counts a step against the budget of [`Lua::call`].
+    pub(super) fn step(&mut self) -> Result<(), Error> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err(self.fatal("script ran too long"));
+        }
+        Ok(())
+    }
+
+    pub(super) fn call_function(
+        &mut self,
+        function: &Function,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        self.step()?;
+        let closure = match function {
+            Function::Native(native) => return native(self, args),
+            Function::Lua(closure) => closure,
+        };
+        if self.depth >= MAX_DEPTH {
+            return Err(self.fatal("stack overflow"));
+        }
+        self.depth += 1;
+        let caller = (
+            std::mem::replace(&mut self.chunk, closure.chunk.clone()),
+            self.line,
+        );
+        let scope = Scope::new(Some(closure.scope.clone()));
+        let mut args = args.into_iter();
+        for param in &closure.body.params {
+            scope.declare(param.clone(), args.next().unwrap_or_default());
+        }
+        let frame = Frame {
+            varargs: if closure.body.vararg {
+                args.collect()
+            } else {
+                Vec::new()
+            },
+        };
+        let flow = self.exec_in(&closure.body.body, &scope, &frame);
+        self.depth -= 1;
+        (self.chunk, self.line) = caller;
+        match flow? {
+            Flow::Return(values) => Ok(values),
+            Flow::Normal | Flow::Break => Ok(Vec::new()),
+        }
+    }
+
+    /// Calls `value`, which has to be a function.
+    pub(super) fn call_value(
+        &mut self,
+        value: &Value,
+        args: Vec<Value>,
+    ) -> Result<Vec<Value>, Error> {
+        match value {
+            Value::Function(function) => self.call_function(function, args),
+            _ => Err(Error::new(format!(
+                "attempt to call a {} value",
+                value.type_name()
+            ))),
+        }
+    }
+
+    fn exec_block(
+        &mut self,
+        block: &Block,
+        scope: &Rc<Scope>,
+        frame: &Frame,
+    ) -> Result<Flow, Error> {
+        self.exec_in(block, &Scope::new(Some(scope.clone())), frame)
+    }
+
+    /// Runs `block` with its locals in `scope`.
+    fn exec_in(&mut self, block: &Block, scope: &Rc<Scope>, frame: &Frame) -> Result<Flow, Error> {
+        for (stat, line) in &block.stats {
+            self.line = *line;
+            self.step()?;
+            match stat {
+                Stat::Local(names, exprs) => {
+                    let mut values = self.eval_list(exprs, scope, frame)?.into_iter();
+                    for name in names {
+                        scope.declare(name.clone(), values.next().unwrap_or_default());
+                    }
+                }
+                Stat::LocalFunction(name, body) => {
+                    scope.declare(name.clone(), Value::Nil);
+                    let function = self.closure(body, scope);
+                    if let Some(cell) = scope.lookup(name) {
+                        *cell.borrow_mut() = function;
+                    }
+                }
+                Stat::Assign(targets, exprs) => {
+                    let places = targets
+                        .iter()
+                        .map(|target| self.place(target, scope, frame))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let mut values = self.eval_list(exprs, scope, frame)?.into_iter();
+                    for (place, target) in places.into_iter().zip(targets) {
+                        self.assign(place, values.next().unwrap_or_default(), target, scope)?;
+                    }
+                }
+                Stat::Call(expr) => {
+                    self.eval_call(expr, scope, frame)?;
+                }
+                Stat::Do(body) => match self.exec_block(body, scope, frame)? {
+                    Flow::Normal => {}
+                    flow => return Ok(flow),
+                },
+                Stat::While(condition, body) => {
+                    while self.eval(condition, scope, frame)?.truthy() {
+                        match self.exec_block(body, scope, frame)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok(flow),
+                        }
+                        self.line = *line;
+                        self.step()?;
+                    }
+                }
+                Stat::Repeat(body, condition) => loop {
+                    // the condition sees the locals of the body
+                    let inner = Scope::new(Some(scope.clone()));
+                    match self.exec_in(body, &inner, frame)? {
+                        Flow::Normal => {}
+                        Flow::Break => break,
+                        flow => return Ok(flow),
+                    }
+                    if self.eval(condition, &inner, frame)?.truthy() {
+                        break;
+                    }
+                    self.line = *line;
+                    self.step()?;
+                },
+                Stat::If(branches, otherwise) => {
+                    let mut chosen = otherwise.as_ref();
+                    for (condition, body) in branches {
+                        if self.eval(condition, scope, frame)?.truthy() {
+                            chosen = Some(body);
+                            break;
+                        }
+                    }
+                    if let Some(body) = chosen {
+                        match self.exec_block(body, scope, frame)? {
+                            Flow::Normal => {}
+                            flow => return Ok(flow),
+                        }
+                    }
+                }
+                Stat::NumericFor(name, start, limit, step, body) => {
+                    let number = |lua: &mut Lua, expr: &Expr, what: &str| {
+                        lua.eval(expr, scope, frame)?
+                            .to_number()
+                            .ok_or_else(|| lua.error(&format!("'for' {} must be a number", what)))
+                    };
+                    let start = number(self, start, "initial value")?;
+                    let limit = number(self, limit, "limit")?;
+                    let step = match step {
+                        Some(step) => number(self, step, "step")?,
+                        None => 1.0,
+                    };
+                    if step == 0.0 {
+                        return Err(self.error("'for' step is zero"));
+                    }
+                    let mut i = start;
+                    while (step > 0.0 && i <= limit) || (step < 0.0 && i >= limit) {
+                        let inner = Scope::new(Some(scope.clone()));
+                        inner.declare(name.clone(), Value::Number(i));
+                        match self.exec_in(body, &inner, frame)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok(flow),
+                        }
+                        self.line = *line;
+                        self.step()?;
+                        i += step;
+                    }
+                }
+                Stat::GenericFor(names, exprs, body) => {
+                    let mut values = self.eval_list(exprs, scope, frame)?.into_iter();
+                    let iterator = values.next().unwrap_or_default();
+                    let state = values.next().unwrap_or_default();
+                    let mut control = values.next().unwrap_or_default();
+                    loop {
+                        let args = vec![state.clone(), control.clone()];
+                        let results = self
+                            .call_value(&iterator, args)
+                            .map_err(|e| e.located(&self.chunk, self.line))?;
+                        let mut results = results.into_iter();
+                        control = results.next().unwrap_or_default();
+                        if control.is_nil() {
+                            break;
+                        }
+                        let inner = Scope::new(Some(scope.clone()));
+                        inner.declare(names[0].clone(), control.clone());
+                        for name in &names[1..] {
+                            inner.declare(name.clone(), results.next().unwrap_or_default());
+                        }
+                        match self.exec_in(body, &inner, frame)? {
+                            Flow::Normal => {}
+                            Flow::Break => break,
+                            flow => return Ok(flow),
+                        }
+                        self.line = *line;
+                        self.step()?;
+                    }
+                }
+                Stat::Return(exprs) => {
+                    return Ok(Flow::Return(self.eval_list(exprs, scope, frame)?))
+                }
+                Stat::Break => return Ok(Flow::Break),
+            }
+        }
+        Ok(Flow::Normal)
+    }
+
+    fn closure(&self, body: &Rc<super::parse::FuncBody>, scope: &Rc<Scope>) -> Value {
+        Value::Function(Function::Lua(Rc::new(Closure {
+            body: body.clone(),
+            scope: scope.clone(),
+            chunk: self.chunk.clone(),
+        })))
+    }
+
+    fn place(&mut self, target: &Expr, scope: &Rc<Scope>, frame: &Frame) -> Result<Place, Error> {
+        Ok(match target {
+            Expr::Name(name) => match scope.lookup(name) {
+                Some(cell) => Place::Local(cell),
+                None => Place::Global(name.clone()),
+            },
+            Expr::Index(object, key) => {
+                let object = self.eval(object, scope, frame)?;
+                Place::Index(object, self.eval(key, scope, frame)?)
+            }
+            _ => unreachable!("the parser only allows names and fields"),
+        })
+    }
+
+    fn assign(
+        &mut self,
+        place: Place,
+        value: Value,
+        target: &Expr,
+        scope: &Rc<Scope>,
+    ) -> Result<(), Error> {
+        match place {
+            Place::Local(cell) => *cell.borrow_mut() = value,
+            Place::Global(name) => {
+                let key = Value::Str(name);
+                self.globals.borrow_mut().set(key, value)?;
+            }
+            Place::Index(Value::Table(table), key) => {
+                let result = table.borrow_mut().set(key, value);
+                result.map_err(|e| e.located(&self.chunk, self.line))?;
+            }
+            Place::Index(object, _) => {
+                let Expr::Index(expr, _) = target else {
+                    unreachable!()
+                };
+                let message = format!(
+                    "attempt to index a {} value{}",
+                    object.type_name(),
+                    describe(expr, scope)
+                );
+                return Err(self.error(&message));
+            }
+        }
+        Ok(())
+    }
+
+    /// The values of `exprs`, all of the last one's and the first of the others.
+    fn eval_list(
+        &mut self,
+        exprs: &[Expr],
+        scope: &Rc<Scope>,
+        frame: &Frame,
+    ) -> Result<Vec<Value>, Error> {
+        let mut values = Vec::with_capacity(exprs.len());
+        for (i, expr) in exprs.iter().enumerate() {
+            if i + 1 == exprs.len() {
+                values.extend(self.eval_multi(expr, scope, frame)?);
+            } else {
+                values.push(self.eval(expr, scope, frame)?);
+            }
+        }
+        Ok(values)
+    }
+
+    fn eval_multi(
+        &mut self,
+        expr: &Expr,
+        scope: &Rc<Scope>,
+        frame: &Frame,
+    ) -> Result<Vec<Value>, Error> {
+        match expr {
+            Expr::Call(..) | Expr::Method(..) => self.eval_call(expr, scope, frame),
+            Expr::Vararg => Ok(frame.varargs.clone()),
+            _ => Ok(vec![self.eval(expr, scope, frame)?]),
+        }
+    }
+
+    fn eval_call(
+        &mut self,
+        expr: &Expr,
+        scope: &Rc<Scope>,
+        frame: &Frame,
+    ) -> Result<Vec<Value>, Error> {
+        let (function, args) = match expr {
+            Expr::Call(callee, args) => {
+                let function = self.eval(callee, scope, frame)?;
+                let Value::Function(function) = function else {
+                    let message = format!(
+                        "attempt to call a {} value{}",
+                        function.type_name(),
+                        describe(callee, scope)
+                    );
+                    return Err(self.error(&message));
+                };
+                (function, self.eval_list(args, scope, frame)?)
+            }
+            Expr::Method(object_expr, name, args) => {
+                let object = self.eval(object_expr, scope, frame)?;
+                let function = self.index(&object, &Value::Str(name.clone()), || {
+                    describe(object_expr, scope)
+                })?;
+                let Value::Function(function) = function else {
+                    let message = format!(
+                        "attempt to call a {} value (method '{}')",
+                        function.type_name(),
+                        String::from_utf8_lossy(name)
+                    );
+                    return Err(self.error(&message));
+                };
+                let mut values = vec![object];
+                values.extend(self.eval_list(args, scope, frame)?);
+                (function, values)
+            }
+            _ => unreachable!("only calls are evaluated as calls"),
+        };
+        let line = self.line;
+        let results = self.call_function(&function, args);
+        self.line = line;
+        // errors of builtins are located where the script called them
+        results.map_err(|e| e.located(&self.chunk, line))
+    }
+
+    /// `object[key]`, with strings indexing the `string` library.
+    fn index(
+        &self,
+        object: &Value,
+        key: &Value,
+        describe: impl FnOnce() -> String,
+    ) -> Result<Value, Error> {
+        match object {
+            Value::Table(table) => Ok(table.borrow().get(key)),
+            Value::Str(_) => Ok(self.strings.borrow().get(key)),
+            _ => {
+                let message = format!(
+                    "attempt to index a {} value{}",
+                    object.type_name(),
+                    describe()
+                );
+                Err(self.error(&message))
+            }
+        }
+    }
+
+    fn eval(&mut self, expr: &Expr, scope: &Rc<Scope>, frame: &Frame) -> Result<Value, Error> {
+        Ok(match expr {
+            Expr::Nil => Value::Nil,
+            Expr::True => Value::Bool(true),
+            Expr::False => Value::Bool(false),
+            Expr::Vararg => frame.varargs.first().cloned().unwrap_or_default(),
+            Expr::Number(n) => Value::Number(*n),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Function(body) => self.closure(body, scope),
+            Expr::Table(fields) => self.table(fields, scope, frame)?,
+            Expr::Name(name) => match scope.lookup(name) {
+                Some(cell) => cell.borrow().clone(),
+                None => self.globals.borrow().get(&Value::Str(name.clone())),
+            },
+            Expr::Index(object, key) => {
+                let value = self.eval(object, scope, frame)?;
+                let key = self.eval(key, scope, frame)?;
+                self.index(&value, &key, || describe(object, scope))?
+            }
+            Expr::Call(..) | Expr::Method(..) => self
+                .eval_call(expr, scope, frame)?
+                .into_iter()
+                .next()
+                .unwrap_or_default(),
+            Expr::Paren(expr) => self.eval(expr, scope, frame)?,
+            Expr::Binary(BinOp::And, left, right) => {
+                let left = self.eval(left, scope, frame)?;
+                if !left.truthy() {
+                    return Ok(left);
+                }
+                self.eval(right, scope, frame)?
+            }
+            Expr::Binary(BinOp::Or, left, right) => {
+                let left = self.eval(left, scope, frame)?;
+                if left.truthy() {
+                    return Ok(left);
+                }
+                self.eval(right, scope, frame)?
+            }
+            Expr::Binary(op, left_expr, right_expr) => {
+                let left = self.eval(left_expr, scope, frame)?;
+                let right = self.eval(right_expr, scope, frame)?;
+                self.binary(*op, &left, &right).map_err(|culprit| {
+                    let expr = if culprit == 0 { left_expr } else { right_expr };
+                    let value = if culprit == 0 { &left } else { &right };
+                    let message = match op {
+                        BinOp::Concat => "attempt to concatenate",
+                        _ => "attempt to perform arithmetic on",
+                    };
+                    self.error(&format!(
+                        "{} a {} value{}",
+                        message,
+                        value.type_name(),
+                        describe(expr, scope)
+                    ))
+                })??
+            }
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand, scope, frame)?;
+                match (op, &value) {
+                    (UnOp::Not, value) => Value::Bool(!value.truthy()),
+                    (UnOp::Neg, value) if value.to_number().is_some() => {
+                        Value::Number(-value.to_number().unwrap_or_default())
+                    }
+                    (UnOp::Len, Value::Str(s)) => Value::Number(s.len() as f64),
+                    (UnOp::Len, Value::Table(table)) => Value::Number(table.borrow().len() as f64),
+                    (op, value) => {
+                        let message = match op {
+                            UnOp::Len => "attempt to get length of",
+                            _ => "attempt to perform arithmetic on",
+                        };
+                        let message = format!(
+                            "{} a {} value{}",
+                            message,
+                            value.type_name(),
+                            describe(operand, scope)
+                        );
+                        return Err(self.error(&message));
+                    }
+                }
+            }
+        })
+    }
+
+    fn table(
+        &mut self,
+        fields: &[Field],
+        scope: &Rc<Scope>,
+        frame: &Frame,
+    ) -> Result<Value, Error> {
+        let mut table = Table::default();
+        let mut n = 0;
+        for (i, field) in fields.iter().enumerate() {
+            match field {
+                Field::Positional(expr) if i + 1 == fields.len() => {
+                    for value in self.eval_multi(expr, scope, frame)? {
+                        n += 1;
+                        table.set(Value::Number(n as f64), value)?;
+                    }
+                }
+                Field::Positional(expr) => {
+                    n += 1;
+                    let value = self.eval(expr, scope, frame)?;
+                    table.set(Value::Number(n as f64), value)?;
+                }
+                Field::Named(key, value) => {
+                    let key = self.eval(key, scope, frame)?;
+                    let value = self.eval(value, scope, frame)?;
+                    table
+                        .set(key, value)
+                        .map_err(|e| e.located(&self.chunk, self.line))?;
+                }
+            }
+        }
+        Ok(Value::Table(Rc::new(RefCell::new(table))))
+    }
+
+    /// Applies `op`, failing with the operand to blame for a type error, or
+    /// with an error of its own.
+    fn binary(
+        &self,
+        op: BinOp,
+        left: &Value,
+        right: &Value,
+    ) -> Result<Result<Value, Error>, usize> {
+        let arithmetic = |f: fn(f64, f64) -> f64| match (left.to_number(), right.to_number()) {
+            (Some(a), Some(b)) => Ok(Ok(Value::Number(f(a, b)))),
+            (None, _) => Err(0),
+            (_, None) => Err(1),
+        };
+        match op {
+            BinOp::Add => arithmetic(|a, b| a + b),
+            BinOp::Sub => arithmetic(|a, b| a - b),
+            BinOp::Mul => arithmetic(|a, b| a * b),
+            BinOp::Div => arithmetic(|a, b| a / b),
+            BinOp::FloorDiv => arithmetic(|a, b| (a / b).floor()),
+            BinOp::Mod => arithmetic(|a, b| {
+                let m = a % b;
+                if m != 0.0 && (m < 0.0) != (b < 0.0) {
+                    m + b
+                } else {
+                    m
+                }
+            }),
+            BinOp::Pow => arithmetic(f64::powf),
+            BinOp::Concat => match (left.to_bytes(), right.to_bytes()) {
+                (Some(a), Some(b)) if a.len() + b.len() > MAX_STRING => {
+                    Ok(Err(self.error("resulting string too large")))
+                }
+                (Some(a), Some(b)) => Ok(Ok(Value::Str([&a[..], &b[..]].concat().into()))),
+                (None, _) => Err(0),
+                (_, None) => Err(1),
+            },
+            BinOp::Eq => Ok(Ok(Value::Bool(left.raw_equal(right)))),
+            BinOp::Ne => Ok(Ok(Value::Bool(!left.raw_equal(right)))),
+            BinOp::Lt => Ok(self.less(left, right, false).map(Value::Bool)),
+            BinOp::Le => Ok(self.less(left, right, true).map(Value::Bool)),
+            BinOp::Gt => Ok(self.less(right, left, false).map(Value::Bool)),
+            BinOp::Ge => Ok(self.less(right, left, true).map(Value::Bool)),
+            BinOp::And | BinOp::Or => unreachable!("evaluated lazily"),
+        }
+    }
+
+    /// `a < b`, or `a <= b` with `or_equal`.
+    pub(super) fn less(&self, a: &Value, b: &Value, or_equal: bool) -> Result<bool, Error> {
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(if or_equal { a <= b } else { a < b }),
+            (Value::Str(a), Value::Str(b)) => Ok(if or_equal { a <= b } else { a < b }),
+            _ if a.type_name() == b.type_name() => {
+                Err(self.error(&format!("attempt to compare two {} values", a.type_name())))
+            }
+            _ => Err(self.error(&format!(
+                "attempt to compare {} with {}",
+                a.type_name(),
+                b.type_name()
+            ))),
+        }
+    }
+
+    /// A string of `value` as `tostring` makes it.
+    pub(super) fn to_string(&self, value: &Value) -> Rc<[u8]> {
+        match value {
+            Value::Str(s) => s.clone(),
+            Value::Number(n) => number_to_string(*n).as_bytes().into(),
+            value => value.to_string().as_bytes().into(),
+        }
+    }
+}
+
+/// What `expr` is, for error messages: ` (local 'x')`, ` (field 'y')`.
+fn describe(expr: &Expr, scope: &Scope) -> String {
+    match expr {
+        Expr::Name(name) => {
+            let kind = if scope.lookup(name).is_some() {
+                "local"
+            } else {
+                "global"
+            };
+            format!(" ({} '{}')", kind, String::from_utf8_lossy(name))
+        }
+        Expr::Index(_, key) => match &**key {
+            Expr::Str(key) => format!(" (field '{}')", String::from_utf8_lossy(key)),
+            _ => String::new(),
+        },
+        Expr::Method(_, name, _) => format!(" (method '{}')", String::from_utf8_lossy(name)),
+        _ => String::new(),
+    }
+}
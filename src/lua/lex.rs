@@ -0,0 +1,433 @@
+//! Splits the source into tokens.
+
+use super::Error;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Name(Rc<[u8]>),
+    Number(f64),
+    Str(Rc<[u8]>),
+    And,
+    Break,
+    Do,
+    Else,
+    Elseif,
+    End,
+    False,
+    For,
+    Function,
+    Goto,
+    If,
+    In,
+    Local,
+    Nil,
+    Not,
+    Or,
+    Repeat,
+    Return,
+    Then,
+    True,
+    Until,
+    While,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    DoubleSlash,
+    Percent,
+    Caret,
+    Hash,
+    Eq,
+    Ne,
+    Le,
+    Ge,
+    Lt,
+    Gt,
+    Assign,
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    DoubleColon,
+    Semicolon,
+    Colon,
+    Comma,
+    Dot,
+    Concat,
+    Ellipsis,
+    Eof,
+}
+
+impl Token {
+    /// How the token is shown in syntax errors.
+    pub fn describe(&self) -> String {
+        let text = match self {
+            Token::Name(name) | Token::Str(name) => return String::from_utf8_lossy(name).into(),
+            Token::Number(n) => return super::number_to_string(*n),
+            Token::Eof => return "<eof>".to_string(),
+            Token::And => "and",
+            Token::Break => "break",
+            Token::Do => "do",
+            Token::Else => "else",
+            Token::Elseif => "elseif",
+            Token::End => "end",
+            Token::False => "false",
+            Token::For => "for",
+            Token::Function => "function",
+            Token::Goto => "goto",
+            Token::If => "if",
+            Token::In => "in",
+            Token::Local => "local",
+            Token::Nil => "nil",
+            Token::Not => "not",
+            Token::Or => "or",
+            Token::Repeat => "repeat",
+            Token::Return => "return",
+            Token::Then => "then",
+            Token::True => "true",
+            Token::Until => "until",
+            Token::While => "while",
+            Token::Plus => "+",
+            Token::Minus => "-",
+            Token::Star => "*",
+            Token::Slash => "/",
+            Token::DoubleSlash => "//",
+            Token::Percent => "%",
+            Token::Caret => "^",
+            Token::Hash => "#",
+            Token::Eq => "==",
+            Token::Ne => "~=",
+            Token::Le => "<=",
+            Token::Ge => ">=",
+            Token::Lt => "<",
+            Token::Gt => ">",
+            Token::Assign => "=",
+            Token::LParen => "(",
+            Token::RParen => ")",
+            Token::LBrace => "{",
+            Token::RBrace => "}",
+            Token::LBracket => "[",
+            Token::RBracket => "]",
+            Token::DoubleColon => "::",
+            Token::Semicolon => ";",
+            Token::Colon => ":",
+            Token::Comma => ",",
+            Token::Dot => ".",
+            Token::Concat => "..",
+            Token::Ellipsis => "...",
+        };
+        text.to_string()
+    }
+}
+
+fn keyword(name: &[u8]) -> Option<Token> {
+    Some(match name {
+        b"and" => Token::And,
+        b"break" => Token::Break,
+        b"do" => Token::Do,
+        b"else" => Token::Else,
+        b"elseif" => Token::Elseif,
+        b"end" => Token::End,
+        b"false" => Token::False,
+        b"for" => Token::For,
+        b"function" => Token::Function,
+        b"goto" => Token::Goto,
+        b"if" => Token::If,
+        b"in" => Token::In,
+        b"local" => Token::Local,
+        b"nil" => Token::Nil,
+        b"not" => Token::Not,
+        b"or" => Token::Or,
+        b"repeat" => Token::Repeat,
+        b"return" => Token::Return,
+        b"then" => Token::Then,
+        b"true" => Token::True,
+        b"until" => Token::Until,
+        b"while" => Token::While,
+        _ => return None,
+    })
+}
+
+struct Lexer<'a> {
+    src: &'a [u8],
+    pos: usize,
+    line: u32,
+}
+
+/// The tokens of `src` with their lines, ending with [`Token::Eof`].
+pub fn tokenize(chunk: &str, src: &[u8]) -> Result<Vec<(Token, u32)>, Error> {
+    let mut lexer = Lexer {
+        src,
+        pos: 0,
+        line: 1,
+    };
+    let mut tokens = Vec::new();
+    loop {
+        let token = lexer
+            .skip_space()
+            .and_then(|()| lexer.token())
+            .map_err(|message| Error::at(chunk, lexer.line, &message))?;
+        let eof = token == Token::Eof;
+        tokens.push((token, lexer.line));
+        if eof {
+            return Ok(tokens);
+        }
+    }
+}
+
+impl Lexer<'_> {
+    fn peek(&self, ahead: usize) -> u8 {
+        self.src.get(self.pos + ahead).copied().unwrap_or(0)
+    }
+
+    fn at_end(&self) -> bool {
+        self.pos >= self.src.len()
+    }
+
+    fn skip_space(&mut self) -> Result<(), String> {
+        loop {
+            match self.peek(0) {
+                b'\n' => {
+                    self.line += 1;
+                    self.pos += 1;
+                }
+                b' ' | b'\t' | b'\r' | 0x0b | 0x0c => self.pos += 1,
+                b'-' if self.peek(1) == b'-' => {
+                    self.pos += 2;
+                    if let Some(level) = self.long_bracket() {
+                        self.long_string(level)?;
+                    } else {
+                        while !self.at_end() && self.peek(0) != b'\n' {
+                            self.pos += 1;
+                        }
+                    }
+                }
+                // a first line like `#!/usr/bin/env lua`
+                b'#' if self.pos == 0 => {
+                    while !self.at_end() && self.peek(0) != b'\n' {
+                        self.pos += 1;
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// The level of a long bracket like `[==[` at the position, consumed.
+    fn long_bracket(&mut self) -> Option<usize> {
+        if self.peek(0) != b'[' {
+            return None;
+        }
+        let mut level = 0;
+        while self.peek(1 + level) == b'=' {
+            level += 1;
+        }
+        if self.peek(1 + level) != b'[' {
+            return None;
+        }
+        self.pos += level + 2;
+        Some(level)
+    }
+
+    /// The content up to the closing bracket of `level`, consumed.
+    fn long_string(&mut self, level: usize) -> Result<Vec<u8>, String> {
+        // a newline right after the opening bracket is skipped
+        if self.peek(0) == b'\r' {
+            self.pos += 1;
+        }
+        if self.peek(0) == b'\n' {
+            self.line += 1;
+            self.pos += 1;
+        }
+        let mut content = Vec::new();
+        loop {
+            if self.at_end() {
+                return Err("unfinished long string or comment".to_string());
+            }
+            let c = self.peek(0);
+            if c == b']'
+                && (1..=level).all(|i| self.peek(i) == b'=')
+                && self.peek(level + 1) == b']'
+            {
+                self.pos += level + 2;
+                return Ok(content);
+            }
+            if c == b'\n' {
+                self.line += 1;
+            }
+            content.push(c);
+            self.pos += 1;
+        }
+    }
+
+    fn token(&mut self) -> Result<Token, String> {
+        if self.at_end() {
+            return Ok(Token::Eof);
+        }
+        let c = self.peek(0);
+        if c.is_ascii_alphabetic() || c == b'_' {
+            let start = self.pos;
+            while self.peek(0).is_ascii_alphanumeric() || self.peek(0) == b'_' {
+                self.pos += 1;
+            }
+            let name = &self.src[start..self.pos];
+            return Ok(keyword(name).unwrap_or_else(|| Token::Name(name.into())));
+        }
+        if c.is_ascii_digit() || (c == b'.' && self.peek(1).is_ascii_digit()) {
+            return self.number();
+        }
+        if c == b'"' || c == b'\'' {
+            return self.string(c);
+        }
+        if let Some(level) = self.long_bracket() {
+            return Ok(Token::Str(self.long_string(level)?.into()));
+        }
+        let (token, len) = match (c, self.peek(1), self.peek(2)) {
+            (b'.', b'.', b'.') => (Token::Ellipsis, 3),
+            (b'.', b'.', _) => (Token::Concat, 2),
+            (b'/', b'/', _) => (Token::DoubleSlash, 2),
+            (b'=', b'=', _) => (Token::Eq, 2),
+            (b'~', b'=', _) => (Token::Ne, 2),
+            (b'<', b'=', _) => (Token::Le, 2),
+            (b'>', b'=', _) => (Token::Ge, 2),
+            (b':', b':', _) => (Token::DoubleColon, 2),
+            (b'+', ..) => (Token::Plus, 1),
+            (b'-', ..) => (Token::Minus, 1),
+            (b'*', ..) => (Token::Star, 1),
+            (b'/', ..) => (Token::Slash, 1),
+            (b'%', ..) => (Token::Percent, 1),
+            (b'^', ..) => (Token::Caret, 1),
+            (b'#', ..) => (Token::Hash, 1),
+            (b'<', ..) => (Token::Lt, 1),
+            (b'>', ..) => (Token::Gt, 1),
+            (b'=', ..) => (Token::Assign, 1),
+            (b'(', ..) => (Token::LParen, 1),
+            (b')', ..) => (Token::RParen, 1),
+            (b'{', ..) => (Token::LBrace, 1),
+            (b'}', ..) => (Token::RBrace, 1),
+            (b'[', ..) => (Token::LBracket, 1),
+            (b']', ..) => (Token::RBracket, 1),
+            (b';', ..) => (Token::Semicolon, 1),
+            (b':', ..) => (Token::Colon, 1),
+            (b',', ..) => (Token::Comma, 1),
+            (b'.', ..) => (Token::Dot, 1),
+            _ => return Err(format!("unexpected symbol near '{}'", c as char)),
+        };
+        self.pos += len;
+        Ok(token)
+    }
+
+    fn number(&mut self) -> Result<Token, String> {
+        let start = self.pos;
+        let hex = self.peek(0) == b'0' && matches!(self.peek(1), b'x' | b'X');
+        let exponent: &[u8] = if hex {
+            self.pos += 2;
+            b"pP"
+        } else {
+            b"eE"
+        };
+        loop {
+            let c = self.peek(0);
+            if exponent.contains(&c) && matches!(self.peek(1), b'+' | b'-') {
+                self.pos += 2;
+            } else if c.is_ascii_hexdigit() || c == b'.' || exponent.contains(&c) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if self.peek(0).is_ascii_alphanumeric() || self.peek(0) == b'_' {
+            self.pos += 1;
+        }
+        let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap_or_default();
+        super::parse_number(text)
+            .map(Token::Number)
+            .ok_or_else(|| format!("malformed number near '{}'", text))
+    }
+
+    fn string(&mut self, quote: u8) -> Result<Token, String> {
+        self.pos += 1;
+        let mut content = Vec::new();
+        loop {
+            let c = self.peek(0);
+            if self.at_end() || c == b'\n' {
+                return Err("unfinished string".to_string());
+            }
+            self.pos += 1;
+            if c == quote {
+                return Ok(Token::Str(content.into()));
+            }
+            if c != b'\\' {
+                content.push(c);
+                continue;
+            }
+            let escaped = self.peek(0);
+            self.pos += 1;
+            match escaped {
+                b'a' => content.push(0x07),
+                b'b' => content.push(0x08),
+                b'f' => content.push(0x0c),
+                b'n' => content.push(b'\n'),
+                b'r' => content.push(b'\r'),
+                b't' => content.push(b'\t'),
+                b'v' => content.push(0x0b),
+                b'\\' | b'"' | b'\'' => content.push(escaped),
+                b'\n' => {
+                    self.line += 1;
+                    content.push(b'\n');
+                }
+                b'x' => {
+                    let digits = self.src.get(self.pos..self.pos + 2).unwrap_or_default();
+                    let byte = std::str::from_utf8(digits)
+                        .ok()
+                        .filter(|digits| digits.len() == 2)
+                        .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                        .ok_or("hexadecimal digit expected")?;
+                    content.push(byte);
+                    self.pos += 2;
+                }
+                b'z' => {
+                    while self.peek(0).is_ascii_whitespace() {
+                        if self.peek(0) == b'\n' {
+                            self.line += 1;
+                        }
+                        self.pos += 1;
+                    }
+                }
+                b'u' => {
+                    if self.peek(0) != b'{' {
+                        return Err("missing '{' in \\u{xxxx}".to_string());
+                    }
+                    let start = self.pos + 1;
+                    let end = self.src[start..]
+                        .iter()
+                        .position(|&c| c == b'}')
+                        .map(|len| start + len)
+                        .ok_or("missing '}' in \\u{xxxx}")?;
+                    let c = std::str::from_utf8(&self.src[start..end])
+                        .ok()
+                        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+                        .and_then(char::from_u32)
+                        .ok_or("UTF-8 value too large")?;
+                    content.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    self.pos = end + 1;
+                }
+                digit if digit.is_ascii_digit() => {
+                    let mut value = u32::from(digit - b'0');
+                    for _ in 0..2 {
+                        if !self.peek(0).is_ascii_digit() {
+                            break;
+                        }
+                        value = value * 10 + u32::from(self.peek(0) - b'0');
+                        self.pos += 1;
+                    }
+                    content.push(u8::try_from(value).map_err(|_| "decimal escape too large")?);
+                }
+                _ => return Err("invalid escape sequence".to_string()),
+            }
+        }
+    }
+}
@@ -0,0 +1,921 @@
+//! A small Lua interpreter for `--script`, so no system library or crate with
+//! C code is needed. It implements the language of Lua 5.3 without integers,
+//! metatables, coroutines and `goto`: numbers are doubles as in Lua 5.1.
+//!
+//! The library is sandboxed. It has the base functions without `load`,
+//! `dofile` and `require`, plus `string` (with Lua patterns), `table`,
+//! `math` and `os.time`. There is no access to files or processes.
+//! [`Lua::call`] gives up after [`MAX_STEPS`] statements and calls, so a
+//! script stuck in a loop can not hang trayson.
+
+mod exec;
+mod lex;
+mod parse;
+mod pattern;
+mod stdlib;
+
+use parse::FuncBody;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Bound;
+use std::rc::Rc;
+
+/// How many statements and calls [`Lua::call`] may run.
+const MAX_STEPS: u64 = 1_000_000;
+/// Strings longer than this are refused instead of growing without bound.
+const MAX_STRING: usize = 16 << 20;
+/// How deep tables may nest when converted to JSON, which also stops at cycles.
+const MAX_JSON_DEPTH: usize = 100;
+
+pub type TableRef = Rc<RefCell<Table>>;
+type NativeFn = dyn Fn(&mut Lua, Vec<Value>) -> Result<Vec<Value>, Error>;
+
+#[derive(Clone, Default)]
+pub enum Value {
+    #[default]
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Str(Rc<[u8]>),
+    Table(TableRef),
+    Function(Function),
+}
+
+#[derive(Clone)]
+pub enum Function {
+    Lua(Rc<Closure>),
+    Native(Rc<NativeFn>),
+}
+
+pub struct Closure {
+    body: Rc<FuncBody>,
+    scope: Rc<Scope>,
+    chunk: Rc<str>,
+}
+
+/// The locals of a block, each in a cell of its own for the closures that
+/// capture it.
+struct Scope {
+    vars: RefCell<Vec<(parse::Name, Rc<RefCell<Value>>)>>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    fn new(parent: Option<Rc<Scope>>) -> Rc<Scope> {
+        Rc::new(Scope {
+            vars: RefCell::new(Vec::new()),
+            parent,
+        })
+    }
+
+    fn declare(&self, name: parse::Name, value: Value) {
+        self.vars
+            .borrow_mut()
+            .push((name, Rc::new(RefCell::new(value))));
+    }
+
+    fn lookup(&self, name: &[u8]) -> Option<Rc<RefCell<Value>>> {
+        let vars = self.vars.borrow();
+        match vars.iter().rev().find(|(var, _)| &var[..] == name) {
+            Some((_, cell)) => Some(cell.clone()),
+            None => self.parent.as_ref()?.lookup(name),
+        }
+    }
+}
+
+impl Value {
+    pub fn str(s: &str) -> Value {
+        Value::Str(s.as_bytes().into())
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::Str(_) => "string",
+            Value::Table(_) => "table",
+            Value::Function(_) => "function",
+        }
+    }
+
+    pub fn truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Bool(false))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        matches!(self, Value::Nil)
+    }
+
+    /// The number, also of a string holding one.
+    fn to_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(*n),
+            Value::Str(s) => parse_number(std::str::from_utf8(s).ok()?),
+            _ => None,
+        }
+    }
+
+    /// The string, also of a number.
+    fn to_bytes(&self) -> Option<Rc<[u8]>> {
+        match self {
+            Value::Str(s) => Some(s.clone()),
+            Value::Number(n) => Some(number_to_string(*n).as_bytes().into()),
+            _ => None,
+        }
+    }
+
+    fn raw_equal(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Nil, Value::Nil) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Str(a), Value::Str(b)) => a == b,
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Function(a), Value::Function(b)) => a.address() == b.address(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Nil => write!(f, "nil"),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Number(n) => write!(f, "{}", number_to_string(*n)),
+            Value::Str(s) => write!(f, "{}", String::from_utf8_lossy(s)),
+            Value::Table(t) => write!(f, "table: {:p}", Rc::as_ptr(t)),
+            Value::Function(function) => write!(f, "function: {:#x}", function.address()),
+        }
+    }
+}
+
+impl fmt::Debug for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "{:?}", String::from_utf8_lossy(s)),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl Function {
+    fn native(f: impl Fn(&mut Lua, Vec<Value>) -> Result<Vec<Value>, Error> + 'static) -> Value {
+        Value::Function(Function::Native(Rc::new(f)))
+    }
+
+    fn address(&self) -> usize {
+        match self {
+            Function::Lua(closure) => Rc::as_ptr(closure) as usize,
+            Function::Native(native) => Rc::as_ptr(native) as *const u8 as usize,
+        }
+    }
+}
+
+/// The key of a table entry: numbers with an integer value are integers, so
+/// `t[1]` and `t[1.0]` are the same entry.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Bool(bool),
+    Int(i64),
+    Float(u64),
+    Str(Rc<[u8]>),
+    Ref(usize),
+}
+
+impl Key {
+    fn of(value: &Value) -> Option<Key> {
+        Some(match value {
+            Value::Nil => return None,
+            Value::Bool(b) => Key::Bool(*b),
+            Value::Number(n) if n.is_nan() => return None,
+            Value::Number(n) => match integer(*n) {
+                Some(i) => Key::Int(i),
+                None => Key::Float(n.to_bits()),
+            },
+            Value::Str(s) => Key::Str(s.clone()),
+            Value::Table(t) => Key::Ref(Rc::as_ptr(t) as usize),
+            Value::Function(function) => Key::Ref(function.address()),
+        })
+    }
+}
+
+/// The value of `n` as an integer, if it has one.
+fn integer(n: f64) -> Option<i64> {
+    (n.fract() == 0.0 && n >= -(2f64.powi(63)) && n < 2f64.powi(63)).then_some(n as i64)
+}
+
+/// A table, with the entries from 1 up to the first nil in a vector.
+#[derive(Default)]
+pub struct Table {
+    array: Vec<Value>,
+    /// Entries by their key, keeping the original key for `next`.
+    hash: BTreeMap<Key, (Value, Value)>,
+}
+
+/// The position in the array part `key` would have, counting from 1.
+fn array_index(key: &Value) -> Option<usize> {
+    match key {
+        Value::Number(n) => integer(*n).filter(|&i| i >= 1).map(|i| i as usize),
+        _ => None,
+    }
+}
+
+impl Table {
+    pub fn get(&self, key: &Value) -> Value {
+        if let Some(i) = array_index(key) {
+            if i <= self.array.len() {
+                return self.array[i - 1].clone();
+            }
+        }
+        Key::of(key)
+            .and_then(|key| self.hash.get(&key))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn get_str(&self, key: &str) -> Value {
+        self.get(&Value::str(key))
+    }
+
+    pub fn set(&mut self, key: Value, value: Value) -> Result<(), Error> {
+        if let Some(i) = array_index(&key) {
+            if i <= self.array.len() {
+                self.array[i - 1] = value;
+                while self.array.last().is_some_and(Value::is_nil) {
+                    self.array.pop();
+                }
+                return Ok(());
+            }
+            if i == self.array.len() + 1 && !value.is_nil() {
+                self.hash.remove(&Key::Int(i as i64));
+                self.array.push(value);
+                // entries that were set before, out of order
+                while let Some((_, value)) =
+                    self.hash.remove(&Key::Int(self.array.len() as i64 + 1))
+                {
+                    self.array.push(value);
+                }
+                return Ok(());
+            }
+        }
+        let Some(hashed) = Key::of(&key) else {
+            let message = if key.is_nil() {
+                "table index is nil"
+            } else {
+                "table index is NaN"
+            };
+            return Err(Error::new(message));
+        };
+        if value.is_nil() {
+            self.hash.remove(&hashed);
+        } else {
+            let key = match hashed {
+                Key::Int(i) => Value::Number(i as f64),
+                _ => key,
+            };
+            self.hash.insert(hashed, (key, value));
+        }
+        Ok(())
+    }
+
+    pub fn set_str(&mut self, key: &str, value: Value) {
+        let _ = self.set(Value::str(key), value);
+    }
+
+    /// A border of the sequence: `t[n]` is not nil but `t[n + 1]` is.
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    /// The entry after `key`, the first one after nil.
+    fn next(&self, key: &Value) -> Result<Option<(Value, Value)>, Error> {
+        let mut i = match key {
+            Value::Nil => 0,
+            _ => match array_index(key) {
+                Some(i) if i <= self.array.len() => i,
+                _ => {
+                    let key = Key::of(key).ok_or_else(|| Error::new("invalid key to 'next'"))?;
+                    let mut after = self.hash.range((Bound::Excluded(key), Bound::Unbounded));
+                    return Ok(after.next().map(|(_, entry)| entry.clone()));
+                }
+            },
+        };
+        while let Some(value) = self.array.get(i) {
+            i += 1;
+            if !value.is_nil() {
+                return Ok(Some((Value::Number(i as f64), value.clone())));
+            }
+        }
+        Ok(self.hash.values().next().cloned())
+    }
+}
+
+/// A Lua error, with the position it was raised at in the message.
+#[derive(Debug, Clone)]
+pub struct Error {
+    value: Value,
+    /// Whether the position is in the message, or there should be none.
+    located: bool,
+    /// Errors `pcall` does not catch, running out of steps or stack.
+    fatal: bool,
+}
+
+impl Error {
+    /// An error to be located where the function raising it was called.
+    fn new(message: impl AsRef<str>) -> Error {
+        Error {
+            value: Value::str(message.as_ref()),
+            located: false,
+            fatal: false,
+        }
+    }
+
+    fn at(chunk: &str, line: u32, message: &str) -> Error {
+        Error {
+            value: Value::str(&format!("{}:{}: {}", chunk, line, message)),
+            located: true,
+            fatal: false,
+        }
+    }
+
+    fn located(mut self, chunk: &str, line: u32) -> Error {
+        if let (false, Value::Str(message)) = (self.located, &self.value) {
+            let message = String::from_utf8_lossy(message);
+            self.value = Value::str(&format!("{}:{}: {}", chunk, line, message));
+        }
+        self.located = true;
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            value @ (Value::Str(_) | Value::Number(_)) => write!(f, "{}", value),
+            value => write!(f, "(error object is a {} value)", value.type_name()),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// The state of an interpreter: its globals and where it is running.
+pub struct Lua {
+    globals: TableRef,
+    /// The `string` library, which strings index for `s:upper()`.
+    strings: TableRef,
+    chunk: Rc<str>,
+    line: u32,
+    steps: u64,
+    depth: usize,
+}
+
+impl Default for Lua {
+    fn default() -> Lua {
+        Lua::new()
+    }
+}
+
+impl Lua {
+    /// An interpreter with the standard library.
+    pub fn new() -> Lua {
+        let mut lua = Lua {
+            globals: Rc::default(),
+            strings: Rc::default(),
+            chunk: "?".into(),
+            line: 0,
+            steps: 0,
+            depth: 0,
+        };
+        stdlib::open(&mut lua);
+        lua
+    }
+
+    /// Compiles `src` into a function running it, named `chunk` in errors.
+    pub fn load(&mut self, chunk: &str, src: &[u8]) -> Result<Value, Error> {
+        let body = parse::parse(chunk, src)?;
+        Ok(Value::Function(Function::Lua(Rc::new(Closure {
+            body: Rc::new(body),
+            scope: Scope::new(None),
+            chunk: chunk.into(),
+        }))))
+    }
+
+    /// Runs `src`, returning what it returns.
+    pub fn exec(&mut self, chunk: &str, src: &[u8]) -> Result<Vec<Value>, Error> {
+        let function = self.load(chunk, src)?;
+        self.call(&function, Vec::new())
+    }
+
+    /// Calls `function` with a fresh budget of [`MAX_STEPS`].
+    pub fn call(&mut self, function: &Value, args: Vec<Value>) -> Result<Vec<Value>, Error> {
+        let Value::Function(function) = function else {
+            return Err(Error::new(format!(
+                "attempt to call a {} value",
+                function.type_name()
+            )));
+        };
+        self.steps = 0;
+        self.depth = 0;
+        self.call_function(function, args)
+    }
+
+    pub fn global(&self, name: &str) -> Value {
+        self.globals.borrow().get_str(name)
+    }
+}
+
+/// A table of `values` from JSON, where `null` is nil.
+pub fn from_json(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Nil,
+        serde_json::Value::Bool(b) => Value::Bool(*b),
+        serde_json::Value::Number(n) => Value::Number(n.as_f64().unwrap_or_default()),
+        serde_json::Value::String(s) => Value::str(s),
+        serde_json::Value::Array(values) => {
+            let mut table = Table::default();
+            for (i, value) in values.iter().enumerate() {
+                let _ = table.set(Value::Number((i + 1) as f64), from_json(value));
+            }
+            Value::Table(Rc::new(RefCell::new(table)))
+        }
+        serde_json::Value::Object(fields) => {
+            let mut table = Table::default();
+            for (key, value) in fields {
+                table.set_str(key, from_json(value));
+            }
+            Value::Table(Rc::new(RefCell::new(table)))
+        }
+    }
+}
+
+/// `value` as JSON. Tables with only a sequence, and empty ones, are arrays,
+/// others objects with their keys as strings.
+pub fn to_json(value: &Value) -> Result<serde_json::Value, Error> {
+    json(value, 0)
+}
+
+fn json(value: &Value, depth: usize) -> Result<serde_json::Value, Error> {
+    Ok(match value {
+        Value::Nil => serde_json::Value::Null,
+        Value::Bool(b) => (*b).into(),
+        Value::Number(n) => match integer(*n) {
+            Some(i) => i.into(),
+            None => serde_json::Number::from_f64(*n).map_or(serde_json::Value::Null, Into::into),
+        },
+        Value::Str(s) => String::from_utf8_lossy(s).into(),
+        Value::Function(_) => return Err(Error::new("can not convert a function to JSON")),
+        Value::Table(table) => {
+            if depth >= MAX_JSON_DEPTH {
+                return Err(Error::new("tables nest too deep for JSON"));
+            }
+            let table = table.borrow();
+            let array = table
+                .array
+                .iter()
+                .map(|value| json(value, depth + 1))
+                .collect::<Result<Vec<_>, _>>()?;
+            if table.hash.is_empty() {
+                return Ok(array.into());
+            }
+            let mut fields = serde_json::Map::new();
+            for (i, value) in array.into_iter().enumerate() {
+                fields.insert((i + 1).to_string(), value);
+            }
+            for (key, value) in table.hash.values() {
+                let key = match key {
+                    Value::Str(_) | Value::Number(_) => key.to_string(),
+                    key => {
+                        let message = format!("can not convert a {} key to JSON", key.type_name());
+                        return Err(Error::new(message));
+                    }
+                };
+                fields.insert(key, json(value, depth + 1)?);
+            }
+            fields.into()
+        }
+    })
+}
+
+/// A numeral of Lua, decimal or hexadecimal, with surrounding whitespace.
+fn parse_number(text: &str) -> Option<f64> {
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
+    let (negative, unsigned) = match text.as_bytes().first()? {
+        b'-' => (true, &text[1..]),
+        b'+' => (false, &text[1..]),
+        _ => (false, text),
+    };
+    let n = match unsigned.get(..2) {
+        Some("0x" | "0X") => parse_hex(&unsigned[2..])?,
+        _ => {
+            let valid = |c: char| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-');
+            if unsigned.is_empty()
+                || !unsigned.chars().all(valid)
+                || unsigned.starts_with(['e', 'E'])
+            {
+                return None;
+            }
+            unsigned.parse().ok()?
+        }
+    };
+    Some(if negative { -n } else { n })
+}
+
+fn parse_hex(digits: &str) -> Option<f64> {
+    let (mantissa, exponent) = match digits.find(['p', 'P']) {
+        Some(at) => (&digits[..at], Some(digits[at + 1..].parse::<i32>().ok()?)),
+        None => (digits, None),
+    };
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if exponent.is_none() && !mantissa.contains('.') {
+        // integers wrap around as in Lua
+        let mut n = 0u64;
+        for c in whole.chars() {
+            n = n.wrapping_mul(16).wrapping_add(c.to_digit(16)? as u64);
+        }
+        return Some(n as i64 as f64);
+    }
+    let mut n = 0f64;
+    for c in whole.chars() {
+        n = n * 16.0 + f64::from(c.to_digit(16)?);
+    }
+    let mut scale = 1.0 / 16.0;
+    for c in fraction.chars() {
+        n += f64::from(c.to_digit(16)?) * scale;
+        scale /= 16.0;
+    }
+    Some(n * 2f64.powi(exponent.unwrap_or(0)))
+}
+
+/// `n` as Lua prints it, integers without a fraction and others like `%.14g`.
+fn number_to_string(n: f64) -> String {
+    match integer(n) {
+        Some(0) if n.is_sign_negative() => "-0".to_string(),
+        Some(i) if n.abs() < 1e15 => i.to_string(),
+        _ => format_g(n, 14, false),
+    }
+}
+
+/// `n` formatted like C's `%.{precision}g`, with trailing zeros unless `alternate`.
+fn format_g(n: f64, precision: usize, alternate: bool) -> String {
+    if n.is_nan() {
+        return if n.is_sign_negative() { "-nan" } else { "nan" }.to_string();
+    }
+    if n.is_infinite() {
+        return if n < 0.0 { "-inf" } else { "inf" }.to_string();
+    }
+    let precision = precision.max(1);
+    let scientific = format!("{:.*e}", precision - 1, n);
+    let (_, exponent) = scientific.split_once('e').unwrap_or_default();
+    let exponent: i32 = exponent.parse().unwrap_or_default();
+    let mut text = if exponent < -4 || exponent >= precision as i32 {
+        format_e(n, precision - 1)
+    } else {
+        format!("{:.*}", (precision as i32 - 1 - exponent) as usize, n)
+    };
+    if !alternate {
+        let (mantissa, suffix) = match text.find('e') {
+            Some(at) => text.split_at(at),
+            None => (text.as_str(), ""),
+        };
+        if mantissa.contains('.') {
+            let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+            text = format!("{}{}", mantissa, suffix);
+        }
+    }
+    text
+}
+
+/// `n` formatted like C's `%.{precision}e`.
+fn format_e(n: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, n);
+    let (mantissa, exponent) = text.split_once('e').unwrap_or((&text, "0"));
+    let exponent: i32 = exponent.parse().unwrap_or_default();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `src` on a stack as large as the script's thread has.
+    fn exec(src: &str) -> Result<Vec<String>, String> {
+        let src = src.to_string();
+        std::thread::Builder::new()
+            .stack_size(64 << 20)
+            .spawn(move || {
+                let mut lua = Lua::new();
+                match lua.exec("test", src.as_bytes()) {
+                    Ok(values) => Ok(values.iter().map(ToString::to_string).collect()),
+                    Err(e) => Err(e.to_string()),
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap()
+    }
+
+    /// What `src` returns, each value as `tostring` shows it.
+    fn run(src: &str) -> Vec<String> {
+        exec(src).unwrap()
+    }
+
+    fn fail(src: &str) -> String {
+        exec(src).unwrap_err()
+    }
+
+    #[test]
+    fn evaluates_expressions() {
+        assert_eq!(
+            run("return 1 + 2 * 3, 2 ^ 3 ^ 2, -2 ^ 2"),
+            ["7", "512", "-4"]
+        );
+        assert_eq!(
+            run("return 7 // 2, -7 % 3, 7 / 2, 10 / 2"),
+            ["3", "2", "3.5", "5"]
+        );
+        assert_eq!(
+            run("return 'a' .. 1 .. 'b', '10' + 1, #'abc'"),
+            ["a1b", "11", "3"]
+        );
+        assert_eq!(
+            run("return 1 < 2, 'a' < 'b', 1 == 1.0, 'x' ~= 'x'"),
+            ["true", "true", "true", "false"]
+        );
+        assert_eq!(
+            run("return nil or 'x', false and 1, 1 and 2, not nil"),
+            ["x", "false", "2", "true"]
+        );
+        assert_eq!(
+            run("return 0x10, 1e2, .5, 0.1 + 0.2"),
+            ["16", "100", "0.5", "0.3"]
+        );
+        assert_eq!(
+            run(r#"return "a\tb\65\x41\u{e9}", [[
+long]]"#),
+            ["a\tbAAé", "long"]
+        );
+    }
+
+    #[test]
+    fn runs_statements() {
+        let src = r#"
+            local sum = 0
+            for i = 1, 10 do
+              if i % 2 == 0 then sum = sum + i elseif i == 5 then break end
+            end
+            local n = 0
+            while n < 3 do n = n + 1 end
+            repeat local m = n; n = n - 1 until m == 1
+            local words = {}
+            for k, v in pairs({a = 1}) do words[#words + 1] = k .. v end
+            for i, v in ipairs({"x", "y", nil, "z"}) do words[#words + 1] = i .. v end
+            for i = 3, 1, -1 do words[#words + 1] = i end
+            return sum, n, table.concat(words, ",")
+        "#;
+        assert_eq!(run(src), ["6", "0", "a1,1x,2y,3,2,1"]);
+    }
+
+    #[test]
+    fn calls_functions() {
+        let src = r#"
+            local function fib(n) if n < 2 then return n end return fib(n - 1) + fib(n - 2) end
+            local function pack(...) return select('#', ...), ... end
+            local counter = {count = 0}
+            function counter:add(n) self.count = self.count + n; return self end
+            counter:add(2):add(3)
+            local function make() local x = 0; return function() x = x + 1; return x end end
+            local next_x = make()
+            next_x()
+            local a, b = (function() return 1, 2 end)()
+            local t = {(function() return 1, 2 end)(), (function() return 3, 4 end)()}
+            return fib(15), pack(1, nil, 3), counter.count, next_x(), a + b, #t
+        "#;
+        assert_eq!(run(src), ["610", "3", "5", "2", "3", "3"]);
+    }
+
+    #[test]
+    fn catches_errors() {
+        let src = r#"
+            local ok, e = pcall(error, "plain")
+            local ok2, e2 = pcall(function() error("located") end)
+            local ok3, e3 = pcall(error, {code = 7})
+            local ok4, e4 = pcall(function() local t = nil; return t.x end)
+            return e, e2, e3.code, e4
+        "#;
+        assert_eq!(
+            run(src),
+            [
+                "plain",
+                "test:3: located",
+                "7",
+                "test:5: attempt to index a nil value (local 't')"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_errors_with_their_line() {
+        assert_eq!(
+            fail("local x = 1\nreturn x + {}"),
+            "test:2: attempt to perform arithmetic on a table value"
+        );
+        assert_eq!(
+            fail("return undefined()"),
+            "test:1: attempt to call a nil value (global 'undefined')"
+        );
+        assert_eq!(
+            fail("local t = {}\nreturn t.a.b"),
+            "test:2: attempt to index a nil value (field 'a')"
+        );
+        assert_eq!(
+            fail("return 1 < 'x'"),
+            "test:1: attempt to compare number with string"
+        );
+        assert_eq!(
+            fail("return ('x'):nope()"),
+            "test:1: attempt to call a nil value (method 'nope')"
+        );
+        assert_eq!(
+            fail("return string.rep()"),
+            "test:1: bad argument #1 to 'rep' (string expected, got no value)"
+        );
+        assert_eq!(fail("return 1 +"), "test:1: unexpected symbol near '<eof>'");
+        assert_eq!(fail("x = = 1"), "test:1: unexpected symbol near '='");
+        assert_eq!(fail("break"), "test:1: break outside a loop near 'break'");
+        assert_eq!(fail("return 'abc"), "test:1: unfinished string");
+        assert_eq!(fail("error({})"), "(error object is a table value)");
+    }
+
+    #[test]
+    fn stops_runaway_scripts() {
+        assert_eq!(fail("while true do end"), "test:1: script ran too long");
+        assert_eq!(
+            fail("local function f() return f() + 1 end\nreturn pcall(f)"),
+            "test:1: stack overflow"
+        );
+        assert_eq!(
+            fail("return pcall(function() repeat until false end)"),
+            "test:1: script ran too long"
+        );
+        assert_eq!(
+            fail("return string.rep('x', 1e9)"),
+            "test:1: resulting string too large"
+        );
+        let nested = format!("return {}1{}", "(".repeat(300), ")".repeat(300));
+        assert_eq!(
+            fail(&nested),
+            "test:1: chunk has too many syntax levels near '('"
+        );
+    }
+
+    #[test]
+    fn formats_strings() {
+        let src = r#"
+            return string.format("%d|%5.1f|%-4s|%x|%q|%g|%g|%03d|%+d|%s", 42, 3.14159, "ab", 255, 'a"b\n', 1e20, 0.1, 7, 5, nil),
+                   tostring(1e15), tostring(2^53), tostring(1/0), tostring(-0.0), 1e100
+        "#;
+        assert_eq!(
+            run(src),
+            [
+                "42|  3.1|ab  |ff|\"a\\\"b\\\n\"|1e+20|0.1|007|+5|nil",
+                "1e+15",
+                "9.007199254741e+15",
+                "inf",
+                "-0",
+                "1e+100"
+            ]
+        );
+    }
+
+    #[test]
+    fn matches_patterns() {
+        let src = r#"
+            local words = {}
+            for word in string.gmatch("one two  three", "%a+") do words[#words + 1] = word end
+            local k, v = string.match("key = value", "(%w+)%s*=%s*(%w+)")
+            local dated = ("2024-01-02"):gsub("(%d+)-(%d+)-(%d+)", "%3.%2.%1")
+            local upper, n = string.gsub("hello world", "o", string.upper)
+            local named = string.gsub("$name is $age", "%$(%w+)", {name = "Ann", age = 5})
+            local found = {
+              table.concat({string.find("a.b", ".", 1, true)}, ","),
+              table.concat({string.find("abc", "b()")}, ","),
+              table.concat({string.find("THE (quick) fox", "%f[%a]%a+", 5)}, ","),
+            }
+            return table.concat(words, ","), k, v, dated, upper, n, named, table.concat(found, " "),
+                   string.match("  trim  ", "^%s*(.-)%s*$"), string.match("[x]", "%b[]"),
+                   string.match("nil", "x")
+        "#;
+        assert_eq!(
+            run(src),
+            [
+                "one,two,three",
+                "key",
+                "value",
+                "02.01.2024",
+                "hellO wOrld",
+                "2",
+                "Ann is 5",
+                "2,2 2,2,3 6,10",
+                "trim",
+                "[x]",
+                "nil"
+            ]
+        );
+        assert_eq!(
+            fail("return string.find('a', '[a')"),
+            "test:1: malformed pattern (missing ']')"
+        );
+    }
+
+    #[test]
+    fn manipulates_tables() {
+        let src = r#"
+            local t = {5, 2, 8, 1}
+            table.sort(t)
+            local sorted = table.concat(t, " ")
+            table.sort(t, function(a, b) return a > b end)
+            table.insert(t, 1, 0)
+            table.insert(t, 9)
+            local removed = table.remove(t, 2)
+            local s = {}
+            s[3] = "c"; s[1] = "a"; s[2] = "b"
+            return sorted, table.concat(t, " "), removed, #s, next({}), math.max(3, 9, 1),
+                   math.floor(-2.5), math.huge, table.unpack({1, 2, 3}, 2)
+        "#;
+        assert_eq!(
+            run(src),
+            [
+                "1 2 5 8",
+                "0 5 2 1 9",
+                "8",
+                "3",
+                "nil",
+                "9",
+                "-3",
+                "inf",
+                "2",
+                "3"
+            ]
+        );
+        assert_eq!(
+            fail("table.sort({1, 'x'})"),
+            "test:1: attempt to compare string with number"
+        );
+    }
+
+    #[test]
+    fn converts_json() {
+        let json = serde_json::json!({
+            "id": "a",
+            "size": 22,
+            "scale": 1.5,
+            "urgent": true,
+            "menu": [],
+            "children": [{"id": 1}, {"id": 2}],
+            "gone": null,
+        });
+        let value = from_json(&json);
+        let mut expected = json.clone();
+        expected.as_object_mut().unwrap().remove("gone");
+        assert_eq!(to_json(&value).unwrap(), expected);
+        let mut lua = Lua::new();
+        let values = lua
+            .exec(
+                "test",
+                b"local t = {1, 2}; t.x = 'y'; return t, {[true] = 1}, {print}",
+            )
+            .unwrap();
+        assert_eq!(
+            to_json(&values[0]).unwrap(),
+            serde_json::json!({"1": 1, "2": 2, "x": "y"})
+        );
+        assert_eq!(
+            to_json(&values[1]).unwrap_err().to_string(),
+            "can not convert a boolean key to JSON"
+        );
+        assert_eq!(
+            to_json(&values[2]).unwrap_err().to_string(),
+            "can not convert a function to JSON"
+        );
+        let cycle = lua
+            .exec("test", b"local t = {}; t.t = t; return t")
+            .unwrap();
+        assert!(to_json(&cycle[0]).is_err());
+    }
+
+    #[test]
+    fn has_no_access_to_the_system() {
+        assert_eq!(
+            run("return io, load, require, dofile, os.execute, os.remove, type(os.time())"),
+            ["nil", "nil", "nil", "nil", "nil", "nil", "number"]
+        );
+    }
+}
@@ -0,0 +1,589 @@
+//! Parses tokens into the tree the interpreter walks.
+
+use super::lex::{tokenize, Token};
+use super::Error;
+use std::rc::Rc;
+
+/// How deep blocks and expressions may nest, so deep input can not overflow
+/// the stack of the parser or the interpreter.
+const MAX_NESTING: usize = 200;
+
+pub type Name = Rc<[u8]>;
+
+#[derive(Debug)]
+pub struct Block {
+    pub stats: Vec<(Stat, u32)>,
+}
+
+#[derive(Debug)]
+pub enum Stat {
+    Local(Vec<Name>, Vec<Expr>),
+    LocalFunction(Name, Rc<FuncBody>),
+    Assign(Vec<Expr>, Vec<Expr>),
+    Call(Expr),
+    Do(Block),
+    While(Expr, Block),
+    Repeat(Block, Expr),
+    If(Vec<(Expr, Block)>, Option<Block>),
+    NumericFor(Name, Expr, Expr, Option<Expr>, Block),
+    GenericFor(Vec<Name>, Vec<Expr>, Block),
+    Return(Vec<Expr>),
+    Break,
+}
+
+#[derive(Debug)]
+pub struct FuncBody {
+    pub params: Vec<Name>,
+    pub vararg: bool,
+    pub body: Block,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    FloorDiv,
+    Mod,
+    Pow,
+    Concat,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnOp {
+    Neg,
+    Not,
+    Len,
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Nil,
+    True,
+    False,
+    Vararg,
+    Number(f64),
+    Str(Rc<[u8]>),
+    Function(Rc<FuncBody>),
+    Table(Vec<Field>),
+    Name(Name),
+    Index(Box<Expr>, Box<Expr>),
+    Call(Box<Expr>, Vec<Expr>),
+    Method(Box<Expr>, Name, Vec<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Unary(UnOp, Box<Expr>),
+    /// An expression in parentheses, cut to one value.
+    Paren(Box<Expr>),
+}
+
+#[derive(Debug)]
+pub enum Field {
+    Positional(Expr),
+    Named(Expr, Expr),
+}
+
+/// Priorities of binary operators as (left, right), as in Lua.
+fn priority(op: BinOp) -> (u8, u8) {
+    match op {
+        BinOp::Or => (1, 1),
+        BinOp::And => (2, 2),
+        BinOp::Eq | BinOp::Ne | BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => (3, 3),
+        BinOp::Concat => (9, 8),
+        BinOp::Add | BinOp::Sub => (10, 10),
+        BinOp::Mul | BinOp::Div | BinOp::FloorDiv | BinOp::Mod => (11, 11),
+        BinOp::Pow => (14, 13),
+    }
+}
+
+const UNARY_PRIORITY: u8 = 12;
+
+fn binary(token: &Token) -> Option<BinOp> {
+    Some(match token {
+        Token::Plus => BinOp::Add,
+        Token::Minus => BinOp::Sub,
+        Token::Star => BinOp::Mul,
+        Token::Slash => BinOp::Div,
+        Token::DoubleSlash => BinOp::FloorDiv,
+        Token::Percent => BinOp::Mod,
+        Token::Caret => BinOp::Pow,
+        Token::Concat => BinOp::Concat,
+        Token::Eq => BinOp::Eq,
+        Token::Ne => BinOp::Ne,
+        Token::Lt => BinOp::Lt,
+        Token::Le => BinOp::Le,
+        Token::Gt => BinOp::Gt,
+        Token::Ge => BinOp::Ge,
+        Token::And => BinOp::And,
+        Token::Or => BinOp::Or,
+        _ => return None,
+    })
+}
+
+struct Parser<'a> {
+    chunk: &'a str,
+    tokens: Vec<(Token, u32)>,
+    pos: usize,
+    depth: usize,
+    /// How many loops enclose the position, in the function parsed.
+    loops: usize,
+}
+
+/// The body of a chunk, a function taking `...`.
+pub fn parse(chunk: &str, src: &[u8]) -> Result<FuncBody, Error> {
+    let mut parser = Parser {
+        chunk,
+        tokens: tokenize(chunk, src)?,
+        pos: 0,
+        depth: 0,
+        loops: 0,
+    };
+    let body = parser.block()?;
+    parser.expect(Token::Eof)?;
+    Ok(FuncBody {
+        params: Vec::new(),
+        vararg: true,
+        body,
+    })
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn line(&self) -> u32 {
+        self.tokens[self.pos].1
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.tokens[self.pos].0.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn check(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.advance();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T, Error> {
+        let message = format!("{} near '{}'", message, self.peek().describe());
+        Err(Error::at(self.chunk, self.line(), &message))
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), Error> {
+        if self.check(&token) {
+            Ok(())
+        } else {
+            self.error(&format!("'{}' expected", token.describe()))
+        }
+    }
+
+    fn name(&mut self) -> Result<Name, Error> {
+        match self.peek() {
+            Token::Name(name) => {
+                let name = name.clone();
+                self.advance();
+                Ok(name)
+            }
+            _ => self.error("<name> expected"),
+        }
+    }
+
+    fn enter(&mut self) -> Result<(), Error> {
+        self.depth += 1;
+        if self.depth > MAX_NESTING {
+            return self.error("chunk has too many syntax levels");
+        }
+        Ok(())
+    }
+
+    fn block(&mut self) -> Result<Block, Error> {
+        self.enter()?;
+        let mut stats = Vec::new();
+        loop {
+            let line = self.line();
+            match self.peek() {
+                Token::Return => {
+                    self.advance();
+                    let values = match self.peek() {
+                        Token::End | Token::Else | Token::Elseif | Token::Until | Token::Eof => {
+                            Vec::new()
+                        }
+                        Token::Semicolon => Vec::new(),
+                        _ => self.expr_list()?,
+                    };
+                    self.check(&Token::Semicolon);
+                    stats.push((Stat::Return(values), line));
+                    break;
+                }
+                Token::End | Token::Else | Token::Elseif | Token::Until | Token::Eof => break,
+                Token::Semicolon => {
+                    self.advance();
+                }
+                _ => {
+                    let stat = self.statement()?;
+                    stats.push((stat, line));
+                }
+            }
+        }
+        self.depth -= 1;
+        Ok(Block { stats })
+    }
+
+    fn loop_body(&mut self) -> Result<Block, Error> {
+        self.loops += 1;
+        let body = self.block();
+        self.loops -= 1;
+        body
+    }
+
+    fn statement(&mut self) -> Result<Stat, Error> {
+        match self.advance() {
+            Token::If => {
+                let mut branches = Vec::new();
+                let condition = self.expr()?;
+                self.expect(Token::Then)?;
+                branches.push((condition, self.block()?));
+                let mut otherwise = None;
+                loop {
+                    match self.advance() {
+                        Token::Elseif => {
+                            let condition = self.expr()?;
+                            self.expect(Token::Then)?;
+                            branches.push((condition, self.block()?));
+                        }
+                        Token::Else => {
+                            otherwise = Some(self.block()?);
+                            self.expect(Token::End)?;
+                            break;
+                        }
+                        Token::End => break,
+                        _ => {
+                            self.pos -= 1;
+                            return self.error("'end' expected");
+                        }
+                    }
+                }
+                Ok(Stat::If(branches, otherwise))
+            }
+            Token::While => {
+                let condition = self.expr()?;
+                self.expect(Token::Do)?;
+                let body = self.loop_body()?;
+                self.expect(Token::End)?;
+                Ok(Stat::While(condition, body))
+            }
+            Token::Do => {
+                let body = self.block()?;
+                self.expect(Token::End)?;
+                Ok(Stat::Do(body))
+            }
+            Token::Repeat => {
+                let body = self.loop_body()?;
+                self.expect(Token::Until)?;
+                Ok(Stat::Repeat(body, self.expr()?))
+            }
+            Token::For => {
+                let first = self.name()?;
+                if self.check(&Token::Assign) {
+                    let start = self.expr()?;
+                    self.expect(Token::Comma)?;
+                    let limit = self.expr()?;
+                    let step = if self.check(&Token::Comma) {
+                        Some(self.expr()?)
+                    } else {
+                        None
+                    };
+                    self.expect(Token::Do)?;
+                    let body = self.loop_body()?;
+                    self.expect(Token::End)?;
+                    return Ok(Stat::NumericFor(first, start, limit, step, body));
+                }
+                let mut names = vec![first];
+                while self.check(&Token::Comma) {
+                    names.push(self.name()?);
+                }
+                self.expect(Token::In)?;
+                let values = self.expr_list()?;
+                self.expect(Token::Do)?;
+                let body = self.loop_body()?;
+                self.expect(Token::End)?;
+                Ok(Stat::GenericFor(names, values, body))
+            }
+            Token::Function => {
+                let mut target = Expr::Name(self.name()?);
+                let mut method = false;
+                loop {
+                    if self.check(&Token::Dot) {
+                        let key = Expr::Str(self.name()?);
+                        target = Expr::Index(Box::new(target), Box::new(key));
+                    } else if self.check(&Token::Colon) {
+                        let key = Expr::Str(self.name()?);
+                        target = Expr::Index(Box::new(target), Box::new(key));
+                        method = true;
+                        break;
+                    } else {
+                        break;
+                    }
+                }
+                let body = self.function_body(method)?;
+                Ok(Stat::Assign(vec![target], vec![Expr::Function(body)]))
+            }
+            Token::Local => {
+                if self.check(&Token::Function) {
+                    let name = self.name()?;
+                    return Ok(Stat::LocalFunction(name, self.function_body(false)?));
+                }
+                let mut names = vec![self.name()?];
+                while self.check(&Token::Comma) {
+                    names.push(self.name()?);
+                }
+                if *self.peek() == Token::Lt {
+                    return self.error("attributes are not supported");
+                }
+                let values = if self.check(&Token::Assign) {
+                    self.expr_list()?
+                } else {
+                    Vec::new()
+                };
+                Ok(Stat::Local(names, values))
+            }
+            Token::Break if self.loops == 0 => {
+                self.pos -= 1;
+                self.error("break outside a loop")
+            }
+            Token::Break => Ok(Stat::Break),
+            Token::Goto | Token::DoubleColon => {
+                self.pos -= 1;
+                self.error("goto is not supported")
+            }
+            _ => {
+                self.pos -= 1;
+                let first = self.suffixed()?;
+                if matches!(self.peek(), Token::Assign | Token::Comma) {
+                    let mut targets = vec![first];
+                    while self.check(&Token::Comma) {
+                        targets.push(self.suffixed()?);
+                    }
+                    if targets
+                        .iter()
+                        .any(|target| !matches!(target, Expr::Name(_) | Expr::Index(..)))
+                    {
+                        return self.error("syntax error");
+                    }
+                    self.expect(Token::Assign)?;
+                    return Ok(Stat::Assign(targets, self.expr_list()?));
+                }
+                match first {
+                    Expr::Call(..) | Expr::Method(..) => Ok(Stat::Call(first)),
+                    _ => self.error("syntax error"),
+                }
+            }
+        }
+    }
+
+    fn function_body(&mut self, method: bool) -> Result<Rc<FuncBody>, Error> {
+        let mut params = Vec::new();
+        if method {
+            params.push(Rc::from(&b"self"[..]));
+        }
+        let mut vararg = false;
+        self.expect(Token::LParen)?;
+        if !self.check(&Token::RParen) {
+            loop {
+                if self.check(&Token::Ellipsis) {
+                    vararg = true;
+                    break;
+                }
+                params.push(self.name()?);
+                if !self.check(&Token::Comma) {
+                    break;
+                }
+            }
+            self.expect(Token::RParen)?;
+        }
+        let loops = std::mem::take(&mut self.loops);
+        let body = self.block()?;
+        self.loops = loops;
+        self.expect(Token::End)?;
+        Ok(Rc::new(FuncBody {
+            params,
+            vararg,
+            body,
+        }))
+    }
+
+    fn expr_list(&mut self) -> Result<Vec<Expr>, Error> {
+        let mut exprs = vec![self.expr()?];
+        while self.check(&Token::Comma) {
+            exprs.push(self.expr()?);
+        }
+        Ok(exprs)
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        self.subexpr(0)
+    }
+
+    fn subexpr(&mut self, limit: u8) -> Result<Expr, Error> {
+        self.enter()?;
+        let unary = match self.peek() {
+            Token::Minus => Some(UnOp::Neg),
+            Token::Not => Some(UnOp::Not),
+            Token::Hash => Some(UnOp::Len),
+            _ => None,
+        };
+        let mut left = match unary {
+            Some(op) => {
+                self.advance();
+                let operand = self.subexpr(UNARY_PRIORITY)?;
+                match (op, operand) {
+                    (UnOp::Neg, Expr::Number(n)) => Expr::Number(-n),
+                    (op, operand) => Expr::Unary(op, Box::new(operand)),
+                }
+            }
+            None => self.simple()?,
+        };
+        while let Some(op) = binary(self.peek()) {
+            let (left_priority, right_priority) = priority(op);
+            if left_priority <= limit {
+                break;
+            }
+            self.advance();
+            let right = self.subexpr(right_priority)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+        self.depth -= 1;
+        Ok(left)
+    }
+
+    fn simple(&mut self) -> Result<Expr, Error> {
+        let expr = match self.peek() {
+            Token::Number(n) => Expr::Number(*n),
+            Token::Str(s) => Expr::Str(s.clone()),
+            Token::Nil => Expr::Nil,
+            Token::True => Expr::True,
+            Token::False => Expr::False,
+            Token::Ellipsis => Expr::Vararg,
+            Token::LBrace => return self.table(),
+            Token::Function => {
+                self.advance();
+                return Ok(Expr::Function(self.function_body(false)?));
+            }
+            _ => return self.suffixed(),
+        };
+        self.advance();
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, Error> {
+        match self.peek() {
+            Token::Name(_) => Ok(Expr::Name(self.name()?)),
+            Token::LParen => {
+                self.advance();
+                let expr = self.expr()?;
+                self.expect(Token::RParen)?;
+                Ok(Expr::Paren(Box::new(expr)))
+            }
+            _ => self.error("unexpected symbol"),
+        }
+    }
+
+    fn suffixed(&mut self) -> Result<Expr, Error> {
+        let mut expr = self.primary()?;
+        loop {
+            expr = match self.peek() {
+                Token::Dot => {
+                    self.advance();
+                    let key = Expr::Str(self.name()?);
+                    Expr::Index(Box::new(expr), Box::new(key))
+                }
+                Token::LBracket => {
+                    self.advance();
+                    let key = self.expr()?;
+                    self.expect(Token::RBracket)?;
+                    Expr::Index(Box::new(expr), Box::new(key))
+                }
+                Token::Colon => {
+                    self.advance();
+                    let name = self.name()?;
+                    let args = self.args()?;
+                    Expr::Method(Box::new(expr), name, args)
+                }
+                Token::LParen | Token::Str(_) | Token::LBrace => {
+                    let args = self.args()?;
+                    Expr::Call(Box::new(expr), args)
+                }
+                _ => return Ok(expr),
+            };
+        }
+    }
+
+    fn args(&mut self) -> Result<Vec<Expr>, Error> {
+        match self.peek() {
+            Token::Str(s) => {
+                let arg = Expr::Str(s.clone());
+                self.advance();
+                Ok(vec![arg])
+            }
+            Token::LBrace => Ok(vec![self.table()?]),
+            Token::LParen => {
+                self.advance();
+                if self.check(&Token::RParen) {
+                    return Ok(Vec::new());
+                }
+                let args = self.expr_list()?;
+                self.expect(Token::RParen)?;
+                Ok(args)
+            }
+            _ => self.error("function arguments expected"),
+        }
+    }
+
+    fn table(&mut self) -> Result<Expr, Error> {
+        self.expect(Token::LBrace)?;
+        let mut fields = Vec::new();
+        while !self.check(&Token::RBrace) {
+            let field = match self.peek() {
+                Token::LBracket => {
+                    self.advance();
+                    let key = self.expr()?;
+                    self.expect(Token::RBracket)?;
+                    self.expect(Token::Assign)?;
+                    Field::Named(key, self.expr()?)
+                }
+                Token::Name(name)
+                    if self.tokens.get(self.pos + 1).map(|(token, _)| token)
+                        == Some(&Token::Assign) =>
+                {
+                    let key = Expr::Str(name.clone());
+                    self.pos += 2;
+                    Field::Named(key, self.expr()?)
+                }
+                _ => Field::Positional(self.expr()?),
+            };
+            fields.push(field);
+            if !self.check(&Token::Comma) && !self.check(&Token::Semicolon) {
+                self.expect(Token::RBrace)?;
+                break;
+            }
+        }
+        Ok(Expr::Table(fields))
+    }
+}
@@ -0,0 +1,360 @@
+//! Lua patterns, as `string.find`, `match`, `gmatch` and `gsub` use them,
+//! following `lstrlib.c`.
+
+const ESCAPE: u8 = b'%';
+const SPECIALS: &[u8] = b"^$*+?.([%-";
+const MAX_CAPTURES: usize = 32;
+/// How deep matching may recurse, as `MAXCCALLS` of Lua.
+const MAX_DEPTH: usize = 200;
+/// How many steps one call may take, since patterns can backtrack a lot.
+const MAX_STEPS: u64 = 10_000_000;
+
+#[derive(Debug, Clone, Copy)]
+enum Length {
+    Unfinished,
+    Position,
+    Closed(usize),
+}
+
+/// A capture of a match.
+#[derive(Debug, PartialEq)]
+pub enum Capture<'a> {
+    Str(&'a [u8]),
+    /// An empty capture `()`, the position counting from 1.
+    Position(usize),
+}
+
+pub struct Matcher<'a> {
+    src: &'a [u8],
+    pat: &'a [u8],
+    captures: Vec<(usize, Length)>,
+    depth: usize,
+    steps: u64,
+}
+
+/// Whether `pat` has characters with a meaning in patterns.
+pub fn has_specials(pat: &[u8]) -> bool {
+    pat.iter().any(|c| SPECIALS.contains(c))
+}
+
+impl<'a> Matcher<'a> {
+    pub fn new(src: &'a [u8], pat: &'a [u8]) -> Matcher<'a> {
+        Matcher {
+            src,
+            pat,
+            captures: Vec::new(),
+            depth: 0,
+            steps: 0,
+        }
+    }
+
+    /// The end of a match of the pattern from `p` on starting at `s`.
+    pub fn match_at(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        self.captures.clear();
+        self.depth = 0;
+        self.do_match(s, p)
+    }
+
+    /// The captures of the last match, the whole match from `s` to `e` if
+    /// there are none and `whole` is set.
+    pub fn captures(&self, s: usize, e: usize, whole: bool) -> Result<Vec<Capture<'a>>, String> {
+        if self.captures.is_empty() && whole {
+            return Ok(vec![Capture::Str(&self.src[s..e])]);
+        }
+        (0..self.captures.len()).map(|i| self.capture(i)).collect()
+    }
+
+    fn capture(&self, i: usize) -> Result<Capture<'a>, String> {
+        let (start, length) = self.captures[i];
+        match length {
+            Length::Unfinished => Err("unfinished capture".to_string()),
+            Length::Position => Ok(Capture::Position(start + 1)),
+            Length::Closed(len) => Ok(Capture::Str(&self.src[start..start + len])),
+        }
+    }
+
+    /// Capture `i` of the last match, the whole match if it has none.
+    pub fn capture_or_whole(&self, i: usize, s: usize, e: usize) -> Result<Capture<'a>, String> {
+        if i >= self.captures.len() {
+            if i == 0 {
+                return Ok(Capture::Str(&self.src[s..e]));
+            }
+            return Err(format!("invalid capture index %{}", i + 1));
+        }
+        self.capture(i)
+    }
+
+    fn do_match(&mut self, mut s: usize, mut p: usize) -> Result<Option<usize>, String> {
+        self.steps += 1;
+        if self.steps > MAX_STEPS {
+            return Err("pattern too complex".to_string());
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err("pattern too complex".to_string());
+        }
+        self.depth += 1;
+        let result = loop {
+            let Some(&c) = self.pat.get(p) else {
+                break Some(s);
+            };
+            match c {
+                b'(' if self.pat.get(p + 1) == Some(&b')') => {
+                    break self.start_capture(s, p + 2, Length::Position)?;
+                }
+                b'(' => break self.start_capture(s, p + 1, Length::Unfinished)?,
+                b')' => break self.end_capture(s, p + 1)?,
+                b'$' if p + 1 == self.pat.len() => {
+                    break (s == self.src.len()).then_some(s);
+                }
+                ESCAPE if self.pat.get(p + 1) == Some(&b'b') => {
+                    match self.match_balance(s, p + 2)? {
+                        Some(end) => {
+                            s = end;
+                            p += 4;
+                        }
+                        None => break None,
+                    }
+                }
+                ESCAPE if self.pat.get(p + 1) == Some(&b'f') => {
+                    p += 2;
+                    if self.pat.get(p) != Some(&b'[') {
+                        return Err("missing '[' after '%f' in pattern".to_string());
+                    }
+                    let end = self.class_end(p)?;
+                    let previous = if s == 0 { 0 } else { self.src[s - 1] };
+                    let current = self.src.get(s).copied().unwrap_or(0);
+                    if !self.match_bracket_class(previous, p, end - 1)
+                        && self.match_bracket_class(current, p, end - 1)
+                    {
+                        p = end;
+                    } else {
+                        break None;
+                    }
+                }
+                ESCAPE if self.pat.get(p + 1).is_some_and(u8::is_ascii_digit) => {
+                    match self.match_capture(s, self.pat[p + 1])? {
+                        Some(end) => {
+                            s = end;
+                            p += 2;
+                        }
+                        None => break None,
+                    }
+                }
+                _ => {
+                    let end = self.class_end(p)?;
+                    let suffix = self.pat.get(end).copied();
+                    if !self.single_match(s, p, end) {
+                        if matches!(suffix, Some(b'*' | b'?' | b'-')) {
+                            p = end + 1;
+                            continue;
+                        }
+                        break None;
+                    }
+                    match suffix {
+                        Some(b'?') => match self.do_match(s + 1, end + 1)? {
+                            Some(result) => break Some(result),
+                            None => p = end + 1,
+                        },
+                        Some(b'+') => break self.max_expand(s + 1, p, end)?,
+                        Some(b'*') => break self.max_expand(s, p, end)?,
+                        Some(b'-') => break self.min_expand(s, p, end)?,
+                        _ => {
+                            s += 1;
+                            p = end;
+                        }
+                    }
+                }
+            }
+        };
+        self.depth -= 1;
+        Ok(result)
+    }
+
+    /// Where the single character class starting at `p` ends.
+    fn class_end(&self, mut p: usize) -> Result<usize, String> {
+        let c = self.pat[p];
+        p += 1;
+        if c == ESCAPE {
+            if p >= self.pat.len() {
+                return Err("malformed pattern (ends with '%')".to_string());
+            }
+            return Ok(p + 1);
+        }
+        if c == b'[' {
+            if self.pat.get(p) == Some(&b'^') {
+                p += 1;
+            }
+            loop {
+                let Some(&c) = self.pat.get(p) else {
+                    return Err("malformed pattern (missing ']')".to_string());
+                };
+                p += 1;
+                if c == ESCAPE && p < self.pat.len() {
+                    p += 1;
+                }
+                if self.pat.get(p) == Some(&b']') {
+                    return Ok(p + 1);
+                }
+            }
+        }
+        Ok(p)
+    }
+
+    fn single_match(&self, s: usize, p: usize, end: usize) -> bool {
+        let Some(&c) = self.src.get(s) else {
+            return false;
+        };
+        match self.pat[p] {
+            b'.' => true,
+            ESCAPE => match_class(c, self.pat[p + 1]),
+            b'[' => self.match_bracket_class(c, p, end - 1),
+            literal => literal == c,
+        }
+    }
+
+    /// Whether `c` is in the set from the `[` at `p` to the `]` at `end`.
+    fn match_bracket_class(&self, c: u8, mut p: usize, end: usize) -> bool {
+        let mut found = true;
+        if self.pat.get(p + 1) == Some(&b'^') {
+            found = false;
+            p += 1;
+        }
+        p += 1;
+        while p < end {
+            if self.pat[p] == ESCAPE {
+                p += 1;
+                if match_class(c, self.pat[p]) {
+                    return found;
+                }
+            } else if self.pat.get(p + 1) == Some(&b'-') && p + 2 < end {
+                if self.pat[p] <= c && c <= self.pat[p + 2] {
+                    return found;
+                }
+                p += 2;
+            } else if self.pat[p] == c {
+                return found;
+            }
+            p += 1;
+        }
+        !found
+    }
+
+    fn max_expand(&mut self, s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        let mut count = 0;
+        while self.single_match(s + count, p, end) {
+            count += 1;
+        }
+        loop {
+            if let Some(result) = self.do_match(s + count, end + 1)? {
+                return Ok(Some(result));
+            }
+            if count == 0 {
+                return Ok(None);
+            }
+            count -= 1;
+        }
+    }
+
+    fn min_expand(&mut self, mut s: usize, p: usize, end: usize) -> Result<Option<usize>, String> {
+        loop {
+            if let Some(result) = self.do_match(s, end + 1)? {
+                return Ok(Some(result));
+            }
+            if !self.single_match(s, p, end) {
+                return Ok(None);
+            }
+            s += 1;
+        }
+    }
+
+    fn start_capture(
+        &mut self,
+        s: usize,
+        p: usize,
+        length: Length,
+    ) -> Result<Option<usize>, String> {
+        if self.captures.len() >= MAX_CAPTURES {
+            return Err("too many captures".to_string());
+        }
+        self.captures.push((s, length));
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures.pop();
+        }
+        Ok(result)
+    }
+
+    fn end_capture(&mut self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let open = self
+            .captures
+            .iter()
+            .rposition(|(_, length)| matches!(length, Length::Unfinished))
+            .ok_or("invalid pattern capture")?;
+        self.captures[open].1 = Length::Closed(s - self.captures[open].0);
+        let result = self.do_match(s, p)?;
+        if result.is_none() {
+            self.captures[open].1 = Length::Unfinished;
+        }
+        Ok(result)
+    }
+
+    /// Matches the text of an earlier capture, `%1` to `%9`.
+    fn match_capture(&mut self, s: usize, digit: u8) -> Result<Option<usize>, String> {
+        let i = usize::from(digit - b'0');
+        let captured = i
+            .checked_sub(1)
+            .and_then(|i| self.captures.get(i))
+            .and_then(|&(start, length)| match length {
+                Length::Closed(len) => Some(&self.src[start..start + len]),
+                _ => None,
+            })
+            .ok_or_else(|| format!("invalid capture index %{}", i))?;
+        Ok(self.src[s..]
+            .starts_with(captured)
+            .then_some(s + captured.len()))
+    }
+
+    /// Matches `%bxy`, text from `x` to the `y` balancing it.
+    fn match_balance(&self, s: usize, p: usize) -> Result<Option<usize>, String> {
+        let (Some(&open), Some(&close)) = (self.pat.get(p), self.pat.get(p + 1)) else {
+            return Err("malformed pattern (missing arguments to '%b')".to_string());
+        };
+        if self.src.get(s) != Some(&open) {
+            return Ok(None);
+        }
+        let mut depth = 1;
+        for (i, &c) in self.src.iter().enumerate().skip(s + 1) {
+            if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(Some(i + 1));
+                }
+            } else if c == open {
+                depth += 1;
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Whether `c` is in the class `%class`, like `%a` or its complement `%A`.
+fn match_class(c: u8, class: u8) -> bool {
+    let found = match class.to_ascii_lowercase() {
+        b'a' => c.is_ascii_alphabetic(),
+        b'c' => c.is_ascii_control(),
+        b'd' => c.is_ascii_digit(),
+        b'g' => c.is_ascii_graphic(),
+        b'l' => c.is_ascii_lowercase(),
+        b'p' => c.is_ascii_punctuation(),
+        b's' => c.is_ascii_whitespace() || c == 0x0b,
+        b'u' => c.is_ascii_uppercase(),
+        b'w' => c.is_ascii_alphanumeric(),
+        b'x' => c.is_ascii_hexdigit(),
+        _ => return class == c,
+    };
+    if class.is_ascii_uppercase() {
+        !found
+    } else {
+        found
+    }
+}
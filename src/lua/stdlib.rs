@@ -0,0 +1,1049 @@
+//! The functions scripts can use, see the module documentation for which.
+
+use super::pattern::{self, Capture, Matcher};
+use super::{
+    format_e, format_g, integer, Error, Function, Lua, Table, TableRef, Value, MAX_STRING,
+};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+type Result<T> = std::result::Result<T, Error>;
+type Values = Result<Vec<Value>>;
+type Builtin = fn(&mut Lua, Vec<Value>) -> Values;
+
+pub fn open(lua: &mut Lua) {
+    let globals = lua.globals.clone();
+    let mut base = globals.borrow_mut();
+    let functions: &[(&str, Builtin)] = &[
+        ("assert", assert),
+        ("error", error),
+        ("ipairs", ipairs),
+        ("next", next),
+        ("pairs", pairs),
+        ("pcall", pcall),
+        ("print", print),
+        ("rawequal", rawequal),
+        ("rawget", rawget),
+        ("rawlen", rawlen),
+        ("rawset", rawset),
+        ("select", select),
+        ("tonumber", tonumber),
+        ("tostring", tostring),
+        ("type", type_),
+        ("unpack", unpack),
+        ("xpcall", xpcall),
+    ];
+    register(&mut base, functions);
+    base.set_str("_VERSION", Value::str("Lua 5.3"));
+
+    let strings: &[(&str, Builtin)] = &[
+        ("byte", byte),
+        ("char", char),
+        ("find", find),
+        ("format", format),
+        ("gmatch", gmatch),
+        ("gsub", gsub),
+        ("len", len),
+        ("lower", lower),
+        ("match", match_),
+        ("rep", rep),
+        ("reverse", reverse),
+        ("sub", sub),
+        ("upper", upper),
+    ];
+    register(&mut lua.strings.borrow_mut(), strings);
+    base.set_str("string", Value::Table(lua.strings.clone()));
+
+    let tables: &[(&str, Builtin)] = &[
+        ("concat", concat),
+        ("insert", insert),
+        ("remove", remove),
+        ("sort", sort),
+        ("unpack", unpack),
+    ];
+    base.set_str("table", library(tables));
+
+    let maths: &[(&str, Builtin)] = &[
+        ("abs", |_, args| math(args, "abs", f64::abs)),
+        ("ceil", |_, args| math(args, "ceil", f64::ceil)),
+        ("exp", |_, args| math(args, "exp", f64::exp)),
+        ("floor", |_, args| math(args, "floor", f64::floor)),
+        ("fmod", fmod),
+        ("log", log),
+        ("max", |_, args| extreme(args, "max", |a, b| a > b)),
+        ("min", |_, args| extreme(args, "min", |a, b| a < b)),
+        ("sqrt", |_, args| math(args, "sqrt", f64::sqrt)),
+        ("tointeger", tointeger),
+        ("modf", modf),
+        ("type", math_type),
+    ];
+    let math = library(maths);
+    if let Value::Table(table) = &math {
+        let mut table = table.borrow_mut();
+        table.set_str("huge", Value::Number(f64::INFINITY));
+        table.set_str("pi", Value::Number(std::f64::consts::PI));
+    }
+    base.set_str("math", math);
+
+    base.set_str("os", library(&[("time", time), ("clock", clock)]));
+}
+
+fn register(table: &mut Table, functions: &[(&str, Builtin)]) {
+    for &(name, function) in functions {
+        table.set_str(name, Function::native(function));
+    }
+}
+
+fn library(functions: &[(&str, Builtin)]) -> Value {
+    let mut table = Table::default();
+    register(&mut table, functions);
+    Value::Table(Rc::new(RefCell::new(table)))
+}
+
+fn arg(args: &[Value], i: usize) -> &Value {
+    const NIL: &Value = &Value::Nil;
+    args.get(i).unwrap_or(NIL)
+}
+
+fn bad_argument(i: usize, name: &str, message: &str) -> Error {
+    Error::new(format!(
+        "bad argument #{} to '{}' ({})",
+        i + 1,
+        name,
+        message
+    ))
+}
+
+fn expected(args: &[Value], i: usize, name: &str, what: &str) -> Error {
+    let got = match args.get(i) {
+        Some(value) => value.type_name(),
+        None => "no value",
+    };
+    bad_argument(i, name, &format!("{} expected, got {}", what, got))
+}
+
+fn check_number(args: &[Value], i: usize, name: &str) -> Result<f64> {
+    arg(args, i)
+        .to_number()
+        .ok_or_else(|| expected(args, i, name, "number"))
+}
+
+fn check_integer(args: &[Value], i: usize, name: &str) -> Result<i64> {
+    let n = check_number(args, i, name)?;
+    integer(n).ok_or_else(|| bad_argument(i, name, "number has no integer representation"))
+}
+
+fn opt_integer(args: &[Value], i: usize, name: &str, default: i64) -> Result<i64> {
+    match arg(args, i) {
+        Value::Nil => Ok(default),
+        _ => check_integer(args, i, name),
+    }
+}
+
+fn check_str(args: &[Value], i: usize, name: &str) -> Result<Rc<[u8]>> {
+    arg(args, i)
+        .to_bytes()
+        .ok_or_else(|| expected(args, i, name, "string"))
+}
+
+fn check_table(args: &[Value], i: usize, name: &str) -> Result<TableRef> {
+    match arg(args, i) {
+        Value::Table(table) => Ok(table.clone()),
+        _ => Err(expected(args, i, name, "table")),
+    }
+}
+
+fn string(bytes: Vec<u8>) -> Value {
+    Value::Str(bytes.into())
+}
+
+fn checked_string(bytes: Vec<u8>) -> Result<Value> {
+    if bytes.len() > MAX_STRING {
+        return Err(Error::new("resulting string too large"));
+    }
+    Ok(string(bytes))
+}
+
+/// The position `i` of a string of `len` bytes is at, counting from its end
+/// when negative.
+fn relative(i: i64, len: usize) -> i64 {
+    if i >= 0 {
+        i
+    } else if i.unsigned_abs() as usize > len {
+        0
+    } else {
+        len as i64 + i + 1
+    }
+}
+
+fn assert(_: &mut Lua, args: Vec<Value>) -> Values {
+    if arg(&args, 0).truthy() {
+        return Ok(args);
+    }
+    match args.get(1) {
+        Some(message) => Err(Error {
+            value: message.clone(),
+            located: true,
+            fatal: false,
+        }),
+        None => Err(Error::new("assertion failed!")),
+    }
+}
+
+fn error(_: &mut Lua, args: Vec<Value>) -> Values {
+    let level = opt_integer(&args, 1, "error", 1)?;
+    let value = arg(&args, 0).clone();
+    Err(Error {
+        located: level <= 0 || !matches!(value, Value::Str(_)),
+        value,
+        fatal: false,
+    })
+}
+
+fn ipairs(_: &mut Lua, args: Vec<Value>) -> Values {
+    if args.is_empty() {
+        return Err(expected(&args, 0, "ipairs", "table"));
+    }
+    let step = Function::native(|_, args| {
+        let i = check_integer(&args, 1, "ipairs")? + 1;
+        let value = match arg(&args, 0) {
+            Value::Table(table) => table.borrow().get(&Value::Number(i as f64)),
+            _ => Value::Nil,
+        };
+        Ok(match value {
+            Value::Nil => vec![Value::Nil],
+            value => vec![Value::Number(i as f64), value],
+        })
+    });
+    Ok(vec![step, args[0].clone(), Value::Number(0.0)])
+}
+
+fn next(_: &mut Lua, args: Vec<Value>) -> Values {
+    let table = check_table(&args, 0, "next")?;
+    let entry = table.borrow().next(arg(&args, 1))?;
+    Ok(match entry {
+        Some((key, value)) => vec![key, value],
+        None => vec![Value::Nil],
+    })
+}
+
+fn pairs(_: &mut Lua, args: Vec<Value>) -> Values {
+    let table = check_table(&args, 0, "pairs")?;
+    Ok(vec![
+        Function::native(next),
+        Value::Table(table),
+        Value::Nil,
+    ])
+}
+
+fn pcall(lua: &mut Lua, mut args: Vec<Value>) -> Values {
+    if args.is_empty() {
+        return Err(expected(&args, 0, "pcall", "value"));
+    }
+    let function = args.remove(0);
+    protect(lua.call_value(&function, args), |e| Ok(vec![e.value]))
+}
+
+fn xpcall(lua: &mut Lua, mut args: Vec<Value>) -> Values {
+    if args.len() < 2 {
+        return Err(expected(&args, 1, "xpcall", "value"));
+    }
+    let function = args.remove(0);
+    let handler = args.remove(0);
+    protect(lua.call_value(&function, args), |e| {
+        lua.call_value(&handler, vec![e.value])
+    })
+}
+
+/// The results of a protected call, prefixed by whether it succeeded.
+fn protect(results: Values, handle: impl FnOnce(Error) -> Values) -> Values {
+    match results {
+        Ok(results) => Ok([vec![Value::Bool(true)], results].concat()),
+        Err(e) if e.fatal => Err(e),
+        Err(e) => Ok([vec![Value::Bool(false)], handle(e)?].concat()),
+    }
+}
+
+fn print(lua: &mut Lua, args: Vec<Value>) -> Values {
+    let line = args
+        .iter()
+        .map(|value| String::from_utf8_lossy(&lua.to_string(value)).into_owned())
+        .collect::<Vec<_>>()
+        .join("\t");
+    tracing::info!(script = %lua.chunk, "{}", line);
+    Ok(Vec::new())
+}
+
+fn rawequal(_: &mut Lua, args: Vec<Value>) -> Values {
+    Ok(vec![Value::Bool(arg(&args, 0).raw_equal(arg(&args, 1)))])
+}
+
+fn rawget(_: &mut Lua, args: Vec<Value>) -> Values {
+    let table = check_table(&args, 0, "rawget")?;
+    let value = table.borrow().get(arg(&args, 1));
+    Ok(vec![value])
+}
+
+fn rawlen(_: &mut Lua, args: Vec<Value>) -> Values {
+    match arg(&args, 0) {
+        Value::Table(table) => Ok(vec![Value::Number(table.borrow().len() as f64)]),
+        Value::Str(s) => Ok(vec![Value::Number(s.len() as f64)]),
+        _ => Err(bad_argument(0, "rawlen", "table or string expected")),
+    }
+}
+
+fn rawset(_: &mut Lua, args: Vec<Value>) -> Values {
+    let table = check_table(&args, 0, "rawset")?;
+    let key = arg(&args, 1).clone();
+    table.borrow_mut().set(key, arg(&args, 2).clone())?;
+    Ok(vec![Value::Table(table)])
+}
+
+fn select(_: &mut Lua, args: Vec<Value>) -> Values {
+    if let Value::Str(s) = arg(&args, 0) {
+        if &s[..] == b"#" {
+            return Ok(vec![Value::Number((args.len() - 1) as f64)]);
+        }
+    }
+    let n = check_integer(&args, 0, "select")?;
+    let count = args.len() as i64 - 1;
+    let first = match n {
+        n if n < 0 && -n <= count => count + n,
+        n if n > 0 => (n - 1).min(count),
+        _ => return Err(bad_argument(0, "select", "index out of range")),
+    };
+    Ok(args[1 + first as usize..].to_vec())
+}
+
+fn tonumber(_: &mut Lua, args: Vec<Value>) -> Values {
+    if arg(&args, 1).is_nil() {
+        if args.is_empty() {
+            return Err(expected(&args, 0, "tonumber", "value"));
+        }
+        return Ok(vec![arg(&args, 0)
+            .to_number()
+            .map_or(Value::Nil, Value::Number)]);
+    }
+    let base = check_integer(&args, 1, "tonumber")?;
+    if !(2..=36).contains(&base) {
+        return Err(bad_argument(1, "tonumber", "base out of range"));
+    }
+    let Value::Str(digits) = arg(&args, 0) else {
+        return Err(expected(&args, 0, "tonumber", "string"));
+    };
+    let digits = String::from_utf8_lossy(digits);
+    let digits = digits.trim();
+    let (negative, digits) = match digits.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, digits),
+    };
+    let n = i64::from_str_radix(digits, base as u32)
+        .ok()
+        .filter(|_| !digits.starts_with('+'))
+        .map(|n| Value::Number(if negative { -n } else { n } as f64));
+    Ok(vec![n.unwrap_or_default()])
+}
+
+fn tostring(lua: &mut Lua, args: Vec<Value>) -> Values {
+    if args.is_empty() {
+        return Err(expected(&args, 0, "tostring", "value"));
+    }
+    Ok(vec![Value::Str(lua.to_string(&args[0]))])
+}
+
+fn type_(_: &mut Lua, args: Vec<Value>) -> Values {
+    if args.is_empty() {
+        return Err(expected(&args, 0, "type", "value"));
+    }
+    Ok(vec![Value::str(args[0].type_name())])
+}
+
+fn unpack(lua: &mut Lua, args: Vec<Value>) -> Values {
+    let table = check_table(&args, 0, "unpack")?;
+    let table = table.borrow();
+    let first = opt_integer(&args, 1, "unpack", 1)?;
+    let last = opt_integer(&args, 2, "unpack", table.len() as i64)?;
+    if last.saturating_sub(first) >= 1_000_000 {
+        return Err(Error::new("too many results to unpack"));
+    }
+    lua.step()?;
+    Ok((first..=last)
+        .map(|i| table.get(&Value::Number(i as f64)))
+        .collect())
+}
+
+fn byte(_: &mut Lua, args: Vec<Value>) -> Values {
+    let s = check_str(&args, 0, "byte")?;
+    let first = relative(opt_integer(&args, 1, "byte", 1)?, s.len()).max(1);
+    let last = relative(opt_integer(&args, 2, "byte", first)?, s.len()).min(s.len() as i64);
+    if first > last {
+        return Ok(Vec::new());
+    }
+    Ok(s[first as usize - 1..last as usize]
+        .iter()
+        .map(|&c| Value::Number(f64::from(c)))
+        .collect())
+}
+
+fn char(_: &mut Lua, args: Vec<Value>) -> Values {
+    let bytes = (0..args.len())
+        .map(|i| {
+            let c = check_integer(&args, i, "char")?;
+            u8::try_from(c).map_err(|_| bad_argument(i, "char", "value out of range"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(vec![string(bytes)])
+}
+
+fn len(_: &mut Lua, args: Vec<Value>) -> Values {
+    Ok(vec![
+        Value::Number(check_str(&args, 0, "len")?.len() as f64),
+    ])
+}
+
+fn lower(_: &mut Lua, args: Vec<Value>) -> Values {
+    Ok(vec![string(
+        check_str(&args, 0, "lower")?.to_ascii_lowercase(),
+    )])
+}
+
+fn upper(_: &mut Lua, args: Vec<Value>) -> Values {
+    Ok(vec![string(
+        check_str(&args, 0, "upper")?.to_ascii_uppercase(),
+    )])
+}
+
+fn reverse(_: &mut Lua, args: Vec<Value>) -> Values {
+    let mut s = check_str(&args, 0, "reverse")?.to_vec();
+    s.reverse();
+    Ok(vec![string(s)])
+}
+
+fn rep(_: &mut Lua, args: Vec<Value>) -> Values {
+    let s = check_str(&args, 0, "rep")?;
+    let n = check_integer(&args, 1, "rep")?;
+    let separator = match arg(&args, 2) {
+        Value::Nil => Rc::from(&b""[..]),
+        _ => check_str(&args, 2, "rep")?,
+    };
+    if n <= 0 {
+        return Ok(vec![Value::str("")]);
+    }
+    let total = (s.len() + separator.len()).saturating_mul(n as usize);
+    if total > MAX_STRING {
+        return Err(Error::new("resulting string too large"));
+    }
+    Ok(vec![string(vec![&s[..]; n as usize].join(&separator[..]))])
+}
+
+fn sub(_: &mut Lua, args: Vec<Value>) -> Values {
+    let s = check_str(&args, 0, "sub")?;
+    let first = relative(opt_integer(&args, 1, "sub", 1)?, s.len()).max(1);
+    let last = relative(opt_integer(&args, 2, "sub", -1)?, s.len()).min(s.len() as i64);
+    if first > last {
+        return Ok(vec![Value::str("")]);
+    }
+    Ok(vec![string(s[first as usize - 1..last as usize].to_vec())])
+}
+
+fn capture_value(capture: Capture) -> Value {
+    match capture {
+        Capture::Str(s) => string(s.to_vec()),
+        Capture::Position(i) => Value::Number(i as f64),
+    }
+}
+
+/// `string.find` and `string.match`, which share how they search.
+fn search(args: &[Value], name: &str, find: bool) -> Values {
+    let s = check_str(args, 0, name)?;
+    let pattern = check_str(args, 1, name)?;
+    let init = relative(opt_integer(args, 2, name, 1)?, s.len()).max(1) as usize - 1;
+    if init > s.len() {
+        return Ok(vec![Value::Nil]);
+    }
+    if find && (arg(args, 3).truthy() || !pattern::has_specials(&pattern)) {
+        let found = s[init..]
+            .windows(pattern.len().max(1))
+            .position(|window| window.starts_with(&pattern))
+            .or((pattern.is_empty()).then_some(0));
+        return Ok(match found {
+            Some(at) => vec![
+                Value::Number((init + at + 1) as f64),
+                Value::Number((init + at + pattern.len()) as f64),
+            ],
+            None => vec![Value::Nil],
+        });
+    }
+    let anchored = pattern.first() == Some(&b'^');
+    let p = usize::from(anchored);
+    let mut matcher = Matcher::new(&s, &pattern);
+    let mut start = init;
+    loop {
+        if let Some(end) = matcher.match_at(start, p).map_err(Error::new)? {
+            let captures = matcher.captures(start, end, !find).map_err(Error::new)?;
+            let mut results = Vec::new();
+            if find {
+                results.push(Value::Number((start + 1) as f64));
+                results.push(Value::Number(end as f64));
+            }
+            results.extend(captures.into_iter().map(capture_value));
+            return Ok(results);
+        }
+        start += 1;
+        if anchored || start > s.len() {
+            return Ok(vec![Value::Nil]);
+        }
+    }
+}
+
+fn find(_: &mut Lua, args: Vec<Value>) -> Values {
+    search(&args, "find", true)
+}
+
+fn match_(_: &mut Lua, args: Vec<Value>) -> Values {
+    search(&args, "match", false)
+}
+
+fn gmatch(_: &mut Lua, args: Vec<Value>) -> Values {
+    let s = check_str(&args, 0, "gmatch")?;
+    let pattern = check_str(&args, 1, "gmatch")?;
+    let position = Cell::new(0);
+    let last_match = Cell::new(None);
+    let step = Function::native(move |lua, _| {
+        let mut matcher = Matcher::new(&s, &pattern);
+        let mut start = position.get();
+        while start <= s.len() {
+            lua.step()?;
+            match matcher.match_at(start, 0).map_err(Error::new)? {
+                Some(end) if Some(end) != last_match.get() => {
+                    position.set(end);
+                    last_match.set(Some(end));
+                    let captures = matcher.captures(start, end, true).map_err(Error::new)?;
+                    return Ok(captures.into_iter().map(capture_value).collect());
+                }
+                _ => start += 1,
+            }
+        }
+        position.set(start);
+        Ok(vec![Value::Nil])
+    });
+    Ok(vec![step])
+}
+
+fn gsub(lua: &mut Lua, args: Vec<Value>) -> Values {
+    let s = check_str(&args, 0, "gsub")?;
+    let pattern = check_str(&args, 1, "gsub")?;
+    let replacement = arg(&args, 2).clone();
+    if !matches!(
+        replacement,
+        Value::Str(_) | Value::Number(_) | Value::Table(_) | Value::Function(_)
+    ) {
+        return Err(expected(&args, 2, "gsub", "string/function/table"));
+    }
+    let max = match arg(&args, 3) {
+        Value::Nil => i64::MAX,
+        _ => check_integer(&args, 3, "gsub")?,
+    };
+    let anchored = pattern.first() == Some(&b'^');
+    let p = usize::from(anchored);
+    let mut matcher = Matcher::new(&s, &pattern);
+    let mut result = Vec::new();
+    let mut start = 0;
+    let mut last_match = None;
+    let mut count = 0;
+    while count < max {
+        lua.step()?;
+        match matcher.match_at(start, p).map_err(Error::new)? {
+            Some(end) if Some(end) != last_match => {
+                count += 1;
+                let whole = &s[start..end];
+                substitute(
+                    lua,
+                    &matcher,
+                    (start, end, whole),
+                    &replacement,
+                    &mut result,
+                )?;
+                start = end;
+                last_match = Some(end);
+            }
+            _ if start < s.len() => {
+                result.push(s[start]);
+                start += 1;
+            }
+            _ => break,
+        }
+        if result.len() > MAX_STRING {
+            return Err(Error::new("resulting string too large"));
+        }
+        if anchored {
+            break;
+        }
+    }
+    result.extend_from_slice(&s[start.min(s.len())..]);
+    Ok(vec![checked_string(result)?, Value::Number(count as f64)])
+}
+
+/// Appends what the match from `start` to `end` is replaced with.
+fn substitute(
+    lua: &mut Lua,
+    matcher: &Matcher,
+    (start, end, whole): (usize, usize, &[u8]),
+    replacement: &Value,
+    result: &mut Vec<u8>,
+) -> Result<()> {
+    let value = match replacement {
+        Value::Table(table) => {
+            let key = capture_value(
+                matcher
+                    .capture_or_whole(0, start, end)
+                    .map_err(Error::new)?,
+            );
+            table.borrow().get(&key)
+        }
+        Value::Function(function) => {
+            let args = matcher.captures(start, end, true).map_err(Error::new)?;
+            let args = args.into_iter().map(capture_value).collect();
+            let results = lua.call_function(function, args)?;
+            results.into_iter().next().unwrap_or_default()
+        }
+        _ => {
+            let template = replacement.to_bytes().unwrap_or_else(|| Rc::from(&b""[..]));
+            let mut i = 0;
+            while i < template.len() {
+                let c = template[i];
+                i += 1;
+                if c != b'%' {
+                    result.push(c);
+                    continue;
+                }
+                match template.get(i) {
+                    Some(b'%') => result.push(b'%'),
+                    Some(b'0') => result.extend_from_slice(whole),
+                    Some(&digit) if digit.is_ascii_digit() => {
+                        let capture = matcher
+                            .capture_or_whole(usize::from(digit - b'1'), start, end)
+                            .map_err(Error::new)?;
+                        match capture {
+                            Capture::Str(s) => result.extend_from_slice(s),
+                            Capture::Position(at) => result.extend(at.to_string().bytes()),
+                        }
+                    }
+                    _ => return Err(Error::new("invalid use of '%' in replacement string")),
+                }
+                i += 1;
+            }
+            return Ok(());
+        }
+    };
+    match value {
+        Value::Nil | Value::Bool(false) => result.extend_from_slice(whole),
+        Value::Str(_) | Value::Number(_) => result.extend_from_slice(&lua.to_string(&value)),
+        value => {
+            let message = format!("invalid replacement value (a {})", value.type_name());
+            return Err(Error::new(message));
+        }
+    }
+    Ok(())
+}
+
+fn format(lua: &mut Lua, args: Vec<Value>) -> Values {
+    let template = check_str(&args, 0, "format")?;
+    let mut result = Vec::new();
+    let mut n = 0;
+    let mut i = 0;
+    while i < template.len() {
+        let c = template[i];
+        i += 1;
+        if c != b'%' {
+            result.push(c);
+            continue;
+        }
+        if template.get(i) == Some(&b'%') {
+            result.push(b'%');
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while template.get(i).is_some_and(|c| b"-+ #0".contains(c)) {
+            i += 1;
+        }
+        let flags = &template[start..i];
+        let width = digits(&template, &mut i);
+        let precision = if template.get(i) == Some(&b'.') {
+            i += 1;
+            Some(digits(&template, &mut i).unwrap_or(0))
+        } else {
+            None
+        };
+        let Some(&conversion) = template.get(i) else {
+            return Err(Error::new("invalid conversion '%' to 'format'"));
+        };
+        i += 1;
+        n += 1;
+        if n >= args.len() {
+            return Err(bad_argument(n, "format", "no value"));
+        }
+        let spec = Spec {
+            left: flags.contains(&b'-'),
+            plus: flags.contains(&b'+'),
+            space: flags.contains(&b' '),
+            alternate: flags.contains(&b'#'),
+            zero: flags.contains(&b'0'),
+            width: width.unwrap_or(0),
+        };
+        let formatted = match conversion {
+            b'd' | b'i' => {
+                let value = check_integer(&args, n, "format")?;
+                let mut text = value.unsigned_abs().to_string();
+                if let Some(precision) = precision {
+                    text = format!("{:0>1$}", text, precision);
+                }
+                spec.number(value < 0, text, precision.is_none())
+            }
+            b'u' => spec.number(false, check_integer(&args, n, "format")?.to_string(), true),
+            b'c' => {
+                let c = check_integer(&args, n, "format")?;
+                spec.pad(vec![c as u8])
+            }
+            b'x' | b'X' | b'o' => {
+                let value = check_integer(&args, n, "format")? as u64;
+                let mut text = match conversion {
+                    b'x' => format!("{:x}", value),
+                    b'X' => format!("{:X}", value),
+                    _ => format!("{:o}", value),
+                };
+                if let Some(precision) = precision {
+                    text = format!("{:0>1$}", text, precision);
+                }
+                if spec.alternate && value != 0 {
+                    text = match conversion {
+                        b'x' => format!("0x{}", text),
+                        b'X' => format!("0X{}", text),
+                        _ => format!("0{}", text),
+                    };
+                }
+                spec.number(false, text, precision.is_none())
+            }
+            b'e' | b'E' | b'f' | b'F' | b'g' | b'G' => {
+                let value = check_number(&args, n, "format")?;
+                let precision = precision.unwrap_or(6);
+                let text = if !value.is_finite() {
+                    if value.is_nan() { "nan" } else { "inf" }.to_string()
+                } else {
+                    match conversion.to_ascii_lowercase() {
+                        b'e' => format_e(value.abs(), precision),
+                        b'f' => format!("{:.*}", precision, value.abs()),
+                        _ => format_g(value.abs(), precision, spec.alternate),
+                    }
+                };
+                let text = if conversion.is_ascii_uppercase() {
+                    text.to_ascii_uppercase()
+                } else {
+                    text
+                };
+                let negative = value.is_sign_negative() && !value.is_nan();
+                spec.number(negative, text, value.is_finite())
+            }
+            b's' => {
+                let mut text = lua.to_string(&args[n]).to_vec();
+                if let Some(precision) = precision {
+                    text.truncate(precision);
+                }
+                spec.pad(text)
+            }
+            b'q' => quote(&args, n)?,
+            _ => {
+                let message = format!("invalid conversion '%{}' to 'format'", conversion as char);
+                return Err(Error::new(message));
+            }
+        };
+        if result.len() + formatted.len() > MAX_STRING {
+            return Err(Error::new("resulting string too large"));
+        }
+        result.extend(formatted);
+    }
+    Ok(vec![string(result)])
+}
+
+fn digits(template: &[u8], i: &mut usize) -> Option<usize> {
+    let start = *i;
+    while *i < template.len() && *i - start < 2 && template[*i].is_ascii_digit() {
+        *i += 1;
+    }
+    std::str::from_utf8(&template[start..*i]).ok()?.parse().ok()
+}
+
+/// The flags and width of a conversion of `string.format`.
+struct Spec {
+    left: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    zero: bool,
+    width: usize,
+}
+
+impl Spec {
+    /// `digits` with its sign, padded with zeros if asked and `zeros` allows.
+    fn number(&self, negative: bool, digits: String, zeros: bool) -> Vec<u8> {
+        let sign = match (negative, self.plus, self.space) {
+            (true, ..) => "-",
+            (false, true, _) => "+",
+            (false, false, true) => " ",
+            _ => "",
+        };
+        if self.zero && zeros && !self.left {
+            let width = self.width.saturating_sub(sign.len());
+            return format!("{}{:0>2$}", sign, digits, width).into_bytes();
+        }
+        self.pad(format!("{}{}", sign, digits).into_bytes())
+    }
+
+    fn pad(&self, text: Vec<u8>) -> Vec<u8> {
+        let fill = vec![b' '; self.width.saturating_sub(text.len())];
+        if self.left {
+            [text, fill].concat()
+        } else {
+            [fill, text].concat()
+        }
+    }
+}
+
+/// `%q`, a value as Lua source reading it back.
+fn quote(args: &[Value], n: usize) -> Result<Vec<u8>> {
+    match &args[n] {
+        Value::Str(s) => {
+            let mut quoted = vec![b'"'];
+            for (i, &c) in s.iter().enumerate() {
+                match c {
+                    b'"' | b'\\' | b'\n' => quoted.extend([b'\\', c]),
+                    b'\r' => quoted.extend(b"\\r"),
+                    0 if s.get(i + 1).is_some_and(u8::is_ascii_digit) => quoted.extend(b"\\000"),
+                    c if c.is_ascii_control() => {
+                        let digit = s.get(i + 1).is_some_and(u8::is_ascii_digit);
+                        let escaped = if digit {
+                            format!("\\{:03}", c)
+                        } else {
+                            format!("\\{}", c)
+                        };
+                        quoted.extend(escaped.bytes());
+                    }
+                    c => quoted.push(c),
+                }
+            }
+            quoted.push(b'"');
+            Ok(quoted)
+        }
+        Value::Number(number) => Ok(match integer(*number) {
+            Some(i) => i.to_string().into_bytes(),
+            None if number.is_nan() => b"(0/0)".to_vec(),
+            None if number.is_infinite() && *number > 0.0 => b"1e9999".to_vec(),
+            None if number.is_infinite() => b"-1e9999".to_vec(),
+            None => format_g(*number, 17, false).into_bytes(),
+        }),
+        Value::Nil | Value::Bool(_) => Ok(args[n].to_string().into_bytes()),
+        _ => Err(bad_argument(n, "format", "value has no literal form")),
+    }
+}
+
+fn concat(lua: &mut Lua, args: Vec<Value>) -> Values {
+    let table = check_table(&args, 0, "concat")?;
+    let separator = match arg(&args, 1) {
+        Value::Nil => Rc::from(&b""[..]),
+        _ => check_str(&args, 1, "concat")?,
+    };
+    let table = table.borrow();
+    let first = opt_integer(&args, 2, "concat", 1)?;
+    let last = opt_integer(&args, 3, "concat", table.len() as i64)?;
+    let mut result = Vec::new();
+    for i in first..=last {
+        lua.step()?;
+        let value = table.get(&Value::Number(i as f64));
+        let Some(text) = value.to_bytes() else {
+            let message = format!("invalid value (at index {}) in table for 'concat'", i);
+            return Err(Error::new(message));
+        };
+        result.extend_from_slice(&text);
+        if i < last {
+            result.extend_from_slice(&separator);
+        }
+        if result.len() > MAX_STRING {
+            return Err(Error::new("resulting string too large"));
+        }
+    }
+    Ok(vec![string(result)])
+}
+
+fn insert(lua: &mut Lua, args: Vec<Value>) -> Values {
+    let table = check_table(&args, 0, "insert")?;
+    let mut table = table.borrow_mut();
+    let end = table.len() as i64 + 1;
+    let (position, value) = match args.len() {
+        2 => (end, args[1].clone()),
+        3 => {
+            let position = check_integer(&args, 1, "insert")?;
+            if position < 1 || position > end {
+                return Err(bad_argument(1, "insert", "position out of bounds"));
+            }
+            (position, args[2].clone())
+        }
+        _ => return Err(Error::new("wrong number of arguments to 'insert'")),
+    };
+    for i in (position..end).rev() {
+        lua.step()?;
+        let moved = table.get(&Value::Number(i as f64));
+        table.set(Value::Number((i + 1) as f64), moved)?;
+    }
+    table.set(Value::Number(position as f64), value)?;
+    Ok(Vec::new())
+}
+
+fn remove(lua: &mut Lua, args: Vec<Value>) -> Values {
+    let table = check_table(&args, 0, "remove")?;
+    let mut table = table.borrow_mut();
+    let size = table.len() as i64;
+    let position = opt_integer(&args, 1, "remove", size)?;
+    if position != size && (position < 1 || position > size + 1) {
+        return Err(bad_argument(1, "remove", "position out of bounds"));
+    }
+    let removed = table.get(&Value::Number(position as f64));
+    let mut i = position;
+    while i < size {
+        lua.step()?;
+        let moved = table.get(&Value::Number((i + 1) as f64));
+        table.set(Value::Number(i as f64), moved)?;
+        i += 1;
+    }
+    table.set(Value::Number(i as f64), Value::Nil)?;
+    Ok(vec![removed])
+}
+
+fn sort(lua: &mut Lua, args: Vec<Value>) -> Values {
+    let table = check_table(&args, 0, "sort")?;
+    let compare = arg(&args, 1).clone();
+    if !matches!(compare, Value::Nil | Value::Function(_)) {
+        return Err(expected(&args, 1, "sort", "function"));
+    }
+    let values: Vec<Value> = {
+        let table = table.borrow();
+        (1..=table.len())
+            .map(|i| table.get(&Value::Number(i as f64)))
+            .collect()
+    };
+    let mut less = |a: &Value, b: &Value| -> Result<bool> {
+        lua.step()?;
+        match &compare {
+            Value::Function(function) => {
+                let results = lua.call_function(function, vec![a.clone(), b.clone()])?;
+                Ok(results.first().is_some_and(Value::truthy))
+            }
+            _ => lua.less(a, b, false),
+        }
+    };
+    let sorted = merge_sort(values, &mut less)?;
+    let mut table = table.borrow_mut();
+    for (i, value) in sorted.into_iter().enumerate() {
+        table.set(Value::Number((i + 1) as f64), value)?;
+    }
+    Ok(Vec::new())
+}
+
+/// A stable sort that fails with the comparison, and does not mind one that
+/// is no order at all, unlike the sorts of the standard library.
+fn merge_sort(
+    mut values: Vec<Value>,
+    less: &mut impl FnMut(&Value, &Value) -> Result<bool>,
+) -> Result<Vec<Value>> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, less)?;
+    let right = merge_sort(right, less)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        if less(b, a)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
+
+fn math(args: Vec<Value>, name: &str, f: fn(f64) -> f64) -> Values {
+    Ok(vec![Value::Number(f(check_number(&args, 0, name)?))])
+}
+
+fn extreme(args: Vec<Value>, name: &str, better: fn(f64, f64) -> bool) -> Values {
+    let mut best = check_number(&args, 0, name)?;
+    for i in 1..args.len() {
+        let n = check_number(&args, i, name)?;
+        if better(n, best) {
+            best = n;
+        }
+    }
+    Ok(vec![Value::Number(best)])
+}
+
+fn fmod(_: &mut Lua, args: Vec<Value>) -> Values {
+    let a = check_number(&args, 0, "fmod")?;
+    let b = check_number(&args, 1, "fmod")?;
+    Ok(vec![Value::Number(a % b)])
+}
+
+fn log(_: &mut Lua, args: Vec<Value>) -> Values {
+    let x = check_number(&args, 0, "log")?;
+    let result = match arg(&args, 1) {
+        Value::Nil => x.ln(),
+        _ => x.log(check_number(&args, 1, "log")?),
+    };
+    Ok(vec![Value::Number(result)])
+}
+
+fn modf(_: &mut Lua, args: Vec<Value>) -> Values {
+    let x = check_number(&args, 0, "modf")?;
+    let fraction = if x.is_infinite() { 0.0 } else { x.fract() };
+    Ok(vec![Value::Number(x.trunc()), Value::Number(fraction)])
+}
+
+fn tointeger(_: &mut Lua, args: Vec<Value>) -> Values {
+    let value = match arg(&args, 0) {
+        Value::Number(n) if integer(*n).is_some() => Value::Number(*n),
+        _ => Value::Nil,
+    };
+    Ok(vec![value])
+}
+
+fn math_type(_: &mut Lua, args: Vec<Value>) -> Values {
+    if args.is_empty() {
+        return Err(expected(&args, 0, "type", "value"));
+    }
+    let name = match &args[0] {
+        Value::Number(n) if integer(*n).is_some() => "integer",
+        Value::Number(_) => "float",
+        _ => return Ok(vec![Value::Nil]),
+    };
+    Ok(vec![Value::str(name)])
+}
+
+fn time(_: &mut Lua, _: Vec<Value>) -> Values {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    Ok(vec![Value::Number(now.as_secs() as f64)])
+}
+
+fn clock(_: &mut Lua, _: Vec<Value>) -> Values {
+    thread_local! {
+        static START: std::time::Instant = std::time::Instant::now();
+    }
+    Ok(vec![Value::Number(
+        START.with(|start| start.elapsed().as_secs_f64()),
+    )])
+}
@@ -0,0 +1,296 @@
+//! The subset of HTML that SNI tooltips may contain, turned into plain text
+//! for `tooltip_plain` and into Pango markup for `tooltip_pango`. Only text
+//! and the formatting tags Pango knows get through: links become their text,
+//! images, scripts and unknown tags are dropped and everything else is
+//! escaped, so a tooltip can't inject markup into the bar showing it.
+
+use serde_json::Value;
+
+/// Tags kept for Pango, with the name they have there.
+const PANGO_TAGS: [(&str, &str); 14] = [
+    ("b", "b"),
+    ("strong", "b"),
+    ("i", "i"),
+    ("em", "i"),
+    ("u", "u"),
+    ("s", "s"),
+    ("strike", "s"),
+    ("del", "s"),
+    ("sub", "sub"),
+    ("sup", "sup"),
+    ("tt", "tt"),
+    ("code", "tt"),
+    ("big", "big"),
+    ("small", "small"),
+];
+
+/// Tags whose content is no text to show.
+const HIDDEN_TAGS: [&str; 3] = ["script", "style", "head"];
+
+enum Token {
+    Text(String),
+    Open(&'static str),
+    Close(&'static str),
+}
+
+/// Splits `markup` into text with the entities decoded and lowercase tag
+/// names. Line breaks and paragraphs become newlines.
+fn tokens(markup: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut rest = markup;
+    let mut hidden = None;
+    while !rest.is_empty() {
+        let text = rest.find('<').unwrap_or(rest.len());
+        if text > 0 {
+            if hidden.is_none() {
+                tokens.push(Token::Text(decode(&rest[..text])));
+            }
+            rest = &rest[text..];
+            continue;
+        }
+        let starts_tag = rest[1..]
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '/' || c == '!');
+        if !starts_tag {
+            if hidden.is_none() {
+                tokens.push(Token::Text("<".to_string()));
+            }
+            rest = &rest[1..];
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            // not a tag after all
+            if hidden.is_none() {
+                tokens.push(Token::Text(decode(rest)));
+            }
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default();
+        let lower = name.to_ascii_lowercase();
+        if let Some(hidden_tag) = hidden {
+            if closing && lower == hidden_tag {
+                hidden = None;
+            }
+            continue;
+        }
+        if let Some(hidden_tag) = HIDDEN_TAGS.iter().find(|hidden| **hidden == lower) {
+            if !closing && !tag.ends_with('/') {
+                hidden = Some(*hidden_tag);
+            }
+            continue;
+        }
+        match (lower.as_str(), closing) {
+            ("br", _) | ("p", true) | ("li", false) | ("div", true) | ("tr", true) => {
+                tokens.push(Token::Text("\n".to_string()))
+            }
+            _ => match PANGO_TAGS.iter().find(|(html, _)| *html == lower) {
+                Some((_, pango)) if closing => tokens.push(Token::Close(pango)),
+                Some((_, pango)) => tokens.push(Token::Open(pango)),
+                None => {}
+            },
+        }
+    }
+    tokens
+}
+
+/// Replaces the character references of HTML.
+fn decode(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';').filter(|end| *end <= 10) else {
+            decoded.push('&');
+            rest = &rest[1..];
+            continue;
+        };
+        let entity = &rest[1..end];
+        let number = entity
+            .strip_prefix("#x")
+            .or_else(|| entity.strip_prefix("#X"))
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .or_else(|| entity.strip_prefix('#')?.parse().ok());
+        if let Some(number) = number {
+            // NUL, surrogates and what is beyond Unicode are dropped
+            decoded.extend(char::from_u32(number).filter(|c| *c != '\0'));
+            rest = &rest[end + 1..];
+            continue;
+        }
+        let c = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => None,
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// The text of `markup`.
+pub fn plain(markup: &str) -> String {
+    tokens(markup)
+        .into_iter()
+        .filter_map(|token| match token {
+            Token::Text(text) => Some(text),
+            _ => None,
+        })
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Plain `text` escaped for Pango markup.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// `markup` as Pango markup, with the tags balanced.
+pub fn pango(markup: &str) -> String {
+    let mut pango = String::with_capacity(markup.len());
+    let mut open = Vec::new();
+    for token in tokens(markup) {
+        match token {
+            Token::Text(text) => pango.push_str(&escape(&text)),
+            Token::Open(tag) => {
+                pango.push_str(&format!("<{}>", tag));
+                open.push(tag);
+            }
+            Token::Close(tag) => {
+                let Some(at) = open.iter().rposition(|open| *open == tag) else {
+                    continue;
+                };
+                // close what was opened inside first
+                for tag in open.drain(at..).rev() {
+                    pango.push_str(&format!("</{}>", tag));
+                }
+            }
+        }
+    }
+    for tag in open.into_iter().rev() {
+        pango.push_str(&format!("</{}>", tag));
+    }
+    pango.trim().to_string()
+}
+
+/// Adds `tooltip_plain`, and with `pango` also `tooltip_pango`, to a
+/// serialized item: the title and description of the tooltip on a line each.
+pub fn annotate(item: &mut Value, pango: bool) {
+    let tooltip = &item["tooltip"];
+    let parts = [&tooltip["title"], &tooltip["description"]]
+        .map(|part| part.as_str().unwrap_or_default().to_string());
+    let join = |convert: fn(&str) -> String| {
+        parts
+            .iter()
+            .map(|part| convert(part))
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    item["tooltip_plain"] = Value::String(join(plain));
+    if pango {
+        item["tooltip_pango"] = Value::String(join(self::pango));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_entities() {
+        assert_eq!(decode("a &amp; b &lt;c&gt; &quot;&apos;"), "a & b <c> \"'");
+        assert_eq!(decode("&#65;&#x42;&#X43;&nbsp;"), "ABC ");
+        assert_eq!(decode("&unknown; & &;"), "&unknown; & &;");
+        assert_eq!(
+            decode("&#xzz; &#; &ampersand-like-but-long;"),
+            "&#xzz; &#; &ampersand-like-but-long;"
+        );
+        assert_eq!(decode("trailing &amp"), "trailing &amp");
+    }
+
+    #[test]
+    fn drops_nul_and_surrogates() {
+        assert_eq!(decode("a&#0;b&#x0;c"), "abc");
+        assert_eq!(decode("a&#xD800;b&#56319;c"), "abc");
+        assert_eq!(decode("a&#x110000;b&#xFFFFFFF;c"), "abc");
+    }
+
+    #[test]
+    fn keeps_the_text_of_html() {
+        assert_eq!(plain("<b>Volume</b>: 50%"), "Volume: 50%");
+        assert_eq!(plain("one<br>two<br/>three"), "one\ntwo\nthree");
+        assert_eq!(plain("<p>a</p><p>b</p>"), "a\nb");
+        assert_eq!(plain("<a href=\"https://example.com\">link</a>"), "link");
+        assert_eq!(plain("<img src=\"x.png\"/>x<blink>y</blink>"), "xy");
+        assert_eq!(plain("<script>alert(1)</script>ok<style>b{}</style>"), "ok");
+        assert_eq!(plain("<SCRIPT>x</ScRiPt>y"), "y");
+    }
+
+    #[test]
+    fn treats_malformed_markup_as_text() {
+        assert_eq!(plain("1 < 2 > 0"), "1 < 2 > 0");
+        assert_eq!(plain("a <b unclosed"), "a <b unclosed");
+        assert_eq!(plain("<b>bold"), "bold");
+        assert_eq!(plain("</i>x"), "x");
+        assert_eq!(plain("<script>never closed"), "");
+    }
+
+    #[test]
+    fn converts_to_balanced_pango() {
+        assert_eq!(pango("<strong>a</strong> <em>b</em>"), "<b>a</b> <i>b</i>");
+        assert_eq!(pango("<b><i>x</b>y</i>"), "<b><i>x</i></b>y");
+        assert_eq!(pango("<b>open"), "<b>open</b>");
+        assert_eq!(pango("</u>stray"), "stray");
+        assert_eq!(pango("<a href=\"x\"><b>l</b></a>"), "<b>l</b>");
+        assert_eq!(
+            pango("<span foreground=\"red\">&lt;tag&gt; &amp;</span>"),
+            "&lt;tag&gt; &amp;"
+        );
+    }
+
+    #[test]
+    fn annotates_items() {
+        let mut item = serde_json::json!({
+            "tooltip": {"title": "<b>Title</b>", "description": ""},
+        });
+        annotate(&mut item, false);
+        assert_eq!(item["tooltip_plain"], "Title");
+        assert!(item.get("tooltip_pango").is_none());
+        item["tooltip"]["description"] = "a &amp; b".into();
+        annotate(&mut item, true);
+        assert_eq!(item["tooltip_plain"], "Title\na & b");
+        assert_eq!(item["tooltip_pango"], "<b>Title</b>\na &amp; b");
+    }
+}
@@ -0,0 +1,168 @@
+//! Client side of `com.canonical.dbusmenu`, the menus exported by tray items.
+
+use crate::icon;
+use async_std::future;
+use futures_util::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::time::Duration;
+use zbus::dbus_proxy;
+use zbus::zvariant::{OwnedValue, Value};
+
+/// How long to wait for the `LayoutUpdated` promised by `AboutToShow`.
+const LAYOUT_WAIT: Duration = Duration::from_millis(250);
+
+/// `(id, properties, children)`, the children being variants of the same type.
+pub type RawLayout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+//https://github.com/AyatanaIndicators/libdbusmenu/blob/master/libdbusmenu-glib/dbus-menu.xml
+#[dbus_proxy(interface = "com.canonical.dbusmenu", gen_async = true)]
+pub trait DBusMenu {
+    fn get_layout(
+        &self,
+        parent_id: i32,
+        recursion_depth: i32,
+        property_names: &[&str],
+    ) -> zbus::Result<(u32, RawLayout)>;
+
+    #[allow(clippy::type_complexity)]
+    fn get_group_properties(
+        &self,
+        ids: &[i32],
+        property_names: &[&str],
+    ) -> zbus::Result<Vec<(i32, HashMap<String, OwnedValue>)>>;
+
+    fn about_to_show(&self, id: i32) -> zbus::Result<bool>;
+
+    fn about_to_show_group(&self, ids: &[i32]) -> zbus::Result<(Vec<i32>, Vec<i32>)>;
+
+    fn event(&self, id: i32, event_id: &str, data: &Value<'_>, timestamp: u32) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn layout_updated(&self, revision: u32, parent: i32) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    #[allow(clippy::type_complexity)]
+    fn items_properties_updated(
+        &self,
+        updated_props: Vec<(i32, HashMap<String, OwnedValue>)>,
+        removed_props: Vec<(i32, Vec<String>)>,
+    ) -> zbus::Result<()>;
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MenuEntry {
+    pub id: i32,
+    /// Label with the `_` mnemonic markers removed
+    pub label: String,
+    pub enabled: bool,
+    pub visible: bool,
+    pub separator: bool,
+    /// `checkmark`, `radio` or empty
+    pub toggle_type: String,
+    /// `1` checked, `0` unchecked, `-1` indeterminate
+    pub toggle_state: i32,
+    /// Themed icon name, empty if the entry has none
+    pub icon_name: String,
+    /// Path of the saved `icon-data`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    pub children: Vec<MenuEntry>,
+    #[serde(skip)]
+    submenu: bool,
+}
+
+impl MenuEntry {
+    fn from_layout((id, props, children): RawLayout) -> Self {
+        let string = |key: &str| {
+            props
+                .get(key)
+                .and_then(|v| String::try_from(v.clone()).ok())
+                .unwrap_or_default()
+        };
+        let boolean = |key: &str| {
+            props
+                .get(key)
+                .and_then(|v| bool::try_from(v.clone()).ok())
+                .unwrap_or(true)
+        };
+        MenuEntry {
+            id,
+            label: strip_mnemonics(&string("label")),
+            enabled: boolean("enabled"),
+            visible: boolean("visible"),
+            separator: string("type") == "separator",
+            toggle_type: string("toggle-type"),
+            toggle_state: props
+                .get("toggle-state")
+                .and_then(|v| i32::try_from(v.clone()).ok())
+                .unwrap_or(-1),
+            icon_name: string("icon-name"),
+            icon: props
+                .get("icon-data")
+                .and_then(|v| Vec::<u8>::try_from(v.clone()).ok())
+                .filter(|data| !data.is_empty())
+                .and_then(|data| icon::save_png(&data)),
+            children: children
+                .into_iter()
+                .filter_map(|child| RawLayout::try_from(child).ok())
+                .map(MenuEntry::from_layout)
+                .collect(),
+            submenu: string("children-display") == "submenu",
+        }
+    }
+}
+
+/// Fetches the complete menu tree below the root entry.
+pub async fn fetch(proxy: &DBusMenuProxy<'_>) -> zbus::Result<Vec<MenuEntry>> {
+    let (_revision, layout) = proxy.get_layout(0, -1, &[]).await?;
+    Ok(MenuEntry::from_layout(layout).children)
+}
+
+/// Like [`fetch`], but first tells the application that its menu is about to be
+/// shown, giving lazily populated menus the chance to fill themselves in.
+pub async fn fetch_shown(proxy: &DBusMenuProxy<'_>) -> zbus::Result<Vec<MenuEntry>> {
+    let mut updates = proxy.receive_layout_updated().await?;
+    if proxy.about_to_show(0).await.unwrap_or(false) {
+        let _ = future::timeout(LAYOUT_WAIT, updates.next()).await;
+    }
+    let entries = fetch(proxy).await?;
+
+    let mut submenus = Vec::new();
+    collect_submenus(&entries, &mut submenus);
+    if submenus.is_empty() {
+        return Ok(entries);
+    }
+    match proxy.about_to_show_group(&submenus).await {
+        Ok((needs_update, _)) if !needs_update.is_empty() => {
+            let _ = future::timeout(LAYOUT_WAIT, updates.next()).await;
+            fetch(proxy).await
+        }
+        _ => Ok(entries),
+    }
+}
+
+fn collect_submenus(entries: &[MenuEntry], ids: &mut Vec<i32>) {
+    for entry in entries {
+        if entry.submenu || !entry.children.is_empty() {
+            ids.push(entry.id);
+        }
+        collect_submenus(&entry.children, ids);
+    }
+}
+
+/// `_File` becomes `File`, `__` an underscore.
+fn strip_mnemonics(label: &str) -> String {
+    let mut out = String::with_capacity(label.len());
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        if c == '_' {
+            if let Some(next) = chars.next() {
+                out.push(next);
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
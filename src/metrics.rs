@@ -0,0 +1,228 @@
+//! Process wide counters in the Prometheus text exposition format, and the
+//! memory use of `trayson stats`.
+
+use crate::icon;
+use crate::item::Item;
+use crate::menu::MenuEntry;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+const BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    items: AtomicU64,
+    updates: AtomicU64,
+    icon_bytes: AtomicU64,
+    dbus_errors: Mutex<BTreeMap<String, u64>>,
+    missing_properties: Mutex<BTreeMap<String, u64>>,
+    init_latency: Histogram,
+}
+
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, value: Duration) {
+        for (bucket, le) in self.buckets.iter().zip(BUCKETS) {
+            if value.as_secs_f64() <= le {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros
+            .fetch_add(value.as_micros() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            items: AtomicU64::new(0),
+            updates: AtomicU64::new(0),
+            icon_bytes: AtomicU64::new(0),
+            dbus_errors: Mutex::new(BTreeMap::new()),
+            missing_properties: Mutex::new(BTreeMap::new()),
+            init_latency: Histogram::new(),
+        }
+    }
+
+    pub fn set_items(&self, count: usize) {
+        self.items.store(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn update(&self) {
+        self.updates.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn icon_written(&self, bytes: u64) {
+        self.icon_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn dbus_error(&self, service: &str) {
+        *self
+            .dbus_errors
+            .lock()
+            .unwrap()
+            .entry(service.to_string())
+            .or_default() += 1;
+    }
+
+    /// An optional property the item doesn't have, which is no failure.
+    pub fn missing_property(&self, service: &str) {
+        *self
+            .missing_properties
+            .lock()
+            .unwrap()
+            .entry(service.to_string())
+            .or_default() += 1;
+    }
+
+    /// Drops the counts of `service`, an item gone or the bus name of one.
+    pub fn forget(&self, service: &str) {
+        self.dbus_errors.lock().unwrap().remove(service);
+        self.missing_properties.lock().unwrap().remove(service);
+    }
+
+    /// Time from a `StatusNotifierItemRegistered` signal to the item being emitted.
+    pub fn item_initialized(&self, latency: Duration) {
+        self.init_latency.observe(latency);
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let counters = [
+            (
+                "trayson_items",
+                "gauge",
+                "Currently tracked items",
+                &self.items,
+            ),
+            (
+                "trayson_updates_total",
+                "counter",
+                "State updates emitted",
+                &self.updates,
+            ),
+            (
+                "trayson_icon_bytes_written_total",
+                "counter",
+                "Bytes of icon files written",
+                &self.icon_bytes,
+            ),
+        ];
+        for (name, kind, help, value) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        let per_item = [
+            (
+                "trayson_dbus_errors_total",
+                "Failed D-Bus calls per item",
+                &self.dbus_errors,
+            ),
+            (
+                "trayson_missing_properties_total",
+                "Properties read per item that it doesn't have",
+                &self.missing_properties,
+            ),
+        ];
+        for (name, help, counts) in per_item {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (service, count) in counts.lock().unwrap().iter() {
+                let _ = writeln!(
+                    out,
+                    "{}{{item=\"{}\"}} {}",
+                    name,
+                    service.replace('\\', "\\\\").replace('"', "\\\""),
+                    count
+                );
+            }
+        }
+
+        let h = &self.init_latency;
+        let name = "trayson_item_init_seconds";
+        let _ = writeln!(out, "# HELP {} Item initialization latency", name);
+        let _ = writeln!(out, "# TYPE {} histogram", name);
+        for (bucket, le) in h.buckets.iter().zip(BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                le,
+                bucket.load(Ordering::Relaxed)
+            );
+        }
+        let count = h.count.load(Ordering::Relaxed);
+        let _ = writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            h.sum_micros.load(Ordering::Relaxed) as f64 / 1e6
+        );
+        let _ = writeln!(out, "{}_count {}", name, count);
+        out
+    }
+}
+
+/// Resident memory of this process in bytes, from `/proc/self/status`.
+fn rss() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?;
+    let kib = line.trim().strip_suffix("kB")?.trim().parse::<u64>().ok()?;
+    Some(kib * 1024)
+}
+
+fn menu_entries(entries: &[MenuEntry]) -> usize {
+    entries
+        .iter()
+        .map(|entry| 1 + menu_entries(&entry.children))
+        .sum()
+}
+
+/// What `stats` of the control socket returns: the resident memory, the icon
+/// files still saved and per item the size of its JSON, of its icon file and
+/// how many menu entries are kept.
+pub fn stats<'a>(items: impl Iterator<Item = &'a Item>) -> Value {
+    let (files, bytes) = icon::saved_usage();
+    let items = items
+        .map(|item| {
+            let icon = std::fs::metadata(&item.effective_icon.path).map_or(0, |meta| meta.len());
+            json!({
+                "id": item.id,
+                "json_bytes": serde_json::to_string(item).map_or(0, |json| json.len()),
+                "icon_bytes": icon,
+                "menu_entries": item.menu.as_deref().map_or(0, menu_entries),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "rss_bytes": rss(),
+        "icons": {"files": files, "bytes": bytes},
+        "items": items,
+    })
+}
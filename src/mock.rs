@@ -0,0 +1,478 @@
+//! `trayson mock-item`: fake StatusNotifierItems for trying out bars, filters
+//! and sinks without the applications behind them.
+//!
+//! Each item gets its own connection and bus name, a square pixmap in a color
+//! of its own and, with `--menu`, a flat dbusmenu. Calls to the items and clicks
+//! on their menus are printed as JSON lines on stdout. `--script` then changes
+//! all items at once, e.g. `status NeedsAttention; sleep 500; icon; sleep 5;
+//! repeat 200` for a storm of icon changes:
+//!
+//! - `status STATUS`, `title TEXT`, `tooltip TEXT`, `label TEXT` set the value
+//!   and emit its signal
+//! - `icon` switches to the next color and emits `NewIcon`
+//! - `sleep MS` waits
+//! - `hang MS` answers no calls for that long, like a frozen application
+//! - `register` registers the item with the watcher again, as some
+//!   applications do on reconnecting
+//! - `repeat N` runs the steps since the previous `repeat` N times in total
+//! - `exit` quits instead of waiting for SIGINT
+
+use crate::args::MockArgs;
+use crate::item::Pixmap;
+use crate::watcher::{self, WATCHER};
+use async_std::task;
+use futures_util::future;
+use serde_json::json;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+use zbus::{dbus_interface, Connection, ConnectionBuilder, SignalContext};
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+
+/// Colors of the pixmaps, cycled through by item and by `icon`.
+const COLORS: [u32; 6] = [0xe53935, 0x43a047, 0x1e88e5, 0xfdd835, 0x8e24aa, 0x00acc1];
+
+#[derive(Debug, Clone)]
+enum Step {
+    Status(String),
+    Title(String),
+    ToolTip(String),
+    Label(String),
+    Icon,
+    Sleep(Duration),
+    Hang(Duration),
+    Register,
+    Repeat(u32),
+    Exit,
+}
+
+#[derive(Debug, Clone)]
+pub struct Script(Vec<Step>);
+
+#[derive(Debug)]
+pub struct ScriptError(String);
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid step `{}`", self.0)
+    }
+}
+
+impl Error for ScriptError {}
+
+impl FromStr for Script {
+    type Err = ScriptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let steps = s
+            .split(';')
+            .map(str::trim)
+            .filter(|step| !step.is_empty())
+            .map(|step| {
+                let (command, arg) = step.split_once(' ').unwrap_or((step, ""));
+                let arg = arg.trim().to_string();
+                let number = || arg.parse().map_err(|_| ScriptError(step.to_string()));
+                Ok(match command {
+                    "status" => Step::Status(arg),
+                    "title" => Step::Title(arg),
+                    "tooltip" => Step::ToolTip(arg),
+                    "label" => Step::Label(arg),
+                    "icon" => Step::Icon,
+                    "sleep" => Step::Sleep(Duration::from_millis(number()?)),
+                    "hang" => Step::Hang(Duration::from_millis(number()?)),
+                    "register" => Step::Register,
+                    "repeat" => Step::Repeat(number()? as u32),
+                    "exit" => Step::Exit,
+                    _ => return Err(ScriptError(step.to_string())),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Script(steps))
+    }
+}
+
+struct MockItem {
+    id: String,
+    title: String,
+    category: String,
+    status: String,
+    tooltip: String,
+    label: String,
+    icon_name: String,
+    attention_icon_name: String,
+    overlay_icon_name: String,
+    pixmap_size: i32,
+    window_id: i32,
+    color: usize,
+    menu: bool,
+}
+
+/// Prints a call to an item or its menu.
+fn print_call(id: &str, call: &str, args: serde_json::Value) {
+    println!("{}", json!({"item": id, "call": call, "args": args}));
+}
+
+#[dbus_interface(name = "org.kde.StatusNotifierItem")]
+impl MockItem {
+    #[dbus_interface(property)]
+    fn category(&self) -> String {
+        self.category.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn title(&self) -> String {
+        self.title.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> String {
+        self.status.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn window_id(&self) -> i32 {
+        self.window_id
+    }
+
+    #[dbus_interface(property)]
+    fn icon_name(&self) -> String {
+        self.icon_name.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn icon_pixmap(&self) -> Pixmap {
+        if self.pixmap_size <= 0 {
+            return Vec::new();
+        }
+        let rgb = COLORS[self.color % COLORS.len()].to_be_bytes();
+        let pixel = [0xff, rgb[1], rgb[2], rgb[3]];
+        let data = pixel.repeat((self.pixmap_size * self.pixmap_size) as usize);
+        vec![(self.pixmap_size, self.pixmap_size, data)]
+    }
+
+    #[dbus_interface(property)]
+    fn attention_icon_name(&self) -> String {
+        self.attention_icon_name.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn overlay_icon_name(&self) -> String {
+        self.overlay_icon_name.clone()
+    }
+
+    #[dbus_interface(property)]
+    fn tool_tip(&self) -> (String, Pixmap, String, String) {
+        (
+            self.icon_name.clone(),
+            Vec::new(),
+            self.title.clone(),
+            self.tooltip.clone(),
+        )
+    }
+
+    #[dbus_interface(property)]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[dbus_interface(property)]
+    fn menu(&self) -> OwnedObjectPath {
+        let path = if self.menu { MENU_PATH } else { "/NO_DBUSMENU" };
+        OwnedObjectPath::try_from(path).unwrap_or_default()
+    }
+
+    #[dbus_interface(property, name = "XAyatanaLabel")]
+    fn x_ayatana_label(&self) -> String {
+        self.label.clone()
+    }
+
+    fn activate(&self, x: i32, y: i32) {
+        print_call(&self.id, "Activate", json!([x, y]));
+    }
+
+    fn secondary_activate(&self, x: i32, y: i32) {
+        print_call(&self.id, "SecondaryActivate", json!([x, y]));
+    }
+
+    fn provide_xdg_activation_token(&self, token: String) {
+        print_call(&self.id, "ProvideXdgActivationToken", json!([token]));
+    }
+
+    fn context_menu(&self, x: i32, y: i32) {
+        print_call(&self.id, "ContextMenu", json!([x, y]));
+    }
+
+    fn scroll(&self, delta: i32, orientation: String) {
+        print_call(&self.id, "Scroll", json!([delta, orientation]));
+    }
+
+    #[dbus_interface(signal)]
+    async fn new_title(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_icon(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_tool_tip(ctxt: &SignalContext<'_>) -> zbus::Result<()>;
+
+    #[dbus_interface(signal)]
+    async fn new_status(ctxt: &SignalContext<'_>, status: &str) -> zbus::Result<()>;
+
+    #[dbus_interface(signal, name = "XAyatanaNewLabel")]
+    async fn x_ayatana_new_label(
+        ctxt: &SignalContext<'_>,
+        label: &str,
+        guide: &str,
+    ) -> zbus::Result<()>;
+}
+
+/// A flat menu of the `--menu` labels, with ids from 1 on.
+struct MockMenu {
+    id: String,
+    labels: Vec<String>,
+}
+
+type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+#[dbus_interface(name = "com.canonical.dbusmenu")]
+impl MockMenu {
+    fn get_layout(
+        &self,
+        _parent_id: i32,
+        _recursion_depth: i32,
+        _property_names: Vec<String>,
+    ) -> (u32, Layout) {
+        let children = self
+            .labels
+            .iter()
+            .zip(1..)
+            .map(|(label, id)| {
+                let mut props = HashMap::new();
+                if label == "-" {
+                    props.insert("type".to_string(), Value::from("separator").into());
+                } else {
+                    props.insert("label".to_string(), Value::from(label.as_str()).into());
+                }
+                let entry: Layout = (id, props, Vec::new());
+                Value::from(entry).into()
+            })
+            .collect();
+        let mut root = HashMap::new();
+        root.insert(
+            "children-display".to_string(),
+            Value::from("submenu").into(),
+        );
+        (1, (0, root, children))
+    }
+
+    fn get_group_properties(
+        &self,
+        _ids: Vec<i32>,
+        _property_names: Vec<String>,
+    ) -> Vec<(i32, HashMap<String, OwnedValue>)> {
+        Vec::new()
+    }
+
+    fn event(&self, id: i32, event_id: String, _data: OwnedValue, _timestamp: u32) {
+        let label = usize::try_from(id - 1)
+            .ok()
+            .and_then(|i| self.labels.get(i));
+        print_call(&self.id, "Event", json!([id, event_id, label]));
+    }
+
+    fn about_to_show(&self, _id: i32) -> bool {
+        false
+    }
+
+    fn about_to_show_group(&self, _ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        (Vec::new(), Vec::new())
+    }
+
+    #[dbus_interface(property)]
+    fn version(&self) -> u32 {
+        3
+    }
+
+    #[dbus_interface(property)]
+    fn text_direction(&self) -> String {
+        "ltr".to_string()
+    }
+
+    #[dbus_interface(property)]
+    fn status(&self) -> String {
+        "normal".to_string()
+    }
+
+    #[dbus_interface(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Registers `--count` items with the running watcher, runs `--script` on all
+/// of them and keeps them until interrupted.
+pub async fn run(args: MockArgs) -> Result<(), Box<dyn Error>> {
+    let mut conns = Vec::new();
+    for n in 0..args.count {
+        let id = match args.count {
+            1 => args.id.clone(),
+            _ => format!("{}-{}", args.id, n + 1),
+        };
+        let item = MockItem {
+            id: id.clone(),
+            title: args.title.clone(),
+            category: args.category.clone(),
+            status: args.status.clone(),
+            tooltip: String::new(),
+            label: String::new(),
+            icon_name: args.icon_name.clone().unwrap_or_default(),
+            attention_icon_name: args.attention_icon_name.clone().unwrap_or_default(),
+            overlay_icon_name: args.overlay_icon_name.clone().unwrap_or_default(),
+            pixmap_size: args.pixmap_size,
+            window_id: args.window_id,
+            color: n as usize,
+            menu: !args.menu.is_empty(),
+        };
+        let name = format!(
+            "org.kde.StatusNotifierItem-{}-{}",
+            std::process::id(),
+            n + 1
+        );
+        let mut builder = ConnectionBuilder::session()?
+            .name(name.as_str())?
+            .serve_at(ITEM_PATH, item)?;
+        if !args.menu.is_empty() {
+            let menu = MockMenu {
+                id,
+                labels: args.menu.clone(),
+            };
+            builder = builder.serve_at(MENU_PATH, menu)?;
+        }
+        let conn = builder.build().await?;
+        register(&conn, &name).await?;
+        conns.push((conn, name));
+    }
+    if let Some(script) = &args.script {
+        let runs = conns.iter().map(|(conn, name)| play(conn, name, script));
+        if future::try_join_all(runs).await?.contains(&true) {
+            return Ok(());
+        }
+    }
+    future::pending::<()>().await;
+    Ok(())
+}
+
+/// Both ends of a connection without a bus, the second serving an item `id`
+/// with `title`, elsewhere than where items are if `id` is `None`, for tests.
+#[cfg(test)]
+pub async fn peers(id: Option<&str>, title: &str) -> zbus::Result<(Connection, Connection)> {
+    let (client, server) = std::os::unix::net::UnixStream::pair()?;
+    let guid = zbus::Guid::generate();
+    let item = MockItem {
+        id: id.unwrap_or_default().to_string(),
+        title: title.to_string(),
+        category: "ApplicationStatus".to_string(),
+        status: "Active".to_string(),
+        tooltip: String::new(),
+        label: String::new(),
+        icon_name: String::new(),
+        attention_icon_name: String::new(),
+        overlay_icon_name: String::new(),
+        pixmap_size: 0,
+        window_id: 0,
+        color: 0,
+        menu: false,
+    };
+    // serving something makes `build` wait for the object server, which
+    // answers calls to objects it does not serve, as a bus would
+    let path = match id {
+        Some(_) => ITEM_PATH,
+        None => "/Elsewhere",
+    };
+    let server = ConnectionBuilder::unix_stream(server)
+        .server(&guid)
+        .p2p()
+        .serve_at(path, item)?
+        .build();
+    let client = ConnectionBuilder::unix_stream(client).p2p().build();
+    let (client, server) = future::try_join(client, server).await?;
+    Ok((client, server))
+}
+
+async fn register(conn: &Connection, name: &str) -> zbus::Result<()> {
+    conn.call_method(
+        Some(WATCHER),
+        watcher::PATH,
+        Some(WATCHER),
+        "RegisterStatusNotifierItem",
+        &name,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Runs `script` on the item served on `conn` as `name`, true if it ends with
+/// `exit`.
+async fn play(conn: &Connection, name: &str, script: &Script) -> zbus::Result<bool> {
+    let iface = conn
+        .object_server()
+        .interface::<_, MockItem>(ITEM_PATH)
+        .await?;
+    let ctxt = iface.signal_context();
+    let mut start = 0;
+    let mut repeated = HashMap::new();
+    let mut i = 0;
+    while let Some(step) = script.0.get(i) {
+        i += 1;
+        match step {
+            Step::Status(status) => {
+                iface.get_mut().await.status = status.clone();
+                MockItem::new_status(ctxt, status).await?;
+            }
+            Step::Title(title) => {
+                iface.get_mut().await.title = title.clone();
+                MockItem::new_title(ctxt).await?;
+            }
+            Step::ToolTip(tooltip) => {
+                iface.get_mut().await.tooltip = tooltip.clone();
+                MockItem::new_tool_tip(ctxt).await?;
+            }
+            Step::Label(label) => {
+                iface.get_mut().await.label = label.clone();
+                MockItem::x_ayatana_new_label(ctxt, label, "").await?;
+            }
+            Step::Icon => {
+                iface.get_mut().await.color += 1;
+                MockItem::new_icon(ctxt).await?;
+            }
+            Step::Sleep(duration) => task::sleep(*duration).await,
+            Step::Hang(duration) => {
+                // calls wait for the interface meanwhile
+                let _frozen = iface.get_mut().await;
+                task::sleep(*duration).await;
+            }
+            Step::Register => register(conn, name).await?,
+            Step::Repeat(times) => {
+                let done = repeated.entry(i).or_insert(1);
+                if *done < *times {
+                    *done += 1;
+                    i = start;
+                } else {
+                    start = i;
+                }
+            }
+            Step::Exit => return Ok(true),
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,280 @@
+//! `--mqtt ADDR` (feature `mqtt`) publishes the items to an MQTT 3.1.1
+//! broker, for home automation dashboards mirroring the tray. Under the
+//! `--mqtt-topic` prefix, `trayson` by default:
+//!
+//! - `trayson/state`: the item list as emitted by a `json` sink, retained
+//! - `trayson/items/<id>`: each item, retained and emptied once it is gone
+//! - `trayson/events`: `{"event": "added"|"changed"|"removed", "id", "item",
+//!   "seq", "ts"}` for each item, and the `error` events of the control socket
+//! - `trayson/online`: `true` while connected, `false` as the last will
+//!
+//! Everything is sent with QoS 0. The connection is made again after it has
+//! been lost, with the retained messages sent again. There is no TLS, so the
+//! broker should be on localhost or reached through a tunnel.
+
+use crate::args::RunArgs;
+use crate::host::RECONNECT_DELAY;
+use crate::rt;
+use crate::schema::SCHEMA_VERSION;
+use crate::stamp::Stamp;
+use async_std::channel::{self, Receiver, Sender};
+use async_std::future;
+use async_std::io::{self, ReadExt, WriteExt};
+use async_std::net::TcpStream;
+use async_std::task;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Pings the broker when nothing was sent for this long.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// How long the broker may take to accept the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const RETAIN: u8 = 0x01;
+const PINGREQ: u8 = 0xc0;
+
+const CLEAN_SESSION: u8 = 0x02;
+const WILL: u8 = 0x04;
+const WILL_RETAIN: u8 = 0x20;
+const PASSWORD: u8 = 0x40;
+const USERNAME: u8 = 0x80;
+
+struct Message {
+    topic: String,
+    payload: Vec<u8>,
+    retain: bool,
+}
+
+struct Login {
+    addr: String,
+    prefix: String,
+    client_id: String,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+pub struct MqttPublisher {
+    prefix: String,
+    messages: Sender<Message>,
+    /// The serialized items published last by their `id`
+    last: Mutex<HashMap<String, String>>,
+}
+
+impl MqttPublisher {
+    /// Connects to the broker of `--mqtt` in the background.
+    pub fn start(args: &RunArgs) -> Result<MqttPublisher, Box<dyn Error>> {
+        let addr = args.mqtt.clone().ok_or("--mqtt is not set")?;
+        let password = match &args.mqtt_password_file {
+            Some(path) => {
+                let password = std::fs::read_to_string(path)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?;
+                Some(password.trim().to_string())
+            }
+            None => None,
+        };
+        let prefix = args.mqtt_topic.trim_end_matches('/').to_string();
+        let login = Login {
+            addr,
+            prefix: prefix.clone(),
+            client_id: format!("trayson-{}", std::process::id()),
+            username: args.mqtt_user.clone(),
+            password,
+        };
+        let (messages, r) = channel::unbounded();
+        rt::spawn(run(login, r));
+        Ok(MqttPublisher {
+            prefix,
+            messages,
+            last: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn send(&self, topic: String, payload: Vec<u8>, retain: bool) {
+        let _ = self.messages.try_send(Message {
+            topic,
+            payload,
+            retain,
+        });
+    }
+
+    /// Publishes the new item list and what changed since the last one.
+    pub fn publish(&self, items: &[Value], stamp: &Stamp) {
+        let state = Value::from(items).to_string();
+        self.send(format!("{}/state", self.prefix), state.into_bytes(), true);
+
+        let mut last = self.last.lock().unwrap();
+        let mut previous = std::mem::take(&mut *last);
+        for item in items {
+            let Some(id) = item["id"].as_str() else {
+                continue;
+            };
+            let json = item.to_string();
+            let event = match previous.remove(id) {
+                None => "added",
+                Some(old) if old != json => "changed",
+                Some(_) => {
+                    last.insert(id.to_string(), json);
+                    continue;
+                }
+            };
+            self.send(self.item_topic(id), json.clone().into_bytes(), true);
+            self.event(json!({"event": event, "id": id, "item": item}), stamp);
+            last.insert(id.to_string(), json);
+        }
+        for id in previous.keys() {
+            // an empty retained message deletes the retained one
+            self.send(self.item_topic(id), Vec::new(), true);
+            self.event(json!({"event": "removed", "id": id}), stamp);
+        }
+    }
+
+    /// Publishes an event like the `error` events of the control socket.
+    pub fn publish_event(&self, event: &Value) {
+        let topic = format!("{}/events", self.prefix);
+        self.send(topic, event.to_string().into_bytes(), false);
+    }
+
+    fn event(&self, mut event: Value, stamp: &Stamp) {
+        stamp.add_to(&mut event);
+        event["schema_version"] = SCHEMA_VERSION.into();
+        self.publish_event(&event);
+    }
+
+    /// `items/<id>`, with the characters that MQTT gives a meaning replaced.
+    fn item_topic(&self, id: &str) -> String {
+        let id = id.replace(['/', '+', '#'], "_");
+        format!("{}/items/{}", self.prefix, id)
+    }
+}
+
+/// Keeps a connection to the broker, sending the retained messages again
+/// after connecting and dropping others while there is none.
+async fn run(login: Login, messages: Receiver<Message>) {
+    let mut retained = BTreeMap::<String, Vec<u8>>::new();
+    loop {
+        let result = session(&login, &messages, &mut retained).await;
+        if messages.is_closed() {
+            return;
+        }
+        if let Err(e) = result {
+            tracing::warn!(broker = %login.addr, error = %e, "MQTT connection lost");
+        }
+        task::sleep(RECONNECT_DELAY).await;
+        while let Ok(message) = messages.try_recv() {
+            if message.retain {
+                retained.insert(message.topic, message.payload);
+            }
+        }
+    }
+}
+
+async fn session(
+    login: &Login,
+    messages: &Receiver<Message>,
+    retained: &mut BTreeMap<String, Vec<u8>>,
+) -> io::Result<()> {
+    let mut stream = TcpStream::connect(&login.addr).await?;
+    stream.set_nodelay(true)?;
+    let online = format!("{}/online", login.prefix);
+    stream.write_all(&connect(login, &online)).await?;
+    let mut connack = [0u8; 4];
+    io::timeout(CONNECT_TIMEOUT, stream.read_exact(&mut connack)).await?;
+    if connack[0] != CONNACK || connack[3] != 0 {
+        let reason = format!("connection refused with code {}", connack[3]);
+        return Err(io::Error::new(io::ErrorKind::ConnectionRefused, reason));
+    }
+    tracing::debug!(broker = %login.addr, "MQTT connected");
+    stream.write_all(&publish(&online, b"true", true)).await?;
+    for (topic, payload) in retained.iter() {
+        stream.write_all(&publish(topic, payload, true)).await?;
+    }
+
+    let mut reader = stream.clone();
+    let read = async {
+        // PINGRESP and nothing else is expected, EOF means the broker is gone
+        let mut buffer = [0u8; 64];
+        while reader.read(&mut buffer).await? > 0 {}
+        Err::<(), _>(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "closed by the broker",
+        ))
+    };
+    let write = async {
+        loop {
+            match future::timeout(KEEP_ALIVE, messages.recv()).await {
+                Ok(Ok(message)) => {
+                    stream
+                        .write_all(&publish(&message.topic, &message.payload, message.retain))
+                        .await?;
+                    if message.retain {
+                        retained.insert(message.topic, message.payload);
+                    }
+                }
+                Ok(Err(_)) => return Err(io::Error::other("the publisher is gone")),
+                Err(_) => stream.write_all(&[PINGREQ, 0]).await?,
+            }
+        }
+    };
+    futures_util::try_join!(read, write).map(|_: ((), ())| ())
+}
+
+/// `data` with its length in front, as MQTT has strings and binary data.
+fn field(packet: &mut Vec<u8>, data: &[u8]) {
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+}
+
+/// A packet of `kind` with the remaining length in front of `body`.
+fn packet(kind: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![kind];
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        packet.push(if len > 0 { byte | 0x80 } else { byte });
+        if len == 0 {
+            break;
+        }
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn connect(login: &Login, online: &str) -> Vec<u8> {
+    let mut flags = CLEAN_SESSION | WILL | WILL_RETAIN;
+    if login.username.is_some() {
+        flags |= USERNAME;
+        if login.password.is_some() {
+            flags |= PASSWORD;
+        }
+    }
+    let mut body = Vec::new();
+    field(&mut body, b"MQTT");
+    // protocol level 4 is MQTT 3.1.1
+    body.extend_from_slice(&[4, flags]);
+    body.extend_from_slice(&(KEEP_ALIVE.as_secs() as u16 * 2).to_be_bytes());
+    field(&mut body, login.client_id.as_bytes());
+    field(&mut body, online.as_bytes());
+    field(&mut body, b"false");
+    if let Some(username) = &login.username {
+        field(&mut body, username.as_bytes());
+        if let Some(password) = &login.password {
+            field(&mut body, password.as_bytes());
+        }
+    }
+    packet(CONNECT, &body)
+}
+
+fn publish(topic: &str, payload: &[u8], retain: bool) -> Vec<u8> {
+    let mut body = Vec::with_capacity(2 + topic.len() + payload.len());
+    field(&mut body, topic.as_bytes());
+    body.extend_from_slice(payload);
+    packet(if retain { PUBLISH | RETAIN } else { PUBLISH }, &body)
+}
@@ -0,0 +1,176 @@
+//! `--field-case` and the `[rename]` table of the config file: the names of
+//! the fields the sinks emit, for consumers with other conventions than
+//! snake_case. They apply to the fields of items at any depth and of the
+//! objects around them, not to the `waybar` format, whose fields are given by
+//! waybar, nor to the category keys of `grouped`:
+//!
+//! ```toml
+//! field_case = "camel"
+//!
+//! [rename]
+//! tooltip_plain = "text"
+//! ```
+//!
+//! Renamed fields are looked up by their snake_case name and emitted exactly
+//! as given. Filters, scripts and the control socket keep seeing snake_case.
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldCase {
+    /// `sni_id`
+    #[default]
+    Snake,
+    /// `sniId`
+    Camel,
+    /// `sni-id`
+    Kebab,
+}
+
+struct Naming {
+    case: FieldCase,
+    rename: Option<HashMap<String, String>>,
+}
+
+static NAMING: RwLock<Naming> = RwLock::new(Naming {
+    case: FieldCase::Snake,
+    rename: None,
+});
+
+/// Applies to everything emitted from now on.
+pub fn configure(case: FieldCase, rename: HashMap<String, String>) {
+    *NAMING.write().unwrap() = Naming {
+        case,
+        rename: Some(rename).filter(|rename| !rename.is_empty()),
+    };
+}
+
+/// Renames the fields of `value` and all objects in it.
+pub fn apply(value: &mut Value) {
+    let naming = NAMING.read().unwrap();
+    if naming.case != FieldCase::Snake || naming.rename.is_some() {
+        rename(value, &naming);
+    }
+}
+
+/// Renames only the fields of the objects in `value`, not its own keys.
+pub fn apply_inside(value: &mut Value) {
+    if let Value::Object(object) = value {
+        object.values_mut().for_each(apply);
+    }
+}
+
+fn rename(value: &mut Value, naming: &Naming) {
+    match value {
+        Value::Object(object) => {
+            let renamed = std::mem::take(object)
+                .into_iter()
+                .map(|(key, mut value)| {
+                    rename(&mut value, naming);
+                    (name(key, naming), value)
+                })
+                .collect::<Map<_, _>>();
+            *object = renamed;
+        }
+        Value::Array(values) => values.iter_mut().for_each(|value| rename(value, naming)),
+        _ => {}
+    }
+}
+
+fn name(key: String, naming: &Naming) -> String {
+    if let Some(renamed) = naming.rename.as_ref().and_then(|rename| rename.get(&key)) {
+        return renamed.clone();
+    }
+    match naming.case {
+        FieldCase::Snake => key,
+        FieldCase::Kebab => key.replace('_', "-"),
+        FieldCase::Camel => {
+            let mut parts = key.split('_');
+            let mut camel = parts.next().unwrap_or_default().to_string();
+            for part in parts {
+                let mut chars = part.chars();
+                if let Some(first) = chars.next() {
+                    camel.extend(first.to_uppercase());
+                    camel.push_str(chars.as_str());
+                }
+            }
+            camel
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn renamed(mut value: Value, case: FieldCase, rename: &[(&str, &str)]) -> Value {
+        let naming = Naming {
+            case,
+            rename: Some(
+                rename
+                    .iter()
+                    .map(|(from, to)| (from.to_string(), to.to_string()))
+                    .collect(),
+            ),
+        };
+        super::rename(&mut value, &naming);
+        value
+    }
+
+    #[test]
+    fn converts_the_case_of_fields() {
+        let item = json!({"sni_id": "nm", "tooltip_plain": "", "icon": {"width": 22}});
+        assert_eq!(
+            renamed(item.clone(), FieldCase::Camel, &[]),
+            json!({"sniId": "nm", "tooltipPlain": "", "icon": {"width": 22}}),
+        );
+        assert_eq!(
+            renamed(item.clone(), FieldCase::Kebab, &[]),
+            json!({"sni-id": "nm", "tooltip-plain": "", "icon": {"width": 22}}),
+        );
+        assert_eq!(renamed(item.clone(), FieldCase::Snake, &[]), item);
+    }
+
+    #[test]
+    fn renames_fields_at_any_depth() {
+        let items = json!([{"menu": [{"toggle_type": "", "children": [{"icon_name": ""}]}]}]);
+        assert_eq!(
+            renamed(items, FieldCase::Camel, &[]),
+            json!([{"menu": [{"toggleType": "", "children": [{"iconName": ""}]}]}]),
+        );
+    }
+
+    #[test]
+    fn renamed_fields_are_emitted_as_given() {
+        let item = json!({"tooltip_plain": "Wi-Fi", "sni_id": "nm"});
+        assert_eq!(
+            renamed(item, FieldCase::Camel, &[("tooltip_plain", "plain_text")]),
+            json!({"plain_text": "Wi-Fi", "sniId": "nm"}),
+        );
+    }
+
+    #[test]
+    fn keeps_values() {
+        let item = json!({"title": "sni_id", "label_guide": ["a_b"]});
+        assert_eq!(
+            renamed(item, FieldCase::Kebab, &[]),
+            json!({"title": "sni_id", "label-guide": ["a_b"]}),
+        );
+    }
+
+    #[test]
+    fn camel_case_skips_empty_parts() {
+        let naming = Naming {
+            case: FieldCase::Camel,
+            rename: None,
+        };
+        assert_eq!(name("icon__name_".to_string(), &naming), "iconName");
+        assert_eq!(name("x".to_string(), &naming), "x");
+    }
+}
@@ -0,0 +1,144 @@
+//! `--notify-attention`: a desktop notification whenever an item changes to
+//! `NeedsAttention`, for bars that hide the tray. It shows the attention icon
+//! of the item and its tooltip, and replaces the one sent for the item before.
+
+use crate::icon;
+use crate::item::{timed, Item, StatusNotifierItemProxy};
+use crate::registry::{Entry, Registry};
+use crate::rt;
+use crate::tray::TrayEvent;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use zbus::dbus_proxy;
+use zbus::zvariant::Value;
+
+#[dbus_proxy(
+    interface = "org.freedesktop.Notifications",
+    default_service = "org.freedesktop.Notifications",
+    default_path = "/org/freedesktop/Notifications"
+)]
+trait Notifications {
+    #[allow(clippy::too_many_arguments)]
+    fn notify(
+        &self,
+        app_name: &str,
+        replaces_id: u32,
+        app_icon: &str,
+        summary: &str,
+        body: &str,
+        actions: &[&str],
+        hints: HashMap<&str, Value<'_>>,
+        expire_timeout: i32,
+    ) -> zbus::Result<u32>;
+}
+
+/// Which items are in `NeedsAttention`, to tell when one changes to it.
+#[derive(Default)]
+pub struct Attention(HashSet<String>);
+
+impl Attention {
+    /// The entry of the item of `event` if it is in `NeedsAttention` now, after
+    /// all changes emitted together, and was not before.
+    pub fn began<'a>(&mut self, event: &TrayEvent, registry: &'a Registry) -> Option<&'a Entry> {
+        let id = match event {
+            TrayEvent::Added(item) | TrayEvent::Changed(item) => &item.id,
+            TrayEvent::Removed(id) => {
+                self.0.remove(id);
+                return None;
+            }
+            TrayEvent::Error { .. } => return None,
+        };
+        match registry.find(id) {
+            Some(entry) if entry.item.status == "NeedsAttention" => {
+                self.0.insert(id.clone()).then_some(entry)
+            }
+            _ => {
+                self.0.remove(id);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Notifier {
+    attention: Attention,
+    /// The last notification of each `id`, replaced by the next one
+    sent: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl Notifier {
+    /// Notifies if the item of `event` changed to `NeedsAttention` and `shows` it.
+    pub fn observe(
+        &mut self,
+        event: &TrayEvent,
+        registry: &Registry,
+        shows: impl Fn(&Item) -> bool,
+    ) {
+        let Some(entry) = self.attention.began(event, registry) else {
+            return;
+        };
+        if !shows(&entry.item) {
+            return;
+        }
+        let (item, proxy, sent) = (entry.item.clone(), entry.proxy.clone(), self.sent.clone());
+        rt::spawn(async move {
+            if let Err(e) = notify(&item, &proxy, &sent).await {
+                tracing::warn!(item = item.id, error = %e, "failed to notify");
+            }
+        });
+    }
+}
+
+async fn notify(
+    item: &Item,
+    proxy: &StatusNotifierItemProxy<'static>,
+    sent: &Mutex<HashMap<String, u32>>,
+) -> zbus::Result<()> {
+    let app = item.app.as_ref();
+    let summary = match (&item.title, app) {
+        (title, _) if !title.is_empty() => title,
+        (_, Some(app)) => &app.name,
+        _ => &item.id,
+    };
+    let body = match &item.tooltip {
+        tooltip if !tooltip.description.is_empty() => &tooltip.description,
+        tooltip if tooltip.title != *summary => &tooltip.title,
+        _ => "",
+    };
+    let mut hints = HashMap::new();
+    if let Some(app) = app {
+        let entry = Path::new(&app.desktop_file).file_stem();
+        if let Some(entry) = entry.and_then(|entry| entry.to_str()) {
+            hints.insert("desktop-entry", Value::from(entry.to_string()));
+        }
+    }
+    let icon = attention_icon(item, proxy).await;
+    let replaces = sent.lock().unwrap().get(&item.id).copied().unwrap_or(0);
+    let notifications = NotificationsProxy::new(proxy.connection()).await?;
+    let id = timed(
+        "Notify",
+        notifications.notify("trayson", replaces, &icon, summary, body, &[], hints, -1),
+    )
+    .await?;
+    sent.lock().unwrap().insert(item.id.clone(), id);
+    Ok(())
+}
+
+/// `AttentionIconName`, else the saved `AttentionIconPixmap`, else the icon.
+async fn attention_icon(item: &Item, proxy: &StatusNotifierItemProxy<'static>) -> String {
+    if let Ok(name) = timed("AttentionIconName", proxy.attention_icon_name()).await {
+        if !name.is_empty() {
+            return name;
+        }
+    }
+    if let Ok(pixmaps) = timed("AttentionIconPixmap", proxy.attention_icon_pixmap()).await {
+        if let Some(pixmap) = pixmaps.first() {
+            if let Ok(icon) = icon::save_pixmap(pixmap).await {
+                return icon.path;
+            }
+        }
+    }
+    item.icon.path.clone()
+}
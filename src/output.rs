@@ -0,0 +1,213 @@
+use crate::rt;
+use async_std::channel::{self, Sender};
+use async_std::fs::{self, File, OpenOptions};
+use async_std::io::{self, WriteExt};
+use async_std::os::unix::net::UnixListener;
+use async_std::sync::Mutex;
+use futures_util::StreamExt;
+use std::fmt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Where a sink writes to, as given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Destination {
+    Stdout,
+    /// Regular file or named pipe.
+    Path(PathBuf),
+    /// Unix socket broadcasting every update to all connected clients.
+    Socket(PathBuf),
+}
+
+/// As [`crate::sink::destination`] reads it, with `file:` left out.
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Destination::Stdout => write!(f, "stdout"),
+            Destination::Path(path) => write!(f, "{}", path.display()),
+            Destination::Socket(path) => write!(f, "socket:{}", path.display()),
+        }
+    }
+}
+
+/// Default state file location, `$XDG_RUNTIME_DIR/trayson/state.json`.
+pub fn default_state_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_RUNTIME_DIR")
+        .map(|dir| PathBuf::from(dir).join("trayson").join("state.json"))
+}
+
+/// Destination for the serialized tray state.
+pub enum Output {
+    Stdout,
+    /// Regular file, replaced atomically on every update.
+    File(PathBuf),
+    /// Named pipe, kept open and reopened once the reader goes away.
+    Fifo {
+        path: PathBuf,
+        file: Option<File>,
+    },
+    Socket(SocketServer),
+}
+
+impl Output {
+    pub async fn new(dest: Destination) -> io::Result<Self> {
+        Ok(match dest {
+            Destination::Stdout => Output::Stdout,
+            Destination::Path(path) => match fs::metadata(&path).await {
+                Ok(meta) if meta.file_type().is_fifo() => Output::Fifo { path, file: None },
+                _ => Output::File(path),
+            },
+            Destination::Socket(path) => Output::Socket(SocketServer::bind(path).await?),
+        })
+    }
+
+    pub async fn write(&mut self, line: &str) -> io::Result<()> {
+        self.write_frame(format!("{}\n", line).as_bytes()).await
+    }
+
+    /// Writes `frame` as it is, which carries its own delimiter like the
+    /// newline of a JSON line.
+    pub async fn write_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        match self {
+            Output::Stdout => {
+                let mut stdout = std::io::stdout().lock();
+                std::io::Write::write_all(&mut stdout, frame)?;
+                std::io::Write::flush(&mut stdout)
+            }
+            Output::File(path) => {
+                let mut tmp = path.clone().into_os_string();
+                tmp.push(".tmp");
+                fs::write(&tmp, frame).await?;
+                fs::rename(&tmp, path).await
+            }
+            Output::Fifo { path, file } => {
+                let f = match file {
+                    Some(f) => f,
+                    None => match open_fifo(path).await {
+                        Ok(f) => file.insert(f),
+                        // nobody is reading, they'll get the next update
+                        Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return Ok(()),
+                        Err(e) => return Err(e),
+                    },
+                };
+                let res = async {
+                    f.write_all(frame).await?;
+                    f.flush().await
+                }
+                .await;
+                match res {
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+                        *file = None;
+                        Ok(())
+                    }
+                    res => res,
+                }
+            }
+            Output::Socket(server) => {
+                server.broadcast(frame).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Opens the write end of a fifo without blocking until a reader shows up.
+async fn open_fifo(path: &PathBuf) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .await?;
+    // writes themselves should block like they do for stdout
+    unsafe {
+        let fd = file.as_raw_fd();
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        libc::fcntl(fd, libc::F_SETFL, flags & !libc::O_NONBLOCK);
+    }
+    Ok(file)
+}
+
+/// Frames queued for a client that doesn't read them, before it is dropped.
+const QUEUED_FRAMES: usize = 64;
+
+/// How long writing a single frame to a client may take.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A client of a socket or WebSocket, written to by a task of its own so one
+/// that stops reading doesn't hold up the others.
+#[derive(Clone)]
+pub struct Subscriber {
+    frames: Sender<Arc<[u8]>>,
+}
+
+impl Subscriber {
+    pub fn spawn(mut writer: impl io::Write + Send + Unpin + 'static) -> Subscriber {
+        let (frames, r) = channel::bounded::<Arc<[u8]>>(QUEUED_FRAMES);
+        rt::spawn(async move {
+            while let Ok(frame) = r.recv().await {
+                let written = io::timeout(WRITE_TIMEOUT, async {
+                    writer.write_all(&frame).await?;
+                    writer.flush().await
+                });
+                if written.await.is_err() {
+                    break;
+                }
+            }
+        });
+        Subscriber { frames }
+    }
+
+    /// Queues `frame`, false once the client is gone or too far behind to
+    /// keep it.
+    pub fn send(&self, frame: Arc<[u8]>) -> bool {
+        self.frames.try_send(frame).is_ok()
+    }
+}
+
+#[derive(Default)]
+struct Clients {
+    /// The frame sent last, for new clients
+    last: Option<Arc<[u8]>>,
+    subscribers: Vec<Subscriber>,
+}
+
+pub struct SocketServer {
+    clients: Arc<Mutex<Clients>>,
+}
+
+impl SocketServer {
+    async fn bind(path: PathBuf) -> io::Result<Self> {
+        // left over from a previous run
+        let _ = fs::remove_file(&path).await;
+        let listener = UnixListener::bind(&path).await?;
+        let clients = Arc::new(Mutex::new(Clients::default()));
+
+        let c = clients.clone();
+        rt::spawn(async move {
+            let mut incoming = listener.incoming();
+            while let Some(Ok(stream)) = incoming.next().await {
+                let subscriber = Subscriber::spawn(stream);
+                let mut clients = c.lock().await;
+                // new clients immediately get the current state
+                if let Some(frame) = &clients.last {
+                    subscriber.send(frame.clone());
+                }
+                clients.subscribers.push(subscriber);
+            }
+        });
+
+        Ok(SocketServer { clients })
+    }
+
+    async fn broadcast(&self, frame: &[u8]) {
+        let frame = Arc::<[u8]>::from(frame);
+        let mut clients = self.clients.lock().await;
+        clients.last = Some(frame.clone());
+        clients
+            .subscribers
+            .retain(|subscriber| subscriber.send(frame.clone()));
+    }
+}
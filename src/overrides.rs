@@ -0,0 +1,191 @@
+//! Per-item overrides from the `[[override]]` tables of the config file,
+//! applied to every item whose SNI `Id` matches the pattern.
+//!
+//! ```toml
+//! [[override]]
+//! id = "chrome_status_icon_*"
+//! title = "{tooltip} (Chrome)"
+//!
+//! [[override]]
+//! id = "discord"
+//! icon = "discord-tray"
+//! ```
+//!
+//! Titles may use `{title}`, `{tooltip}`, `{description}` and `{id}` for the
+//! values published by the application. Icons are either a file or the name of
+//! a themed icon, and replace the pixmap of the application.
+
+use crate::filter::{Pattern, PatternError};
+use crate::icon::{self, Icon};
+use serde::Deserialize;
+use serde_json::Value;
+use std::path::Path;
+
+/// An `[[override]]` table as written in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverrideConfig {
+    id: String,
+    title: Option<String>,
+    icon: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Override {
+    id: Pattern,
+    title: Option<String>,
+    icon: Option<Icon>,
+}
+
+impl OverrideConfig {
+    /// Resolves the icon, looking up names in `theme` first.
+    pub fn resolve(self, theme: Option<&str>) -> Result<Override, PatternError> {
+        let icon = self.icon.and_then(|icon| {
+            let path = if icon.contains('/') {
+                Some(Path::new(&icon).to_path_buf())
+            } else {
+                icon::find_themed(&icon, theme)
+            };
+            let icon = path
+                .filter(|path| path.is_file())
+                .map(|path| Icon::from_file(&path));
+            if icon.is_none() {
+                tracing::warn!(id = %self.id, "icon of the override not found");
+            }
+            icon
+        });
+        Ok(Override {
+            id: self.id.parse()?,
+            title: self.title,
+            icon,
+        })
+    }
+}
+
+impl Override {
+    /// Rewrites a serialized item if its `sni_id` matches.
+    pub fn apply(&self, item: &mut Value) {
+        if !self.id.matches(item["sni_id"].as_str().unwrap_or_default()) {
+            return;
+        }
+        if let Some(title) = &self.title {
+            item["title"] = Value::String(expand(title, item));
+        }
+        if let Some(icon) = self
+            .icon
+            .as_ref()
+            .and_then(|i| serde_json::to_value(i).ok())
+        {
+            item["effective_icon"] = icon.clone();
+            item["icon"] = icon;
+        }
+    }
+}
+
+/// Replaces the placeholders in `template`, leaving unknown ones as they are.
+fn expand(template: &str, item: &Value) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find('}') else {
+            break;
+        };
+        let value = match &rest[1..end] {
+            "title" => &item["title"],
+            "tooltip" => &item["tooltip"]["title"],
+            "description" => &item["tooltip"]["description"],
+            "id" => &item["sni_id"],
+            _ => {
+                expanded.push_str(&rest[..=end]);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        };
+        expanded.push_str(value.as_str().unwrap_or_default());
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn resolve(config: &str) -> Override {
+        toml::from_str::<OverrideConfig>(config)
+            .unwrap()
+            .resolve(None)
+            .unwrap()
+    }
+
+    fn chrome() -> Value {
+        json!({
+            "sni_id": "chrome_status_icon_1",
+            "title": "",
+            "tooltip": {"title": "Slack", "description": "3 unread"},
+        })
+    }
+
+    #[test]
+    fn expands_placeholders() {
+        let item = chrome();
+        assert_eq!(expand("{tooltip} (Chrome)", &item), "Slack (Chrome)");
+        assert_eq!(
+            expand("{id}: {description}{title}", &item),
+            "chrome_status_icon_1: 3 unread"
+        );
+    }
+
+    #[test]
+    fn keeps_unknown_and_unclosed_placeholders() {
+        let item = chrome();
+        assert_eq!(expand("{name} {tooltip}", &item), "{name} Slack");
+        assert_eq!(expand("{tooltip} {id", &item), "Slack {id");
+        assert_eq!(expand("}{}", &item), "}{}");
+    }
+
+    #[test]
+    fn rewrites_the_title_of_matching_items() {
+        let rule = resolve("id = 'chrome_status_icon_*'\ntitle = '{tooltip} (Chrome)'");
+        let mut item = chrome();
+        rule.apply(&mut item);
+        assert_eq!(item["title"], "Slack (Chrome)");
+        let mut other = json!({"sni_id": "discord", "title": "Discord"});
+        rule.apply(&mut other);
+        assert_eq!(other["title"], "Discord");
+    }
+
+    #[test]
+    fn replaces_the_icon_with_a_file() {
+        let path =
+            std::env::temp_dir().join(format!("trayson-override-{}.png", std::process::id()));
+        image::RgbaImage::new(16, 8).save(&path).unwrap();
+        let rule = resolve(&format!("id = 'discord'\nicon = '{}'", path.display()));
+        let mut item =
+            json!({"sni_id": "discord", "icon": {"width": 22, "height": 22, "path": ""}});
+        rule.apply(&mut item);
+        let icon = json!({"width": 16, "height": 8, "path": path.to_string_lossy()});
+        assert_eq!(item["icon"], icon);
+        assert_eq!(item["effective_icon"], icon);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn keeps_the_icon_if_the_file_is_missing() {
+        let rule = resolve("id = 'discord'\nicon = '/nonexistent/discord.png'");
+        let mut item =
+            json!({"sni_id": "discord", "icon": {"width": 22, "height": 22, "path": "a"}});
+        let before = item.clone();
+        rule.apply(&mut item);
+        assert_eq!(item, before);
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(toml::from_str::<OverrideConfig>("id = 'a'\nname = 'b'").is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! Uncompressed icon formats for `[icon] format`, written without the deflate
+//! pass of PNG. On a tmpfs icon dir that pass is most of the work per update,
+//! which adds up for items animating their icon:
+//!
+//! - `ppm`: binary RGB, alpha is dropped
+//! - `farbfeld`: 16 bit RGBA, read by the `image` crate
+//! - `qoi`: RGBA with the cheap run and difference coding of
+//!   <https://qoiformat.org>, smallest of the three
+//!
+//! The image crate reads the first two. [`decode_qoi`] reads what
+//! [`encode_qoi`] wrote, for the strip, the forwarder and the debug window.
+
+use image::RgbaImage;
+
+pub fn encode_ppm(image: &RgbaImage) -> Vec<u8> {
+    let mut data = format!("P6\n{} {}\n255\n", image.width(), image.height()).into_bytes();
+    data.reserve(image.as_raw().len() / 4 * 3);
+    for pixel in image.pixels() {
+        data.extend_from_slice(&pixel.0[..3]);
+    }
+    data
+}
+
+pub fn encode_farbfeld(image: &RgbaImage) -> Vec<u8> {
+    let mut data = Vec::with_capacity(16 + image.as_raw().len() * 2);
+    data.extend_from_slice(b"farbfeld");
+    data.extend_from_slice(&image.width().to_be_bytes());
+    data.extend_from_slice(&image.height().to_be_bytes());
+    for &channel in image.as_raw() {
+        // 0xab to 0xabab, so 0xff stays opaque
+        data.extend_from_slice(&[channel, channel]);
+    }
+    data
+}
+
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RUN: u8 = 0xc0;
+const QOI_OP_RGB: u8 = 0xfe;
+const QOI_OP_RGBA: u8 = 0xff;
+const QOI_END: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+
+fn qoi_hash([r, g, b, a]: [u8; 4]) -> usize {
+    (r as usize * 3 + g as usize * 5 + b as usize * 7 + a as usize * 11) % 64
+}
+
+pub fn encode_qoi(image: &RgbaImage) -> Vec<u8> {
+    let mut data = Vec::with_capacity(14 + image.as_raw().len() / 2);
+    data.extend_from_slice(b"qoif");
+    data.extend_from_slice(&image.width().to_be_bytes());
+    data.extend_from_slice(&image.height().to_be_bytes());
+    // 4 channels, sRGB with linear alpha
+    data.extend_from_slice(&[4, 0]);
+
+    let mut index = [[0u8; 4]; 64];
+    let mut previous = [0, 0, 0, 255];
+    let mut run = 0u8;
+    for pixel in image.pixels().map(|pixel| pixel.0) {
+        if pixel == previous {
+            run += 1;
+            if run == 62 {
+                data.push(QOI_OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            data.push(QOI_OP_RUN | (run - 1));
+            run = 0;
+        }
+        let hash = qoi_hash(pixel);
+        if index[hash] == pixel {
+            data.push(QOI_OP_INDEX | hash as u8);
+        } else if pixel[3] != previous[3] {
+            data.push(QOI_OP_RGBA);
+            data.extend_from_slice(&pixel);
+        } else {
+            let [dr, dg, db] = [0, 1, 2].map(|c| pixel[c].wrapping_sub(previous[c]) as i8);
+            let (dr_dg, db_dg) = (dr.wrapping_sub(dg), db.wrapping_sub(dg));
+            if (-2..2).contains(&dr) && (-2..2).contains(&dg) && (-2..2).contains(&db) {
+                data.push(
+                    QOI_OP_DIFF | ((dr + 2) as u8) << 4 | ((dg + 2) as u8) << 2 | (db + 2) as u8,
+                );
+            } else if (-32..32).contains(&dg)
+                && (-8..8).contains(&dr_dg)
+                && (-8..8).contains(&db_dg)
+            {
+                data.push(QOI_OP_LUMA | (dg + 32) as u8);
+                data.push(((dr_dg + 8) as u8) << 4 | (db_dg + 8) as u8);
+            } else {
+                data.push(QOI_OP_RGB);
+                data.extend_from_slice(&pixel[..3]);
+            }
+        }
+        index[hash] = pixel;
+        previous = pixel;
+    }
+    if run > 0 {
+        data.push(QOI_OP_RUN | (run - 1));
+    }
+    data.extend_from_slice(&QOI_END);
+    data
+}
+
+/// Decodes a QOI image, `None` if `data` is no QOI image or is cut short.
+pub fn decode_qoi(data: &[u8]) -> Option<RgbaImage> {
+    let header = data.get(..14)?;
+    if &header[..4] != b"qoif" {
+        return None;
+    }
+    let width = u32::from_be_bytes(header[4..8].try_into().ok()?);
+    let height = u32::from_be_bytes(header[8..12].try_into().ok()?);
+    let len = (width as usize)
+        .checked_mul(height as usize)?
+        .checked_mul(4)?;
+    // no op makes more than 62 pixels of a byte
+    if len / 4 > data.len() * 62 {
+        return None;
+    }
+    let mut pixels = Vec::with_capacity(len);
+    let mut bytes = data[14..].iter().copied();
+    let mut index = [[0u8; 4]; 64];
+    let mut pixel = [0, 0, 0, 255];
+    while pixels.len() < len {
+        let op = bytes.next()?;
+        let mut run = 1;
+        match op {
+            QOI_OP_RGB => {
+                for channel in &mut pixel[..3] {
+                    *channel = bytes.next()?;
+                }
+            }
+            QOI_OP_RGBA => {
+                for channel in &mut pixel {
+                    *channel = bytes.next()?;
+                }
+            }
+            _ => match op & 0xc0 {
+                QOI_OP_INDEX => pixel = index[op as usize],
+                QOI_OP_DIFF => {
+                    for (c, shift) in [(0, 4), (1, 2), (2, 0)] {
+                        pixel[c] = pixel[c].wrapping_add((op >> shift & 3).wrapping_sub(2));
+                    }
+                }
+                QOI_OP_LUMA => {
+                    let dg = (op & 0x3f).wrapping_sub(32);
+                    let next = bytes.next()?;
+                    pixel[0] = pixel[0].wrapping_add(dg.wrapping_add((next >> 4).wrapping_sub(8)));
+                    pixel[1] = pixel[1].wrapping_add(dg);
+                    pixel[2] = pixel[2].wrapping_add(dg.wrapping_add((next & 0xf).wrapping_sub(8)));
+                }
+                _ => run = (op & 0x3f) as usize + 1,
+            },
+        }
+        index[qoi_hash(pixel)] = pixel;
+        for _ in 0..run.min((len - pixels.len()) / 4) {
+            pixels.extend_from_slice(&pixel);
+        }
+    }
+    RgbaImage::from_raw(width, height, pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn image(pixels: &[[u8; 4]]) -> RgbaImage {
+        let data = pixels.concat();
+        RgbaImage::from_raw(pixels.len() as u32, 1, data).unwrap()
+    }
+
+    /// The ops of `pixels` encoded, without header and end marker.
+    fn ops(pixels: &[[u8; 4]]) -> Vec<u8> {
+        let data = encode_qoi(&image(pixels));
+        assert_eq!(&data[data.len() - 8..], &QOI_END);
+        assert_eq!(decode_qoi(&data).unwrap(), image(pixels));
+        data[14..data.len() - 8].to_vec()
+    }
+
+    #[test]
+    fn round_trips_an_empty_qoi_image() {
+        let empty = RgbaImage::new(0, 0);
+        let data = encode_qoi(&empty);
+        assert_eq!(data.len(), 14 + 8);
+        assert_eq!(&data[..4], b"qoif");
+        assert_eq!(decode_qoi(&data).unwrap().dimensions(), (0, 0));
+    }
+
+    #[test]
+    fn encodes_runs() {
+        // the start pixel, then a run longer than one op holds
+        let black = [0, 0, 0, 255];
+        assert_eq!(ops(&[black; 100]), [QOI_OP_RUN | 61, QOI_OP_RUN | 37]);
+        assert_eq!(ops(&[black; 62]), [QOI_OP_RUN | 61]);
+        let red = [200, 0, 0, 255];
+        assert_eq!(
+            ops(&[red, red, red]),
+            [QOI_OP_RGB, 200, 0, 0, QOI_OP_RUN | 1]
+        );
+    }
+
+    #[test]
+    fn encodes_index_hits() {
+        let (a, b) = ([10, 20, 30, 255], [200, 100, 50, 255]);
+        let ops = ops(&[a, b, a]);
+        assert_eq!(ops[ops.len() - 1], QOI_OP_INDEX | qoi_hash(a) as u8);
+    }
+
+    #[test]
+    fn encodes_differences_up_to_their_bounds() {
+        let start = [100, 100, 100, 255];
+        let with = |[r, g, b]: [u8; 3]| ops(&[start, [r, g, b, 255]])[4..].to_vec();
+        assert_eq!(with([98, 101, 100]), [QOI_OP_DIFF | 0b00_11_10]);
+        assert_eq!(with([101, 99, 98]), [QOI_OP_DIFF | 0b11_01_00]);
+        // a red difference of 2 takes a luma op
+        assert_eq!(with([102, 100, 100]), [QOI_OP_LUMA | 32, 0xa8]);
+        assert_eq!(with([68, 68, 75]), [QOI_OP_LUMA, 0x8f]);
+        assert_eq!(with([138, 131, 124]), [QOI_OP_LUMA | 63, 0xf1]);
+        // beyond the luma op
+        assert_eq!(with([132, 132, 132]), [QOI_OP_RGB, 132, 132, 132]);
+        assert_eq!(with([108, 100, 100]), [QOI_OP_RGB, 108, 100, 100]);
+        // wrapping around
+        assert_eq!(
+            ops(&[[255, 0, 1, 255], [0, 255, 0, 255]]),
+            [QOI_OP_DIFF | 0b01_10_11, QOI_OP_DIFF | 0b11_01_01]
+        );
+    }
+
+    #[test]
+    fn encodes_alpha_changes() {
+        let ops = ops(&[[1, 1, 1, 255], [1, 1, 1, 128], [1, 1, 1, 128]]);
+        let rgba = [QOI_OP_RGBA, 1, 1, 1, 128];
+        assert_eq!(
+            ops,
+            [&[QOI_OP_DIFF | 0b11_11_11][..], &rgba, &[QOI_OP_RUN]].concat()
+        );
+    }
+
+    #[test]
+    fn round_trips_noise() {
+        let mut state = 0x2545f491u32;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        let image = RgbaImage::from_fn(37, 23, |_, _| {
+            let value = next();
+            // runs, small differences and a few alpha changes
+            match value % 4 {
+                0 => Rgba([7, 7, 7, 255]),
+                1 => Rgba([value as u8 % 4 + 7, 7, 8, 255]),
+                2 => Rgba([
+                    (value >> 8) as u8,
+                    (value >> 16) as u8,
+                    (value >> 24) as u8,
+                    255,
+                ]),
+                _ => Rgba(value.to_le_bytes()),
+            }
+        });
+        assert_eq!(decode_qoi(&encode_qoi(&image)).unwrap(), image);
+    }
+
+    #[test]
+    fn rejects_broken_qoi_data() {
+        let data = encode_qoi(&image(&[[1, 2, 3, 4], [5, 6, 7, 8]]));
+        assert!(decode_qoi(&data[..data.len() - 12]).is_none());
+        assert!(decode_qoi(b"qoif").is_none());
+        assert!(decode_qoi(&[b"png!", &data[4..]].concat()).is_none());
+        // more pixels than the data could hold
+        let mut huge = data.clone();
+        huge[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(decode_qoi(&huge).is_none());
+    }
+
+    #[test]
+    fn writes_ppm() {
+        let data = encode_ppm(&image(&[[1, 2, 3, 4], [5, 6, 7, 8]]));
+        assert_eq!(data, b"P6\n2 1\n255\n\x01\x02\x03\x05\x06\x07");
+        let read = image::load_from_memory_with_format(&data, image::ImageFormat::Pnm).unwrap();
+        assert_eq!(read.to_rgba8().as_raw(), &[1, 2, 3, 255, 5, 6, 7, 255]);
+    }
+
+    #[test]
+    fn writes_farbfeld() {
+        let original = RgbaImage::from_raw(1, 2, vec![0xab, 0, 0xff, 0x80, 1, 2, 3, 4]).unwrap();
+        let data = encode_farbfeld(&original);
+        assert_eq!(&data[..16], b"farbfeld\0\0\0\x01\0\0\0\x02");
+        assert_eq!(data.len(), 16 + 2 * 4 * 2);
+        assert_eq!(&data[16..24], &[0xab, 0xab, 0, 0, 0xff, 0xff, 0x80, 0x80]);
+        let read =
+            image::load_from_memory_with_format(&data, image::ImageFormat::Farbfeld).unwrap();
+        assert_eq!(read.to_rgba8(), original);
+    }
+}
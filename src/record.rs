@@ -0,0 +1,159 @@
+//! `--record` writes what happened to each item as JSON lines, which
+//! `trayson replay` feeds back through filters, overrides and sinks:
+//!
+//! ```text
+//! {"item":{"id":"nm-applet",...},"service":":1.42","time":0.52}
+//! {"args":["NeedsAttention"],"service":":1.42","signal":"NewStatus","time":3.1}
+//! {"item":{"id":"nm-applet","status":"NeedsAttention",...},"service":":1.42","time":3.1}
+//! {"removed":true,"service":":1.42","time":9.8}
+//! ```
+//!
+//! `time` is in seconds since the start. Icons are referenced by path, like
+//! in the output, so they have to be attached separately.
+
+use crate::actions::ClickCommands;
+use crate::args::ReplayArgs;
+use crate::config;
+use crate::item::Item;
+use crate::script::Script;
+use crate::sink::Sink;
+use crate::stamp::Stamp;
+use async_std::task;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, LineWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Recording {
+    file: LineWriter<File>,
+    started: Instant,
+}
+
+static RECORDING: Mutex<Option<Recording>> = Mutex::new(None);
+
+/// Starts writing to `path`, replacing it.
+pub fn start(path: &Path) -> io::Result<()> {
+    let file = LineWriter::new(File::create(path)?);
+    *RECORDING.lock().unwrap() = Some(Recording {
+        file,
+        started: Instant::now(),
+    });
+    Ok(())
+}
+
+fn write(mut record: Value) {
+    let mut recording = RECORDING.lock().unwrap();
+    let Some(recording) = recording.as_mut() else {
+        return;
+    };
+    record["time"] = json!(recording.started.elapsed().as_secs_f64());
+    if let Err(e) = writeln!(recording.file, "{}", record) {
+        tracing::error!(error = %e, "failed to write the recording");
+    }
+}
+
+/// A signal received from the item of `service`.
+pub fn signal(service: &str, signal: &str, args: Value) {
+    write(json!({"service": service, "signal": signal, "args": args}));
+}
+
+/// The item of `service` as it is now.
+pub fn item(service: &str, item: &Item) {
+    write(json!({"service": service, "item": item}));
+}
+
+pub fn removed(service: &str) {
+    write(json!({"service": service, "removed": true}));
+}
+
+/// All items were dropped, as when the bus is gone.
+pub fn reset() {
+    write(json!({"reset": true}));
+}
+
+#[derive(Debug)]
+pub struct ReplayError(String);
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "replay: {}", self.0)
+    }
+}
+
+impl Error for ReplayError {}
+
+/// Emits the items of a recording to the sinks of `args`, with the original
+/// timing unless `--fast` is given.
+pub async fn replay(args: ReplayArgs) -> Result<(), Box<dyn Error>> {
+    let settings = config::load(args.run)?;
+    let click_commands = settings
+        .click_commands
+        .then(|| ClickCommands::new(settings.control_socket.as_deref()));
+    let mut sinks = Vec::new();
+    for config in settings.sink_configs() {
+        sinks.push(Sink::open(config).await?);
+    }
+    let text = std::fs::read_to_string(&args.file)
+        .map_err(|e| ReplayError(format!("{}: {}", args.file.display(), e)))?;
+    let records = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str::<Value>(line)
+                .map_err(|e| ReplayError(format!("line {}: {}", i + 1, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let script = settings.script.as_deref().map(Script::new);
+    let time = |record: &Value| Duration::from_secs_f64(record["time"].as_f64().unwrap_or(0.0));
+    let started = Instant::now();
+    let mut items = HashMap::new();
+    let mut records = records.iter().peekable();
+    while let Some(first) = records.next() {
+        if !args.fast {
+            task::sleep(time(first).saturating_sub(started.elapsed())).await;
+        }
+        // emitted together as in the live output
+        let mut batch = vec![first];
+        while let Some(next) =
+            records.next_if(|next| time(next) <= time(first) + settings.coalesce())
+        {
+            batch.push(next);
+        }
+        let mut changed = false;
+        for record in batch {
+            let service = record["service"].as_str().unwrap_or_default();
+            if let Some(item) = record.get("item") {
+                items.insert(service.to_string(), item.clone());
+                changed = true;
+            } else if record["removed"] == true {
+                changed |= items.remove(service).is_some();
+            } else if record["reset"] == true {
+                items.clear();
+                changed = true;
+            } else if let Some(signal) = record["signal"].as_str() {
+                tracing::debug!(service, signal, args = %record["args"], "recorded signal");
+            }
+        }
+        if !changed {
+            continue;
+        }
+        let mut values = items.values().cloned().collect::<Vec<_>>();
+        values.sort_by(|a, b| a["id"].as_str().cmp(&b["id"].as_str()));
+        let mut values = crate::cli::render(values, &settings, click_commands.as_ref());
+        if let Some(script) = &script {
+            values = script.transform(values).await;
+        }
+        let stamp = Stamp::next();
+        for sink in sinks.iter_mut() {
+            sink.emit(&values, &stamp).await?;
+        }
+    }
+    Ok(())
+}
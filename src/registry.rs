@@ -0,0 +1,335 @@
+use crate::item::{self, Item, StatusNotifierItemProxy};
+use crate::metrics::METRICS;
+use async_std::sync::Mutex;
+use futures_util::future::AbortHandle;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub type SharedRegistry = Arc<Mutex<Registry>>;
+
+pub struct Entry {
+    pub item: Item,
+    pub proxy: StatusNotifierItemProxy<'static>,
+}
+
+/// All currently tracked items, keyed by the service they registered with.
+#[derive(Default)]
+pub struct Registry {
+    entries: HashMap<String, Entry>,
+    /// Cancels the tasks following an item, which may run before it is inserted
+    tasks: HashMap<String, AbortHandle>,
+    /// The last error reading the item of a service, for the state dump
+    errors: HashMap<String, String>,
+}
+
+impl Registry {
+    /// Adds or replaces the item of `service`, assigning its [`Item::id`]. That
+    /// only changes with the SNI `Id`, e.g. once an unresponsive item shown by
+    /// its service answers, which callers emit as a removal and an addition.
+    pub fn insert(
+        &mut self,
+        service: String,
+        mut item: Item,
+        proxy: StatusNotifierItemProxy<'static>,
+    ) {
+        item.id = match self.entries.remove(&service) {
+            Some(entry) if entry.item.sni_id == item.sni_id => {
+                // read again, the item still needs attention since the same time
+                if item.urgent {
+                    item.attention_since = entry.item.attention_since.or(item.attention_since);
+                }
+                entry.item.id
+            }
+            _ => self.unique_id(&service, &item),
+        };
+        self.entries.insert(service, Entry { item, proxy });
+    }
+
+    /// The slugified SNI `Id`, which is stable across restarts. Should another
+    /// item have it, like another instance of an application, a hash of what
+    /// identifies this one across restarts is appended, see [`stable_key`].
+    /// Ids already given out stay as they are.
+    fn unique_id(&self, service: &str, item: &Item) -> String {
+        let taken = |id: &str| self.entries.values().any(|entry| entry.item.id == id);
+        let id = base_id(service, item);
+        if !taken(&id) {
+            return id;
+        }
+        let suffixed = format!(
+            "{}-{:08x}",
+            id,
+            fnv1a(stable_key(service, item).as_bytes()) as u32
+        );
+        // instances alike in everything, told apart by their order
+        let mut unique = suffixed.clone();
+        for n in 2.. {
+            if !taken(&unique) {
+                break;
+            }
+            unique = format!("{}-{}", suffixed, n);
+        }
+        unique
+    }
+
+    /// Drops the item of `service` and cancels its tasks.
+    pub fn remove(&mut self, service: &str) {
+        if let Some(tasks) = self.tasks.remove(service) {
+            tasks.abort();
+        }
+        self.entries.remove(service);
+        self.errors.remove(service);
+        self.forget(service);
+    }
+
+    /// Drops the metrics of `service`, counted by it or by its bus name, the
+    /// latter unless other items are still there.
+    fn forget(&self, service: &str) {
+        METRICS.forget(service);
+        let (name, _) = item::parse_service(service);
+        if !self
+            .entries
+            .keys()
+            .any(|other| item::parse_service(other).0 == name)
+        {
+            METRICS.forget(name);
+        }
+    }
+
+    /// Remembers how to cancel the tasks of `service`, cancelling previous ones.
+    pub fn track(&mut self, service: String, tasks: AbortHandle) {
+        if let Some(previous) = self.tasks.insert(service, tasks) {
+            previous.abort();
+        }
+    }
+
+    /// Keeps `reason` as the last error of `service` until it is removed.
+    pub fn failed(&mut self, service: &str, reason: String) {
+        self.errors.insert(service.to_string(), reason);
+    }
+
+    /// The last error reading the item of `service`.
+    pub fn error(&self, service: &str) -> Option<&str> {
+        self.errors.get(service).map(String::as_str)
+    }
+
+    /// Services followed whose item was not read yet, e.g. while retrying.
+    pub fn pending(&self) -> impl Iterator<Item = &str> {
+        self.tasks
+            .keys()
+            .filter(|service| !self.entries.contains_key(*service))
+            .map(String::as_str)
+    }
+
+    /// Drops the entry of `stale` in favor of `live`, which takes over its id.
+    pub fn collapse(&mut self, stale: &str, live: &str) {
+        if let Some(tasks) = self.tasks.remove(stale) {
+            tasks.abort();
+        }
+        self.errors.remove(stale);
+        let Some(removed) = self.entries.remove(stale) else {
+            return;
+        };
+        self.forget(stale);
+        if let Some(entry) = self.entries.get_mut(live) {
+            entry.item.id = removed.item.id;
+        }
+    }
+
+    /// Other entries with the same non-empty SNI `Id` as the one of `service`.
+    pub fn duplicates(&self, service: &str) -> Vec<(String, StatusNotifierItemProxy<'static>)> {
+        let Some(sni_id) = self.entries.get(service).map(|entry| &entry.item.sni_id) else {
+            return Vec::new();
+        };
+        self.entries
+            .iter()
+            .filter(|(other, entry)| {
+                !sni_id.is_empty() && *other != service && entry.item.sni_id == *sni_id
+            })
+            .map(|(other, entry)| (other.clone(), entry.proxy.clone()))
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        for (_, tasks) in self.tasks.drain() {
+            tasks.abort();
+        }
+        for service in self.entries.keys() {
+            METRICS.forget(service);
+            METRICS.forget(item::parse_service(service).0);
+        }
+        self.entries.clear();
+        self.errors.clear();
+    }
+
+    /// All entries with the service they registered with.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Entry)> {
+        self.entries
+            .iter()
+            .map(|(service, entry)| (service.as_str(), entry))
+    }
+
+    /// All items sorted by `id`, so the output order only changes with the items.
+    pub fn items(&self) -> impl Iterator<Item = &Item> {
+        let mut items = self
+            .entries
+            .values()
+            .map(|entry| &entry.item)
+            .collect::<Vec<_>>();
+        items.sort_by(|a, b| a.id.cmp(&b.id));
+        items.into_iter()
+    }
+
+    /// Looks up an item by its `id` or service name.
+    pub fn find(&self, key: &str) -> Option<&Entry> {
+        self.entries
+            .values()
+            .find(|entry| entry.item.id == key)
+            .or_else(|| self.entries.get(key))
+    }
+}
+
+/// Lowercase ASCII alphanumerics separated by single dashes, safe to use
+/// unquoted in shell commands.
+fn slug(s: &str) -> String {
+    let mut slug = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// The id of an item before telling it apart from others, the slugified SNI
+/// `Id` or else service.
+fn base_id(service: &str, item: &Item) -> String {
+    match slug(&item.sni_id) {
+        id if id.is_empty() => slug(service),
+        id => id,
+    }
+}
+
+/// What identifies an item across restarts of its application: the executable,
+/// the well-known name it registered with, without the process id some put in
+/// there, and its object path. Unique names change on every connection.
+fn stable_key(service: &str, item: &Item) -> String {
+    let (name, path) = item::parse_service(service);
+    let pid = item.pid.map(|pid| pid.to_string());
+    let name = match name.starts_with(':') {
+        true => String::new(),
+        false => name
+            .split('-')
+            .filter(|part| Some(*part) != pid.as_deref())
+            .collect::<Vec<_>>()
+            .join("-"),
+    };
+    format!(
+        "{}\0{}\0{}",
+        item.exe.as_deref().unwrap_or_default(),
+        name,
+        path
+    )
+}
+
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock;
+
+    const SERVICE: &str = "org.kde.StatusNotifierItem-1-1";
+
+    fn proxy() -> StatusNotifierItemProxy<'static> {
+        crate::rt::block_on(async {
+            let (conn, _server) = mock::peers(Some("app"), "App").await?;
+            item::proxy(&conn, SERVICE).await
+        })
+        .unwrap()
+    }
+
+    fn item(sni_id: &str, exe: &str) -> Item {
+        Item {
+            sni_id: sni_id.to_string(),
+            exe: Some(exe.to_string()),
+            ..Item::default()
+        }
+    }
+
+    fn id(registry: &Registry, service: &str) -> String {
+        registry.entries[service].item.id.clone()
+    }
+
+    #[test]
+    fn suffixes_only_the_newcomer() {
+        let (proxy, mut registry) = (proxy(), Registry::default());
+        registry.insert(":1.1".to_string(), item("App", "/bin/app"), proxy.clone());
+        assert_eq!(id(&registry, ":1.1"), "app");
+        registry.insert(":1.2".to_string(), item("App", "/bin/fork"), proxy.clone());
+        let suffixed = format!(
+            "app-{:08x}",
+            fnv1a(stable_key(":1.2", &item("App", "/bin/fork")).as_bytes()) as u32
+        );
+        assert_eq!(id(&registry, ":1.1"), "app");
+        assert_eq!(id(&registry, ":1.2"), suffixed);
+        // alike in everything to the second
+        registry.insert(":1.3".to_string(), item("App", "/bin/fork"), proxy.clone());
+        assert_eq!(id(&registry, ":1.3"), format!("{}-2", suffixed));
+        // read again, the ids stay
+        registry.insert(":1.1".to_string(), item("App", "/bin/app"), proxy);
+        assert_eq!(id(&registry, ":1.1"), "app");
+        assert_eq!(id(&registry, ":1.2"), suffixed);
+    }
+
+    #[test]
+    fn placeholder_takes_the_real_id() {
+        let (proxy, mut registry) = (proxy(), Registry::default());
+        registry.insert(SERVICE.to_string(), item("", "/bin/app"), proxy.clone());
+        assert_eq!(id(&registry, SERVICE), "org-kde-statusnotifieritem-1-1");
+        registry.insert(SERVICE.to_string(), item("App", "/bin/app"), proxy);
+        assert_eq!(id(&registry, SERVICE), "app");
+    }
+
+    #[test]
+    fn collapse_hands_over_the_id() {
+        let (proxy, mut registry) = (proxy(), Registry::default());
+        registry.insert(":1.1".to_string(), item("App", "/bin/app"), proxy.clone());
+        registry.insert(":1.2".to_string(), item("App", "/bin/app"), proxy);
+        assert_ne!(id(&registry, ":1.2"), "app");
+        registry.collapse(":1.1", ":1.2");
+        assert!(!registry.entries.contains_key(":1.1"));
+        assert_eq!(id(&registry, ":1.2"), "app");
+        assert_eq!(registry.items().count(), 1);
+    }
+
+    #[test]
+    fn forgets_the_metrics_of_removed_items() {
+        let (proxy, mut registry) = (proxy(), Registry::default());
+        let counted = |service: &str| {
+            METRICS
+                .render()
+                .contains(&format!("{{item=\"{}\"}}", service))
+        };
+        for service in [":1.41/a", ":1.41/b"] {
+            registry.insert(
+                service.to_string(),
+                item(service, "/bin/app"),
+                proxy.clone(),
+            );
+        }
+        METRICS.dbus_error(":1.41/a");
+        METRICS.missing_property(":1.41");
+        registry.remove(":1.41/a");
+        assert!(!counted(":1.41/a"));
+        // another item of the same connection is still there
+        assert!(counted(":1.41"));
+        registry.remove(":1.41/b");
+        assert!(!counted(":1.41"));
+    }
+}
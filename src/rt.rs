@@ -0,0 +1,94 @@
+//! The executor background tasks run on: async-std's, or with the `tokio`
+//! feature the tokio runtime of the caller, so a [`Tray`](crate::Tray) in a
+//! tokio program does not start a second one. Channels, timers and sockets
+//! are async-std's either way, they go through async-io, which drives itself
+//! and works under both. That is why async-std is not optional: only its
+//! executor is replaced, which is all a tokio program needs to avoid.
+
+use std::fmt;
+use std::future::Future;
+
+/// The runtime tasks go to when spawned outside of it, as from zbus's own
+/// executor serving the watcher.
+#[cfg(feature = "tokio")]
+static RUNTIME: std::sync::Mutex<Option<tokio::runtime::Handle>> = std::sync::Mutex::new(None);
+
+/// A task spawned with the `tokio` feature from outside of the runtime before
+/// any was entered, so there is none to run it on.
+#[derive(Debug)]
+pub struct NoRuntime;
+
+impl fmt::Display for NoRuntime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a task was spawned before entering the tokio runtime")
+    }
+}
+
+impl std::error::Error for NoRuntime {}
+
+#[cfg(feature = "tokio")]
+fn handle() -> Result<tokio::runtime::Handle, NoRuntime> {
+    let mut runtime = RUNTIME.lock().unwrap();
+    match tokio::runtime::Handle::try_current() {
+        // the latest one seen, earlier ones may have shut down since
+        Ok(handle) => Ok(runtime.insert(handle).clone()),
+        Err(_) => runtime.clone().ok_or(NoRuntime),
+    }
+}
+
+/// Runs `future` in the background, detached. Without a runtime it is
+/// dropped, see [`try_spawn`].
+pub fn spawn<F>(future: F)
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if let Err(e) = try_spawn(future) {
+        tracing::error!(error = %e, "task dropped");
+    }
+}
+
+/// Like [`spawn`], failing when there is no runtime to run `future` on.
+pub fn try_spawn<F>(future: F) -> Result<(), NoRuntime>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    #[cfg(feature = "tokio")]
+    handle()?.spawn(future);
+    #[cfg(not(feature = "tokio"))]
+    async_std::task::spawn(future);
+    Ok(())
+}
+
+/// Runs `f` on a thread that may block, as the windows of `gui`, the X11 calls
+/// of `x11` and the encoding of icons do.
+pub async fn spawn_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    #[cfg(feature = "tokio")]
+    if let Ok(handle) = handle() {
+        return match handle.spawn_blocking(f).await {
+            Ok(value) => value,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        };
+    }
+    // async-std's threads start on demand, outside of any runtime
+    async_std::task::spawn_blocking(f).await
+}
+
+/// Runs `future` to completion on a new runtime, for the command line.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    #[cfg(feature = "tokio")]
+    {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .build()
+            .expect("failed to start the tokio runtime");
+        *RUNTIME.lock().unwrap() = Some(runtime.handle().clone());
+        runtime.block_on(future)
+    }
+    #[cfg(not(feature = "tokio"))]
+    async_std::task::block_on(future)
+}
@@ -0,0 +1,348 @@
+//! The JSON Schema of the output, printed by `trayson schema`. Every item,
+//! every object a sink emits and every event has `schema_version`, which
+//! goes up with changes that would break a parser: fields that are removed,
+//! renamed or change their type. New fields keep the version. The fields are
+//! named as without `--field-case` and `[rename]`.
+
+use crate::sink::Format;
+use serde_json::{json, Value};
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Sets `schema_version` of an output object.
+pub fn mark(record: &mut Value) {
+    if let Value::Object(record) = record {
+        record.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    }
+}
+
+/// The schema of what a sink with `format` emits, with the items, events and
+/// menu entries under `$defs`.
+pub fn schema(format: Format) -> Value {
+    let items = json!({"type": "array", "items": {"$ref": "#/$defs/item"}});
+    let body = match format {
+        Format::Json | Format::Cbor => items,
+        Format::Grouped => json!({
+            "type": "object",
+            "description": "The items by their category",
+            "additionalProperties": items,
+        }),
+        Format::Waybar => envelope(json!({
+            "text": {"type": "string"},
+            "tooltip": {"type": "string"},
+            "class": {"type": "array", "items": {"type": "string"}},
+        })),
+        Format::I3blocks => envelope(json!({
+            "full_text": {"type": "string"},
+            "short_text": {"type": "string"},
+            "markup": {"const": "pango"},
+            "urgent": {"type": "boolean"},
+        })),
+        Format::Strip => envelope(json!({
+            "path": {"type": ["string", "null"]},
+            "width": {"type": "integer"},
+            "height": {"type": "integer"},
+            "tooltip": {"type": "string"},
+            "items": {
+                "type": "array",
+                "items": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string"},
+                        "x": {"type": "integer"},
+                        "width": {"type": "integer"},
+                    },
+                    "required": ["id", "x", "width"],
+                },
+            },
+        })),
+        Format::Stamped => envelope(json!({
+            "seq": {"type": "integer"},
+            "ts": {"type": "string", "format": "date-time"},
+            "items": items,
+        })),
+        Format::Count => envelope(json!({
+            "total": {"type": "integer"},
+            "urgent": {"type": "integer"},
+        })),
+    };
+    let Value::Object(mut schema) = body else {
+        unreachable!()
+    };
+    schema.insert(
+        "$schema".to_string(),
+        "https://json-schema.org/draft/2020-12/schema".into(),
+    );
+    schema.insert(
+        "title".to_string(),
+        format!("trayson output, schema_version {}", SCHEMA_VERSION).into(),
+    );
+    schema.insert(
+        "$defs".to_string(),
+        json!({"item": item(), "menu_entry": menu_entry(), "event": event()}),
+    );
+    Value::Object(schema)
+}
+
+/// An object with these and `schema_version` as required properties.
+fn envelope(properties: Value) -> Value {
+    let mut properties = properties;
+    properties["schema_version"] = version();
+    let required = properties
+        .as_object()
+        .map(|properties| properties.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+fn version() -> Value {
+    json!({"const": SCHEMA_VERSION})
+}
+
+fn item() -> Value {
+    let string = json!({"type": "string"});
+    let integer = json!({"type": "integer"});
+    let boolean = json!({"type": "boolean"});
+    json!({
+        "type": "object",
+        "description": "Fields added by `--script` are kept",
+        "properties": {
+            "schema_version": version(),
+            "id": string,
+            "sni_id": string,
+            "title": string,
+            "category": string,
+            "status": {"type": "string", "examples": ["Passive", "Active", "NeedsAttention"]},
+            "urgent": boolean,
+            "attention_since": integer,
+            "tooltip": {
+                "type": "object",
+                "properties": {"title": string, "description": string},
+                "required": ["title", "description"],
+            },
+            "tooltip_plain": string,
+            "tooltip_pango": string,
+            "icon": {
+                "type": "object",
+                "properties": {"width": integer, "height": integer, "path": string},
+                "required": ["width", "height", "path"],
+            },
+            "icon_name": string,
+            "effective_icon": {
+                "type": "object",
+                "properties": {"width": integer, "height": integer, "path": string},
+                "required": ["width", "height", "path"],
+            },
+            "unresponsive": boolean,
+            "stale": boolean,
+            "ordering_index": integer,
+            "label": string,
+            "label_guide": string,
+            "pid": integer,
+            "uid": integer,
+            "foreign": boolean,
+            "exe": string,
+            "window_id": integer,
+            "app": {
+                "type": "object",
+                "properties": {"name": string, "icon": string, "desktop_file": string},
+                "required": ["name", "desktop_file"],
+            },
+            "menu": {"type": "array", "items": {"$ref": "#/$defs/menu_entry"}},
+            "onclick": string,
+            "onmiddleclick": string,
+            "onrightclick": string,
+            "onscrollup": string,
+            "onscrolldown": string,
+        },
+        "required": [
+            "schema_version",
+            "id",
+            "sni_id",
+            "title",
+            "category",
+            "status",
+            "urgent",
+            "tooltip",
+            "tooltip_plain",
+            "icon",
+        ],
+    })
+}
+
+fn menu_entry() -> Value {
+    let string = json!({"type": "string"});
+    let boolean = json!({"type": "boolean"});
+    json!({
+        "type": "object",
+        "properties": {
+            "id": {"type": "integer"},
+            "label": string,
+            "enabled": boolean,
+            "visible": boolean,
+            "separator": boolean,
+            "toggle_type": {"enum": ["", "checkmark", "radio"]},
+            "toggle_state": {"enum": [-1, 0, 1]},
+            "icon_name": string,
+            "icon": string,
+            "children": {"type": "array", "items": {"$ref": "#/$defs/menu_entry"}},
+        },
+        "required": [
+            "id",
+            "label",
+            "enabled",
+            "visible",
+            "separator",
+            "toggle_type",
+            "toggle_state",
+            "icon_name",
+            "children",
+        ],
+    })
+}
+
+/// The `event` notifications of the control socket and lines on stderr.
+fn event() -> Value {
+    json!({
+        "type": "object",
+        "properties": {
+            "schema_version": version(),
+            "event": {"const": "error"},
+            "item": {"type": "string"},
+            "reason": {"type": "string"},
+            "seq": {"type": "integer"},
+            "ts": {"type": "string", "format": "date-time"},
+        },
+        "required": ["schema_version", "event", "item", "reason", "seq", "ts"],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::RunArgs;
+    use crate::cli;
+    use crate::icon::Icon;
+    use crate::item::{Item, ToolTip};
+
+    /// Checks the keywords the schema uses, returning the first violation.
+    fn validate(value: &Value, schema: &Value, root: &Value, at: &str) -> Result<(), String> {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/$defs/");
+            return validate(value, &root["$defs"][name], root, at);
+        }
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                return Err(format!("{}: {} is not {}", at, value, expected));
+            }
+        }
+        if let Some(allowed) = schema["enum"].as_array() {
+            if !allowed.contains(value) {
+                return Err(format!("{}: {} is not one of {:?}", at, value, allowed));
+            }
+        }
+        let types = match &schema["type"] {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => vec![],
+        };
+        let is = |name: &str| match name {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            _ => unreachable!("{}", name),
+        };
+        if !types.is_empty() && !types.into_iter().any(is) {
+            return Err(format!("{}: {} is not a {}", at, value, schema["type"]));
+        }
+        if let Value::Object(object) = value {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                let required = required.as_str().unwrap();
+                if !object.contains_key(required) {
+                    return Err(format!("{}: {} is missing", at, required));
+                }
+            }
+            for (key, field) in object {
+                let at = format!("{}.{}", at, key);
+                let property = schema["properties"]
+                    .get(key)
+                    .or_else(|| schema.get("additionalProperties"));
+                if let Some(property) = property {
+                    validate(field, property, root, &at)?;
+                }
+            }
+        }
+        if let (Value::Array(values), Some(items)) = (value, schema.get("items")) {
+            for (i, value) in values.iter().enumerate() {
+                validate(value, items, root, &format!("{}[{}]", at, i))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rendered() -> Vec<Value> {
+        let item = Item {
+            id: "nm-applet".to_string(),
+            sni_id: "nm-applet".to_string(),
+            title: "Network".to_string(),
+            category: "SystemServices".to_string(),
+            status: "NeedsAttention".to_string(),
+            urgent: true,
+            attention_since: Some(1_700_000_000),
+            tooltip: ToolTip {
+                title: "Wi-Fi".to_string(),
+                description: "<b>Connected</b>".to_string(),
+            },
+            icon: Icon {
+                width: 22,
+                height: 22,
+                path: "/tmp/nm-applet.png".to_string(),
+            },
+            label: "73%".to_string(),
+            pid: Some(1234),
+            ..Default::default()
+        };
+        let items = vec![serde_json::to_value(item).unwrap()];
+        cli::render(items, &RunArgs::defaults(), None)
+    }
+
+    #[test]
+    fn rendered_items_match_the_schema() {
+        let schema = schema(Format::Json);
+        let items = Value::Array(rendered());
+        validate(&items, &schema, &schema, "items").unwrap();
+    }
+
+    #[test]
+    fn reports_items_missing_required_fields() {
+        let schema = schema(Format::Json);
+        let mut item = rendered().remove(0);
+        item.as_object_mut().unwrap().remove("tooltip_plain");
+        let error = validate(&json!([item]), &schema, &schema, "items").unwrap_err();
+        assert_eq!(error, "items[0]: tooltip_plain is missing");
+    }
+
+    #[test]
+    fn grouped_items_match_the_schema() {
+        let schema = schema(Format::Grouped);
+        let grouped = json!({"SystemServices": rendered()});
+        validate(&grouped, &schema, &schema, "grouped").unwrap();
+    }
+
+    #[test]
+    fn marks_objects_only() {
+        let mut record = json!({"total": 1});
+        mark(&mut record);
+        assert_eq!(record["schema_version"], SCHEMA_VERSION);
+        let mut items = json!([]);
+        mark(&mut items);
+        assert_eq!(items, json!([]));
+    }
+}
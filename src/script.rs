@@ -0,0 +1,251 @@
+//! `--script PATH`: a Lua file that rewrites the items before they are
+//! emitted, for what filters and overrides cannot express. It defines
+//! `transform(items)`, which gets the items as an array of tables and returns
+//! the array to emit instead, or nothing to emit the items as it changed them.
+//! It may drop, reorder and change items or add fields, which the `filter=`
+//! of sinks can route on:
+//!
+//! ```lua
+//! function transform(items)
+//!   for _, item in ipairs(items) do
+//!     item.bar = item.category == "Communications" and "left" or "right"
+//!   end
+//! end
+//! ```
+//!
+//! ```text
+//! trayson run --script route.lua --sink 'stdout filter=bar == "left"' --sink '/tmp/right'
+//! ```
+//!
+//! The script runs in the interpreter of [`crate::lua`], which it is loaded
+//! into once and keeps its globals in between updates. In the config file, a
+//! relative `script` is in the config directory, next to the file. Should the
+//! script fail to load, raise an error or run for more than a million
+//! statements, the items are emitted as they are. Fields that are
+//! `null` are nil in Lua, so they are gone from what it returns.
+//!
+//! The interpreter has a thread of its own, which `trayson run` hands the
+//! items to without waiting, see [`ScriptRunner`].
+
+use crate::host::Update;
+use crate::lua::{self, Lua};
+use crate::rt;
+use async_std::channel::{self, Sender};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+use std::thread;
+
+/// The stack of the interpreter's thread, which recurses with the script.
+const STACK_SIZE: usize = 64 << 20;
+
+type Request = (Vec<Value>, Sender<Vec<Value>>);
+
+pub struct Script {
+    requests: Sender<Request>,
+}
+
+impl Script {
+    pub fn new(path: &Path) -> Script {
+        let (requests, pending) = channel::unbounded::<Request>();
+        let path = path.to_path_buf();
+        let spawned = thread::Builder::new()
+            .name("script".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let mut interpreter = load(&path);
+                while let Ok((items, answer)) = pending.recv_blocking() {
+                    let items = match &mut interpreter {
+                        Some(lua) => transform(lua, items).unwrap_or_else(|(e, items)| {
+                            tracing::warn!(script = %path.display(), error = %e, "failed");
+                            items
+                        }),
+                        None => items,
+                    };
+                    let _ = answer.send_blocking(items);
+                }
+            });
+        if let Err(e) = spawned {
+            tracing::error!(error = %e, "failed to start the script's thread");
+        }
+        Script { requests }
+    }
+
+    /// What the script makes of `items`, or `items` if it fails.
+    pub async fn transform(&self, items: Vec<Value>) -> Vec<Value> {
+        let (answer, answered) = channel::bounded(1);
+        if self.requests.send((items.clone(), answer)).await.is_err() {
+            return items;
+        }
+        answered.recv().await.unwrap_or(items)
+    }
+}
+
+/// The interpreter with the script run, if it defines `transform`.
+fn load(path: &Path) -> Option<Lua> {
+    let src = match fs::read(path) {
+        Ok(src) => src,
+        Err(e) => {
+            tracing::warn!(script = %path.display(), error = %e, "failed to read");
+            return None;
+        }
+    };
+    let chunk = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut lua = Lua::new();
+    if let Err(e) = lua.exec(&chunk, &src) {
+        tracing::warn!(script = %path.display(), error = %e, "failed to load");
+        return None;
+    }
+    if !matches!(lua.global("transform"), lua::Value::Function(_)) {
+        tracing::warn!(script = %path.display(), "defines no function transform");
+        return None;
+    }
+    Some(lua)
+}
+
+/// Calls `transform` of the script, failing with the items as they were.
+fn transform(lua: &mut Lua, items: Vec<Value>) -> Result<Vec<Value>, (String, Vec<Value>)> {
+    let items = Value::Array(items);
+    let table = lua::from_json(&items);
+    let Value::Array(items) = items else {
+        unreachable!()
+    };
+    let transform = lua.global("transform");
+    let returned = match lua.call(&transform, vec![table.clone()]) {
+        Ok(returned) => returned.into_iter().next().unwrap_or_default(),
+        Err(e) => return Err((e.to_string(), items)),
+    };
+    let result = if returned.is_nil() { table } else { returned };
+    match lua::to_json(&result) {
+        Ok(Value::Array(transformed)) => Ok(transformed),
+        Ok(_) => Err(("transform returned no array".to_string(), items)),
+        Err(e) => Err((e.to_string(), items)),
+    }
+}
+
+/// A [`Script`] on a task of its own, answering with an [`Update::Scripted`]
+/// for the latest of the item lists sent meanwhile. It stops once dropped.
+pub struct ScriptRunner {
+    items: Sender<Vec<Value>>,
+}
+
+impl ScriptRunner {
+    pub fn start(path: &Path, updates: Sender<Update>) -> ScriptRunner {
+        let (items, pending) = channel::unbounded::<Vec<Value>>();
+        let script = Script::new(path);
+        rt::spawn(async move {
+            while let Ok(mut latest) = pending.recv().await {
+                while let Ok(newer) = pending.try_recv() {
+                    latest = newer;
+                }
+                let items = script.transform(latest).await;
+                if updates.send(Update::Scripted(items)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        ScriptRunner { items }
+    }
+
+    /// Hands `items` to the script, the answer comes later.
+    pub fn submit(&self, items: Vec<Value>) {
+        let _ = self.items.try_send(items);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn script(src: &str) -> Lua {
+        let mut lua = Lua::new();
+        lua.exec("test.lua", src.as_bytes()).unwrap();
+        lua
+    }
+
+    #[test]
+    fn adds_fields_to_the_items() {
+        let mut lua = script(
+            r#"
+            function transform(items)
+              for _, item in ipairs(items) do
+                item.bar = item.category == "Communications" and "left" or "right"
+              end
+            end
+            "#,
+        );
+        let items = vec![
+            json!({"id": "a", "category": "Communications"}),
+            json!({"id": "b", "category": "Hardware"}),
+        ];
+        let items = transform(&mut lua, items).unwrap();
+        assert_eq!(items[0]["bar"], "left");
+        assert_eq!(items[1]["bar"], "right");
+    }
+
+    #[test]
+    fn emits_what_transform_returns() {
+        let mut lua = script(
+            r#"
+            function transform(items)
+              local kept = {}
+              for _, item in ipairs(items) do
+                if item.status ~= "Passive" then
+                  table.insert(kept, item)
+                end
+              end
+              return kept
+            end
+            "#,
+        );
+        let items = vec![
+            json!({"id": "a", "status": "Passive"}),
+            json!({"id": "b", "status": "Active", "menu": [], "pid": 12}),
+        ];
+        let items = transform(&mut lua, items).unwrap();
+        assert_eq!(
+            items,
+            vec![json!({"id": "b", "status": "Active", "menu": [], "pid": 12})]
+        );
+        let items = transform(&mut lua, vec![json!({"id": "a", "status": "Passive"})]).unwrap();
+        assert_eq!(items, Vec::<Value>::new());
+    }
+
+    #[test]
+    fn keeps_the_items_when_the_script_fails() {
+        let mut lua = script("function transform(items) return items[1].missing.field end");
+        let items = vec![json!({"id": "a"})];
+        let (error, kept) = transform(&mut lua, items.clone()).unwrap_err();
+        assert_eq!(
+            error,
+            "test.lua:1: attempt to index a nil value (field 'missing')"
+        );
+        assert_eq!(kept, items);
+        let mut lua = script("function transform(items) while true do end end");
+        let (error, _) = transform(&mut lua, items.clone()).unwrap_err();
+        assert_eq!(error, "test.lua:1: script ran too long");
+        let mut lua = script("function transform(items) return 'no' end");
+        let (error, _) = transform(&mut lua, items).unwrap_err();
+        assert_eq!(error, "transform returned no array");
+    }
+
+    #[test]
+    fn keeps_globals_between_updates() {
+        let mut lua = script(
+            r#"
+            local updates = 0
+            function transform(items)
+              updates = updates + 1
+              items[1].updates = updates
+            end
+            "#,
+        );
+        transform(&mut lua, vec![json!({})]).unwrap();
+        let items = transform(&mut lua, vec![json!({})]).unwrap();
+        assert_eq!(items[0]["updates"], 2);
+    }
+}
@@ -0,0 +1,316 @@
+use crate::cbor;
+use crate::filter::{Filter, Pattern};
+use crate::icon;
+use crate::naming;
+use crate::output::{Destination, Output};
+use crate::rt;
+use crate::schema::{self, SCHEMA_VERSION};
+use crate::stamp::Stamp;
+use async_std::io;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Format {
+    /// JSON array of all items
+    Json,
+    /// JSON object of arrays of the items keyed by their category
+    Grouped,
+    /// Waybar custom module record (`text`, `tooltip`, `class`)
+    Waybar,
+    /// i3blocks persistent block (`full_text`, `short_text`, `markup`), see
+    /// [`crate::i3blocks`]
+    I3blocks,
+    /// Path of one PNG with all icons side by side, see [`crate::strip`]
+    Strip,
+    /// JSON object with the `seq` and `ts` of the state and its `items`, see
+    /// [`crate::stamp`]
+    Stamped,
+    /// JSON object with the `total` number of items and how many are
+    /// `urgent`, only emitted when one of them changes
+    Count,
+    /// The array of `json` as CBOR, see [`crate::cbor`]
+    Cbor,
+}
+
+impl Format {
+    /// What a sink emits for `items` as one frame: a line of JSON, or the
+    /// self-described CBOR item for `cbor`.
+    pub fn render(&self, items: &[&Value], stamp: &Stamp) -> Vec<u8> {
+        self.render_for(items, stamp, None)
+    }
+
+    /// Like [`Format::render`], for the sink writing to `sink`, which a
+    /// `strip` is remembered as for [`crate::strip::hit_test`].
+    fn render_for(&self, items: &[&Value], stamp: &Stamp, sink: Option<&str>) -> Vec<u8> {
+        let value = self.value(items, stamp, sink);
+        match self {
+            Format::Cbor => cbor::frame(&value),
+            _ => format!("{}\n", value).into_bytes(),
+        }
+    }
+
+    /// What a sink emits for `items`, with the fields named as configured
+    /// in [`crate::naming`].
+    fn value(&self, items: &[&Value], stamp: &Stamp, sink: Option<&str>) -> Value {
+        let mut rendered = match self {
+            Format::Json | Format::Cbor => json!(items),
+            Format::Grouped => {
+                let mut groups = serde_json::Map::new();
+                for item in items {
+                    let category = item["category"].as_str().unwrap_or_default();
+                    if let Value::Array(group) = groups
+                        .entry(category)
+                        .or_insert_with(|| Value::Array(Vec::new()))
+                    {
+                        group.push((*item).clone());
+                    }
+                }
+                let mut groups = Value::Object(groups);
+                if icon::emits_uris() {
+                    for group in groups
+                        .as_object_mut()
+                        .into_iter()
+                        .flat_map(|g| g.values_mut())
+                    {
+                        group
+                            .as_array_mut()
+                            .into_iter()
+                            .flatten()
+                            .for_each(icon::to_uris);
+                    }
+                }
+                naming::apply_inside(&mut groups);
+                return groups;
+            }
+            Format::Waybar => {
+                let titles = items
+                    .iter()
+                    .filter_map(|item| item["title"].as_str())
+                    .collect::<Vec<_>>();
+                let mut class = items
+                    .iter()
+                    .filter_map(|item| item["status"].as_str())
+                    .map(str::to_lowercase)
+                    .collect::<Vec<_>>();
+                class.sort();
+                class.dedup();
+                return json!({
+                    "text": titles.join(" "),
+                    "tooltip": titles.join("\n"),
+                    "class": class,
+                    "schema_version": SCHEMA_VERSION,
+                });
+            }
+            Format::I3blocks => return crate::i3blocks::render(items),
+            Format::Strip => {
+                let mut strip = crate::strip::render(items, sink);
+                if icon::emits_uris() {
+                    icon::to_uri(&mut strip, "path");
+                }
+                schema::mark(&mut strip);
+                strip
+            }
+            Format::Stamped => json!({
+                "seq": stamp.seq,
+                "ts": stamp.ts,
+                "items": items,
+                "schema_version": SCHEMA_VERSION,
+            }),
+            Format::Count => json!({
+                "total": items.len(),
+                "urgent": items.iter().filter(|item| item["urgent"] == true).count(),
+                "schema_version": SCHEMA_VERSION,
+            }),
+        };
+        if icon::emits_uris() {
+            let items = match self {
+                Format::Stamped => rendered.get_mut("items"),
+                Format::Json | Format::Cbor => Some(&mut rendered),
+                _ => None,
+            };
+            items
+                .and_then(Value::as_array_mut)
+                .into_iter()
+                .flatten()
+                .for_each(icon::to_uris);
+        }
+        naming::apply(&mut rendered);
+        rendered
+    }
+}
+
+/// Configuration of a single sink, parsed from
+/// `DEST [format=FORMAT] [filter=EXPR]` where `DEST` is `stdout`,
+/// `socket:PATH` or a file/fifo path. The filter takes the rest of the spec.
+/// The `[sink.NAME]` tables of the config file also take `only` and `ignore`.
+#[derive(Debug, Clone)]
+pub struct SinkConfig {
+    pub dest: Destination,
+    pub format: Format,
+    pub filter: Option<Filter>,
+    /// Only emit items whose SNI `Id` matches one of these, if any
+    pub only: Vec<Pattern>,
+    /// Leave out items whose SNI `Id` matches one of these
+    pub ignore: Vec<Pattern>,
+}
+
+impl SinkConfig {
+    pub fn new(dest: Destination, format: Format) -> SinkConfig {
+        SinkConfig {
+            dest,
+            format,
+            filter: None,
+            only: Vec::new(),
+            ignore: Vec::new(),
+        }
+    }
+
+    fn passes(&self, item: &Value) -> bool {
+        let id = item["sni_id"].as_str().unwrap_or_default();
+        self.filter.as_ref().is_none_or(|f| f.matches(item))
+            && !self.ignore.iter().any(|pattern| pattern.matches(id))
+            && (self.only.is_empty() || self.only.iter().any(|pattern| pattern.matches(id)))
+    }
+}
+
+/// `stdout` (or `-`), `socket:PATH`, `file:PATH` or a path.
+pub fn destination(dest: &str) -> Result<Destination, SinkParseError> {
+    Ok(match dest {
+        "" => return Err(SinkParseError("missing destination".to_string())),
+        "-" | "stdout" => Destination::Stdout,
+        _ => match dest.split_once(':') {
+            Some(("socket", path)) => Destination::Socket(PathBuf::from(path)),
+            Some(("file", path)) => Destination::Path(PathBuf::from(path)),
+            _ => Destination::Path(PathBuf::from(dest)),
+        },
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct SinkParseError(String);
+
+impl fmt::Display for SinkParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid sink: {}", self.0)
+    }
+}
+
+impl std::error::Error for SinkParseError {}
+
+impl FromStr for SinkConfig {
+    type Err = SinkParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim_start();
+        let (dest, mut rest) = s.split_once(char::is_whitespace).unwrap_or((s, ""));
+        let mut config = SinkConfig::new(destination(dest)?, Format::Json);
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(expr) = rest.strip_prefix("filter=") {
+                config.filter = Some(expr.parse().map_err(|e| SinkParseError(format!("{}", e)))?);
+                break;
+            }
+            let (option, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            match option.split_once('=') {
+                Some(("format", format)) => {
+                    config.format = Format::from_str(format, true).map_err(SinkParseError)?;
+                }
+                _ => return Err(SinkParseError(format!("unknown option `{}`", option))),
+            }
+            rest = tail;
+        }
+        Ok(config)
+    }
+}
+
+pub struct Sink {
+    config: SinkConfig,
+    output: Output,
+    /// What `count` emitted last
+    last: Option<Vec<u8>>,
+}
+
+impl Sink {
+    pub async fn open(config: SinkConfig) -> io::Result<Self> {
+        Ok(Sink {
+            output: Output::new(config.dest.clone()).await?,
+            config,
+            last: None,
+        })
+    }
+
+    /// The `stamped` file of `--state-file` at `path`, `None` without one or
+    /// if its directory can't be created.
+    pub async fn state_file(path: Option<PathBuf>) -> Option<Sink> {
+        let path = path?;
+        if let Err(e) = async_std::fs::create_dir_all(path.parent()?).await {
+            tracing::warn!(error = %e, "state file disabled");
+            return None;
+        }
+        Sink::open(SinkConfig::new(Destination::Path(path), Format::Stamped))
+            .await
+            .ok()
+    }
+
+    /// Applies new configs, keeping the outputs of unchanged destinations open
+    /// so socket clients stay connected.
+    pub async fn reload(sinks: &mut Vec<Sink>, configs: Vec<SinkConfig>) -> io::Result<()> {
+        let mut reloaded = Vec::with_capacity(configs.len());
+        for config in configs {
+            match sinks
+                .iter()
+                .position(|sink| sink.config.dest == config.dest)
+            {
+                Some(i) => {
+                    let mut sink = sinks.swap_remove(i);
+                    if config.format != Format::Strip {
+                        crate::strip::forget(&config.dest.to_string());
+                    }
+                    sink.config = config;
+                    sink.last = None;
+                    reloaded.push(sink);
+                }
+                None => reloaded.push(Sink::open(config).await?),
+            }
+        }
+        for gone in sinks.iter() {
+            crate::strip::forget(&gone.config.dest.to_string());
+        }
+        *sinks = reloaded;
+        Ok(())
+    }
+
+    pub async fn emit(&mut self, items: &[Value], stamp: &Stamp) -> io::Result<()> {
+        let items = items
+            .iter()
+            .filter(|item| self.config.passes(item))
+            .collect::<Vec<_>>();
+        let rendered = match self.config.format {
+            // decodes and encodes all icons
+            Format::Strip => {
+                let items = items.into_iter().cloned().collect::<Vec<_>>();
+                let (stamp, sink) = (stamp.clone(), self.config.dest.to_string());
+                rt::spawn_blocking(move || {
+                    let items = items.iter().collect::<Vec<_>>();
+                    Format::Strip.render_for(&items, &stamp, Some(&sink))
+                })
+                .await
+            }
+            format => format.render(&items, stamp),
+        };
+        if self.config.format == Format::Count {
+            if self.last.as_ref() == Some(&rendered) {
+                return Ok(());
+            }
+            self.last = Some(rendered.clone());
+        }
+        self.output.write_frame(&rendered).await
+    }
+}
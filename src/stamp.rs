@@ -0,0 +1,116 @@
+//! `seq` and `ts` of the emitted states and events: a counter increasing by
+//! one per state, another per error event, and the time in RFC 3339.
+//! Consumers reconnecting to a socket or reading logs can tell what they
+//! missed. The events have their own, as sinks and the state file only get
+//! the states and would otherwise see a gap for every event.
+//!
+//! The `update` notifications of the control socket, the bridge and the
+//! WebSocket carry them next to `items`, events next to their fields, and the
+//! `stamped` sink format emits `{"seq", "ts", "items"}`.
+
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+static EVENT_SEQ: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone)]
+pub struct Stamp {
+    pub seq: u64,
+    pub ts: String,
+}
+
+impl Stamp {
+    /// The stamp of the next state.
+    pub fn next() -> Stamp {
+        Stamp::from(&SEQ)
+    }
+
+    /// The stamp of the next error event.
+    pub fn event() -> Stamp {
+        Stamp::from(&EVENT_SEQ)
+    }
+
+    fn from(counter: &AtomicU64) -> Stamp {
+        Stamp {
+            seq: counter.fetch_add(1, Ordering::Relaxed) + 1,
+            ts: rfc3339(SystemTime::now()),
+        }
+    }
+
+    /// Sets `seq` and `ts` of a record, replacing those it came with.
+    pub fn add_to(&self, record: &mut Value) {
+        if let Value::Object(record) = record {
+            record.insert("seq".to_string(), self.seq.into());
+            record.insert("ts".to_string(), self.ts.clone().into());
+        }
+    }
+}
+
+/// `time` in UTC with milliseconds, like `2024-05-01T12:00:00.000Z`.
+pub fn rfc3339(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // days to the civil date, as in http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        since.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn at(secs: u64, millis: u64) -> String {
+        rfc3339(UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn formats_utc_with_milliseconds() {
+        assert_eq!(at(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(at(1714564800, 123), "2024-05-01T12:00:00.123Z");
+        assert_eq!(at(1704067199, 999), "2023-12-31T23:59:59.999Z");
+    }
+
+    #[test]
+    fn handles_leap_days() {
+        assert_eq!(at(951782400, 0), "2000-02-29T00:00:00.000Z");
+        assert_eq!(at(1709251199, 0), "2024-02-29T23:59:59.000Z");
+        assert_eq!(at(1709251200, 0), "2024-03-01T00:00:00.000Z");
+        assert_eq!(at(4107542400, 0), "2100-03-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn numbers_events_apart_from_states() {
+        let state = Stamp::next().seq;
+        Stamp::event();
+        Stamp::event();
+        assert_eq!(Stamp::next().seq, state + 1);
+    }
+
+    #[test]
+    fn clamps_times_before_the_epoch() {
+        let before = UNIX_EPOCH - Duration::from_secs(1);
+        assert_eq!(rfc3339(before), "1970-01-01T00:00:00.000Z");
+    }
+}
@@ -0,0 +1,291 @@
+//! `format=strip`: for bars that can only show a single image, the icons of
+//! the items put side by side into one PNG. The sink emits
+//! `{"path", "width", "height", "tooltip", "items": [{"id", "x", "width"}]}`
+//! on every change, which the `image` module of waybar reads with
+//! `return-type = "json"`. `trayson hit-test X` answers which of the items
+//! is at offset `X` of the strip emitted last, with `--sink DEST` of the strip
+//! sink writing to `DEST` when there are several:
+//!
+//! ```text
+//! trayson activate "$(trayson hit-test 30)"
+//! trayson activate "$(trayson hit-test --sink socket:/run/user/1000/bar1.sock 30)"
+//! ```
+//!
+//! The `[strip]` table of the config file sets the look:
+//!
+//! ```toml
+//! [strip]
+//! size = 24
+//! spacing = 4
+//! padding = 2
+//! background = "#1e1e2ecc"
+//! ```
+//!
+//! Icons that cannot be read as a bitmap, like SVGs, are left out.
+
+use crate::icon;
+use image::{imageops, DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, RwLock};
+
+/// Height of the icons in the strip unless configured.
+const DEFAULT_SIZE: u32 = 22;
+
+/// How the strip is drawn, the `[strip]` table of the config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StripConfig {
+    /// Scale icons to this many pixels square [default: 22]
+    pub size: Option<u32>,
+    /// Pixels between two icons
+    pub spacing: u32,
+    /// Pixels around all icons
+    pub padding: u32,
+    /// Color behind the icons as `#rrggbb` or `#rrggbbaa` [default: transparent]
+    pub background: Color,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color([u8; 4]);
+
+#[derive(Debug)]
+pub struct ColorError(String);
+
+impl fmt::Display for ColorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid color `{}`, expected #rrggbb or #rrggbbaa",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ColorError {}
+
+impl TryFrom<String> for Color {
+    type Error = ColorError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        let hex = s.strip_prefix('#').unwrap_or(&s);
+        let channel = |i: usize| {
+            hex.get(i * 2..i * 2 + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok())
+        };
+        match (hex.len(), channel(0), channel(1), channel(2)) {
+            (6, Some(r), Some(g), Some(b)) => Ok(Color([r, g, b, 255])),
+            (8, Some(r), Some(g), Some(b)) => match channel(3) {
+                Some(a) => Ok(Color([r, g, b, a])),
+                None => Err(ColorError(s)),
+            },
+            _ => Err(ColorError(s)),
+        }
+    }
+}
+
+/// Where an item is in the strip.
+#[derive(Debug, Clone, Serialize)]
+struct Slot {
+    id: String,
+    x: u32,
+    width: u32,
+}
+
+static CONFIG: RwLock<StripConfig> = RwLock::new(StripConfig {
+    size: None,
+    spacing: 0,
+    padding: 0,
+    background: Color([0; 4]),
+});
+
+/// Width and items of the strip emitted last by each sink, by its destination,
+/// for [`hit_test`].
+static LAYOUTS: Mutex<BTreeMap<String, (u32, Vec<Slot>)>> = Mutex::new(BTreeMap::new());
+
+/// Applies to all strips drawn from now on.
+pub fn configure(config: StripConfig) {
+    *CONFIG.write().unwrap() = config;
+}
+
+/// Draws the icons of `items` into one image and saves it, remembering where
+/// they are as the strip of `sink`.
+pub fn render(items: &[&Value], sink: Option<&str>) -> Value {
+    let config = CONFIG.read().unwrap().clone();
+    let size = config.size.unwrap_or(DEFAULT_SIZE);
+    let icons = items
+        .iter()
+        .filter_map(|item| {
+            let icon = icon::open(item["icon"]["path"].as_str()?)?;
+            let icon = imageops::resize(&icon, size, size, imageops::FilterType::Triangle);
+            Some((item["id"].as_str()?.to_string(), icon))
+        })
+        .collect::<Vec<_>>();
+
+    let count = icons.len() as u32;
+    let width = 2 * config.padding + count * size + count.saturating_sub(1) * config.spacing;
+    let height = 2 * config.padding + size;
+    let mut strip = RgbaImage::from_pixel(width.max(1), height, Rgba(config.background.0));
+    let mut layout = Vec::with_capacity(icons.len());
+    let mut x = config.padding;
+    for (id, icon) in icons {
+        imageops::overlay(&mut strip, &icon, x, config.padding);
+        layout.push(Slot { id, x, width: size });
+        x += size + config.spacing;
+    }
+
+    let (width, height) = strip.dimensions();
+    let mut png = Vec::new();
+    let encoded = DynamicImage::ImageRgba8(strip).write_to(&mut png, ImageOutputFormat::Png);
+    let path = match encoded {
+        Ok(()) => icon::save_png(&png),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to encode the strip");
+            None
+        }
+    };
+    let tooltip = items
+        .iter()
+        .filter_map(|item| item["title"].as_str())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let value = json!({
+        "path": path,
+        "width": width,
+        "height": height,
+        "tooltip": tooltip,
+        "items": layout,
+    });
+    if let Some(sink) = sink {
+        LAYOUTS
+            .lock()
+            .unwrap()
+            .insert(sink.to_string(), (width, layout));
+    }
+    value
+}
+
+/// Forgets the strip of `sink`, which is gone.
+pub fn forget(sink: &str) {
+    LAYOUTS.lock().unwrap().remove(sink);
+}
+
+/// `id` of the item at offset `x` of the strip `sink` emitted last, or of the
+/// only one without `sink`. The spacing between two icons belongs to the
+/// nearer one.
+pub fn hit_test(x: i32, sink: Option<&str>) -> Option<String> {
+    let layouts = LAYOUTS.lock().unwrap();
+    let (width, layout) = match sink {
+        Some(sink) => layouts.get(sink)?,
+        // which of several is meant is anyone's guess
+        None if layouts.len() == 1 => layouts.values().next()?,
+        None => return None,
+    };
+    if x < 0 || x >= *width as i32 {
+        return None;
+    }
+    layout
+        .iter()
+        .min_by_key(|slot| {
+            let (start, end) = (slot.x as i32, (slot.x + slot.width) as i32);
+            match x {
+                x if x < start => start - x,
+                x if x >= end => x - end + 1,
+                _ => 0,
+            }
+        })
+        .map(|slot| slot.id.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Held by the tests, which share [`LAYOUTS`].
+    static LAYOUTS_IN_USE: Mutex<()> = Mutex::new(());
+
+    fn icon_dir() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("trayson-strip-test-{}", std::process::id()))
+    }
+
+    /// Items with icons of the `size` the strip has by default.
+    fn items(ids: &[&str]) -> Vec<Value> {
+        let dir = icon_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        ids.iter()
+            .map(|id| {
+                let path = dir.join(format!("{}.png", id));
+                RgbaImage::from_pixel(DEFAULT_SIZE, DEFAULT_SIZE, Rgba([255, 0, 0, 255]))
+                    .save(&path)
+                    .unwrap();
+                json!({"id": id, "title": id, "icon": {"path": path}})
+            })
+            .collect()
+    }
+
+    fn remove_icons() {
+        let _ = std::fs::remove_dir_all(icon_dir());
+    }
+
+    fn render(ids: &[&str], sink: &str) -> Value {
+        let items = items(ids);
+        let strip = super::render(&items.iter().collect::<Vec<_>>(), Some(sink));
+        let _ = std::fs::remove_file(strip["path"].as_str().unwrap());
+        strip
+    }
+
+    #[test]
+    fn finds_the_icon_under_the_click_per_sink() {
+        let _layouts = LAYOUTS_IN_USE.lock().unwrap();
+        let a = render(&["a", "b", "c"], "socket:/tests/one.sock");
+        assert_eq!(a["width"], 3 * DEFAULT_SIZE);
+        assert_eq!(a["items"][1], json!({"id": "b", "x": 22, "width": 22}));
+        render(&["d"], "/tests/two");
+        remove_icons();
+        let at = |x, sink| hit_test(x, Some(sink));
+        assert_eq!(at(0, "socket:/tests/one.sock").as_deref(), Some("a"));
+        assert_eq!(at(21, "socket:/tests/one.sock").as_deref(), Some("a"));
+        assert_eq!(at(22, "socket:/tests/one.sock").as_deref(), Some("b"));
+        assert_eq!(at(65, "socket:/tests/one.sock").as_deref(), Some("c"));
+        assert_eq!(at(66, "socket:/tests/one.sock"), None);
+        assert_eq!(at(-1, "socket:/tests/one.sock"), None);
+        // the other strip is drawn later, but has only one icon
+        assert_eq!(at(30, "/tests/two"), None);
+        assert_eq!(at(10, "/tests/two").as_deref(), Some("d"));
+        assert_eq!(at(10, "/tests/unknown"), None);
+
+        forget("socket:/tests/one.sock");
+        assert_eq!(at(0, "socket:/tests/one.sock"), None);
+        forget("/tests/two");
+    }
+
+    #[test]
+    fn gives_the_spacing_to_the_nearer_icon() {
+        let _layouts = LAYOUTS_IN_USE.lock().unwrap();
+        let slot = |id: &str, x| Slot {
+            id: id.to_string(),
+            x,
+            width: 10,
+        };
+        // padding 2, spacing 6
+        let layout = (2 + 10 + 6 + 10 + 2, vec![slot("a", 2), slot("b", 18)]);
+        LAYOUTS.lock().unwrap().insert("stdout".to_string(), layout);
+        let at = |x| hit_test(x, None);
+        assert_eq!(at(0).as_deref(), Some("a"));
+        assert_eq!(at(14).as_deref(), Some("a"));
+        assert_eq!(at(15).as_deref(), Some("b"));
+        assert_eq!(at(29).as_deref(), Some("b"));
+        assert_eq!(at(30), None);
+        LAYOUTS
+            .lock()
+            .unwrap()
+            .insert("/second".to_string(), (30, Vec::new()));
+        // ambiguous now
+        assert_eq!(at(0), None);
+        assert_eq!(hit_test(0, Some("stdout")).as_deref(), Some("a"));
+        LAYOUTS.lock().unwrap().clear();
+    }
+}
@@ -0,0 +1,49 @@
+//! `sd_notify` for running as a `Type=notify` user service.
+//!
+//! - `READY=1` once the names are acquired
+//! - `WATCHDOG=1` at half the interval systemd asks for with `WatchdogSec=`
+//! - `STATUS=` with the number of items
+//!
+//! Everything is a no-op when not started by systemd.
+
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+/// Sends `state`, e.g. `READY=1`, to the socket in `$NOTIFY_SOCKET`.
+pub fn notify(state: &str) {
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let Ok(socket) = UnixDatagram::unbound() else {
+        return;
+    };
+    let path = path.to_string_lossy();
+    let _ = match path.strip_prefix('@') {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            std::os::unix::net::SocketAddr::from_abstract_name(name)
+                .and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr))
+        }
+        None => socket.send_to(state.as_bytes(), &*path),
+    };
+}
+
+/// How often to send `WATCHDOG=1`, if the watchdog is enabled for us.
+fn watchdog_interval() -> Option<Duration> {
+    let pid = std::env::var("WATCHDOG_PID").ok();
+    if pid.is_some_and(|pid| pid != std::process::id().to_string()) {
+        return None;
+    }
+    let usec = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// Keeps pinging the watchdog, returns right away if it is disabled.
+pub async fn watchdog() {
+    if let Some(interval) = watchdog_interval() {
+        loop {
+            notify("WATCHDOG=1");
+            async_std::task::sleep(interval).await;
+        }
+    }
+}
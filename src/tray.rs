@@ -0,0 +1,244 @@
+//! Embedding the host in a Rust program instead of parsing the output of
+//! `trayson run`: a [`Tray`] follows all items the same way and is a
+//! [`Stream`] of what changed.
+//!
+//! ```no_run
+//! use futures_util::StreamExt;
+//! use trayson::{Tray, TrayEvent};
+//!
+//! # async fn bar() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut tray = Tray::new().await?;
+//! while let Some(event) = tray.next().await {
+//!     match event {
+//!         TrayEvent::Added(item) => println!("+ {} {}", item.id, item.title),
+//!         TrayEvent::Removed(id) => println!("- {}", id),
+//!         _ => {}
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::args::Mode;
+use crate::command::{Command, CommandError};
+use crate::host::{self, Roles, Update};
+use crate::item::Item;
+use crate::registry::{Registry, SharedRegistry};
+use crate::rt;
+use async_std::channel::{self, Receiver};
+use async_std::sync::Mutex;
+use futures_util::future::{self, AbortHandle, Abortable, Either};
+use futures_util::Stream;
+use std::error::Error;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// A change to the items of a [`Tray`].
+#[derive(Debug, Clone)]
+pub enum TrayEvent {
+    /// A new item, or one that got another `id`
+    Added(Item),
+    Changed(Item),
+    /// The `id` of an item that went away
+    Removed(String),
+    /// Something went wrong with the item of this `id` or service, which stays
+    /// as it was or is removed
+    Error {
+        item: String,
+        reason: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct TrayError(String);
+
+impl fmt::Display for TrayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for TrayError {}
+
+/// Follows the items on the session bus until dropped.
+pub struct Tray {
+    registry: SharedRegistry,
+    events: Receiver<TrayEvent>,
+    /// Stop the host and the task tracking its updates
+    tasks: [AbortHandle; 2],
+}
+
+impl Tray {
+    /// Starts following the items, serving the watcher unless another
+    /// process already does, like `trayson run`.
+    pub async fn new() -> Result<Tray, TrayError> {
+        Tray::with_mode(Mode::Auto).await
+    }
+
+    /// Starts following the items, taking on the roles of `mode`, which must
+    /// not be [`Mode::Watcher`].
+    pub async fn with_mode(mode: Mode) -> Result<Tray, TrayError> {
+        if mode == Mode::Watcher {
+            return Err(TrayError("a tray has to be a host".to_string()));
+        }
+        let roles = Roles {
+            mode,
+            reject_foreign: false,
+            conformant_watcher: false,
+            tray_interface: false,
+        };
+        let (updates, received) = channel::unbounded();
+        let (failed, failure) = channel::bounded(1);
+        let (host, registration) = AbortHandle::new_pair();
+        let sender = updates.clone();
+        rt::spawn(Abortable::new(
+            async move {
+                let bus = std::sync::Mutex::new(None);
+                // only returns if the first session fails
+                let reason = match host::follow_bus(roles, &sender, &bus).await {
+                    Ok(()) => return,
+                    Err(e) => e.to_string(),
+                };
+                let _ = failed.send(reason).await;
+            },
+            registration,
+        ));
+        match future::select(received.recv(), failure.recv()).await {
+            Either::Left((Ok(Update::Ready), _)) => {}
+            Either::Right((Ok(reason), _)) => return Err(TrayError(reason)),
+            _ => {
+                host.abort();
+                return Err(TrayError("the host stopped".to_string()));
+            }
+        }
+
+        let registry = Arc::new(Mutex::new(Registry::default()));
+        let (events, stream) = channel::unbounded();
+        let (track, registration) = AbortHandle::new_pair();
+        let tracked = registry.clone();
+        rt::spawn(Abortable::new(
+            async move {
+                while let Ok(update) = received.recv().await {
+                    let changes = host::apply_update(&mut *tracked.lock().await, update, &updates);
+                    for event in changes {
+                        let _ = events.send(event).await;
+                    }
+                }
+            },
+            registration,
+        ));
+        Ok(Tray {
+            registry,
+            events: stream,
+            tasks: [host, track],
+        })
+    }
+
+    #[cfg(feature = "ffi")]
+    pub(crate) fn registry(&self) -> &SharedRegistry {
+        &self.registry
+    }
+
+    /// All current items, sorted by `id`.
+    pub async fn items(&self) -> Vec<Item> {
+        self.registry.lock().await.items().cloned().collect()
+    }
+
+    /// Activates the item of `id`, usually on a left click at `x`, `y`.
+    pub async fn activate(&self, id: &str, x: i32, y: i32) -> Result<(), CommandError> {
+        let item = id.to_string();
+        let token = None;
+        Command::Activate { item, x, y, token }
+            .dispatch(&self.registry)
+            .await
+    }
+
+    /// As [`Tray::activate`], passing the XDG activation `token` of the click
+    /// on first so the window raised can take focus under Wayland.
+    pub async fn activate_with_token(
+        &self,
+        id: &str,
+        x: i32,
+        y: i32,
+        token: &str,
+    ) -> Result<(), CommandError> {
+        let (item, token) = (id.to_string(), Some(token.to_string()));
+        Command::Activate { item, x, y, token }
+            .dispatch(&self.registry)
+            .await
+    }
+
+    /// Usually on a middle click.
+    pub async fn secondary_activate(&self, id: &str, x: i32, y: i32) -> Result<(), CommandError> {
+        let item = id.to_string();
+        let token = None;
+        Command::SecondaryActivate { item, x, y, token }
+            .dispatch(&self.registry)
+            .await
+    }
+
+    /// Asks the window manager to activate the window of the item's
+    /// `window_id`, with the `x11` feature.
+    pub async fn raise(&self, id: &str) -> Result<(), CommandError> {
+        let item = id.to_string();
+        Command::Raise { item }.dispatch(&self.registry).await
+    }
+
+    /// Asks the item to open its own context menu, usually on a right click.
+    pub async fn context_menu(&self, id: &str, x: i32, y: i32) -> Result<(), CommandError> {
+        let item = id.to_string();
+        Command::ContextMenu { item, x, y }
+            .dispatch(&self.registry)
+            .await
+    }
+
+    /// Scrolls by `delta` in `orientation`, `vertical` or `horizontal`.
+    pub async fn scroll(
+        &self,
+        id: &str,
+        delta: i32,
+        orientation: &str,
+    ) -> Result<(), CommandError> {
+        Command::Scroll {
+            item: id.to_string(),
+            delta,
+            orientation: orientation.to_string(),
+        }
+        .dispatch(&self.registry)
+        .await
+    }
+
+    /// Sends `event`, usually `clicked`, for the entry `menu_id` of the menu.
+    pub async fn menu_event(
+        &self,
+        id: &str,
+        menu_id: i32,
+        event: &str,
+    ) -> Result<(), CommandError> {
+        Command::MenuEvent {
+            item: id.to_string(),
+            menu_id,
+            event: event.to_string(),
+        }
+        .dispatch(&self.registry)
+        .await
+    }
+}
+
+impl Stream for Tray {
+    type Item = TrayEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<TrayEvent>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
+
+impl Drop for Tray {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
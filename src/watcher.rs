@@ -0,0 +1,453 @@
+//! The `StatusNotifierWatcher`, served under both the `org.kde` and the
+//! `org.freedesktop` names some toolkits use instead.
+//!
+//! Both interfaces share one list of items and emit their signals together, so
+//! hosts of either flavor see every item. Items and hosts are dropped as soon
+//! as their bus name goes away.
+//!
+//! By default the host signals carry the name of the host and items may also
+//! `UnregisterStatusNotifierItem`, as with the watchers of KDE before. With
+//! `--conformant-watcher` the interfaces match `dbus/org.kde.StatusNotifierWatcher.xml`
+//! exactly, the one of the specification as KDE ships it with `knotifications`.
+
+use crate::item;
+use crate::rt;
+use async_std::channel::Sender;
+use async_std::future;
+use futures_util::{stream, StreamExt};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::Instrument;
+use zbus::fdo::{DBusProxy, Properties};
+use zbus::names::{BusName, InterfaceName};
+use zbus::{
+    dbus_interface, dbus_proxy, Connection, ConnectionBuilder, MessageHeader, SignalContext,
+};
+
+pub const WATCHER: &str = "org.kde.StatusNotifierWatcher";
+pub const FREEDESKTOP_WATCHER: &str = "org.freedesktop.StatusNotifierWatcher";
+pub const PATH: &str = "/StatusNotifierWatcher";
+
+/// How long [`scan_items`] waits for each connection to answer.
+const SCAN_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[dbus_proxy(
+    interface = "org.kde.StatusNotifierWatcher",
+    default_path = "/StatusNotifierWatcher"
+)]
+pub trait StatusNotifierWatcher {
+    #[dbus_proxy(property)]
+    fn registered_status_notifier_items(&self) -> zbus::Result<Vec<String>>;
+
+    #[dbus_proxy(signal)]
+    fn status_notifier_item_registered(&self, service: &str) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn status_notifier_item_unregistered(&self, service: &str) -> zbus::Result<()>;
+}
+
+#[derive(Default)]
+struct State {
+    hosts: HashSet<String>,
+    items: HashSet<String>,
+    /// Refuse items of processes running as another user
+    reject_foreign: bool,
+    /// Told about items registering while already known, which emit no
+    /// signal again
+    registered_again: Option<Sender<String>>,
+    /// Serving the interfaces of the specification
+    conformant: bool,
+}
+
+/// Serves the watcher interfaces at [`PATH`], those of the specification only
+/// if `conformant`; the names are requested by the caller. Items registering a
+/// second time are sent on `registered_again`.
+pub fn serve(
+    builder: ConnectionBuilder<'_>,
+    reject_foreign: bool,
+    conformant: bool,
+    registered_again: Option<Sender<String>>,
+) -> zbus::Result<ConnectionBuilder<'_>> {
+    let state = Arc::new(Mutex::new(State {
+        reject_foreign,
+        registered_again,
+        conformant,
+        ..Default::default()
+    }));
+    match conformant {
+        true => builder
+            .serve_at(PATH, SpecKdeWatcher(state.clone()))?
+            .serve_at(PATH, SpecFreedesktopWatcher(state)),
+        false => builder
+            .serve_at(PATH, KdeWatcher(state.clone()))?
+            .serve_at(PATH, FreedesktopWatcher(state)),
+    }
+}
+
+/// Whether the watcher served on `conn` knows no items and no hosts but `own_host`.
+pub async fn idle(conn: &Connection, own_host: &str) -> bool {
+    let server = conn.object_server();
+    let state = match server.interface::<_, KdeWatcher>(PATH).await {
+        Ok(watcher) => watcher.get().await.0.clone(),
+        Err(_) => match server.interface::<_, SpecKdeWatcher>(PATH).await {
+            Ok(watcher) => watcher.get().await.0.clone(),
+            Err(_) => return true,
+        },
+    };
+    let state = state.lock().unwrap();
+    state.items.is_empty() && state.hosts.iter().all(|host| host == own_host)
+}
+
+/// Unique names of the connections exporting an item at the default path, like
+/// applications that registered with a watcher gone before we started.
+pub async fn scan_items(conn: &Connection) -> zbus::Result<Vec<String>> {
+    let names = DBusProxy::new(conn).await?.list_names().await?;
+    let found = stream::iter(names)
+        .filter(|name| future::ready(name.starts_with(':')))
+        .map(|name| async move {
+            let span = tracing::debug_span!("probe", service = name.as_str());
+            let probe = item::probe(conn, name.as_str()).instrument(span);
+            match future::timeout(SCAN_TIMEOUT, probe).await {
+                Ok(Ok(Some(_))) => Some(name.to_string()),
+                _ => None,
+            }
+        })
+        .buffer_unordered(16)
+        .filter_map(future::ready)
+        .collect()
+        .await;
+    Ok(found)
+}
+
+/// Items may register with a bus name, an object path on their own connection
+/// or `busname/objectpath`; the latter two are stored as `busname/objectpath`.
+fn normalize(service: &str, header: &MessageHeader<'_>) -> zbus::fdo::Result<String> {
+    if !service.starts_with('/') {
+        return Ok(service.to_string());
+    }
+    let sender = header
+        .sender()?
+        .ok_or_else(|| zbus::fdo::Error::InvalidArgs("no sender to resolve the path".into()))?;
+    Ok(format!("{}{}", sender, service))
+}
+
+/// Returns once `name` has no owner anymore.
+async fn vanished(conn: &Connection, name: BusName<'_>) -> zbus::Result<()> {
+    let dbus = DBusProxy::new(conn).await?;
+    let mut changes = dbus
+        .receive_name_owner_changed_with_args(&[(0, name.as_str())])
+        .await?;
+    if !dbus.name_has_owner(name.clone()).await? {
+        return Ok(());
+    }
+    while let Some(change) = changes.next().await {
+        if change.args()?.new_owner().is_none() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+enum Event<'a> {
+    ItemRegistered(&'a str),
+    ItemUnregistered(&'a str),
+    HostRegistered(&'a str),
+    HostUnregistered(&'a str),
+}
+
+/// Emits `event` and the matching property change on both interfaces, those
+/// of the specification if `conformant`.
+async fn notify(ctxt: &SignalContext<'_>, event: Event<'_>, conformant: bool) -> zbus::Result<()> {
+    let property = match event {
+        Event::HostRegistered(_) | Event::HostUnregistered(_) => "IsStatusNotifierHostRegistered",
+        _ => "RegisteredStatusNotifierItems",
+    };
+    for interface in [WATCHER, FREEDESKTOP_WATCHER] {
+        let name = InterfaceName::from_static_str_unchecked(interface);
+        Properties::properties_changed(ctxt, name, &Default::default(), &[property]).await?;
+    }
+    match conformant {
+        true => {
+            SpecKdeWatcher::emit(ctxt, &event).await?;
+            SpecFreedesktopWatcher::emit(ctxt, &event).await
+        }
+        false => {
+            KdeWatcher::emit(ctxt, &event).await?;
+            FreedesktopWatcher::emit(ctxt, &event).await
+        }
+    }
+}
+
+/// Both interfaces are identical apart from their name. `$host` names the
+/// argument of the host signals and `$unregister` adds
+/// `UnregisterStatusNotifierItem`, both missing from the specification.
+macro_rules! watcher_interface {
+    ($(#[$attr:meta])* $name:ident $(, host: $host:ident)? $(, unregister: $unregister:ident)?) => {
+        struct $name(Arc<Mutex<State>>);
+
+        impl $name {
+            /// Emits the signal of `event`.
+            async fn emit(ctxt: &SignalContext<'_>, event: &Event<'_>) -> zbus::Result<()> {
+                match *event {
+                    Event::ItemRegistered(service) => {
+                        Self::status_notifier_item_registered(ctxt, service).await
+                    }
+                    Event::ItemUnregistered(service) => {
+                        Self::status_notifier_item_unregistered(ctxt, service).await
+                    }
+                    Event::HostRegistered(service) => {
+                        let _ = service;
+                        $(let $host = service;)?
+                        Self::status_notifier_host_registered(ctxt $(, $host)?).await
+                    }
+                    Event::HostUnregistered(service) => {
+                        let _ = service;
+                        $(let $host = service;)?
+                        Self::status_notifier_host_unregistered(ctxt $(, $host)?).await
+                    }
+                }
+            }
+        }
+
+        $(#[$attr])*
+        impl $name {
+            #[dbus_interface(signal)]
+            async fn status_notifier_item_registered(
+                ctxt: &SignalContext<'_>,
+                service: &str,
+            ) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn status_notifier_item_unregistered(
+                ctxt: &SignalContext<'_>,
+                service: &str,
+            ) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn status_notifier_host_registered(
+                ctxt: &SignalContext<'_>,
+                $($host: &str)?
+            ) -> zbus::Result<()>;
+
+            #[dbus_interface(signal)]
+            async fn status_notifier_host_unregistered(
+                ctxt: &SignalContext<'_>,
+                $($host: &str)?
+            ) -> zbus::Result<()>;
+
+            async fn register_status_notifier_item(
+                &self,
+                service: &str,
+                #[zbus(header)] header: MessageHeader<'_>,
+                #[zbus(connection)] conn: &Connection,
+                #[zbus(signal_context)] ctxt: SignalContext<'_>,
+            ) -> zbus::fdo::Result<()> {
+                let service = normalize(service, &header)?;
+                let (destination, _) = item::parse_service(&service);
+                let name = BusName::try_from(destination.to_string())
+                    .map_err(|e| zbus::fdo::Error::InvalidArgs(e.to_string()))?;
+                let reject_foreign = self.0.lock().unwrap().reject_foreign;
+                if reject_foreign {
+                    let uid = DBusProxy::new(conn)
+                        .await?
+                        .get_connection_unix_user(name.clone())
+                        .await?;
+                    if item::is_foreign(uid) {
+                        return Err(zbus::fdo::Error::AccessDenied(format!(
+                            "{} belongs to another user",
+                            service
+                        )));
+                    }
+                }
+                {
+                    let mut state = self.0.lock().unwrap();
+                    if !state.items.insert(service.clone()) {
+                        if let Some(again) = &state.registered_again {
+                            let _ = again.try_send(service);
+                        }
+                        return Ok(());
+                    }
+                }
+                let conformant = self.0.lock().unwrap().conformant;
+                notify(&ctxt, Event::ItemRegistered(&service), conformant).await?;
+
+                let (conn, ctxt, state) = (conn.clone(), ctxt.to_owned(), self.0.clone());
+                rt::spawn(async move {
+                    if vanished(&conn, name).await.is_ok()
+                        && state.lock().unwrap().items.remove(&service)
+                    {
+                        let _ = notify(&ctxt, Event::ItemUnregistered(&service), conformant).await;
+                    }
+                });
+                Ok(())
+            }
+
+            $(
+            async fn $unregister(
+                &self,
+                service: &str,
+                #[zbus(header)] header: MessageHeader<'_>,
+                #[zbus(signal_context)] ctxt: SignalContext<'_>,
+            ) -> zbus::fdo::Result<()> {
+                // as registered, an object path with the sender before it
+                let service = normalize(service, &header)?;
+                if self.0.lock().unwrap().items.remove(&service) {
+                    notify(&ctxt, Event::ItemUnregistered(&service), false).await?;
+                }
+                Ok(())
+            }
+            )?
+
+            async fn register_status_notifier_host(
+                &self,
+                service: &str,
+                #[zbus(connection)] conn: &Connection,
+                #[zbus(signal_context)] ctxt: SignalContext<'_>,
+            ) -> zbus::fdo::Result<()> {
+                let name = BusName::try_from(service.to_string())
+                    .map_err(|e| zbus::fdo::Error::InvalidArgs(e.to_string()))?;
+                if !self.0.lock().unwrap().hosts.insert(service.to_string()) {
+                    return Ok(());
+                }
+                let conformant = self.0.lock().unwrap().conformant;
+                notify(&ctxt, Event::HostRegistered(service), conformant).await?;
+
+                let (conn, ctxt, state) = (conn.clone(), ctxt.to_owned(), self.0.clone());
+                rt::spawn(async move {
+                    if vanished(&conn, name.clone()).await.is_ok()
+                        && state.lock().unwrap().hosts.remove(name.as_str())
+                    {
+                        let _ = notify(&ctxt, Event::HostUnregistered(&name), conformant).await;
+                    }
+                });
+                Ok(())
+            }
+
+            #[dbus_interface(property)]
+            async fn protocol_version(&self) -> i32 {
+                1
+            }
+
+            #[dbus_interface(property)]
+            async fn is_status_notifier_host_registered(&self) -> bool {
+                !self.0.lock().unwrap().hosts.is_empty()
+            }
+
+            #[dbus_interface(property)]
+            async fn registered_status_notifier_items(&self) -> Vec<String> {
+                self.0.lock().unwrap().items.iter().cloned().collect()
+            }
+        }
+    };
+}
+
+watcher_interface!(
+    #[dbus_interface(name = "org.kde.StatusNotifierWatcher")]
+    KdeWatcher,
+    host: service,
+    unregister: unregister_status_notifier_item
+);
+watcher_interface!(
+    #[dbus_interface(name = "org.freedesktop.StatusNotifierWatcher")]
+    FreedesktopWatcher,
+    host: service,
+    unregister: unregister_status_notifier_item
+);
+watcher_interface!(
+    #[dbus_interface(name = "org.kde.StatusNotifierWatcher")]
+    SpecKdeWatcher
+);
+watcher_interface!(
+    #[dbus_interface(name = "org.freedesktop.StatusNotifierWatcher")]
+    SpecFreedesktopWatcher
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use std::collections::BTreeSet;
+    use zbus::Interface;
+
+    /// The members of the interfaces in introspection `xml`, one line each
+    /// with the types of their arguments, ignoring names of arguments and
+    /// annotations.
+    fn members(xml: &str) -> BTreeSet<String> {
+        let tag =
+            Regex::new(r"<(/?)(interface|method|property|signal|arg)\b([^>]*?)(/?)>").unwrap();
+        let attribute = Regex::new(r#"(\w+)="([^"]*)""#).unwrap();
+        let mut found = BTreeSet::new();
+        let (mut interface, mut current) = (String::new(), None::<String>);
+        for tag in tag.captures_iter(xml) {
+            let closing = &tag[1] == "/";
+            let attributes = attribute
+                .captures_iter(&tag[3])
+                .map(|a| (a[1].to_string(), a[2].to_string()))
+                .collect::<std::collections::HashMap<_, _>>();
+            let get = |key: &str| attributes.get(key).cloned().unwrap_or_default();
+            match (&tag[2], closing) {
+                ("interface", false) => interface = get("name"),
+                ("arg", false) => {
+                    if let Some(member) = &mut current {
+                        let direction = get("direction");
+                        member.push_str(&format!(" {}{}", direction, get("type")));
+                    }
+                }
+                ("property", false) => {
+                    let property = format!(
+                        "{} property {} {} {}",
+                        interface,
+                        get("name"),
+                        get("type"),
+                        get("access")
+                    );
+                    found.insert(property);
+                }
+                ("method" | "signal", false) => {
+                    let member = format!("{} {} {}:", interface, &tag[2], get("name"));
+                    match &tag[4] {
+                        "/" => {
+                            found.insert(member);
+                        }
+                        _ => current = Some(member),
+                    }
+                }
+                ("method" | "signal", true) => found.extend(current.take()),
+                _ => {}
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn conformant_interface_matches_the_specification() {
+        let spec = include_str!("../dbus/org.kde.StatusNotifierWatcher.xml");
+        let mut served = String::new();
+        SpecKdeWatcher(Default::default()).introspect_to_writer(&mut served, 0);
+        let served = members(&served);
+        assert_eq!(served, members(spec));
+        let mut legacy = String::new();
+        KdeWatcher(Default::default()).introspect_to_writer(&mut legacy, 0);
+        assert_ne!(members(&legacy), served);
+        assert!(served.contains("org.kde.StatusNotifierWatcher property ProtocolVersion i read"));
+        assert!(
+            served.contains("org.kde.StatusNotifierWatcher signal StatusNotifierHostUnregistered:")
+        );
+        assert!(served.contains(
+            "org.kde.StatusNotifierWatcher property RegisteredStatusNotifierItems as read"
+        ));
+    }
+
+    #[test]
+    fn freedesktop_interface_differs_only_in_name() {
+        let mut kde = String::new();
+        let mut freedesktop = String::new();
+        SpecKdeWatcher(Default::default()).introspect_to_writer(&mut kde, 0);
+        SpecFreedesktopWatcher(Default::default()).introspect_to_writer(&mut freedesktop, 0);
+        assert_eq!(
+            members(&kde),
+            members(&freedesktop.replace(FREEDESKTOP_WATCHER, WATCHER))
+        );
+    }
+}
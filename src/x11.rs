@@ -0,0 +1,221 @@
+//! Just enough of the X11 protocol to ask the window manager to activate a
+//! window, for `raise` on items with a `WindowId`: connect to `$DISPLAY` with
+//! the cookie of `$XAUTHORITY`, look up `_NET_ACTIVE_WINDOW` and send it to the
+//! root window as EWMH has pagers do. Only local displays are supported.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How long the X server may take to answer.
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+const FAMILY_LOCAL: u16 = 256;
+const FAMILY_WILD: u16 = 65535;
+const COOKIE: &[u8] = b"MIT-MAGIC-COOKIE-1";
+
+const INTERN_ATOM: u8 = 16;
+const SEND_EVENT: u8 = 25;
+const GET_INPUT_FOCUS: u8 = 43;
+const CLIENT_MESSAGE: u8 = 33;
+const SUBSTRUCTURE_NOTIFY: u32 = 1 << 19;
+const SUBSTRUCTURE_REDIRECT: u32 = 1 << 20;
+/// `_NET_ACTIVE_WINDOW` source indication of pagers and other tools acting
+/// on behalf of the user
+const SOURCE_PAGER: u32 = 2;
+
+#[derive(Debug)]
+pub struct X11Error(String);
+
+impl fmt::Display for X11Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "X11: {}", self.0)
+    }
+}
+
+impl std::error::Error for X11Error {}
+
+impl From<io::Error> for X11Error {
+    fn from(e: io::Error) -> Self {
+        X11Error(e.to_string())
+    }
+}
+
+/// Asks the window manager to raise and focus `window`.
+pub fn activate(window: u32) -> Result<(), X11Error> {
+    let mut x = Connection::open()?;
+    let atom = x.intern_atom("_NET_ACTIVE_WINDOW")?;
+    let mut event = [0u8; 32];
+    event[0] = CLIENT_MESSAGE;
+    event[1] = 32;
+    event[4..8].copy_from_slice(&window.to_le_bytes());
+    event[8..12].copy_from_slice(&atom.to_le_bytes());
+    // the source, then CurrentTime and no window active before
+    event[12..16].copy_from_slice(&SOURCE_PAGER.to_le_bytes());
+    let mut request = request(SEND_EVENT, 0, 11);
+    request.extend_from_slice(&x.root.to_le_bytes());
+    request.extend_from_slice(&(SUBSTRUCTURE_NOTIFY | SUBSTRUCTURE_REDIRECT).to_le_bytes());
+    request.extend_from_slice(&event);
+    x.stream.write_all(&request)?;
+    // SendEvent has no reply, one to GetInputFocus tells that it went through
+    x.stream.write_all(&request_header(GET_INPUT_FOCUS, 0, 1))?;
+    x.reply().map(|_| ())
+}
+
+struct Connection {
+    stream: UnixStream,
+    root: u32,
+}
+
+fn request_header(opcode: u8, data: u8, words: u16) -> [u8; 4] {
+    let [low, high] = words.to_le_bytes();
+    [opcode, data, low, high]
+}
+
+fn request(opcode: u8, data: u8, words: u16) -> Vec<u8> {
+    let mut request = Vec::with_capacity(words as usize * 4);
+    request.extend_from_slice(&request_header(opcode, data, words));
+    request
+}
+
+fn pad(data: &mut Vec<u8>) {
+    data.resize(data.len().next_multiple_of(4), 0);
+}
+
+/// The number of the display in `$DISPLAY`, like `0` of `:0.0`.
+fn display() -> Result<String, X11Error> {
+    let display = std::env::var("DISPLAY").map_err(|_| X11Error("DISPLAY is not set".into()))?;
+    let (host, rest) = display
+        .rsplit_once(':')
+        .ok_or_else(|| X11Error(format!("invalid DISPLAY `{}`", display)))?;
+    if !host.is_empty() && host != "unix" {
+        return Err(X11Error(format!("`{}` is no local display", display)));
+    }
+    let number = rest.split('.').next().unwrap_or_default();
+    match !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()) {
+        true => Ok(number.to_string()),
+        false => Err(X11Error(format!("invalid DISPLAY `{}`", display))),
+    }
+}
+
+/// The MIT-MAGIC-COOKIE-1 for `display` in `$XAUTHORITY`, if there is one.
+fn cookie(display: &str) -> Option<Vec<u8>> {
+    let path = match std::env::var_os("XAUTHORITY") {
+        Some(path) => PathBuf::from(path),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".Xauthority"),
+    };
+    let data = std::fs::read(path).ok()?;
+    let mut rest = data.as_slice();
+    let field = |rest: &mut &[u8]| -> Option<Vec<u8>> {
+        let len = u16::from_be_bytes(rest.get(..2)?.try_into().ok()?) as usize;
+        let value = rest.get(2..2 + len)?.to_vec();
+        *rest = &rest[2 + len..];
+        Some(value)
+    };
+    while rest.len() >= 2 {
+        let family = u16::from_be_bytes(rest[..2].try_into().ok()?);
+        rest = &rest[2..];
+        let (_address, number, name, data) = (
+            field(&mut rest)?,
+            field(&mut rest)?,
+            field(&mut rest)?,
+            field(&mut rest)?,
+        );
+        let ours = number.is_empty() || number == display.as_bytes();
+        if matches!(family, FAMILY_LOCAL | FAMILY_WILD) && ours && name == COOKIE {
+            return Some(data);
+        }
+    }
+    None
+}
+
+impl Connection {
+    fn open() -> Result<Connection, X11Error> {
+        let display = display()?;
+        let path = format!("/tmp/.X11-unix/X{}", display);
+        let stream =
+            UnixStream::connect(&path).map_err(|e| X11Error(format!("{}: {}", path, e)))?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut x = Connection { stream, root: 0 };
+
+        let cookie = cookie(&display);
+        let name = if cookie.is_some() { COOKIE } else { b"" };
+        let cookie = cookie.unwrap_or_default();
+        // little endian, protocol 11.0
+        let mut setup = vec![b'l', 0, 11, 0, 0, 0];
+        setup.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        setup.extend_from_slice(&(cookie.len() as u16).to_le_bytes());
+        setup.extend_from_slice(&[0, 0]);
+        setup.extend_from_slice(name);
+        pad(&mut setup);
+        setup.extend_from_slice(&cookie);
+        pad(&mut setup);
+        x.stream.write_all(&setup)?;
+
+        let mut header = [0u8; 8];
+        x.stream.read_exact(&mut header)?;
+        let mut info = vec![0u8; u16::from_le_bytes([header[6], header[7]]) as usize * 4];
+        x.stream.read_exact(&mut info)?;
+        if header[0] != 1 {
+            let reason = info.get(..header[1] as usize).unwrap_or_default();
+            let reason = String::from_utf8_lossy(reason);
+            return Err(X11Error(format!("refused: {}", reason.trim_end())));
+        }
+        // the root window of the first screen, after the vendor and pixmap formats
+        let field = |at: usize| {
+            info.get(at..at + 2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        };
+        let vendor = field(16).ok_or_else(|| X11Error("short setup reply".into()))? as usize;
+        let formats = *info.get(21).unwrap_or(&0) as usize;
+        let screen = 32 + vendor.next_multiple_of(4) + 8 * formats;
+        x.root = info
+            .get(screen..screen + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| X11Error("no screen".into()))?;
+        Ok(x)
+    }
+
+    fn intern_atom(&mut self, name: &str) -> Result<u32, X11Error> {
+        let words = 2 + name.len().div_ceil(4);
+        let mut request = request(INTERN_ATOM, 0, words as u16);
+        request.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        request.extend_from_slice(&[0, 0]);
+        request.extend_from_slice(name.as_bytes());
+        pad(&mut request);
+        self.stream.write_all(&request)?;
+        let reply = self.reply()?;
+        Ok(u32::from_le_bytes([
+            reply[8], reply[9], reply[10], reply[11],
+        ]))
+    }
+
+    /// The next reply, skipping events and failing on errors.
+    fn reply(&mut self) -> Result<[u8; 32], X11Error> {
+        loop {
+            let mut packet = [0u8; 32];
+            self.stream.read_exact(&mut packet)?;
+            match packet[0] {
+                0 => {
+                    let code = packet[1];
+                    let reason = match code {
+                        3 => "no such window".to_string(),
+                        code => format!("error {}", code),
+                    };
+                    return Err(X11Error(reason));
+                }
+                1 => {
+                    // the replies asked for here are the 32 bytes only
+                    let extra = u32::from_le_bytes([packet[4], packet[5], packet[6], packet[7]]);
+                    let mut skip = vec![0u8; extra as usize * 4];
+                    self.stream.read_exact(&mut skip)?;
+                    return Ok(packet);
+                }
+                _ => {}
+            }
+        }
+    }
+}